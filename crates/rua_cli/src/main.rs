@@ -1,22 +1,30 @@
 mod ui;
 mod utils;
 
-use crate::utils::file_finder::FileFinder;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::*;
 use figlet_rs::FIGfont;
 use rua_core::constants::*;
 use rua_core::fastboot::FastbootClient;
 use rua_core::flasher::Flasher;
-use rua_core::ConnectedDevice;
-use rustyline::DefaultEditor;
+use rua_core::{ConnectedDevice, DeviceEvent, DeviceMode, DeviceMonitor, FlashPlan, FlashStep, PlanReporter};
+use rua_core::journal::FlashJournal;
+use rua_core::android_info::AndroidInfo;
+use rua_core::xiaomi;
+use rua_core::avb;
+use rua_core::bootimg;
+use rua_core::cpio::{CpioCommand, PatchState};
+use rua_core::kernel_info;
+use rua_core::kmod;
+use rua_core::lkm;
+use rustyline::{DefaultEditor, ExternalPrinter};
 use std::env;
 use std::fs;
 use std::io::{self, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use rua_core::payload::{self, ProgressReporter};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::sync::{Arc, Mutex};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::path::{Path, PathBuf};
 use std::collections::{HashMap, HashSet};
 use std::time::{Instant, Duration};
@@ -95,6 +103,30 @@ impl ProgressReporter for ConsoleReporter {
     }
 }
 
+struct BatchReporter { bars: HashMap<String, ProgressBar> }
+impl PlanReporter for BatchReporter {
+    fn should_cancel(&self) -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+    fn on_step_start(&self, serial: &str, _index: usize, _total: usize, step: &FlashStep) {
+        expect_planned_reboot(serial, step);
+        if let Some(pb) = self.bars.get(serial) {
+            pb.set_message(step.describe());
+        }
+    }
+    fn on_step_done(&self, serial: &str, index: usize, total: usize, step: &FlashStep, result: &rua_core::Result<()>) {
+        let Some(pb) = self.bars.get(serial) else { return };
+        match result {
+            Ok(_) if index + 1 == total => {
+                pb.set_position(total as u64);
+                pb.finish_with_message("✓ 完成".to_string());
+            }
+            Ok(_) => pb.set_position(index as u64 + 1),
+            Err(e) => pb.abandon_with_message(format!("✗ {} 失败: {}", step.describe(), e)),
+        }
+    }
+}
+
 #[cfg(target_os = "windows")]
 use windows_sys::Win32::System::Console::{
     GetStdHandle, GetConsoleMode, SetConsoleMode, SetConsoleOutputCP, GetConsoleScreenBufferInfo,
@@ -105,10 +137,151 @@ use windows_sys::Win32::System::Console::{
 use windows_sys::Win32::Foundation::HANDLE;
 
 pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static MONITOR: OnceLock<DeviceMonitor> = OnceLock::new();
+/// 刷机日志是否记录镜像 sha256，用于续刷时判断镜像是否被替换
+static JOURNAL_HASH: AtomicBool = AtomicBool::new(true);
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-struct Args {}
+struct Args {
+    /// 不带子命令时进入交互菜单
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// 刷机日志只记录镜像大小与修改时间，不计算 sha256；镜像很大时可以更快开始刷机
+    #[arg(long)]
+    no_journal_hash: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 查看 boot / init_boot / vendor_boot 镜像信息
+    Inspect {
+        image: PathBuf,
+    },
+    /// 解包 boot 镜像到目录 (kernel、ramdisk.cpio、dtb、header.json 等)
+    Unpack {
+        image: PathBuf,
+        /// 默认为镜像同名目录
+        out_dir: Option<PathBuf>,
+    },
+    /// 按解包目录重新打包 boot 镜像
+    Repack {
+        dir: PathBuf,
+        output: PathBuf,
+    },
+    /// 修改 boot 镜像头部 (命令行、系统版本、安全补丁、vendor_boot 的 bootconfig)
+    Edit {
+        image: PathBuf,
+        /// 默认为 <镜像名>_edited.img
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// 追加命令行参数，同名参数会被替换，可多次指定
+        #[arg(long)]
+        cmdline_add: Vec<String>,
+        /// 删除命令行参数，可写参数名或完整参数，可多次指定
+        #[arg(long)]
+        cmdline_remove: Vec<String>,
+        /// 系统版本，如 14 或 14.0.0
+        #[arg(long)]
+        os_version: Option<String>,
+        /// 安全补丁级别，如 2024-05 或 2024-05-05
+        #[arg(long)]
+        os_patch_level: Option<String>,
+        /// 追加 bootconfig 项 (key=value)，可多次指定
+        #[arg(long)]
+        bootconfig_add: Vec<String>,
+        /// 删除 bootconfig 项，可多次指定
+        #[arg(long)]
+        bootconfig_remove: Vec<String>,
+    },
+    /// 按 KMI 自动选择 KernelSU LKM 模块并修补镜像
+    KsuLkm {
+        image: PathBuf,
+        /// KernelSU 分支，只有一个分支时可省略
+        #[arg(long)]
+        branch: Option<String>,
+        /// KernelSU 版本，只有一个版本时可省略
+        #[arg(long)]
+        version: Option<String>,
+        /// 用于读取 KMI 并校验内核模块的 boot 镜像或内核文件，修补 init_boot / vendor_boot 时使用，默认为 image
+        #[arg(long)]
+        kernel: Option<PathBuf>,
+        /// 从已连接的 ADB 设备读取 KMI
+        #[arg(long)]
+        from_device: bool,
+        /// 目标分区，决定输出文件名
+        #[arg(short, long, default_value = "boot")]
+        partition: String,
+        /// vendor_boot 中要修补的 vendor ramdisk (名称或类型)
+        #[arg(long)]
+        vendor_ramdisk: Option<String>,
+    },
+    /// 分析内核：版本、编译器、GKI / KMI、关键配置与可用的 Root 方式
+    Kernel {
+        /// boot 镜像、kernel 分区镜像或 Image.gz 等内核文件
+        image: PathBuf,
+        /// 导出内核内置的 .config
+        #[arg(long)]
+        config_out: Option<PathBuf>,
+    },
+    /// 查看、提取、替换 DTB / DTBO 并修补其中的 fstab
+    Dtb {
+        #[command(subcommand)]
+        action: DtbAction,
+    },
+    /// 按顺序对镜像的 ramdisk 执行 magiskboot 风格的 cpio 命令，如 "add 0644 init.custom.rc init.custom.rc"
+    Cpio {
+        image: PathBuf,
+        /// 每个参数为一条命令: add MODE ENTRY INFILE | rm [-r] ENTRY | mkdir MODE ENTRY | mv SOURCE DEST |
+        /// ln TARGET ENTRY | extract [ENTRY OUT] | exists ENTRY | test | backup ORIG | restore。
+        /// 含空格的路径用引号括起；exists / test 的结果作为退出码
+        #[arg(required = true)]
+        commands: Vec<String>,
+        /// 默认为 <镜像名>_patched.img
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// vendor_boot 中要修改的 vendor ramdisk (名称或类型)
+        #[arg(long)]
+        vendor_ramdisk: Option<String>,
+    },
+}
+
+/// image 可以是 dtbo.img、单独的 DTB 文件或 boot / vendor_boot 镜像
+#[derive(Subcommand, Debug)]
+enum DtbAction {
+    /// 列出各个 DTB / overlay
+    Info {
+        image: PathBuf,
+    },
+    /// 提取各个 DTB / overlay 到目录
+    Extract {
+        image: PathBuf,
+        /// 默认为镜像同名目录
+        out_dir: Option<PathBuf>,
+    },
+    /// 用文件替换第 index 个 DTB / overlay (从 0 开始)
+    Replace {
+        image: PathBuf,
+        index: usize,
+        file: PathBuf,
+        /// 默认为 <镜像名>_patched.<扩展名>
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// 去除 fstab 节点中的 AVB 校验与强制加密选项
+    Fstab {
+        image: PathBuf,
+        /// 默认为 <镜像名>_patched.<扩展名>
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// 保留 verify / avb 选项
+        #[arg(long)]
+        keep_verity: bool,
+        /// 保留 forceencrypt / fileencryption 选项
+        #[arg(long)]
+        keep_encryption: bool,
+    },
+}
 
 #[cfg(target_os = "windows")]
 fn set_console_window_properties() {
@@ -184,8 +357,12 @@ async fn main() -> anyhow::Result<()> {
     #[cfg(target_os = "windows")]
     set_console_window_properties();
 
-    let _args = Args::parse();
-    
+    let args = Args::parse();
+    JOURNAL_HASH.store(!args.no_journal_hash, Ordering::SeqCst);
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
+
     ctrlc::set_handler(move || {
         if INTERRUPTED.load(Ordering::SeqCst) {
             std::process::exit(130);
@@ -195,6 +372,7 @@ async fn main() -> anyhow::Result<()> {
     }).expect("Error setting Ctrl-C handler");
 
     let client = FastbootClient::new()?;
+    let _ = MONITOR.set(DeviceMonitor::start(client.clone(), rua_core::AdbClient::new().ok(), Duration::from_secs(1)));
     
     if let Err(e) = run_interactive_loop(client).await {
         ui::err(&format!("程序发生异常错误: {:?}", e));
@@ -203,8 +381,112 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Inspect { image } => print_boot_report(&bootimg::inspect(&image)?),
+        Command::Unpack { image, out_dir } => {
+            let out_dir = out_dir.unwrap_or_else(|| default_unpack_dir(&image));
+            bootimg::unpack(&image, &out_dir)?;
+            print_unpacked_dir(&out_dir);
+        }
+        Command::Repack { dir, output } => {
+            let config = bootimg::repack(&dir, &output)?;
+            ui::ok(&format!("已生成: {}", output.display()));
+            if config.avb_footer_removed {
+                warn_avb_footer_removed();
+            }
+        }
+        Command::Edit { image, output, cmdline_add, cmdline_remove, os_version, os_patch_level, bootconfig_add, bootconfig_remove } => {
+            let edit = bootimg::HeaderEdit { cmdline_add, cmdline_remove, os_version, os_patch_level, bootconfig_add, bootconfig_remove };
+            let output = output.unwrap_or_else(|| default_edit_output(&image));
+            let report = bootimg::edit_header(&image, &output, &edit)?;
+            print_header_edit_report(&report, &output);
+        }
+        Command::KsuLkm { image, branch, version, kernel, from_device, partition, vendor_ramdisk } => {
+            let branches = lkm::find_branches(&ksu_lkm_base_dir());
+            let selected = lkm::select_branch(&branches, branch.as_deref())?.select_version(version.as_deref())?;
+            let kernel = kernel.unwrap_or_else(|| image.clone()).to_string_lossy().to_string();
+            let kmi = if from_device {
+                detect_kmi_from_device().await.ok_or_else(|| anyhow::anyhow!("无法从设备读取 KMI (需要且仅连接一台已开启 USB 调试的设备)"))?
+            } else {
+                lkm::kmi_from_image(Path::new(&kernel))?.ok_or_else(|| anyhow::anyhow!("{} 不是 GKI 内核，无法确定 KMI", kernel))?
+            };
+            let target = if from_device { kmod::TargetKernel::Kmi(&kmi) } else { kmod::TargetKernel::File(&kernel) };
+            let package = match selected.resolve(Some(&kmi)) {
+                lkm::LkmResolution::Exact(package) => package,
+                lkm::LkmResolution::Unresolved(reason) => anyhow::bail!(reason),
+            };
+            ui::ok(&format!("KMI {} -> {}", kmi, package.ko_path.display()));
+            let out_name = Flasher::kernelsu_lkm_patch(
+                &image.to_string_lossy(),
+                &selected.ksuinit_path.to_string_lossy(),
+                Some(&selected.ksuinit_d_path.to_string_lossy()),
+                &package.ko_path.to_string_lossy(),
+                &partition,
+                vendor_ramdisk.as_deref().map(bootimg::RamdiskChoice::Name),
+                Some(target),
+                false,
+            )
+            .await?;
+            ui::ok(&format!("已生成: {}", out_name));
+        }
+        Command::Kernel { image, config_out } => {
+            let analysis = bootimg::analyze_kernel(&image)?;
+            print_kernel_report(&analysis);
+            if let Some(out) = config_out {
+                export_kernel_config(&analysis, &out)?;
+            }
+        }
+        Command::Dtb { action } => match action {
+            DtbAction::Info { image } => print_dtb_info(&bootimg::DtbFile::load(&image)?)?,
+            DtbAction::Extract { image, out_dir } => {
+                let out_dir = out_dir.unwrap_or_else(|| default_unpack_dir(&image));
+                extract_dtbs(&bootimg::DtbFile::load(&image)?, &out_dir)?;
+            }
+            DtbAction::Replace { image, index, file, output } => {
+                let output = output.unwrap_or_else(|| default_patched_output(&image));
+                replace_dtb_entry(&bootimg::DtbFile::load(&image)?, index, &file, &output)?;
+            }
+            DtbAction::Fstab { image, output, keep_verity, keep_encryption } => {
+                let patch = rua_core::dtb::FstabPatch { remove_verity: !keep_verity, remove_encryption: !keep_encryption };
+                let output = output.unwrap_or_else(|| default_patched_output(&image));
+                patch_dtb_fstab(&bootimg::DtbFile::load(&image)?, &patch, &output)?;
+            }
+        },
+        Command::Cpio { image, commands, output, vendor_ramdisk } => {
+            let commands = commands.iter().map(|c| CpioCommand::parse(c)).collect::<rua_core::Result<Vec<_>>>()?;
+            let mut ramdisk = bootimg::ImageRamdisk::load(&image, vendor_ramdisk.as_deref().map(bootimg::RamdiskChoice::Name))?;
+            // 与 magiskboot 一致，以最后一条 exists / test 的结果作为进程退出码
+            let mut exit_code = 0;
+            for command in &commands {
+                exit_code = command.exit_code(&ramdisk.cpio).unwrap_or(exit_code);
+                println!("  {}", ramdisk.cpio.exec(command)?);
+            }
+            if commands.iter().any(CpioCommand::modifies) {
+                save_ramdisk_image(&ramdisk, &output.unwrap_or_else(|| default_patched_output(&image)))?;
+            }
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn run_interactive_loop(client: FastbootClient) -> anyhow::Result<()> {
     let mut rl = DefaultEditor::new()?;
+    if let (Some(monitor), Ok(mut printer)) = (MONITOR.get(), rl.create_external_printer()) {
+        let mut rx = monitor.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => { let _ = printer.print(format_device_event(&event)); }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+        });
+    }
     loop {
         refresh_ui();
         let readline = rl.readline("> ");
@@ -254,6 +536,7 @@ fn refresh_ui() {
     if !QQ_GROUPS.is_empty() {
         println!("QQ交流群: {}", QQ_GROUPS.join(", ").blue());
     }
+    println!("{}", device_status_line());
 
     let divider = "=".repeat(100).white();
     println!("{}", divider);
@@ -272,6 +555,38 @@ fn refresh_ui() {
     println!("{}", divider);
 }
 
+fn mode_label(mode: &DeviceMode) -> ColoredString {
+    match mode {
+        DeviceMode::Fastboot => "Fastboot".yellow(),
+        DeviceMode::FastbootD => "FastbootD".yellow(),
+        DeviceMode::ADB => "ADB".green(),
+        DeviceMode::Recovery => "Recovery".magenta(),
+        DeviceMode::Sideload => "Sideload".magenta(),
+        DeviceMode::Unknown(s) => s.white(),
+    }
+}
+
+fn device_status_line() -> String {
+    let Some(monitor) = MONITOR.get() else { return String::new() };
+    let devices = monitor.devices();
+    if devices.is_empty() {
+        return format!("设备: {}", "未连接".bright_black());
+    }
+    let list: Vec<String> = devices.iter().map(|d| {
+        format!("{} [{}] {}", d.serial.cyan(), mode_label(&d.mode), d.product.as_deref().unwrap_or(""))
+    }).collect();
+    format!("设备: {}", list.join("  |  "))
+}
+
+fn format_device_event(event: &DeviceEvent) -> String {
+    match event {
+        DeviceEvent::Connected(d) => format!("{} 设备已连接: {} [{}]", "+".green().bold(), d.serial.cyan(), mode_label(&d.mode)),
+        DeviceEvent::Disconnected(d) => format!("{} 设备已断开: {}", "-".red().bold(), d.serial.cyan()),
+        DeviceEvent::ModeChanged { device, from } => format!("{} 设备模式切换: {} [{}] -> [{}]",
+            "~".yellow().bold(), device.serial.cyan(), mode_label(from), mode_label(&device.mode)),
+    }
+}
+
 async fn handle_menu_action(choice: &str, client: &FastbootClient) {
     let flasher = Flasher::new(client.clone());
     println!();
@@ -298,6 +613,8 @@ async fn handle_menu_action(choice: &str, client: &FastbootClient) {
         "20" => switch_slot(client).await,
         "21" => activate_adb_menu().await,
         "22" => open_device_manager(),
+        "23" => batch_flash(client).await,
+        "24" => image_toolbox().await,
         "0" => ui::ok("感谢使用 RuaFlashTool，再见！"),
         _ => ui::warn(&format!("未知选项: {}", choice)),
     }
@@ -306,97 +623,137 @@ async fn handle_menu_action(choice: &str, client: &FastbootClient) {
 async fn flash_xiaomi_fastboot() {
     ui::step("小米线刷包一键刷入...");
     if let Some(dir) = ui::select_directory("请选择小米线刷包解压后的目录") {
-        let bat_files = [
-            ("flash_all.bat", "刷机并清除所有数据"),
-            ("flash_all_lock.bat", "刷机、清除数据并回锁 Bootloader"),
-            ("flash_all_except_storage.bat", "刷机并保留个人数据"),
-        ];
-
-        let available_bats: Vec<(String, String)> = bat_files
-            .iter()
-            .filter(|(name, _)| dir.join(name).exists())
-            .map(|(name, desc)| (name.to_string(), desc.to_string()))
-            .collect();
+        let scripts = xiaomi::find_flash_scripts(&dir);
 
-        if available_bats.is_empty() {
+        if scripts.is_empty() {
             ui::err("未在目录下找到任何刷机脚本文件 (flash_all.bat / flash_all_lock.bat / flash_all_except_storage.bat)");
+            return;
+        }
+        println!("\n检测到以下可用的刷机脚本:");
+        let divider = "=".repeat(60).white();
+        println!("{}", divider);
+        for (i, script) in scripts.iter().enumerate() {
+            println!("{}{}", format!("{:>2}. ", i + 1).bright_cyan(), script.description);
+        }
+        println!("{}", divider);
+
+        print!("请选择刷机方式 (输入序号): ");
+        let _ = io::stdout().flush();
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+
+        let choice: usize = input.trim().parse().unwrap_or(0);
+        if choice == 0 || choice > scripts.len() {
+            ui::err("无效的选择。");
+            return;
+        }
+        let script = &scripts[choice - 1];
+
+        let plan = match xiaomi::parse_flash_script(&script.path) {
+            Ok(plan) => plan,
+            Err(e) => {
+                ui::err(&format!("解析刷机脚本失败: {}", e));
+                return;
+            }
+        };
+        let missing = plan.missing_images();
+        if !missing.is_empty() {
+            ui::err("刷机脚本引用的以下镜像不存在，线刷包可能不完整:");
+            for path in missing {
+                println!("  {}", path.display());
+            }
+            return;
+        }
+        ui::step(&format!("已解析 {}，共 {} 个步骤。", script.path.display(), plan.steps.len()));
+
+        if !script.wipes_data() {
+            ui::warn("警告: 此选项将保留设备上的所有个人数据！");
+            ui::warn("如果系统版本与当前设备不匹配，可能导致开机异常。");
+            if !ui::confirm("确定要保留数据刷入吗？", true) {
+                ui::warn("已取消刷机操作。");
+                return;
+            }
+        } else if script.locks_bootloader() {
+            ui::warn("警告: 此选项将在刷机完成后回锁 Bootloader！");
+            ui::warn("回锁后可能需要重新解锁才能刷入第三方固件。");
+            if !ui::confirm("确定要回锁 Bootloader 吗？", false) {
+                ui::warn("已取消刷机操作。");
+                return;
+            }
         } else {
-            println!("\n检测到以下可用的刷机脚本:");
-            let divider = "=".repeat(60).white();
-            println!("{}", divider);
-            for (i, (_, desc)) in available_bats.iter().enumerate() {
-                println!("{}{}", format!("{:>2}. ", i + 1).bright_cyan(), desc);
+            ui::warn("警告: 此操作将清除设备上的所有个人数据！");
+            if !ui::confirm("确定要继续刷机吗？", false) {
+                ui::warn("已取消刷机操作。");
+                return;
             }
-            println!("{}", divider);
+        }
 
-            print!("请选择刷机方式 (输入序号): ");
-            let _ = io::stdout().flush();
-            let mut input = String::new();
-            let _ = io::stdin().read_line(&mut input);
+        ui::step("正在检测 Fastboot 设备...");
+        let mut client = match FastbootClient::new() {
+            Ok(client) => client,
+            Err(e) => {
+                ui::err(&format!("初始化 Fastboot 客户端失败: {:?}", e));
+                return;
+            }
+        };
+        let serial = select_device(&client).await;
+        if serial.is_empty() {
+            ui::warn("未选择设备，取消刷机。");
+            return;
+        }
+        ui::step(&format!("已选择设备: {}", serial));
+        client.set_serial(Some(serial.clone()));
 
-            let choice: usize = input.trim().parse().unwrap_or(0);
-            if choice > 0 && choice <= available_bats.len() {
-                let selected_bat = &available_bats[choice - 1].0;
-                let bat_path = dir.join(selected_bat);
+        // 防回滚版本由计划中的校验步骤在刷入任何分区之前与设备核对
+        match plan.steps.iter().find_map(|s| match s {
+            FlashStep::CheckAntiRollback { package_version } => Some(*package_version),
+            _ => None,
+        }) {
+            Some(p) => ui::step(&format!("刷机包防回滚 (ARB) 版本: {}，刷入前将与设备核对。", p)),
+            None => ui::warn("未能从刷机包中读取防回滚版本，无法确认是否安全，请自行核对。"),
+        }
+        let flasher = Flasher::new(client);
 
-                let mut should_proceed = true;
+        let Some(mut journal) = resume_journal(&plan.name, &serial).or_else(|| create_journal(&serial, &plan)) else { return };
+        if run_journal(&flasher, &mut journal).await.is_ok() {
+            ui::ok(&format!("{} 执行完成。", script.stem()));
+        }
+    }
+}
 
-                if selected_bat == "flash_all_except_storage.bat" {
-                    ui::warn("警告: 此选项将保留设备上的所有个人数据！");
-                    ui::warn("如果系统版本与当前设备不匹配，可能导致开机异常。");
-                    if !ui::confirm("确定要保留数据刷入吗？", true) {
-                        ui::warn("已取消刷机操作。");
-                        should_proceed = false;
-                    }
-                } else if selected_bat == "flash_all_lock.bat" {
-                    ui::warn("警告: 此选项将在刷机完成后回锁 Bootloader！");
-                    ui::warn("回锁后可能需要重新解锁才能刷入第三方固件。");
-                    if !ui::confirm("确定要回锁 Bootloader 吗？", false) {
-                        ui::warn("已取消刷机操作。");
-                        should_proceed = false;
-                    }
-                } else {
-                    ui::warn("警告: 此操作将清除设备上的所有个人数据！");
-                    if !ui::confirm("确定要继续刷机吗？", false) {
-                        ui::warn("已取消刷机操作。");
-                        should_proceed = false;
-                    }
-                }
+/// 计划中的重启步骤会让设备短暂断开，提前告知监视器不要当作意外断开
+fn expect_planned_reboot(serial: &str, step: &FlashStep) {
+    if let (Some(monitor), FlashStep::Reboot { .. }) = (MONITOR.get(), step) {
+        monitor.expect_reboot(serial);
+    }
+}
 
-                if should_proceed {
-                    ui::step("正在检测 Fastboot 设备...");
-                    let serial = match FastbootClient::new() {
-                        Ok(client) => {
-                            let s = select_device(&client).await;
-                            if s.is_empty() {
-                                ui::warn("未选择设备，取消刷机。");
-                                return;
-                            }
-                            s
-                        }
-                        Err(e) => {
-                            ui::err(&format!("初始化 Fastboot 客户端失败: {:?}", e));
-                            return;
-                        }
-                    };
-                    ui::step(&format!("已选择设备: {}", serial));
-
-                    ui::step(&format!("正在启动 {} ...", selected_bat));
-                    // 使用 start "" /wait "<bat>" -s <serial>，把序列号透传给脚本中的 fastboot %*
-                    let _ = tokio::process::Command::new("cmd")
-                        .arg("/c")
-                        .arg("start")
-                        .arg("")
-                        .arg("/wait")
-                        .arg(&bat_path)
-                        .arg("-s")
-                        .arg(&serial)
-                        .spawn();
-                    ui::ok("刷机脚本已启动，并已指定目标设备序列号。");
-                }
-            } else {
-                ui::err("无效的选择。");
+/// 单设备执行刷机计划时的控制台进度
+struct StepReporter {
+    pb: ProgressBar,
+}
+
+impl PlanReporter for StepReporter {
+    fn should_cancel(&self) -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+    fn on_step_start(&self, serial: &str, _index: usize, _total: usize, step: &FlashStep) {
+        expect_planned_reboot(serial, step);
+        self.pb.set_message(step.describe());
+    }
+    fn confirm_override(&self, _serial: &str, reason: &str) -> bool {
+        self.pb.suspend(|| {
+            ui::warn(reason);
+            ui::confirm("仍要继续吗？", false)
+        })
+    }
+    fn on_step_done(&self, _serial: &str, index: usize, _total: usize, step: &FlashStep, result: &rua_core::Result<()>) {
+        match result {
+            Ok(_) => {
+                self.pb.println(format!("{} {}", "✓".green(), step.describe()));
+                self.pb.set_position(index as u64 + 1);
             }
+            Err(e) => self.pb.println(format!("{} {}: {}", "✗".red(), step.describe(), e)),
         }
     }
 }
@@ -448,23 +805,17 @@ async fn flash_all_partitions(flasher: &Flasher, fastboot_mode: bool) {
     let mode_str = if fastboot_mode { "Fastboot" } else { "FastbootD" };
     ui::step(&format!("正在目录下查找分区镜像刷入 ({})...", mode_str));
     if let Some(dir) = ui::select_directory("请选择包含分区镜像 (.img) 的目录") {
-        let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().flatten()
-            .filter(|e| e.path().is_file() && e.path().extension().map_or(false, |ext| ext == "img"))
-            .collect();
-        entries.sort_by_key(|e| e.file_name());
-        let parts: Vec<(String, String)> = entries.iter().map(|e| {
-            let p = e.path();
-            let name = p.file_stem().unwrap().to_string_lossy().to_string();
-            (name, p.to_string_lossy().to_string())
-        }).collect();
-        if parts.is_empty() {
-            ui::warn("目录下未发现任何 .img 文件");
-            return;
-        }
+        let full_plan = match FlashPlan::from_image_dir(&dir, &HashSet::new()) {
+            Ok(plan) => plan,
+            Err(_) => {
+                ui::warn("目录下未发现任何 .img 文件");
+                return;
+            }
+        };
         println!("\n待刷入分区列表:");
         let divider = "=".repeat(60).white();
         println!("{}", divider);
-        for (i, (n, _)) in parts.iter().enumerate() {
+        for (i, n) in full_plan.partitions().iter().enumerate() {
             println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), n);
         }
         println!("{}", divider);
@@ -474,105 +825,231 @@ async fn flash_all_partitions(flasher: &Flasher, fastboot_mode: bool) {
             ui::warn("未选择设备，取消刷入。");
             return;
         }
-        print!("输入要跳过的分区名，逗号分隔，直接回车全部刷入: ");
-        let _ = io::stdout().flush();
-        let mut skip_line = String::new();
-        let _ = io::stdin().read_line(&mut skip_line);
-        let skip_set: HashSet<String> = skip_line
-            .split(',')
-            .map(|s| s.trim().to_lowercase())
-            .filter(|s| !s.is_empty())
-            .collect();
-        for (name, path) in parts {
-            if skip_set.contains(&name.to_lowercase()) {
-                ui::warn(&format!("跳过 {}", name));
-                continue;
-            }
-            ui::step(&format!("正在刷入 {}: {} ...", name, path));
-            if let Err(e) = flasher.flash_partition(&target_device, &name, &path).await {
-                ui::err(&format!("✗ {} 刷入失败: {:?}", name, e));
-            } else {
-                ui::ok(&format!("✓ {} 刷入成功", name));
+
+        if !check_dir_before_flash(&flasher.client, &dir, std::slice::from_ref(&target_device)).await {
+            ui::warn("已取消刷入。");
+            return;
+        }
+
+        let journal = match resume_journal(&full_plan.name, &target_device) {
+            Some(journal) => Some(journal),
+            None => {
+                print!("输入要跳过的分区名，逗号分隔，直接回车全部刷入: ");
+                let _ = io::stdout().flush();
+                let mut skip_line = String::new();
+                let _ = io::stdin().read_line(&mut skip_line);
+                let skip_set: HashSet<String> = skip_line
+                    .split(',')
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                for name in full_plan.partitions() {
+                    if skip_set.contains(&name.to_lowercase()) {
+                        ui::warn(&format!("跳过 {}", name));
+                    }
+                }
+                match FlashPlan::from_image_dir(&dir, &skip_set) {
+                    Ok(plan) => create_journal(&target_device, &plan),
+                    Err(_) => {
+                        ui::warn("所有分区均已跳过。");
+                        return;
+                    }
+                }
             }
+        };
+        let Some(mut journal) = journal else { return };
+        if run_journal(flasher, &mut journal).await.is_ok() {
+            ui::ok("刷入完成。");
         }
-        ui::ok("刷入完成。");
     }
 }
 
-async fn flash_select_partitions_in_dir(flasher: &Flasher, dir: &Path, fastboot_mode: bool) {
-    let mode_str = if fastboot_mode { "Fastboot" } else { "FastbootD" };
-    ui::step(&format!("从目录选择分区刷入 ({}) ...", mode_str));
-    let mut entries: Vec<_> = match fs::read_dir(dir) {
-        Ok(rd) => rd.flatten()
-            .filter(|e| e.path().is_file() && e.path().extension().map_or(false, |ext| ext == "img"))
-            .collect(),
-        Err(_) => Vec::new(),
+/// 从目录刷入前的公共检查：逐台设备核对 android-info.txt，再校验 vbmeta，返回是否继续刷入
+async fn check_dir_before_flash(client: &FastbootClient, dir: &Path, serials: &[String]) -> bool {
+    for serial in serials {
+        if serials.len() > 1 {
+            ui::step(&format!("设备 {}:", serial));
+        }
+        if !check_android_info(client, dir, serial).await {
+            return false;
+        }
+    }
+    verify_dir_before_flash(dir)
+}
+
+/// 检查刷机包 android-info.txt 中的约束；不满足时需用户明确确认才继续，返回是否继续刷入
+async fn check_android_info(client: &FastbootClient, dir: &Path, serial: &str) -> bool {
+    let Some(path) = AndroidInfo::find_in_dir(dir) else { return true };
+    let info = match AndroidInfo::load(&path) {
+        Ok(info) => info,
+        Err(e) => {
+            ui::err(&format!("解析 android-info.txt 失败: {}", e));
+            return ui::confirm("无法校验刷机包要求，仍要继续刷入吗？", false);
+        }
     };
-    entries.sort_by_key(|e| e.file_name());
-    let parts: Vec<(String, String)> = entries.iter().map(|e| {
-        let p = e.path();
-        let name = p.file_stem().unwrap().to_string_lossy().to_string();
-        (name, p.to_string_lossy().to_string())
-    }).collect();
-    if parts.is_empty() {
-        ui::warn("目录下未发现任何 .img 文件");
-        return;
+    if info.requirements.is_empty() {
+        return true;
     }
-    println!("\n解包得到的分区列表:");
+    ui::step("正在校验 android-info.txt 中的设备要求...");
+    let results = info.check(client, serial).await;
     let divider = "=".repeat(60).white();
     println!("{}", divider);
-    for (i, (n, _)) in parts.iter().enumerate() {
-        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), n);
+    for r in &results {
+        let status = if r.skipped { "跳过".dimmed() } else if r.passed { "通过".green() } else { "不符".red() };
+        println!("[{}] {}  设备值: {}", status, r.requirement.describe(), r.actual.as_deref().unwrap_or("无法读取"));
     }
     println!("{}", divider);
-    print!("请输入要刷入的分区序号或名称，逗号分隔，直接回车表示全部: ");
-    let _ = io::stdout().flush();
-    let mut sel = String::new();
-    let _ = io::stdin().read_line(&mut sel);
-    let sel = sel.trim();
-    let selected: Vec<(String, String)> = if sel.is_empty() {
-        parts.clone()
-    } else {
-        let tokens: Vec<String> = sel.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
-        let mut picked = Vec::new();
-        for t in tokens {
-            if let Ok(idx) = t.parse::<usize>() {
-                if idx >= 1 && idx <= parts.len() {
-                    picked.push(parts[idx - 1].clone());
-                }
-            } else {
-                if let Some(p) = parts.iter().find(|(n, _)| n.eq_ignore_ascii_case(&t)) {
-                    picked.push(p.clone());
-                }
-            }
+    if results.iter().all(|r| r.passed) {
+        ui::ok("设备满足刷机包的全部要求。");
+        return true;
+    }
+    ui::err("设备不满足刷机包的要求，继续刷入可能导致设备无法启动！");
+    ui::confirm("确认忽略以上不符项并强制刷入吗？", false)
+}
+
+/// 查找该计划在此设备上未完成的刷机日志并询问是否续刷；镜像已改动时拒绝续刷
+fn resume_journal(plan_name: &str, serial: &str) -> Option<FlashJournal> {
+    let journal = FlashJournal::load_incomplete(&FlashJournal::default_dir(), plan_name, serial)?;
+    let next = journal.first_incomplete().unwrap_or(0);
+    ui::warn(&format!(
+        "发现未完成的刷机日志: 设备 {}，已完成 {}/{} 步，下一步: {}",
+        journal.serial,
+        journal.done_count(),
+        journal.entries.len(),
+        journal.entries[next].step.describe()
+    ));
+    ui::step("正在校验设备与镜像...");
+    if let Err(e) = journal.check_resume(serial) {
+        ui::err(&format!("{}", e));
+        ui::warn("将重新开始完整的刷入流程。");
+        return None;
+    }
+    if ui::confirm("是否从中断处继续刷入？", true) { Some(journal) } else { None }
+}
+
+fn create_journal(serial: &str, plan: &FlashPlan) -> Option<FlashJournal> {
+    let hash_images = JOURNAL_HASH.load(Ordering::SeqCst);
+    if hash_images {
+        ui::step("正在计算镜像 sha256 并创建刷机日志...");
+    }
+    match FlashJournal::create(&FlashJournal::default_dir(), serial, plan, hash_images) {
+        Ok(journal) => Some(journal),
+        Err(e) => {
+            ui::err(&format!("创建刷机日志失败: {}", e));
+            None
         }
-        if picked.is_empty() { parts.clone() } else { picked }
+    }
+}
+
+/// 在单台设备上按刷机日志执行计划并显示进度。监听设备断开（计划中的重启除外），
+/// 断开后立即中止；失败时日志保留，再次选择同一计划即可从中断处续刷
+async fn run_journal(flasher: &Flasher, journal: &mut FlashJournal) -> rua_core::Result<()> {
+    let pb = ProgressBar::new(journal.entries.len() as u64);
+    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}").unwrap()
+        .progress_chars("#>-"));
+    pb.set_position(journal.done_count() as u64);
+    let reporter = StepReporter { pb: pb.clone() };
+    let _guard = MONITOR.get().map(|m| m.hold(&journal.serial));
+    let mut watch = MONITOR.get().map(|m| m.watch(&journal.serial));
+    let lost = watch.as_mut().is_some_and(|w| w.is_lost());
+    let res = match watch.as_mut() {
+        _ if lost => Err(rua_core::FlashError::DeviceNotFound),
+        Some(w) => tokio::select! {
+            res = flasher.run_journaled(journal, &reporter) => res,
+            _ = w.disconnected() => Err(rua_core::FlashError::DeviceNotFound),
+        },
+        None => flasher.run_journaled(journal, &reporter).await,
     };
-    if selected.is_empty() {
-        ui::warn("未选择任何分区。");
-        return;
+    match &res {
+        Ok(_) => pb.finish_and_clear(),
+        Err(rua_core::FlashError::CheckFailed(reason)) => {
+            pb.abandon();
+            ui::err(&format!("刷机包与设备不匹配，已停止刷机: {}", reason));
+        }
+        Err(e) => {
+            pb.abandon();
+            match e {
+                rua_core::FlashError::DeviceNotFound => ui::err(&format!("设备 {} 已断开，已中止剩余步骤。", journal.serial)),
+                rua_core::FlashError::Interrupted => ui::warn("刷入已中断。"),
+                e => ui::err(&format!("刷机失败: {}", e)),
+            }
+            ui::warn(&format!(
+                "已完成 {}/{} 步，排除问题后再次选择同一目录即可从中断处续刷，刷机日志: {}",
+                journal.done_count(),
+                journal.entries.len(),
+                journal.path().display()
+            ));
+        }
     }
-    println!("\n即将刷入以下分区:");
+    res
+}
+
+async fn flash_select_partitions_in_dir(flasher: &Flasher, dir: &Path, fastboot_mode: bool) {
+    let mode_str = if fastboot_mode { "Fastboot" } else { "FastbootD" };
+    ui::step(&format!("从目录选择分区刷入 ({}) ...", mode_str));
+    let full_plan = match FlashPlan::from_image_dir(dir, &HashSet::new()) {
+        Ok(plan) => plan,
+        Err(_) => {
+            ui::warn("目录下未发现任何 .img 文件");
+            return;
+        }
+    };
+    let parts = full_plan.partitions();
+    println!("\n解包得到的分区列表:");
+    let divider = "=".repeat(60).white();
     println!("{}", divider);
-    for (n, _) in &selected {
-        println!("{}", n);
+    for (i, n) in parts.iter().enumerate() {
+        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), n);
     }
     println!("{}", divider);
-    if !ui::confirm("确认开始刷入吗？", true) { ui::warn("已取消刷入。"); return; }
     let target_device = select_device(&flasher.client).await;
     if target_device.is_empty() {
         ui::warn("未选择设备，取消刷入。");
         return;
     }
-    for (name, path) in selected {
-        ui::step(&format!("正在刷入 {}: {} ...", name, path));
-        if let Err(e) = flasher.flash_partition(&target_device, &name, &path).await {
-            ui::err(&format!("✗ {} 刷入失败: {:?}", name, e));
-        } else {
-            ui::ok(&format!("✓ {} 刷入成功", name));
+    if !check_dir_before_flash(&flasher.client, dir, std::slice::from_ref(&target_device)).await {
+        ui::warn("已取消刷入。");
+        return;
+    }
+
+    let journal = match resume_journal(&full_plan.name, &target_device) {
+        Some(journal) => Some(journal),
+        None => {
+            print!("请输入要刷入的分区序号或名称，逗号分隔，直接回车表示全部: ");
+            let _ = io::stdout().flush();
+            let mut sel = String::new();
+            let _ = io::stdin().read_line(&mut sel);
+            let mut picked: HashSet<String> = HashSet::new();
+            for t in sel.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                if let Ok(idx) = t.parse::<usize>() {
+                    if idx >= 1 && idx <= parts.len() {
+                        picked.insert(parts[idx - 1].to_string());
+                    }
+                } else if let Some(p) = parts.iter().find(|n| n.eq_ignore_ascii_case(t)) {
+                    picked.insert(p.to_string());
+                }
+            }
+            let plan = FlashPlan {
+                name: full_plan.name.clone(),
+                steps: full_plan.steps.iter().filter(|step| match step {
+                    FlashStep::Flash { partition, .. } => picked.is_empty() || picked.contains(partition),
+                    _ => true,
+                }).cloned().collect(),
+            };
+            println!("\n即将刷入以下分区:");
+            println!("{}", divider);
+            for n in plan.partitions() {
+                println!("{}", n);
+            }
+            println!("{}", divider);
+            if !ui::confirm("确认开始刷入吗？", true) { ui::warn("已取消刷入。"); return; }
+            create_journal(&target_device, &plan)
         }
+    };
+    let Some(mut journal) = journal else { return };
+    if run_journal(flasher, &mut journal).await.is_ok() {
+        ui::ok("刷入完成。");
     }
-    ui::ok("刷入完成。");
 }
 
 async fn manage_bootloader(client: &FastbootClient) {
@@ -834,8 +1311,10 @@ async fn flash_magisk(flasher: &Flasher) {
             let boot_path_str = boot_path.to_string_lossy().to_string();
             let boot_file_name = boot_path.file_name().unwrap_or_default().to_string_lossy();
 
+            let vendor_ramdisk = select_vendor_ramdisk(&boot_path);
+
             ui::step("正在修补镜像...");
-            match flasher.magisk_patch_with_files(&boot_path_str, &files, "").await {
+            match flasher.magisk_patch_with_files(&boot_path_str, &files, vendor_ramdisk.map(bootimg::RamdiskChoice::Index)).await {
                 Ok(patched_path) => {
                     ui::ok("镜像修补成功！");
 
@@ -858,7 +1337,7 @@ async fn flash_magisk(flasher: &Flasher) {
                         match select_avb_key_dir_and_file(exe_dir) {
                             Some((_key_dir, key_path)) => {
                                 ui::step(&format!("将使用密钥: {}", key_path.display()));
-                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &partition, &key_path).await {
+                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &boot_path, &partition, &key_path).await {
                                     Ok(signed_path) => {
                                         ui::ok(&format!("签名成功: {}", signed_path));
                                         final_image_path = signed_path;
@@ -946,8 +1425,10 @@ async fn flash_magisk(flasher: &Flasher) {
             let boot_path_str = boot_path.to_string_lossy().to_string();
             let boot_file_name = boot_path.file_name().unwrap_or_default().to_string_lossy();
 
+            let vendor_ramdisk = select_vendor_ramdisk(&boot_path);
+
             ui::step("正在修补镜像...");
-            match flasher.magisk_patch(&boot_path_str, &apk.to_string_lossy(), "").await {
+            match flasher.magisk_patch(&boot_path_str, &apk.to_string_lossy(), vendor_ramdisk.map(bootimg::RamdiskChoice::Index)).await {
                 Ok(patched_path) => {
                     ui::ok("镜像修补成功！");
 
@@ -969,7 +1450,7 @@ async fn flash_magisk(flasher: &Flasher) {
                         match select_avb_key_dir_and_file(exe_dir) {
                             Some((_dir, key_path)) => {
                                 ui::step(&format!("将使用密钥: {}", key_path.display()));
-                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &partition, &key_path).await {
+                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &boot_path, &partition, &key_path).await {
                                     Ok(signed_path) => {
                                         ui::ok(&format!("签名成功: {}", signed_path));
                                         final_image_path = signed_path;
@@ -1074,6 +1555,9 @@ async fn flash_apatch(flasher: &Flasher) {
     };
 
     if let Some(boot_path) = maybe_path {
+        if !confirm_root_method(&boot_path, kernel_info::RootMethod::APatch) {
+            return;
+        }
         ui::step("正在使用 APatch 修补...");
         
         // 先修补，不自动刷入，以便后面询问
@@ -1094,7 +1578,7 @@ async fn flash_apatch(flasher: &Flasher) {
                      match select_avb_key_dir_and_file(exe_dir) {
                          Some((_key_dir, key_path)) => {
                              ui::step(&format!("将使用密钥: {}", key_path.display()));
-                             match try_sign_with_external_tools(&flasher.client, None, &final_image_path, target_partition, &key_path).await {
+                             match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &boot_path, target_partition, &key_path).await {
                                  Ok(signed_path) => {
                                      ui::ok(&format!("签名成功: {}", signed_path));
                                      final_image_path = signed_path;
@@ -1154,28 +1638,20 @@ fn select_avb_key_dir_and_file(exe_dir: &Path) -> Option<(PathBuf, PathBuf)> {
         ui::select_directory("请选择存放 AVB 密钥 (.pem) 的目录")?
     };
 
-    let mut pem_all: Vec<PathBuf> = std::fs::read_dir(&key_dir).ok()?
-        .flatten()
-        .map(|e| e.path())
-        .filter(|p| p.is_file() && p.extension().map_or(false, |e| e.eq_ignore_ascii_case("pem")))
-        .collect();
-    pem_all.sort();
-
-    if pem_all.is_empty() {
-        ui::err("该目录下未找到任何 .pem 文件。");
+    let keys = avb::keys::list_keys(&key_dir);
+    if keys.is_empty() {
+        ui::err("该目录下未找到任何可用的 RSA 密钥 (.pem)。");
         return None;
     }
 
-    let pem_files = pem_all;
-
     println!("\n{} {}", ">>".cyan().bold(), "检测到以下可用密钥:".bright_white());
     let divider = "=".repeat(60).white();
     println!("{}", divider);
-    for (i, p) in pem_files.iter().enumerate() {
-        let name = p.file_name().and_then(|s| s.to_str()).unwrap_or("<unknown>");
-        let mut line = name.to_string();
-        if name.to_lowercase().contains("pub") {
-            line.push_str("  (公钥，一般不可用)");
+    for (i, key) in keys.iter().enumerate() {
+        let name = key.path.file_name().and_then(|s| s.to_str()).unwrap_or("<unknown>");
+        let mut line = format!("{}  RSA{}  sha1 {}", name, key.bits(), key.fingerprint());
+        if !key.is_private() {
+            line.push_str("  (公钥，不可用于签名)");
         }
         println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), line);
     }
@@ -1185,53 +1661,71 @@ fn select_avb_key_dir_and_file(exe_dir: &Path) -> Option<(PathBuf, PathBuf)> {
     let mut input = String::new();
     let _ = io::stdin().read_line(&mut input);
     let idx: usize = input.trim().parse().unwrap_or(0);
-    if idx == 0 || idx > pem_files.len() {
+    if idx == 0 || idx > keys.len() {
         ui::err("无效的选择。");
         return None;
     }
-    let picked = pem_files[idx - 1].clone();
-    let picked_name = picked.file_name().and_then(|s| s.to_str()).unwrap_or("");
-    if picked_name.to_lowercase().contains("pub") {
+    let key = &keys[idx - 1];
+    if !key.is_private() {
         ui::err("选择的是公钥文件，无法用于签名。请使用私钥 .pem。");
         return None;
     }
+    let picked = key.path.clone();
     Some((key_dir, picked))
 }
 
+/// 按密钥模数长度选择签名算法；原始镜像使用 SHA512 系列时保持 SHA512
+fn avb_algorithm_for_key(key_path: &Path, original: Option<&Path>) -> Option<&'static str> {
+    let key = avb::AvbKey::load(key_path).ok()?;
+    let sha512 = original
+        .and_then(|p| avb::VbmetaImage::load(p).ok())
+        .and_then(|image| image.algorithm())
+        .is_some_and(|a| a.hash_len() == 64);
+    avb::Algorithm::for_key_bits(key.bits(), sha512).map(|a| a.name())
+}
+
 async fn try_sign_with_external_tools(
     _base_client: &FastbootClient,
     _serial: Option<&str>,
     image_path: &str,
+    original_image: &Path,
     partition: &str,
     key_path: &Path,
 ) -> anyhow::Result<String> {
     println!("{}", ">> 开始 AVB 签名流程".cyan());
 
-    let img_len = std::fs::metadata(image_path).map(|m| m.len()).unwrap_or(0);
-    let mib = 1024u64 * 1024u64;
-    // 兜底：为 vbmeta+footer 预留余量（至少 2 MiB），再按 MiB 向上取整
-    let min_slack = 2 * mib;
-    let required = img_len.saturating_add(min_slack);
-    let part_size_bytes = ((required + mib - 1) / mib) * mib;
-    println!("{}", format!(">> 分区大小(兜底，含余量): {} bytes", part_size_bytes).yellow());
-
-    let algo = if key_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(|n| n.to_lowercase().contains("rsa4096"))
-        .unwrap_or(false)
-    {
-        "SHA256_RSA4096"
-    } else {
-        "SHA256_RSA2048"
+    let opts = match avb::HashFooterOptions::from_original(original_image, partition) {
+        Ok(Some(opts)) => {
+            println!("{}", format!(">> 沿用原始镜像的 AVB 元数据，分区大小: {} bytes", opts.partition_size).yellow());
+            println!("{}", format!(
+                ">> 回滚索引: {}  flags: {}  属性: {} 个  salt: {} 字节",
+                opts.rollback_index, opts.flags, opts.properties.len(), opts.salt.len()
+            ).yellow());
+            opts
+        }
+        _ => {
+            let img_len = std::fs::metadata(image_path).map(|m| m.len()).unwrap_or(0);
+            let mib = 1024u64 * 1024u64;
+            // 原始镜像没有 AVB footer 时兜底：为 vbmeta+footer 预留余量（至少 2 MiB），再按 MiB 向上取整
+            let min_slack = 2 * mib;
+            let required = img_len.saturating_add(min_slack);
+            let part_size_bytes = ((required + mib - 1) / mib) * mib;
+            println!("{}", ">> 原始镜像不含 AVB footer，无法得知真实分区大小".yellow());
+            println!("{}", format!(">> 分区大小(兜底，含余量): {} bytes", part_size_bytes).yellow());
+            avb::HashFooterOptions::new(part_size_bytes)
+        }
     };
 
-    let signed = rua_core::avb::add_hash_footer(
+    let algorithm = avb_algorithm_for_key(key_path, Some(original_image))
+        .ok_or_else(|| anyhow::anyhow!("不支持的密钥: {}", key_path.display()))?;
+    println!("{}", format!(">> 签名算法: {}", algorithm).yellow());
+
+    let signed = avb::add_hash_footer_with_options(
         image_path,
         partition,
-        part_size_bytes,
         &key_path.to_string_lossy(),
-        algo,
+        algorithm,
+        &opts,
     )
     .await
     .map_err(|e| anyhow::anyhow!(format!("{:?}", e)))?;
@@ -1239,38 +1733,103 @@ async fn try_sign_with_external_tools(
     Ok(signed)
 }
 
-async fn flash_kernelsu_lkm(flasher: &Flasher) {
+/// KSUINIT 与 LKM 文件夹所在目录
+fn ksu_lkm_base_dir() -> PathBuf {
     let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
     let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
     
     // 兼容开发环境
-    let base_dir = if exe_path.to_string_lossy().contains("target\\") {
+    if exe_path.to_string_lossy().contains("target\\") {
         exe_dir.join("..").join("..").canonicalize().unwrap_or(exe_dir.to_path_buf())
     } else {
         exe_dir.to_path_buf()
-    };
-
-    ui::step("正在扫描 KernelSU LKM 分支和版本...");
-    let branches = FileFinder::find_ksu_lkm_branches(&base_dir);
+    }
+}
 
-    if branches.is_empty() {
-        ui::err("未在 KSUINIT 或 LKM 文件夹中找到任何版本。");
-        ui::warn(&format!("请确保根目录下存在 KSUINIT 和 LKM 文件夹，且结构正确。"));
-        return;
+/// 只有一台 ADB 设备时读取其 KMI
+async fn detect_kmi_from_device() -> Option<String> {
+    let adb = rua_core::AdbClient::new().ok()?;
+    let devices: Vec<ConnectedDevice> =
+        adb.list_devices().await.ok()?.into_iter().filter(|d| d.mode == DeviceMode::ADB).collect();
+    let [device] = devices.as_slice() else { return None };
+    ui::step(&format!("正在从设备 {} 读取 KMI...", device.serial));
+    match lkm::kmi_from_device(&adb, &device.serial).await {
+        Ok(Some(kmi)) => {
+            ui::ok(&format!("设备 KMI: {}", kmi));
+            Some(kmi)
+        }
+        Ok(None) => {
+            ui::warn("设备内核不是 GKI 内核，无法确定 KMI。");
+            None
+        }
+        Err(e) => {
+            ui::warn(&format!("读取设备内核版本失败: {}", e));
+            None
+        }
     }
+}
+
+/// 有完全匹配的 .ko 时确认后直接使用，否则说明原因并列出全部 .ko 供手动选择
+fn select_lkm_package<'a>(version: &'a lkm::KsuLkmVersion, kmi: Option<&str>) -> Option<&'a lkm::LkmPackage> {
+    let recommended = match version.resolve(kmi) {
+        lkm::LkmResolution::Exact(package) => {
+            ui::ok(&format!("已根据 KMI 自动选择: {}", package.ko_path.file_name().unwrap_or_default().to_string_lossy()));
+            if ui::confirm("是否使用该模块？", true) {
+                return Some(package);
+            }
+            version.ko_files.iter().position(|p| std::ptr::eq(p, package))
+        }
+        lkm::LkmResolution::Unresolved(reason) => {
+            ui::warn(&reason);
+            None
+        }
+    };
 
-    // 1. 选择分支
-    println!("\n{} {}", ">>".cyan().bold(), "请选择 KernelSU 分支:".bright_white());
     let divider = "=".repeat(60).white();
+    println!("\n{} {}", ">>".cyan().bold(), "请选择匹配的 KMI (.ko):".bright_white());
     println!("{}", divider);
-    for (i, branch) in branches.iter().enumerate() {
-        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), branch.name.yellow());
+    for (i, ko) in version.ko_files.iter().enumerate() {
+        let label = if recommended == Some(i) { format!("{} (推荐)", ko.kmi).green().to_string() } else { ko.kmi.clone() };
+        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), label);
     }
     println!("{}", divider);
 
-    print!("请选择: ");
-    let _ = io::stdout().flush();
-    let mut input = String::new();
+    let default_idx = recommended.map_or(1, |i| i + 1);
+    let input = ui::input(&format!("请选择 [默认: {}]:", default_idx));
+    let ko_idx = if input.is_empty() { default_idx } else { input.parse().unwrap_or(0) };
+    if ko_idx == 0 || ko_idx > version.ko_files.len() {
+        ui::err("无效的选择。");
+        return None;
+    }
+    Some(&version.ko_files[ko_idx - 1])
+}
+
+async fn flash_kernelsu_lkm(flasher: &Flasher) {
+    let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
+    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+    let base_dir = ksu_lkm_base_dir();
+
+    ui::step("正在扫描 KernelSU LKM 分支和版本...");
+    let branches = lkm::find_branches(&base_dir);
+
+    if branches.is_empty() {
+        ui::err("未在 KSUINIT 或 LKM 文件夹中找到任何版本。");
+        ui::warn(&format!("请确保根目录下存在 KSUINIT 和 LKM 文件夹，且结构正确。"));
+        return;
+    }
+
+    // 1. 选择分支
+    println!("\n{} {}", ">>".cyan().bold(), "请选择 KernelSU 分支:".bright_white());
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    for (i, branch) in branches.iter().enumerate() {
+        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), branch.name.yellow());
+    }
+    println!("{}", divider);
+
+    print!("请选择: ");
+    let _ = io::stdout().flush();
+    let mut input = String::new();
     let _ = io::stdin().read_line(&mut input);
     let branch_idx: usize = input.trim().parse().unwrap_or(0);
 
@@ -1355,25 +1914,13 @@ async fn flash_kernelsu_lkm(flasher: &Flasher) {
 
     // 5. 自动识别 KMI（分区差异化逻辑）
     let mut detected_kmi: Option<String> = None;
+    // 用于校验所选 .ko 的内核：boot 为镜像本身，init_boot / vendor_boot 需另外提供 boot 或内核文件
+    let mut kernel_image: Option<PathBuf> = None;
     if partition.eq_ignore_ascii_case("ramdisk") {
         ui::warn("ramdisk 分区不支持自动检测 KMI，已跳过。");
     } else if partition.eq_ignore_ascii_case("boot") {
-        ui::step("正在读取内核版本并判断 KMI...");
-        match Flasher::read_kernel_version_and_kmi_from_boot_img(&img_path.to_string_lossy()) {
-            Ok((kmi_opt, full_opt)) => {
-                if let Some(full) = full_opt {
-                    println!("- 内核版本字符串: {}", full);
-                }
-                if let Some(kmi) = kmi_opt {
-                    ui::ok(&format!("检测到 KMI: {}", kmi));
-                    detected_kmi = Some(kmi);
-                } else {
-                    ui::warn("无法根据内核版本字符串判断 KMI。");
-                }
-            }
-            Err(e) => ui::warn(&format!("读取内核版本失败: {:?}", e)),
-        }
-    } else if partition.eq_ignore_ascii_case("init_boot") {
+        kernel_image = Some(img_path.clone());
+    } else if partition.eq_ignore_ascii_case("init_boot") || partition.eq_ignore_ascii_case("vendor_boot") {
         if let Some(payload_path) = payload_origin.clone() {
             ui::step("正在额外提取 boot 分区用于 KMI 检测...");
             let out_dir = Path::new("extracted_payload");
@@ -1383,20 +1930,7 @@ async fn flash_kernelsu_lkm(flasher: &Flasher) {
             match rua_core::payload::extract_single_partition(&payload_path, "boot", out_dir, reporter_dyn).await {
                 Ok(boot_img) => {
                     reporter.print_summary();
-                    match Flasher::read_kernel_version_and_kmi_from_boot_img(&boot_img.to_string_lossy()) {
-                        Ok((kmi_opt, full_opt)) => {
-                            if let Some(full) = full_opt {
-                                println!("- 内核版本字符串: {}", full);
-                            }
-                            if let Some(kmi) = kmi_opt {
-                                ui::ok(&format!("检测到 KMI: {}", kmi));
-                                detected_kmi = Some(kmi);
-                            } else {
-                                ui::warn("无法根据内核版本字符串判断 KMI。");
-                            }
-                        }
-                        Err(e) => ui::warn(&format!("读取内核版本失败: {:?}", e)),
-                    }
+                    kernel_image = Some(boot_img);
                 }
                 Err(e) => {
                     if INTERRUPTED.load(Ordering::SeqCst) {
@@ -1408,55 +1942,61 @@ async fn flash_kernelsu_lkm(flasher: &Flasher) {
                 }
             }
         } else {
-            ui::warn("init_boot 来源为本地镜像，无法自动提取 boot 进行 KMI 检测。");
+            ui::step(&format!("{} 中没有内核，请选择同一版本的 boot 镜像或内核文件用于 KMI 检测 (取消则从设备读取)。", partition));
+            kernel_image = ui::select_file("请选择 boot 镜像或内核文件", &["img", "gz", "lz4"]);
         }
     }
 
-    // 6. 选择 KMI (.ko 文件)
-    println!("\n{} {}", ">>".cyan().bold(), "请选择匹配的 KMI (.ko):".bright_white());
-    println!("{}", divider);
-    
-    let mut recommended_idx = None;
-    for (i, ko) in selected_ver.ko_files.iter().enumerate() {
-        let mut label = ko.kmi.clone();
-        if let Some(ref dkmi) = detected_kmi {
-            // 如果检测到的 KMI 包含在文件名中，标记为推荐
-            if dkmi.contains(&ko.kmi) || ko.kmi.contains(dkmi) {
-                label = format!("{} (推荐)", label).green().to_string();
-                recommended_idx = Some(i + 1);
+    if let Some(kernel) = &kernel_image {
+        ui::step("正在读取内核版本并判断 KMI...");
+        match Flasher::read_kernel_version_and_kmi_from_boot_img(&kernel.to_string_lossy()) {
+            Ok((kmi_opt, full_opt)) => {
+                if let Some(full) = full_opt {
+                    println!("- 内核版本字符串: {}", full);
+                }
+                if let Some(kmi) = kmi_opt {
+                    ui::ok(&format!("检测到 KMI: {}", kmi));
+                    detected_kmi = Some(kmi);
+                } else {
+                    ui::warn("无法根据内核版本字符串判断 KMI。");
+                }
             }
+            Err(e) => ui::warn(&format!("读取内核版本失败: {:?}", e)),
+        }
+        if !confirm_root_method(kernel, kernel_info::RootMethod::KernelSuLkm) {
+            return;
         }
-        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), label);
     }
-    println!("{}", divider);
-
-    let default_idx = recommended_idx.unwrap_or(1);
-    print!("请选择 [默认: {}]: ", default_idx);
-    let _ = io::stdout().flush();
-    let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
-    let ko_choice = input.trim();
-    
-    let ko_idx = if ko_choice.is_empty() {
-        default_idx
-    } else {
-        ko_choice.parse().unwrap_or(0)
-    };
 
-    if ko_idx == 0 || ko_idx > selected_ver.ko_files.len() {
-        ui::err("无效的选择。");
-        return;
+    // 没有可用的内核文件时改用设备 KMI 校验
+    let mut device_kmi: Option<String> = None;
+    if detected_kmi.is_none()
+        && let Some(kmi) = detect_kmi_from_device().await
+    {
+        detected_kmi = Some(kmi.clone());
+        device_kmi = Some(kmi);
     }
-    let selected_ko = &selected_ver.ko_files[ko_idx - 1];
 
-    // 7. 执行修补
+    // 6. 按 KMI 自动选择 .ko，没有完全匹配时手动选择
+    let Some(selected_ko) = select_lkm_package(selected_ver, detected_kmi.as_deref()) else { return; };
+
+    // 7. 执行修补，修补前强制校验 .ko 与内核匹配
+    let kernel_file = kernel_image.as_ref().map(|p| p.to_string_lossy().to_string());
+    let target = match (&kernel_file, &device_kmi) {
+        (Some(file), _) => Some(kmod::TargetKernel::File(file)),
+        (None, Some(kmi)) => Some(kmod::TargetKernel::Kmi(kmi)),
+        (None, None) => None,
+    };
+    let vendor_ramdisk = select_vendor_ramdisk(&img_path);
     ui::step("正在使用 KernelSU LKM 修补...");
-    match flasher.kernelsu_lkm_patch(
+    match Flasher::kernelsu_lkm_patch(
         &img_path.to_string_lossy(),
         &selected_ver.ksuinit_path.to_string_lossy(),
         Some(&selected_ver.ksuinit_d_path.to_string_lossy()),
         &selected_ko.ko_path.to_string_lossy(),
         &partition,
+        vendor_ramdisk.map(bootimg::RamdiskChoice::Index),
+        target,
         false
     ).await {
         Ok(out_name) => {
@@ -1483,7 +2023,7 @@ async fn flash_kernelsu_lkm(flasher: &Flasher) {
                 match select_avb_key_dir_and_file(exe_dir) {
                     Some((_key_dir, key_path)) => {
                         ui::step(&format!("将使用密钥: {}", key_path.display()));
-                        match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &partition, &key_path).await {
+                        match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &img_path, &partition, &key_path).await {
                             Ok(signed_path) => {
                                 ui::ok(&format!("签名成功: {}", signed_path));
                                 final_image_path = signed_path;
@@ -1572,6 +2112,9 @@ async fn flash_anykernel3(flasher: &Flasher) {
         };
 
         if let Some(boot_path) = maybe_boot {
+            if !confirm_root_method(&boot_path, kernel_info::RootMethod::KernelReplace) {
+                return;
+            }
             ui::step("正在解压 AnyKernel3 并修补内核...");
             match flasher.anykernel3_root(&zip_path.to_string_lossy(), &boot_path.to_string_lossy(), target_partition, is_raw_kernel, false).await {
                 Ok(out_name) => {
@@ -1588,7 +2131,7 @@ async fn flash_anykernel3(flasher: &Flasher) {
                         match select_avb_key_dir_and_file(exe_dir) {
                             Some((_key_dir, key_path)) => {
                                 ui::step(&format!("将使用密钥: {}", key_path.display()));
-                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, target_partition, &key_path).await {
+                                match try_sign_with_external_tools(&flasher.client, None, &final_image_path, &boot_path, target_partition, &key_path).await {
                                     Ok(signed_path) => {
                                         ui::ok(&format!("签名成功: {}", signed_path));
                                         final_image_path = signed_path;
@@ -1623,128 +2166,1240 @@ async fn flash_anykernel3(flasher: &Flasher) {
                         println!("已取消刷入，修补镜像已保存为: {}", final_image_path);
                     }
                 }
-                Err(e) => ui::err(&format!("AnyKernel3 修补失败: {:?}", e)),
+                Err(e) => ui::err(&format!("AnyKernel3 修补失败: {:?}", e)),
+            }
+        }
+    }
+    pause_before_back();
+}
+
+async fn flash_custom_partition(flasher: &Flasher) {
+    if !ui::confirm("确定要继续吗？此操作将刷入自定义分区镜像。", true) { return; }
+
+    print!("请输入分区名 (如 boot/init_boot/recovery/system/vendor): ");
+    let _ = io::stdout().flush();
+    let mut partition = String::new();
+    let _ = io::stdin().read_line(&mut partition);
+    let partition = partition.trim().to_string();
+    if partition.is_empty() { ui::err("分区名不能为空。"); return; }
+
+    println!("\n{} {}", ">>".cyan().bold(), "请选择镜像来源:".bright_white());
+    println!("{}", "=".repeat(60).white());
+    println!("{} 本地镜像", "1)".bright_cyan());
+    println!("{} 从 Payload/卡刷包 获取", "2)".bright_cyan());
+    println!("{}", "=".repeat(60).white());
+    print!("请选择 [1/2]: ");
+    let _ = io::stdout().flush();
+    let mut src_choice = String::new();
+    let _ = io::stdin().read_line(&mut src_choice);
+    let src_choice = src_choice.trim();
+
+    let img_path: Option<PathBuf> = if src_choice == "2" {
+        ui::step(&format!("正在从 Payload 提取 {} 分区镜像...", partition));
+        let Some(payload_path) = ui::select_file("请选择 Payload.bin 或卡刷包 ZIP", &["bin", "zip"]) else { return; };
+        let out_dir = Path::new("extracted_payload");
+        let _ = fs::create_dir_all(out_dir);
+        let reporter = Arc::new(ConsoleReporter::new());
+        let reporter_dyn: Arc<dyn ProgressReporter> = reporter.clone();
+        match rua_core::payload::extract_single_partition(&payload_path, &partition, out_dir, reporter_dyn).await {
+            Ok(p) => { reporter.print_summary(); Some(p) },
+            Err(e) => {
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    reporter.clear_current(">> 已取消提取");
+                    ui::warn("已取消操作。");
+                } else {
+                    ui::err(&format!("从 Payload 提取分区失败: {:?}", e));
+                }
+                None
+            }
+        }
+    } else {
+        ui::select_file("请选择要刷入的自定义分区镜像", &["img"])
+    };
+
+    let Some(path) = img_path else { return; };
+    let target_device = select_device(&flasher.client).await;
+    if target_device.is_empty() {
+        ui::warn("未检测到设备，取消刷入。");
+        return;
+    }
+    ui::step(&format!("正在刷入 {}: {} ...", partition, path.display()));
+    match flasher.flash_partition(&target_device, &partition, &path.to_string_lossy()).await {
+        Ok(_) => ui::ok("刷入成功！"),
+        Err(e) => ui::err(&format!("刷入失败: {:?}", e)),
+    }
+}
+
+async fn batch_flash(client: &FastbootClient) {
+    ui::step("多设备并行刷入：所有选中的设备将执行同一个刷机计划");
+    println!("\n{} {}", ">>".cyan().bold(), "请选择刷机内容:".bright_white());
+    println!("{}", "=".repeat(60).white());
+    println!("{} 目录下全部分区镜像", "1)".bright_cyan());
+    println!("{} 单个镜像 (如修补好的 boot/init_boot)", "2)".bright_cyan());
+    println!("{} 用 Magisk 修补原厂 boot/init_boot 后刷入", "3)".bright_cyan());
+    println!("{}", "=".repeat(60).white());
+    print!("请选择 [1/2/3]: ");
+    let _ = io::stdout().flush();
+    let mut src_choice = String::new();
+    let _ = io::stdin().read_line(&mut src_choice);
+
+    let mut image_dir = None;
+    let plan = if matches!(src_choice.trim(), "2" | "3") {
+        print!("请输入分区名 (如 boot/init_boot): ");
+        let _ = io::stdout().flush();
+        let mut partition = String::new();
+        let _ = io::stdin().read_line(&mut partition);
+        let partition = partition.trim().to_string();
+        if partition.is_empty() { ui::err("分区名不能为空。"); return; }
+        if src_choice.trim() == "3" {
+            let Some(image) = ui::select_file("请选择原厂 boot/init_boot 镜像", &["img"]) else { return; };
+            let Some(apk) = ui::select_file("请选择 Magisk APK", &["apk"]) else { return; };
+            FlashPlan::patch_and_flash(&partition, &image, &apk)
+        } else {
+            let Some(image) = ui::select_file("请选择要刷入的镜像", &["img"]) else { return; };
+            FlashPlan::single(&partition, &image)
+        }
+    } else {
+        let Some(dir) = ui::select_directory("请选择包含分区镜像 (.img) 的目录") else { return; };
+        print!("输入要跳过的分区名，逗号分隔，直接回车全部刷入: ");
+        let _ = io::stdout().flush();
+        let mut skip_line = String::new();
+        let _ = io::stdin().read_line(&mut skip_line);
+        let skip_set: HashSet<String> = skip_line
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let plan = match FlashPlan::from_image_dir(&dir, &skip_set) {
+            Ok(plan) => plan,
+            Err(e) => { ui::err(&format!("{}", e)); return; }
+        };
+        image_dir = Some(dir);
+        plan
+    };
+
+    println!("\n刷机计划 ({} 步):", plan.steps.len());
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    for (i, step) in plan.steps.iter().enumerate() {
+        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), step.describe());
+    }
+    println!("{}", divider);
+
+    ui::step("正在搜索 Fastboot 设备...");
+    let devices: Vec<ConnectedDevice> = client.list_devices().await.unwrap_or_default()
+        .into_iter()
+        .filter(|d| matches!(d.mode, DeviceMode::Fastboot | DeviceMode::FastbootD))
+        .collect();
+    if devices.is_empty() {
+        ui::err("未检测到任何 Fastboot 设备。");
+        return;
+    }
+    for (i, d) in devices.iter().enumerate() {
+        println!("{}{} [{}] {}", format!("{:>3}. ", i + 1).bright_cyan(), d.serial.yellow(), mode_label(&d.mode), d.product.as_deref().unwrap_or(""));
+    }
+    print!("请输入要刷入的设备序号，逗号分隔，直接回车表示全部: ");
+    let _ = io::stdout().flush();
+    let mut sel = String::new();
+    let _ = io::stdin().read_line(&mut sel);
+    let mut serials: Vec<String> = Vec::new();
+    for t in sel.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match t.parse::<usize>() {
+            Ok(idx) if idx >= 1 && idx <= devices.len() => {
+                if !serials.contains(&devices[idx - 1].serial) {
+                    serials.push(devices[idx - 1].serial.clone());
+                }
+            }
+            _ => ui::warn(&format!("忽略无效的序号: {}", t)),
+        }
+    }
+    if serials.is_empty() {
+        serials = devices.iter().map(|d| d.serial.clone()).collect();
+    }
+    if let Some(dir) = &image_dir
+        && !check_dir_before_flash(client, dir, &serials).await
+    {
+        ui::warn("已取消刷入。");
+        return;
+    }
+
+    if !ui::confirm(&format!("确认在 {} 台设备上并行执行以上刷机计划吗？", serials.len()), false) {
+        ui::warn("已取消刷入。");
+        return;
+    }
+
+    // 设备型号相同，修补只需进行一次，各设备刷入同一个修补结果
+    let plan = if plan.needs_patch() {
+        ui::step("正在修补镜像...");
+        match Flasher::new(client.clone()).patch_plan(&plan).await {
+            Ok(patched) => patched,
+            Err(e) => {
+                ui::err(&format!("修补失败: {}", e));
+                return;
+            }
+        }
+    } else {
+        plan
+    };
+
+    let mut journals = Vec::with_capacity(serials.len());
+    for serial in &serials {
+        match resume_journal(&plan.name, serial).or_else(|| create_journal(serial, &plan)) {
+            Some(journal) => journals.push(journal),
+            None => return,
+        }
+    }
+
+    let mp = MultiProgress::new();
+    let style = ProgressStyle::with_template("{prefix:.cyan} [{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} {msg}").unwrap()
+        .progress_chars("#>-");
+    let mut bars = HashMap::new();
+    for journal in &journals {
+        let pb = mp.add(ProgressBar::new(journal.entries.len() as u64));
+        pb.set_style(style.clone());
+        pb.set_prefix(journal.serial.clone());
+        pb.set_position(journal.done_count() as u64);
+        pb.set_message("等待开始".to_string());
+        bars.insert(journal.serial.clone(), pb);
+    }
+    let reporter: Arc<dyn PlanReporter> = Arc::new(BatchReporter { bars });
+    let _guards: Vec<_> = MONITOR.get().map(|m| serials.iter().map(|s| m.hold(s)).collect()).unwrap_or_default();
+    let outcomes = rua_core::batch::run_journals_on_devices(client, journals, reporter).await;
+
+    println!("\n{}", "批量刷机结果".bright_white().bold());
+    println!("{}", divider);
+    println!("{:<24} {:<6} {:<8} {:<10} {}", "序列号", "结果", "步骤", "耗时", "错误");
+    for o in &outcomes {
+        let status = if o.is_success() { "成功".green() } else { "失败".red() };
+        println!("{:<24} {:<6} {:<8} {:<10} {}",
+            o.serial, status, format!("{}/{}", o.steps_done, o.steps_total),
+            format!("{:.1}s", o.elapsed.as_secs_f64()), o.error.as_deref().unwrap_or(""));
+    }
+    println!("{}", divider);
+    let ok = outcomes.iter().filter(|o| o.is_success()).count();
+    if ok == outcomes.len() {
+        ui::ok(&format!("全部 {} 台设备刷入成功。", ok));
+    } else {
+        ui::warn(&format!("{} 台成功，{} 台失败，再次执行同一计划时失败的设备可从中断处续刷。", ok, outcomes.len() - ok));
+    }
+}
+
+fn install_usb_driver() {
+    ui::step("正在安装驱动...");
+    let driver_exe = Path::new("drivers/QcomMtk_Driver_Setup_3.2.1.exe");
+    if driver_exe.exists() {
+        let _ = tokio::process::Command::new(driver_exe).spawn();
+    } else {
+        ui::err("未找到驱动安装包 (drivers/usb_driver_setup.exe)");
+    }
+}
+
+async fn disable_avb(flasher: &Flasher) {
+    println!("\n{} {}", ">>".cyan().bold(), "请选择 vbmeta 镜像来源:".bright_white());
+    println!("{}", "=".repeat(60).white());
+    println!("{} 本地 vbmeta.img", "1)".bright_cyan());
+    println!("{} 从 Payload/卡刷包 提取 vbmeta", "2)".bright_cyan());
+    println!("{}", "=".repeat(60).white());
+    print!("请选择 [1/2]: ");
+    let _ = io::stdout().flush();
+    let mut src_choice = String::new();
+    let _ = io::stdin().read_line(&mut src_choice);
+    let src_choice = src_choice.trim();
+
+    let img_path: Option<PathBuf> = if src_choice == "2" {
+        ui::step("正在从 Payload 提取 vbmeta 分区镜像...");
+        let Some(payload_path) = ui::select_file("请选择 Payload.bin 或卡刷包 ZIP", &["bin", "zip"]) else { return; };
+        let out_dir = Path::new("extracted_payload");
+        let _ = fs::create_dir_all(out_dir);
+        let reporter = Arc::new(ConsoleReporter::new());
+        let reporter_dyn: Arc<dyn ProgressReporter> = reporter.clone();
+        match rua_core::payload::extract_single_partition(&payload_path, "vbmeta", out_dir, reporter_dyn).await {
+            Ok(p) => { reporter.print_summary(); Some(p) },
+            Err(e) => {
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    reporter.clear_current(">> 已取消提取");
+                    ui::warn("已取消操作。");
+                } else {
+                    ui::err(&format!("从 Payload 提取 vbmeta 失败: {:?}", e));
+                }
+                None
+            }
+        }
+    } else {
+        ui::select_file("请选择 vbmeta.img", &["img"])
+    };
+
+    let Some(vbmeta_path) = img_path else { return; };
+
+    match avb::VbmetaImage::load(&vbmeta_path) {
+        Ok(image) => {
+            let algorithm = image.algorithm().map(|a| a.name()).unwrap_or("未知");
+            ui::step(&format!("vbmeta 算法: {}，当前 flags: {}", algorithm, avb::describe_vbmeta_flags(image.header.flags)));
+            if !image.partitions().is_empty() {
+                ui::step(&format!("该 vbmeta 校验的分区: {}", image.partitions().join(", ")));
+            }
+        }
+        Err(e) => ui::warn(&format!("无法解析 vbmeta: {}", e)),
+    }
+
+    println!("\n{} {}", ">>".cyan().bold(), "请选择要写入的 vbmeta flags:".bright_white());
+    println!("{}", "=".repeat(60).white());
+    println!("{} 关闭 verity 与 verification (3，推荐)", "1)".bright_cyan());
+    println!("{} 仅关闭 verity (1)", "2)".bright_cyan());
+    println!("{} 仅关闭 verification (2)", "3)".bright_cyan());
+    println!("{}", "=".repeat(60).white());
+    print!("请选择 [1/2/3]，直接回车默认 1: ");
+    let _ = io::stdout().flush();
+    let mut flag_choice = String::new();
+    let _ = io::stdin().read_line(&mut flag_choice);
+    let flags = match flag_choice.trim() {
+        "2" => avb::FLAG_HASHTREE_DISABLED,
+        "3" => avb::FLAG_VERIFICATION_DISABLED,
+        _ => avb::FLAG_HASHTREE_DISABLED | avb::FLAG_VERIFICATION_DISABLED,
+    };
+
+    if ui::confirm("仅导出修补后的 vbmeta 文件而不刷入设备吗？", false) {
+        let stem = vbmeta_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "vbmeta".to_string());
+        let output = PathBuf::from(format!("{}.flags{}.img", stem, flags));
+        match avb::patch_vbmeta_flags(&vbmeta_path, flags, &output) {
+            Ok(patch) => {
+                print_vbmeta_flags_patch(&patch);
+                ui::ok(&format!("已导出: {}", output.display()));
+            }
+            Err(e) => ui::err(&format!("修补 vbmeta 失败: {}", e)),
+        }
+        return;
+    }
+
+    let target_device = select_device(&flasher.client).await;
+    if target_device.is_empty() {
+        ui::err("未检测到 Fastboot 设备，无法执行刷入。");
+        return;
+    }
+
+    // vbmeta_system.img 刷入 vbmeta_system，带 AVB footer 的 boot.img 等刷回其自身分区
+    let default_partition = vbmeta_path
+        .file_name()
+        .and_then(|n| n.to_string_lossy().split('.').next().map(str::to_string))
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "vbmeta".to_string());
+    let partition = ui::input(&format!("请输入要刷入的分区名，直接回车为 {}:", default_partition));
+    let partition = if partition.is_empty() { default_partition } else { partition };
+
+    ui::step(&format!("正在修补 vbmeta flags 并刷入 {}...", partition));
+    match flasher.flash_vbmeta_with_flags(&target_device, &partition, &vbmeta_path.to_string_lossy(), flags).await {
+        Ok(patch) => {
+            print_vbmeta_flags_patch(&patch);
+            ui::ok(&format!("{} 刷入成功，AVB 校验已禁用。", partition));
+        }
+        Err(e) => ui::err(&format!("{} 刷入失败: {:?}", partition, e)),
+    }
+}
+
+async fn image_toolbox() {
+    println!("\n{} {}", ">>".cyan().bold(), "镜像工具箱:".bright_white());
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    println!("{} 查看 vbmeta / AVB footer 信息", "1)".bright_cyan());
+    println!("{} 校验 vbmeta 签名与分区哈希", "2)".bright_cyan());
+    println!("{} 为 system/vendor 等镜像添加 hashtree footer 并签名", "3)".bright_cyan());
+    println!("{} 按目录中的镜像重新生成并签名 vbmeta.img (含链式分区)", "4)".bright_cyan());
+    println!("{} AVB 密钥管理 (生成密钥 / 导出 avb_custom_key)", "5)".bright_cyan());
+    println!("{} 查看 boot / init_boot / vendor_boot 镜像信息", "6)".bright_cyan());
+    println!("{} 解包 / 重新打包 boot 镜像", "7)".bright_cyan());
+    println!("{} 修改 boot 镜像头部 (命令行 / 系统版本 / 安全补丁 / bootconfig)", "8)".bright_cyan());
+    println!("{} DTB / DTBO 查看、提取、替换与 fstab 修补", "9)".bright_cyan());
+    println!("{} 分析内核 (版本 / GKI / .config / 可用 Root 方式)", "10)".bright_cyan());
+    println!("{} 编辑 ramdisk (magiskboot 风格 cpio 命令)", "11)".bright_cyan());
+    println!("{}", divider);
+    print!("请选择: ");
+    let _ = io::stdout().flush();
+    let mut choice = String::new();
+    let _ = io::stdin().read_line(&mut choice);
+    match choice.trim() {
+        "1" => inspect_vbmeta(),
+        "2" => verify_vbmeta(),
+        "3" => add_hashtree_footer().await,
+        "4" => make_vbmeta(),
+        "5" => manage_avb_keys(),
+        "6" => inspect_boot_image(),
+        "7" => unpack_repack_boot_image(),
+        "8" => edit_boot_header(),
+        "9" => dtb_tools(),
+        "10" => analyze_kernel_image(),
+        "11" => edit_ramdisk(),
+        _ => ui::err("无效的选择。"),
+    }
+}
+
+fn bundled_avbkey_dir() -> Option<PathBuf> {
+    let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
+    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+    let dir = key_dir_fallback(exe_dir);
+    dir.is_dir().then_some(dir)
+}
+
+fn verify_vbmeta() {
+    let Some(path) = ui::select_file("请选择 vbmeta.img 或带 AVB footer 的分区镜像", &["img"]) else { return; };
+    let mut opts = avb::VerifyOptions { key_dir: bundled_avbkey_dir(), ..Default::default() };
+    if ui::confirm("是否指定期望的签名密钥 (.pem)？", false) {
+        let Some(key_path) = ui::select_file("请选择期望的公钥或私钥 (.pem)", &["pem"]) else { return; };
+        match fs::read_to_string(&key_path).map_err(rua_core::FlashError::from).and_then(|t| avb::public_key_blob_from_pem(&t)) {
+            Ok(blob) => opts.expected_key = Some(blob),
+            Err(e) => {
+                ui::err(&format!("读取密钥失败: {}", e));
+                return;
+            }
+        }
+    }
+    ui::step("正在校验签名与分区哈希...");
+    match avb::verify::verify_image(&path, &opts) {
+        Ok(report) => print_verify_report(&report),
+        Err(e) => ui::err(&format!("校验失败: {}", e)),
+    }
+}
+
+fn check_status_label(status: &avb::CheckStatus) -> (ColoredString, String) {
+    match status {
+        avb::CheckStatus::Pass => ("通过".green(), String::new()),
+        avb::CheckStatus::Fail(reason) => ("失败".red(), reason.clone()),
+        avb::CheckStatus::Skipped(reason) => ("跳过".dimmed(), reason.clone()),
+    }
+}
+
+fn print_verify_report(report: &avb::VerifyReport) {
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    let (label, reason) = check_status_label(&report.signature);
+    println!("签名 [{}] {} {}", label, report.algorithm.map(|a| a.name()).unwrap_or("未知算法"), reason);
+    println!("公钥 sha1: {}", report.public_key_sha1.as_deref().unwrap_or("无"));
+    match &report.signed_by {
+        Some(path) => println!("签名密钥: {}", path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default().yellow()),
+        None => println!("签名密钥: 不是程序自带的 avbkey 测试密钥"),
+    }
+    match report.expected_key_matches {
+        Some(true) => println!("期望密钥: {}", "一致".green()),
+        Some(false) => println!("期望密钥: {}", "不一致".red()),
+        None => {}
+    }
+    for p in &report.partitions {
+        let (label, reason) = check_status_label(&p.status);
+        println!("[{}] {:<8} {:<20} {}", label, p.kind, p.partition, reason);
+    }
+    println!("{}", divider);
+    if report.is_ok() {
+        ui::ok("AVB 校验通过。");
+    } else {
+        ui::err("AVB 校验未通过，镜像可能被修改或来源不可信。");
+    }
+}
+
+/// 刷入前校验目录中的 vbmeta.img；没有 vbmeta 或校验通过时直接继续，校验失败或无法校验时由用户确认
+fn verify_dir_before_flash(dir: &Path) -> bool {
+    let vbmeta = dir.join("vbmeta.img");
+    if !vbmeta.is_file() {
+        return true;
+    }
+    ui::step("正在校验 vbmeta 签名与分区哈希...");
+    let opts = avb::VerifyOptions { image_dir: Some(dir.to_path_buf()), key_dir: bundled_avbkey_dir(), ..Default::default() };
+    match avb::verify::verify_image(&vbmeta, &opts) {
+        Ok(report) => {
+            print_verify_report(&report);
+            report.is_ok() || ui::confirm("仍要继续刷入吗？", false)
+        }
+        Err(e) => {
+            ui::err(&format!("无法校验 vbmeta: {}", e));
+            ui::confirm("无法确认镜像完整性，仍要继续刷入吗？", false)
+        }
+    }
+}
+
+async fn add_hashtree_footer() {
+    let Some(image_path) = ui::select_file("请选择 system/vendor/product 等文件系统镜像", &["img"]) else { return; };
+    let default_name = image_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.trim_end_matches("_a").trim_end_matches("_b").to_string())
+        .unwrap_or_default();
+    let name = ui::input(&format!("请输入分区名 (默认 {}):", default_name));
+    let partition = if name.is_empty() { default_name } else { name };
+    if partition.is_empty() {
+        ui::err("分区名不能为空。");
+        return;
+    }
+
+    let opts = match avb::HashtreeFooterOptions::from_original(&image_path, &partition) {
+        Ok(Some(opts)) => {
+            ui::ok(&format!(
+                "沿用原始镜像的 AVB 元数据: 分区大小 {} bytes，{}，块大小 {}，FEC roots {}",
+                opts.base.partition_size, opts.base.hash_algorithm, opts.block_size, opts.fec_num_roots
+            ));
+            opts
+        }
+        _ => {
+            let size = ui::input("请输入分区大小 (字节，直接回车按最小大小生成，适用于 super 中的动态分区):");
+            let partition_size = if size.is_empty() {
+                0
+            } else {
+                match size.parse::<u64>() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        ui::err("无效的分区大小。");
+                        return;
+                    }
+                }
+            };
+            let mut opts = avb::HashtreeFooterOptions::new(partition_size);
+            let (hash_algorithm, salt_len) = match ui::input("哈希算法 1) sha1 (默认)  2) sha256  3) sha512:").as_str() {
+                "2" => ("sha256", 32),
+                "3" => ("sha512", 64),
+                _ => ("sha1", 20),
+            };
+            opts.base.hash_algorithm = hash_algorithm.to_string();
+            let salt = ui::input("salt (十六进制，直接回车随机生成):");
+            opts.base.salt = if salt.is_empty() {
+                avb::random_salt(salt_len)
+            } else {
+                match avb::parser::parse_hex(&salt) {
+                    Some(salt) => salt,
+                    None => {
+                        ui::err("无效的 salt。");
+                        return;
+                    }
+                }
+            };
+            if ui::confirm("是否生成 FEC 纠错数据 (耗时较长)？", false) {
+                opts.fec_num_roots = 2;
+            }
+            opts
+        }
+    };
+
+    let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
+    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+    let Some((_, key_path)) = select_avb_key_dir_and_file(exe_dir) else { return; };
+    if opts.base.partition_size == 0 && !ui::confirm("未指定分区大小，输出镜像将按最小大小生成，继续吗？", true) {
+        return;
+    }
+
+    let Some(algorithm) = avb_algorithm_for_key(&key_path, Some(&image_path)) else {
+        ui::err("不支持的密钥长度，AVB 只支持 RSA 2048/4096/8192。");
+        return;
+    };
+
+    ui::step(&format!("正在计算哈希树并以 {} 签名，大镜像需要较长时间...", algorithm));
+    match avb::add_hashtree_footer(
+        &image_path.to_string_lossy(),
+        &partition,
+        &key_path.to_string_lossy(),
+        algorithm,
+        &opts,
+    )
+    .await
+    {
+        Ok(out) => {
+            ui::ok(&format!("已生成: {}", out));
+            match avb::verify::verify_image(Path::new(&out), &avb::VerifyOptions::default()) {
+                Ok(report) => print_verify_report(&report),
+                Err(e) => ui::warn(&format!("无法校验输出镜像: {}", e)),
+            }
+        }
+        Err(e) => ui::err(&format!("添加 hashtree footer 失败: {}", e)),
+    }
+}
+
+fn manage_avb_keys() {
+    let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
+    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+    let key_dir = key_dir_fallback(exe_dir);
+
+    println!("{} 生成新的 AVB 私钥", "1)".bright_cyan());
+    println!("{} 导出公钥 (avb_custom_key 格式)", "2)".bright_cyan());
+    println!("{} 查看密钥指纹", "3)".bright_cyan());
+    match ui::input("请选择:").as_str() {
+        "1" => {
+            let bits = match ui::input("密钥长度 1) 2048  2) 4096 (默认)  3) 8192:").as_str() {
+                "1" => 2048,
+                "3" => 8192,
+                _ => 4096,
+            };
+            let name = ui::input(&format!("文件名 (默认 custom_rsa{}.pem):", bits));
+            let name = if name.is_empty() { format!("custom_rsa{}.pem", bits) } else { name };
+            if let Err(e) = fs::create_dir_all(&key_dir) {
+                ui::err(&format!("无法创建密钥目录: {}", e));
+                return;
+            }
+            let path = key_dir.join(name);
+            if path.exists() && !ui::confirm(&format!("{} 已存在，要覆盖吗？", path.display()), false) {
+                return;
+            }
+            ui::step(&format!("正在生成 RSA{} 密钥{}...", bits, if bits == 8192 { "，可能需要数分钟" } else { "" }));
+            match avb::AvbKey::generate(bits, &path) {
+                Ok(key) => {
+                    ui::ok(&format!("已生成: {}", path.display()));
+                    println!("公钥 sha1: {}", key.display_fingerprint().yellow());
+                    ui::warn("请妥善备份私钥，丢失后将无法再为已锁定 bootloader 的设备签名。");
+                }
+                Err(e) => ui::err(&format!("生成密钥失败: {}", e)),
+            }
+        }
+        "2" => {
+            let Some(path) = ui::select_file("请选择公钥或私钥 (.pem)", &["pem"]) else { return; };
+            let key = match avb::AvbKey::load(&path) {
+                Ok(key) => key,
+                Err(e) => {
+                    ui::err(&format!("读取密钥失败: {}", e));
+                    return;
+                }
+            };
+            let output = path.with_extension("avbpubkey");
+            match key.write_public_key_blob(&output) {
+                Ok(_) => {
+                    ui::ok(&format!("已导出: {}", output.display()));
+                    println!("公钥 sha1: {}", key.display_fingerprint().yellow());
+                    println!("刷入方式: fastboot flash avb_custom_key {}", output.display());
+                }
+                Err(e) => ui::err(&format!("导出失败: {}", e)),
+            }
+        }
+        "3" => {
+            let keys = avb::keys::list_keys(&key_dir);
+            if keys.is_empty() {
+                ui::warn(&format!("{} 中没有可用的密钥。", key_dir.display()));
+                return;
+            }
+            for key in keys {
+                let name = key.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let kind = if key.is_private() { "私钥" } else { "公钥" };
+                println!("{:<28} {} RSA{}  {}", name, kind, key.bits(), key.display_fingerprint().yellow());
+            }
+        }
+        _ => ui::err("无效的选择。"),
+    }
+}
+
+fn make_vbmeta() {
+    let Some(dir) = ui::select_directory("请选择存放各分区镜像 (及原 vbmeta.img) 的目录") else { return; };
+    let mut opts = match avb::MakeVbmetaOptions::from_image_dir(&dir) {
+        Ok(opts) => opts,
+        Err(e) => {
+            ui::err(&format!("扫描目录失败: {}", e));
+            return;
+        }
+    };
+    if opts.include_images.is_empty() && opts.chain_partitions.is_empty() {
+        ui::err("目录中没有带 AVB 签名的镜像。");
+        return;
+    }
+
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    for chain in &opts.chain_partitions {
+        println!(
+            "{} {:<16} 位置 {}  公钥 sha1 {}",
+            "[链式]".bright_cyan(),
+            chain.partition_name,
+            chain.rollback_index_location,
+            chain.public_key_sha1()
+        );
+    }
+    for path in &opts.include_images {
+        println!("{} {}", "[包含]".bright_cyan(), path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default());
+    }
+    println!("{}", divider);
+
+    let flags = ui::input(&format!("vbmeta flags (当前 {}，直接回车保持不变):", avb::describe_vbmeta_flags(opts.flags)));
+    if !flags.is_empty() {
+        match flags.parse::<u32>() {
+            Ok(v) if v <= 3 => opts.flags = v,
+            _ => {
+                ui::err("无效的 flags，只能为 0~3。");
+                return;
+            }
+        }
+    }
+    let rollback = ui::input(&format!("回滚索引 (当前 {}，直接回车保持不变):", opts.rollback_index));
+    if !rollback.is_empty() {
+        match rollback.parse::<u64>() {
+            Ok(v) => opts.rollback_index = v,
+            Err(_) => {
+                ui::err("无效的回滚索引。");
+                return;
+            }
+        }
+    }
+
+    let exe_path = env::current_exe().unwrap_or(PathBuf::from("rua_flash_tool.exe"));
+    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+    let Some((_, key_path)) = select_avb_key_dir_and_file(exe_dir) else { return; };
+    let Some(algorithm) = avb_algorithm_for_key(&key_path, Some(&dir.join("vbmeta.img"))) else {
+        ui::err("不支持的密钥长度，AVB 只支持 RSA 2048/4096/8192。");
+        return;
+    };
+    let output = PathBuf::from("vbmeta.signed.img");
+    ui::step(&format!("正在生成 vbmeta 并以 {} 签名...", algorithm));
+    if let Err(e) = avb::make_vbmeta_image(&output, &key_path.to_string_lossy(), algorithm, &opts) {
+        ui::err(&format!("生成 vbmeta 失败: {}", e));
+        return;
+    }
+    ui::ok(&format!("已生成: {}", output.display()));
+    let verify_opts = avb::VerifyOptions { image_dir: Some(dir), key_dir: bundled_avbkey_dir(), ..Default::default() };
+    match avb::verify::verify_image(&output, &verify_opts) {
+        Ok(report) => print_verify_report(&report),
+        Err(e) => ui::warn(&format!("无法校验输出镜像: {}", e)),
+    }
+}
+
+fn inspect_boot_image() {
+    let Some(path) = ui::select_file("请选择 boot / init_boot / vendor_boot 镜像", &["img"]) else { return; };
+    match bootimg::inspect(&path) {
+        Ok(report) => print_boot_report(&report),
+        Err(e) => ui::err(&format!("解析失败: {}", e)),
+    }
+}
+
+fn default_unpack_dir(image: &Path) -> PathBuf {
+    let stem = image.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "boot".to_string());
+    image.with_file_name(format!("{}_unpacked", stem))
+}
+
+fn print_unpacked_dir(dir: &Path) {
+    ui::ok(&format!("已解包到: {}", dir.display()));
+    if let Ok(entries) = fs::read_dir(dir) {
+        let mut files: Vec<(String, u64)> = entries
+            .flatten()
+            .map(|e| (e.file_name().to_string_lossy().to_string(), e.metadata().map(|m| m.len()).unwrap_or(0)))
+            .collect();
+        files.sort();
+        for (name, size) in files {
+            println!("  {:<24} {} 字节", name, size);
+        }
+    }
+    println!("修改 {} 中的命令行、系统版本等字段或替换文件后，选择重新打包即可。", bootimg::unpack::HEADER_FILE.yellow());
+}
+
+fn unpack_repack_boot_image() {
+    println!("{} 解包镜像到目录", "1)".bright_cyan());
+    println!("{} 从目录重新打包", "2)".bright_cyan());
+    match ui::input("请选择:").as_str() {
+        "1" => {
+            let Some(image) = ui::select_file("请选择 boot / init_boot / vendor_boot 镜像", &["img"]) else { return; };
+            let out_dir = default_unpack_dir(&image);
+            if out_dir.exists() && !ui::confirm(&format!("{} 已存在，是否覆盖其中的文件？", out_dir.display()), false) {
+                return;
+            }
+            match bootimg::unpack(&image, &out_dir) {
+                Ok(_) => print_unpacked_dir(&out_dir),
+                Err(e) => ui::err(&format!("解包失败: {}", e)),
+            }
+        }
+        "2" => {
+            let Some(dir) = ui::select_directory("请选择解包目录 (包含 header.json)") else { return; };
+            let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let output = dir.with_file_name(format!("{}_repacked.img", name.trim_end_matches("_unpacked")));
+            ui::step("正在重新打包...");
+            match bootimg::repack(&dir, &output) {
+                Ok(config) => {
+                    ui::ok(&format!("已生成: {}", output.display()));
+                    if config.avb_footer_removed {
+                        warn_avb_footer_removed();
+                    }
+                }
+                Err(e) => ui::err(&format!("打包失败: {}", e)),
             }
         }
+        _ => ui::err("无效的选择。"),
     }
-    pause_before_back();
 }
 
-async fn flash_custom_partition(flasher: &Flasher) {
-    if !ui::confirm("确定要继续吗？此操作将刷入自定义分区镜像。", true) { return; }
+fn default_edit_output(image: &Path) -> PathBuf {
+    let stem = image.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "boot".to_string());
+    image.with_file_name(format!("{}_edited.img", stem))
+}
 
-    print!("请输入分区名 (如 boot/init_boot/recovery/system/vendor): ");
-    let _ = io::stdout().flush();
-    let mut partition = String::new();
-    let _ = io::stdin().read_line(&mut partition);
-    let partition = partition.trim().to_string();
-    if partition.is_empty() { ui::err("分区名不能为空。"); return; }
+fn print_header_edit_report(report: &bootimg::HeaderEditReport, output: &Path) {
+    let h = &report.header;
+    ui::ok(&format!("已生成: {}", output.display()));
+    println!("  命令行:         {}", h.full_cmdline());
+    if h.kind == bootimg::ImageKind::Boot {
+        println!("  系统版本:       {}", h.os_version_string().unwrap_or_else(|| "未设置".to_string()));
+        println!("  安全补丁:       {}", h.os_patch_level().unwrap_or_else(|| "未设置".to_string()));
+    }
+    if let Some(bootconfig) = &report.bootconfig {
+        println!("  bootconfig:");
+        for line in bootconfig.lines() {
+            println!("    {}", line);
+        }
+    }
+    if report.avb_footer_removed {
+        ui::warn("原镜像的 AVB footer 已去除，设备强制校验 AVB 时需要重新签名或关闭校验。");
+    }
+}
 
-    println!("\n{} {}", ">>".cyan().bold(), "请选择镜像来源:".bright_white());
-    println!("{}", "=".repeat(60).white());
-    println!("{} 本地镜像", "1)".bright_cyan());
-    println!("{} 从 Payload/卡刷包 获取", "2)".bright_cyan());
-    println!("{}", "=".repeat(60).white());
-    print!("请选择 [1/2]: ");
-    let _ = io::stdout().flush();
-    let mut src_choice = String::new();
-    let _ = io::stdin().read_line(&mut src_choice);
-    let src_choice = src_choice.trim();
+/// 按空白分隔输入的多个参数
+fn split_args(input: &str) -> Vec<String> {
+    input.split_whitespace().map(str::to_string).collect()
+}
 
-    let img_path: Option<PathBuf> = if src_choice == "2" {
-        ui::step(&format!("正在从 Payload 提取 {} 分区镜像...", partition));
-        let Some(payload_path) = ui::select_file("请选择 Payload.bin 或卡刷包 ZIP", &["bin", "zip"]) else { return; };
-        let out_dir = Path::new("extracted_payload");
-        let _ = fs::create_dir_all(out_dir);
-        let reporter = Arc::new(ConsoleReporter::new());
-        let reporter_dyn: Arc<dyn ProgressReporter> = reporter.clone();
-        match rua_core::payload::extract_single_partition(&payload_path, &partition, out_dir, reporter_dyn).await {
-            Ok(p) => { reporter.print_summary(); Some(p) },
-            Err(e) => {
-                if INTERRUPTED.load(Ordering::SeqCst) {
-                    reporter.clear_current(">> 已取消提取");
-                    ui::warn("已取消操作。");
-                } else {
-                    ui::err(&format!("从 Payload 提取分区失败: {:?}", e));
-                }
-                None
-            }
+fn edit_boot_header() {
+    let Some(image) = ui::select_file("请选择 boot / init_boot / vendor_boot 镜像", &["img"]) else { return; };
+    let header = match fs::read(&image).map_err(rua_core::FlashError::from).and_then(|d| bootimg::BootHeader::parse(&d)) {
+        Ok(h) => h,
+        Err(e) => {
+            ui::err(&format!("解析失败: {}", e));
+            return;
         }
-    } else {
-        ui::select_file("请选择要刷入的自定义分区镜像", &["img"])
     };
+    println!("  当前命令行:     {}", header.full_cmdline());
 
-    let Some(path) = img_path else { return; };
-    let target_device = select_device(&flasher.client).await;
-    if target_device.is_empty() {
-        ui::warn("未检测到设备，取消刷入。");
+    let mut edit = bootimg::HeaderEdit {
+        cmdline_add: split_args(&ui::input("追加命令行参数 (空格分隔，如 androidboot.selinux=permissive，回车跳过):")),
+        cmdline_remove: split_args(&ui::input("删除命令行参数 (空格分隔，可只写参数名，回车跳过):")),
+        ..Default::default()
+    };
+    if header.kind == bootimg::ImageKind::Boot {
+        println!("  当前系统版本:   {}", header.os_version_string().unwrap_or_else(|| "未设置".to_string()));
+        println!("  当前安全补丁:   {}", header.os_patch_level().unwrap_or_else(|| "未设置".to_string()));
+        let version = ui::input("新的系统版本 (如 14.0.0，回车保持不变):");
+        let level = ui::input("新的安全补丁级别 (如 2024-05，回车保持不变):");
+        edit.os_version = (!version.is_empty()).then_some(version);
+        edit.os_patch_level = (!level.is_empty()).then_some(level);
+    } else if header.header_version >= 4 {
+        edit.bootconfig_add = split_args(&ui::input("追加 bootconfig 项 (空格分隔，如 androidboot.hardware=qcom，回车跳过):"));
+        edit.bootconfig_remove = split_args(&ui::input("删除 bootconfig 项 (空格分隔，可只写名称，回车跳过):"));
+    }
+    if edit.is_empty() {
+        ui::warn("未修改任何字段。");
         return;
     }
-    ui::step(&format!("正在刷入 {}: {} ...", partition, path.display()));
-    match flasher.flash_partition(&target_device, &partition, &path.to_string_lossy()).await {
-        Ok(_) => ui::ok("刷入成功！"),
-        Err(e) => ui::err(&format!("刷入失败: {:?}", e)),
+
+    let output = default_edit_output(&image);
+    match bootimg::edit_header(&image, &output, &edit) {
+        Ok(report) => print_header_edit_report(&report, &output),
+        Err(e) => ui::err(&format!("修改失败: {}", e)),
     }
 }
 
-fn install_usb_driver() {
-    ui::step("正在安装驱动...");
-    let driver_exe = Path::new("drivers/QcomMtk_Driver_Setup_3.2.1.exe");
-    if driver_exe.exists() {
-        let _ = tokio::process::Command::new(driver_exe).spawn();
-    } else {
-        ui::err("未找到驱动安装包 (drivers/usb_driver_setup.exe)");
+fn default_patched_output(image: &Path) -> PathBuf {
+    let stem = image.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "dtbo".to_string());
+    let ext = image.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_else(|| "img".to_string());
+    image.with_file_name(format!("{}_patched.{}", stem, ext))
+}
+
+fn warn_avb_footer_removed() {
+    ui::warn("原镜像的 AVB footer 已去除，设备强制校验 AVB 时需要重新签名或关闭校验。");
+}
+
+fn warn_dtb_avb_footer(dtb_file: &bootimg::DtbFile) {
+    if dtb_file.avb_footer_removed() {
+        warn_avb_footer_removed();
     }
 }
 
-async fn disable_avb(flasher: &Flasher) {
-    println!("\n{} {}", ">>".cyan().bold(), "请选择 vbmeta 镜像来源:".bright_white());
-    println!("{}", "=".repeat(60).white());
-    println!("{} 本地 vbmeta.img", "1)".bright_cyan());
-    println!("{} 从 Payload/卡刷包 提取 vbmeta", "2)".bright_cyan());
-    println!("{}", "=".repeat(60).white());
-    print!("请选择 [1/2]: ");
-    let _ = io::stdout().flush();
-    let mut src_choice = String::new();
-    let _ = io::stdin().read_line(&mut src_choice);
-    let src_choice = src_choice.trim();
+fn print_dtb_info(dtb_file: &bootimg::DtbFile) -> rua_core::Result<()> {
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    if let bootimg::DtbFile::Dtbo(dtbo) = dtb_file {
+        println!("  dtbo 版本 {}  页大小 {}  共 {} 个 overlay", dtbo.version, dtbo.page_size, dtbo.entries.len());
+    }
+    for (i, blob) in dtb_file.fdts()?.iter().enumerate() {
+        let fdt = rua_core::dtb::Fdt::parse(blob)?;
+        let mut line = format!("  #{:<3} {:>8} 字节", i, blob.len());
+        if let bootimg::DtbFile::Dtbo(dtbo) = dtb_file {
+            line += &format!("  id=0x{:08x} rev=0x{:08x}", dtbo.entries[i].id, dtbo.entries[i].rev);
+        }
+        println!("{}", line);
+        if let Some(model) = fdt.model() {
+            println!("        model:      {}", model);
+        }
+        if let Some(compatible) = fdt.compatible() {
+            println!("        compatible: {}", compatible);
+        }
+    }
+    println!("{}", divider);
+    Ok(())
+}
 
-    let img_path: Option<PathBuf> = if src_choice == "2" {
-        ui::step("正在从 Payload 提取 vbmeta 分区镜像...");
-        let Some(payload_path) = ui::select_file("请选择 Payload.bin 或卡刷包 ZIP", &["bin", "zip"]) else { return; };
-        let out_dir = Path::new("extracted_payload");
-        let _ = fs::create_dir_all(out_dir);
-        let reporter = Arc::new(ConsoleReporter::new());
-        let reporter_dyn: Arc<dyn ProgressReporter> = reporter.clone();
-        match rua_core::payload::extract_single_partition(&payload_path, "vbmeta", out_dir, reporter_dyn).await {
-            Ok(p) => { reporter.print_summary(); Some(p) },
-            Err(e) => {
-                if INTERRUPTED.load(Ordering::SeqCst) {
-                    reporter.clear_current(">> 已取消提取");
-                    ui::warn("已取消操作。");
-                } else {
-                    ui::err(&format!("从 Payload 提取 vbmeta 失败: {:?}", e));
-                }
-                None
+fn extract_dtbs(dtb_file: &bootimg::DtbFile, out_dir: &Path) -> rua_core::Result<()> {
+    let prefix = if matches!(dtb_file, bootimg::DtbFile::Dtbo(_)) { "dtbo" } else { "dtb" };
+    fs::create_dir_all(out_dir)?;
+    let fdts = dtb_file.fdts()?;
+    for (i, blob) in fdts.iter().enumerate() {
+        fs::write(out_dir.join(format!("{}.{}.dtb", prefix, i)), blob)?;
+    }
+    ui::ok(&format!("已提取 {} 个 DTB 到: {}", fdts.len(), out_dir.display()));
+    Ok(())
+}
+
+fn replace_dtb_entry(dtb_file: &bootimg::DtbFile, index: usize, file: &Path, output: &Path) -> rua_core::Result<()> {
+    let data = dtb_file.replace_fdt(index, &fs::read(file)?)?;
+    fs::write(output, data)?;
+    ui::ok(&format!("已生成: {}", output.display()));
+    warn_dtb_avb_footer(dtb_file);
+    Ok(())
+}
+
+fn patch_dtb_fstab(dtb_file: &bootimg::DtbFile, patch: &rua_core::dtb::FstabPatch, output: &Path) -> rua_core::Result<()> {
+    let (data, changes) = dtb_file.patch_fstab(patch)?;
+    if changes.is_empty() {
+        ui::warn("fstab 中没有需要去除的选项，未生成新文件。");
+        return Ok(());
+    }
+    fs::write(output, data)?;
+    for (path, flag) in &changes {
+        println!("  {:<40} - {}", path, flag);
+    }
+    ui::ok(&format!("已去除 {} 个选项，已生成: {}", changes.len(), output.display()));
+    warn_dtb_avb_footer(dtb_file);
+    Ok(())
+}
+
+fn dtb_tools() {
+    let Some(image) = ui::select_file("请选择 dtbo.img、DTB 文件或 boot / vendor_boot 镜像", &["img", "dtb", "dtbo"]) else { return; };
+    let dtb_file = match bootimg::DtbFile::load(&image) {
+        Ok(f) => f,
+        Err(e) => {
+            ui::err(&format!("解析失败: {}", e));
+            return;
+        }
+    };
+    if let Err(e) = print_dtb_info(&dtb_file) {
+        ui::err(&format!("解析失败: {}", e));
+        return;
+    }
+    println!("{} 提取全部 DTB", "1)".bright_cyan());
+    println!("{} 替换其中一个 DTB", "2)".bright_cyan());
+    println!("{} 修补 fstab (去除 AVB 校验 / 强制加密)", "3)".bright_cyan());
+    let output = default_patched_output(&image);
+    let result = match ui::input("请选择:").as_str() {
+        "1" => extract_dtbs(&dtb_file, &default_unpack_dir(&image)),
+        "2" => {
+            let Ok(index) = ui::input("要替换的序号:").parse::<usize>() else {
+                ui::err("无效的序号。");
+                return;
+            };
+            let Some(file) = ui::select_file("请选择新的 DTB 文件", &["dtb", "dtbo", "img"]) else { return; };
+            replace_dtb_entry(&dtb_file, index, &file, &output)
+        }
+        "3" => {
+            let patch = rua_core::dtb::FstabPatch {
+                remove_verity: ui::confirm("是否去除 AVB 校验选项 (verify / avb)？", true),
+                remove_encryption: ui::confirm("是否去除强制加密选项 (forceencrypt / fileencryption)？", false),
+            };
+            patch_dtb_fstab(&dtb_file, &patch, &output)
+        }
+        _ => {
+            ui::err("无效的选择。");
+            return;
+        }
+    };
+    if let Err(e) = result {
+        ui::err(&format!("操作失败: {}", e));
+    }
+}
+
+fn print_kernel_analysis(analysis: &kernel_info::KernelAnalysis) {
+    match &analysis.banner {
+        Some(banner) => {
+            println!("  版本:           {}", banner.release);
+            if let Some(compiler) = &banner.compiler {
+                println!("  编译器:         {}", compiler);
+            }
+            if let Some(date) = &banner.build_date {
+                println!("  构建时间:       {}", date);
             }
         }
-    } else {
-        ui::select_file("请选择 vbmeta.img", &["img"])
+        None => println!("  版本:           未识别"),
+    }
+    let kmi = match analysis.kmi() {
+        Some(kmi) if analysis.is_gki() => format!("{} (GKI)", kmi),
+        Some(kmi) => format!("{} (非 GKI)", kmi),
+        None => "非 GKI".to_string(),
     };
+    println!("  KMI:            {}", kmi);
+    println!("  架构:           {}", if analysis.arm64 { "arm64" } else { "非 arm64" });
+    match &analysis.config {
+        Some(config) => {
+            let options: Vec<String> = kernel_info::KEY_CONFIGS
+                .iter()
+                .map(|name| format!("{}={}", name, config.get(name).unwrap_or("n")))
+                .collect();
+            println!("  内核配置:       {}", options.join("  "));
+        }
+        None => println!("  内核配置:       未内置 (CONFIG_IKCONFIG 未启用)"),
+    }
+    println!("  可用 Root 方式:");
+    for check in analysis.root_methods() {
+        let mark = match check.viability {
+            kernel_info::Viability::Viable => "可用".green(),
+            kernel_info::Viability::Unknown => "待确认".yellow(),
+            kernel_info::Viability::NotViable => "不可用".red(),
+        };
+        println!("    {:<24} {}  {}", check.method.name(), mark, check.reason);
+    }
+}
 
-    let Some(vbmeta_path) = img_path else { return; };
+fn print_kernel_report(analysis: &kernel_info::KernelAnalysis) {
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    println!("{}  压缩: {}", "内核".bright_white().bold(), analysis.format.name());
+    print_kernel_analysis(analysis);
+    println!("{}", divider);
+}
 
-    let target_device = select_device(&flasher.client).await;
-    if target_device.is_empty() {
-        ui::err("未检测到 Fastboot 设备，无法执行刷入。");
+fn export_kernel_config(analysis: &kernel_info::KernelAnalysis, out: &Path) -> rua_core::Result<()> {
+    let config = analysis
+        .config
+        .as_ref()
+        .ok_or_else(|| rua_core::FlashError::PatchError("内核未内置配置 (CONFIG_IKCONFIG 未启用)".to_string()))?;
+    fs::write(out, &config.text)?;
+    ui::ok(&format!("已导出 .config: {}", out.display()));
+    Ok(())
+}
+
+fn analyze_kernel_image() {
+    let Some(image) = ui::select_file("请选择 boot 镜像、kernel 分区镜像或内核文件", &["img", "gz", "lz4"]) else { return; };
+    let analysis = match bootimg::analyze_kernel(&image) {
+        Ok(a) => a,
+        Err(e) => {
+            ui::err(&format!("分析失败: {}", e));
+            return;
+        }
+    };
+    print_kernel_report(&analysis);
+    if analysis.config.is_some() && ui::confirm("是否导出内核 .config？", false) {
+        let stem = image.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "kernel".to_string());
+        if let Err(e) = export_kernel_config(&analysis, &image.with_file_name(format!("{}.config", stem))) {
+            ui::err(&format!("导出失败: {}", e));
+        }
+    }
+}
+
+fn save_ramdisk_image(ramdisk: &bootimg::ImageRamdisk, output: &Path) -> rua_core::Result<()> {
+    fs::write(output, ramdisk.to_image()?)?;
+    ui::ok(&format!("已按原格式 ({:?}) 重新打包 ramdisk，已生成: {}", ramdisk.format, output.display()));
+    if ramdisk.avb_footer_removed {
+        warn_avb_footer_removed();
+    }
+    Ok(())
+}
+
+fn edit_ramdisk() {
+    let Some(image) = ui::select_file("请选择 boot / init_boot / vendor_boot 镜像", &["img"]) else { return; };
+    let vendor_ramdisk = select_vendor_ramdisk(&image);
+    let mut ramdisk = match bootimg::ImageRamdisk::load(&image, vendor_ramdisk.map(bootimg::RamdiskChoice::Index)) {
+        Ok(r) => r,
+        Err(e) => {
+            ui::err(&format!("读取 ramdisk 失败: {}", e));
+            return;
+        }
+    };
+    let state = match ramdisk.cpio.patch_state() {
+        PatchState::Stock => "未修补",
+        PatchState::Magisk => "已由 Magisk 修补",
+        PatchState::Other => "已由其他 Root 方案修补",
+    };
+    ui::step(&format!("ramdisk: {} 项，压缩格式 {:?}，{}", ramdisk.cpio.len(), ramdisk.format, state));
+    println!("  可用命令: add MODE ENTRY INFILE | rm [-r] ENTRY | mkdir MODE ENTRY | mv SOURCE DEST");
+    println!("            ln TARGET ENTRY | extract [ENTRY OUT] | exists ENTRY | test | backup ORIG | restore");
+    println!("  含空格的路径请用引号括起，如 add 0644 \"etc/my file.rc\" \"D:\\my file.rc\"");
+
+    let mut modified = false;
+    loop {
+        let line = ui::input("请输入 cpio 命令，直接回车结束:");
+        if line.is_empty() {
+            break;
+        }
+        let result = CpioCommand::parse(&line).and_then(|command| {
+            let message = ramdisk.cpio.exec(&command)?;
+            modified |= command.modifies();
+            Ok(message)
+        });
+        match result {
+            Ok(message) => ui::ok(&message),
+            Err(e) => ui::err(&e.to_string()),
+        }
+    }
+    if !modified {
         return;
     }
+    if let Err(e) = save_ramdisk_image(&ramdisk, &default_patched_output(&image)) {
+        ui::err(&format!("重新打包失败: {}", e));
+    }
+}
+
+/// 修补前按内核判断所选 Root 方式是否可行，不可行时由用户决定是否继续；无法读取内核时不拦截
+fn confirm_root_method(image: &Path, method: kernel_info::RootMethod) -> bool {
+    let Ok(analysis) = bootimg::analyze_kernel(image) else { return true };
+    let Some(check) = analysis.root_methods().into_iter().find(|c| c.method == method) else { return true };
+    match check.viability {
+        kernel_info::Viability::Viable => {
+            ui::ok(&format!("{}: {}", check.method.name(), check.reason));
+            true
+        }
+        kernel_info::Viability::Unknown => {
+            ui::warn(&format!("{}: {}", check.method.name(), check.reason));
+            true
+        }
+        kernel_info::Viability::NotViable => {
+            ui::err(&format!("{} 不适用于该内核: {}", check.method.name(), check.reason));
+            ui::confirm("仍要继续吗？", false)
+        }
+    }
+}
+
+fn print_boot_report(report: &bootimg::BootImageReport) {
+    let divider = "=".repeat(60).white();
+    let h = &report.header;
+    let kind = match h.kind {
+        bootimg::ImageKind::Boot if report.kernel.is_none() => "init_boot",
+        bootimg::ImageKind::Boot => "boot",
+        bootimg::ImageKind::VendorBoot => "vendor_boot",
+    };
+    println!("{}", divider);
+    println!("{}", "镜像头部".bright_white().bold());
+    println!("  类型:           {} (header v{})", kind.yellow(), h.header_version);
+    println!("  镜像大小:       {} 字节", report.image_size);
+    println!("  页大小:         {}", h.page_size);
+    println!("  系统版本:       {}", h.os_version_string().unwrap_or_else(|| "未设置".to_string()));
+    println!("  安全补丁:       {}", h.os_patch_level().unwrap_or_else(|| "未设置".to_string()));
+    if !h.name.is_empty() {
+        println!("  名称:           {}", h.name);
+    }
+    println!("  命令行:         {}", h.full_cmdline());
+    for (section, offset, size) in h.sections() {
+        println!("  {:<15} 0x{:08x} (+{})", format!("{}:", section.name()), offset, size);
+    }
+
+    println!("{}", "内核".bright_white().bold());
+    match &report.kernel {
+        Some(k) => {
+            println!("  大小:           {} 字节  压缩: {}{}", k.size, k.format.name(), if k.mtk_header { "  (MTK 头部)" } else { "" });
+            match &k.analysis {
+                Some(analysis) => print_kernel_analysis(analysis),
+                None => println!("  版本:           无法解压内核"),
+            }
+        }
+        None => println!("  无内核"),
+    }
+
+    println!("{}", "Ramdisk".bright_white().bold());
+    match &report.ramdisk {
+        Some(r) => {
+            println!("  大小:           {} 字节  格式: {:?}{}", r.size, r.format, if r.mtk_header { "  (MTK 头部)" } else { "" });
+            if r.vendor_header > 0 {
+                println!("  厂商头部:       cpio 前有 {} 字节", r.vendor_header);
+            }
+        }
+        None if report.vendor_ramdisks.is_empty() => println!("  无 ramdisk"),
+        None => {}
+    }
+    for (i, (entry, r)) in report.vendor_ramdisks.iter().enumerate() {
+        println!(
+            "  {:<15} {} 字节  类型: {}  格式: {:?}",
+            format!("{}:", entry.display_name(i)),
+            r.size,
+            entry.ramdisk_type.name(),
+            r.format
+        );
+    }
+
+    let dtb = match (&report.kernel, report.dtb_size) {
+        (_, size) if size > 0 => format!("dtb 段 {} 字节", size),
+        (Some(bootimg::inspect::KernelInfo { appended_dtb: Some(off), .. }), _) => format!("附加在内核 0x{:x} 处", off),
+        _ => "无".to_string(),
+    };
+    println!("{}", "其他".bright_white().bold());
+    println!("  DTB:            {}", dtb);
+    match &report.avb {
+        Some(image) => println!(
+            "  AVB footer:     {} {}  公钥 sha1: {}",
+            image.partitions().first().copied().unwrap_or("?"),
+            image.algorithm().map(|a| a.name()).unwrap_or("未知算法"),
+            image.public_key_sha1().unwrap_or_else(|| "无 (未签名)".to_string())
+        ),
+        None => println!("  AVB footer:     无"),
+    }
+    if report.root.is_empty() {
+        println!("  Root 修补:      {}", "未检测到".green());
+    } else {
+        let names: Vec<&str> = report.root.iter().map(|r| r.name()).collect();
+        println!("  Root 修补:      {}", names.join(", ").yellow());
+    }
+    println!("{}", divider);
+}
+
+fn inspect_vbmeta() {
+    let Some(path) = ui::select_file("请选择 vbmeta.img 或带 AVB footer 的分区镜像", &["img"]) else { return; };
+    match avb::VbmetaImage::load(&path) {
+        Ok(image) => print_vbmeta_info(&image),
+        Err(e) => ui::err(&format!("解析失败: {}", e)),
+    }
+}
+
+fn print_vbmeta_info(image: &avb::VbmetaImage) {
+    let divider = "=".repeat(60).white();
+    let h = &image.header;
+    println!("{}", divider);
+    if let Some(footer) = &image.footer {
+        println!("{}", "AVB Footer".bright_white().bold());
+        println!("  原始镜像大小:   {} 字节", footer.original_image_size);
+        println!("  vbmeta 偏移:    0x{:x}", footer.vbmeta_offset);
+        println!("  vbmeta 大小:    {} 字节", footer.vbmeta_size);
+    }
+    println!("{}", "VBMeta".bright_white().bold());
+    println!("  算法:           {}", image.algorithm().map(|a| a.name().to_string()).unwrap_or_else(|| format!("未知 ({})", h.algorithm_type)));
+    println!("  libavb 版本:    {}.{}", h.required_libavb_version_major, h.required_libavb_version_minor);
+    println!("  回滚索引:       {} (位置 {})", h.rollback_index, h.rollback_index_location);
+    println!("  Flags:          {}", avb::describe_vbmeta_flags(h.flags));
+    println!("  Release:        {}", h.release_string);
+    println!("  公钥 sha1:      {}", image.public_key_sha1().unwrap_or_else(|| "无 (未签名)".to_string()));
+    println!("{}", "描述符".bright_white().bold());
+    for d in &image.descriptors {
+        match d {
+            avb::Descriptor::Hash(d) => {
+                println!("  {} {}", "哈希".bright_cyan(), d.partition_name.yellow());
+                println!("    镜像大小: {} 字节  算法: {}", d.image_size, d.hash_algorithm);
+                println!("    Salt:   {}", avb::parser::hex(&d.salt));
+                println!("    Digest: {}", avb::parser::hex(&d.digest));
+            }
+            avb::Descriptor::Hashtree(d) => {
+                println!("  {} {}", "哈希树".bright_cyan(), d.partition_name.yellow());
+                println!("    镜像大小: {} 字节  算法: {}  dm-verity v{}", d.image_size, d.hash_algorithm, d.dm_verity_version);
+                println!("    块大小: {}/{}  哈希树: 0x{:x} (+{})", d.data_block_size, d.hash_block_size, d.tree_offset, d.tree_size);
+                if d.fec_num_roots > 0 {
+                    println!("    FEC: {} roots  0x{:x} (+{})", d.fec_num_roots, d.fec_offset, d.fec_size);
+                }
+                println!("    Salt:        {}", avb::parser::hex(&d.salt));
+                println!("    Root Digest: {}", avb::parser::hex(&d.root_digest));
+            }
+            avb::Descriptor::ChainPartition(d) => {
+                println!("  {} {}", "链式分区".bright_cyan(), d.partition_name.yellow());
+                println!("    回滚索引位置: {}  公钥 sha1: {}", d.rollback_index_location, d.public_key_sha1());
+            }
+            avb::Descriptor::KernelCmdline { flags, cmdline } => {
+                println!("  {} (flags {}) {}", "内核命令行".bright_cyan(), flags, cmdline);
+            }
+            avb::Descriptor::Property { key, value } => {
+                println!("  {} {} = {}", "属性".bright_cyan(), key, String::from_utf8_lossy(value));
+            }
+            avb::Descriptor::Unknown { tag, data } => {
+                println!("  {} tag {} ({} 字节)", "未知描述符".bright_cyan(), tag, data.len());
+            }
+        }
+    }
+    println!("{}", divider);
+}
 
-    ui::step("正在刷入 vbmeta.img 并关闭 AVB 校验...");
-    match flasher.flash_vbmeta(&target_device, &vbmeta_path.to_string_lossy()).await {
-        Ok(_) => ui::ok("vbmeta 刷入成功，AVB 校验已禁用。"),
-        Err(e) => ui::err(&format!("vbmeta 刷入失败: {:?}", e)),
+fn print_vbmeta_flags_patch(patch: &avb::VbmetaFlagsPatch) {
+    if patch.vbmeta_offset != 0 {
+        ui::step(&format!("vbmeta 位于 AVB footer 中，偏移 0x{:x}", patch.vbmeta_offset));
     }
+    ui::step(&format!(
+        "flags: {} -> {}",
+        avb::describe_vbmeta_flags(patch.old_flags),
+        avb::describe_vbmeta_flags(patch.new_flags)
+    ));
 }
 
 fn open_cmd() {
@@ -1811,11 +3466,8 @@ async fn detect_device(client: &FastbootClient) {
             println!("{}", divider);
             for dev in devices {
                 let mode_str = match dev.mode {
-                    rua_core::device::DeviceMode::Fastboot => "Fastboot".yellow(),
-                    rua_core::device::DeviceMode::FastbootD => "FastbootD".yellow(),
-                    rua_core::device::DeviceMode::ADB => "ADB (系统)".green(),
-                    rua_core::device::DeviceMode::Recovery => "Recovery".magenta(),
-                    _ => format!("{:?}", dev.mode).white(),
+                    DeviceMode::ADB => "ADB (系统)".green(),
+                    ref mode => mode_label(mode),
                 };
                 let product = dev.product.unwrap_or_else(|| "未知型号".to_string());
                 println!("  {}  序列号: {}  型号: {}", mode_str, dev.serial.cyan(), product.bright_white());
@@ -1992,10 +3644,7 @@ async fn reboot_device(client: &FastbootClient) {
     } else {
         println!("\n{} 请选择要重启的设备:", ">>".cyan());
         for (i, d) in all_devs.iter().enumerate() {
-            let mode_str = match d.mode {
-                rua_core::device::DeviceMode::ADB => "ADB".green(),
-                _ => "Fastboot".yellow(),
-            };
+            let mode_str = mode_label(&d.mode);
             println!("  {}. [{}] {} ({})", i + 1, mode_str, d.serial, d.product.as_deref().unwrap_or("未知"));
         }
         print!("请选择: ");
@@ -2034,7 +3683,7 @@ async fn reboot_device(client: &FastbootClient) {
     ui::step(&format!("正在重启设备 {} ...", selected_dev.serial));
     
     let res = match selected_dev.mode {
-        rua_core::device::DeviceMode::ADB => {
+        DeviceMode::ADB | DeviceMode::Recovery | DeviceMode::Sideload => {
             if let Ok(adb) = rua_core::AdbClient::new() {
                 adb.reboot(&selected_dev.serial, target).await
             } else {
@@ -2295,6 +3944,7 @@ fn select_partition() -> String {
     println!("{}{}", format!("{:>3}. ", 1).bright_cyan(), "boot");
     println!("{}{}", format!("{:>3}. ", 2).bright_cyan(), "init_boot");
     println!("{}{}", format!("{:>3}. ", 3).bright_cyan(), "ramdisk");
+    println!("{}{}", format!("{:>3}. ", 4).bright_cyan(), "vendor_boot");
     println!("{}", divider);
 
     print!("请选择: ");
@@ -2305,11 +3955,64 @@ fn select_partition() -> String {
     match input.trim() {
         "2" => "init_boot".to_string(),
         "3" => "ramdisk".to_string(),
+        "4" => "vendor_boot".to_string(),
         _ => "boot".to_string(),
     }
 }
 
+/// vendor_boot 含多个 vendor ramdisk 时让用户选择修补目标，返回 None 表示使用默认选择
+/// 返回所选 vendor ramdisk 在 ramdisk 表中的序号；不是 vendor_boot 或只有一个片段时返回 None
+fn select_vendor_ramdisk(image: &Path) -> Option<usize> {
+    let data = fs::read(image).ok()?;
+    if !bootimg::vendor::is_vendor_boot(&data) {
+        return None;
+    }
+    let vendor = match bootimg::VendorBootImage::parse(&data) {
+        Ok(v) => v,
+        Err(e) => {
+            ui::warn(&format!("解析 vendor_boot 失败: {}", e));
+            return None;
+        }
+    };
+    if vendor.ramdisks.len() <= 1 {
+        return None;
+    }
+    let default = vendor.select_ramdisk(None).ok();
+
+    println!("\n{} {}", ">>".cyan().bold(), "请选择要修补的 vendor ramdisk:".bright_white());
+    let divider = "=".repeat(60).white();
+    println!("{}", divider);
+    for (i, (entry, data)) in vendor.ramdisks.iter().enumerate() {
+        let mut label = format!("{} [{}] {} bytes", entry.display_name(i), entry.ramdisk_type.name(), data.len());
+        if default == Some(i) {
+            label = format!("{} (默认)", label).green().to_string();
+        }
+        println!("{}{}", format!("{:>3}. ", i + 1).bright_cyan(), label);
+    }
+    println!("{}", divider);
+
+    let prompt = if default.is_some() { "请输入序号，直接回车使用默认:" } else { "请输入序号:" };
+    loop {
+        let choice = ui::input(prompt);
+        // 没有默认片段时直接回车由修补时报错，避免输入关闭后反复提示
+        if choice.is_empty() {
+            return default;
+        }
+        match choice.parse::<usize>() {
+            Ok(index) if (1..=vendor.ramdisks.len()).contains(&index) => return Some(index - 1),
+            _ => ui::warn(&format!("无效的序号，请输入 1-{}。", vendor.ramdisks.len())),
+        }
+    }
+}
+
 async fn select_device(client: &FastbootClient) -> String {
+    // 只有一台设备时默认选中，但仍需用户确认，避免误刷刚插上的其他设备
+    let confirm_single = |dev: &ConnectedDevice| {
+        ui::confirm(&format!("检测到唯一连接的设备: {} [{:?}]，是否使用该设备？", dev.serial, dev.mode), true)
+    };
+    if let Some(dev) = MONITOR.get().and_then(|m| m.single_device(&[DeviceMode::Fastboot, DeviceMode::FastbootD])) {
+        return if confirm_single(&dev) { dev.serial } else { String::new() };
+    }
     ui::step("正在搜索设备...");
     match client.list_devices().await {
         Ok(devices) => {
@@ -2317,6 +4020,9 @@ async fn select_device(client: &FastbootClient) -> String {
                 ui::err("未检测到任何设备。");
                 return String::new();
             }
+            if devices.len() == 1 {
+                return if confirm_single(&devices[0]) { devices[0].serial.clone() } else { String::new() };
+            }
 
             let devices: Vec<&ConnectedDevice> = devices.iter().collect();
 
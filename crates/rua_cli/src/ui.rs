@@ -1,6 +1,8 @@
 use colored::*;
 use rfd::FileDialog;
+use std::io::{self, Write};
 use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
 use rustyline::DefaultEditor;
 
 pub fn step(msg: &str) {
@@ -38,8 +40,7 @@ pub fn confirm(msg: &str, default_yes: bool) -> bool {
     } else {
         println!("{} [y/N]", msg.cyan());
     }
-    let mut rl = DefaultEditor::new().unwrap();
-    if let Ok(line) = rl.readline("> ") {
+    if let Some(line) = read_line() {
         let line = line.trim().to_lowercase();
         if line.is_empty() {
             default_yes
@@ -50,3 +51,26 @@ pub fn confirm(msg: &str, default_yes: bool) -> bool {
         default_yes
     }
 }
+
+/// 读取一行输入并去掉首尾空白，直接回车时返回空字符串
+pub fn input(msg: &str) -> String {
+    println!("{}", msg.cyan());
+    read_line().map(|line| line.trim().to_string()).unwrap_or_default()
+}
+
+/// 各提示共用一个行编辑器；rustyline 无法初始化时（如输入被重定向）退回到标准输入
+fn read_line() -> Option<String> {
+    static EDITOR: OnceLock<Option<Mutex<DefaultEditor>>> = OnceLock::new();
+    match EDITOR.get_or_init(|| DefaultEditor::new().ok().map(Mutex::new)) {
+        Some(editor) => editor.lock().ok()?.readline("> ").ok(),
+        None => {
+            print!("> ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(n) if n > 0 => Some(line),
+                _ => None,
+            }
+        }
+    }
+}
@@ -1,2 +1 @@
-pub mod file_finder;
 pub mod path_resolver;
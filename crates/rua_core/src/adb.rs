@@ -84,37 +84,53 @@ impl AdbClient {
         }
     }
 
+    pub async fn list_serials(&self) -> Result<Vec<(String, String)>> {
+        let mut serials = Vec::new();
+        let output = Command::new(&self.adb_path)
+            .arg("devices")
+            .output()
+            .await?;
+        for line in String::from_utf8_lossy(&output.stdout).lines().skip(1) {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                serials.push((parts[0].to_string(), parts[1].to_string()));
+            }
+        }
+        Ok(serials)
+    }
+
     pub async fn list_devices(&self) -> Result<Vec<ConnectedDevice>> {
         let mut devices = Vec::new();
 
-        if let Ok(output) = self.capture(&["devices"]).await {
-            for line in output.lines().skip(1) {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let serial = parts[0].to_string();
-                    let status = parts[1].to_string();
-                    let mode = DeviceMode::ADB;
-
-                    let mut dev = ConnectedDevice {
-                        serial: serial.clone(),
-                        mode,
-                        status,
-                        product: None,
-                        current_slot: None,
-                    };
-
-                    if let Ok(model) = self.get_prop(&serial, "ro.product.model").await {
-                        dev.product = Some(model);
-                    }
-
-                    devices.push(dev);
-                }
+        if let Ok(serials) = self.list_serials().await {
+            for (serial, status) in serials {
+                devices.push(self.describe(&serial, &status).await);
             }
         }
 
         Ok(devices)
     }
 
+    pub(crate) async fn describe(&self, serial: &str, status: &str) -> ConnectedDevice {
+        let mode = match DeviceMode::from(status) {
+            DeviceMode::Unknown(_) => DeviceMode::ADB,
+            mode => mode,
+        };
+
+        let mut dev = ConnectedDevice {
+            serial: serial.to_string(),
+            mode,
+            status: status.to_string(),
+            product: None,
+            current_slot: None,
+        };
+
+        if let Ok(model) = self.get_prop(serial, "ro.product.model").await {
+            dev.product = Some(model);
+        }
+        dev
+    }
+
     pub async fn shell(&self, serial: &str, command: &str) -> Result<String> {
         self.capture(&["-s", serial, "shell", command]).await
     }
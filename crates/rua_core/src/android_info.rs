@@ -0,0 +1,198 @@
+use crate::error::{FlashError, Result};
+use crate::fastboot::FastbootClient;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const ANDROID_INFO_FILE: &str = "android-info.txt";
+
+/// android-info.txt 中的一条约束，例如 `require board=foo|bar`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub name: String,
+    pub values: Vec<String>,
+    /// `reject` 行：设备值命中任一取值时失败
+    pub reject: bool,
+    /// `require-for-product:<product>` 行：仅对该产品生效
+    pub for_product: Option<String>,
+}
+
+impl Requirement {
+    /// 与 AOSP fastboot 一致的别名：board 对应 product，bootloader/baseband 对应 version-*
+    pub fn getvar_name(&self) -> String {
+        match self.name.as_str() {
+            "board" => "product".to_string(),
+            "bootloader" => "version-bootloader".to_string(),
+            "baseband" => "version-baseband".to_string(),
+            other => other.to_string(),
+        }
+    }
+
+    pub fn is_partition_exists(&self) -> bool {
+        self.name == "partition-exists"
+    }
+
+    /// 任一取值匹配即视为命中，以 `*` 结尾的取值按前缀匹配，均不区分大小写
+    pub fn value_matches(&self, actual: &str) -> bool {
+        let actual = actual.trim().to_lowercase();
+        self.values.iter().any(|v| {
+            let v = v.to_lowercase();
+            match v.strip_suffix('*') {
+                Some(prefix) => actual.starts_with(prefix),
+                None => actual == v,
+            }
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let mut s = format!(
+            "{} {}={}",
+            if self.reject { "reject" } else { "require" },
+            self.name,
+            self.values.join("|")
+        );
+        if let Some(p) = &self.for_product {
+            s.push_str(&format!(" (仅 {})", p));
+        }
+        s
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequirementCheck {
+    pub requirement: Requirement,
+    pub actual: Option<String>,
+    pub passed: bool,
+    /// require-for-product 与当前设备不符时跳过
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AndroidInfo {
+    pub requirements: Vec<Requirement>,
+}
+
+impl AndroidInfo {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut requirements = Vec::new();
+        for (lineno, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (head, body) = match line.split_once(char::is_whitespace) {
+                Some((head, body)) => (head, body.trim()),
+                None => continue,
+            };
+            let (reject, for_product) = if head == "require" {
+                (false, None)
+            } else if head == "reject" {
+                (true, None)
+            } else if let Some(product) = head.strip_prefix("require-for-product:") {
+                (false, Some(product.to_string()))
+            } else {
+                continue;
+            };
+            let Some((name, values)) = body.split_once('=') else {
+                return Err(FlashError::InvalidChoice(format!(
+                    "android-info.txt 第 {} 行格式错误: {}",
+                    lineno + 1,
+                    line
+                )));
+            };
+            let values: Vec<String> = values.split('|').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+            if values.is_empty() {
+                return Err(FlashError::InvalidChoice(format!("android-info.txt 第 {} 行缺少取值: {}", lineno + 1, line)));
+            }
+            requirements.push(Requirement { name: name.trim().to_lowercase(), values, reject, for_product });
+        }
+        Ok(Self { requirements })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn find_in_dir(dir: &Path) -> Option<PathBuf> {
+        let path = dir.join(ANDROID_INFO_FILE);
+        path.is_file().then_some(path)
+    }
+
+    /// 通过 getvar 逐条检查约束，返回每一条的检查结果
+    pub async fn check(&self, client: &FastbootClient, serial: &str) -> Vec<RequirementCheck> {
+        let product = client.get_var(serial, "product").await.ok();
+        let mut results = Vec::with_capacity(self.requirements.len());
+
+        for req in &self.requirements {
+            if let Some(target) = &req.for_product
+                && !product.as_deref().is_some_and(|p| p.eq_ignore_ascii_case(target))
+            {
+                results.push(RequirementCheck { requirement: req.clone(), actual: product.clone(), passed: true, skipped: true });
+                continue;
+            }
+
+            let (actual, matched) = if req.is_partition_exists() {
+                // has-slot 对存在的分区返回 yes/no，不存在的分区返回错误
+                let mut missing = Vec::new();
+                for partition in &req.values {
+                    match client.get_var(serial, &format!("has-slot:{}", partition)).await {
+                        Ok(v) if v == "yes" || v == "no" => {}
+                        _ => missing.push(partition.clone()),
+                    }
+                }
+                if missing.is_empty() {
+                    (Some("存在".to_string()), true)
+                } else {
+                    (Some(format!("缺少 {}", missing.join(","))), false)
+                }
+            } else {
+                match client.get_var(serial, &req.getvar_name()).await {
+                    Ok(v) => {
+                        let matched = req.value_matches(&v);
+                        (Some(v), matched)
+                    }
+                    Err(_) => (None, false),
+                }
+            };
+
+            let passed = if req.reject { actual.is_some() && !matched } else { matched };
+            results.push(RequirementCheck { requirement: req.clone(), actual, passed, skipped: false });
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requirements() {
+        let text = "require board=oriole|raven\n\
+                    require version-bootloader=slider-1.2-9152140\n\
+                    require-for-product:raven version-baseband=g5123b-*\n\
+                    reject version-baseband=g5123b-0001\n\
+                    require partition-exists=vendor_kernel_boot\n\
+                    board=ignored\n";
+        let info = AndroidInfo::parse(text).unwrap();
+        assert_eq!(info.requirements.len(), 5);
+        assert_eq!(info.requirements[0].getvar_name(), "product");
+        assert_eq!(info.requirements[0].values, vec!["oriole", "raven"]);
+        assert_eq!(info.requirements[2].for_product.as_deref(), Some("raven"));
+        assert!(info.requirements[3].reject);
+        assert!(info.requirements[4].is_partition_exists());
+    }
+
+    #[test]
+    fn test_value_matches_wildcard() {
+        let info = AndroidInfo::parse("require version-baseband=g5123b-*|other").unwrap();
+        let req = &info.requirements[0];
+        assert!(req.value_matches("g5123b-102852-220720-B-8798946"));
+        assert!(req.value_matches("OTHER"));
+        assert!(!req.value_matches("g5300b-1"));
+    }
+
+    #[test]
+    fn test_parse_malformed_line() {
+        assert!(AndroidInfo::parse("require board").is_err());
+    }
+}
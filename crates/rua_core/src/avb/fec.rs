@@ -0,0 +1,191 @@
+use crate::error::{FlashError, Result};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom};
+
+/// 与 AOSP libfec 相同的参数：GF(2^8)，本原多项式 0x11d，RS(255, 255 - roots)，4K 交织
+const FEC_BLOCKSIZE: usize = 4096;
+const FEC_RSM: usize = 255;
+const FEC_MAGIC: u32 = 0xFECF_ECFE;
+const FEC_HEADER_SIZE: usize = 60;
+const GF_POLY: u32 = 0x11d;
+/// 每次读取的交织列数，内存占用约为 rs_n * CHUNK
+const CHUNK: usize = 64 * 1024;
+
+struct ReedSolomon {
+    alpha_to: [u8; 256],
+    index_of: [u8; 256],
+    /// 生成多项式，以指数形式存放
+    genpoly: Vec<u8>,
+    nroots: usize,
+}
+
+const A0: u8 = 255;
+
+fn modnn(x: usize) -> usize {
+    x % 255
+}
+
+impl ReedSolomon {
+    fn new(nroots: usize) -> Self {
+        let mut alpha_to = [0u8; 256];
+        let mut index_of = [0u8; 256];
+        index_of[0] = A0;
+        alpha_to[A0 as usize] = 0;
+        let mut sr = 1u32;
+        for (i, alpha) in alpha_to.iter_mut().enumerate().take(255) {
+            index_of[sr as usize] = i as u8;
+            *alpha = sr as u8;
+            sr <<= 1;
+            if sr & 0x100 != 0 {
+                sr ^= GF_POLY;
+            }
+            sr &= 0xff;
+        }
+
+        // fcr = 0, prim = 1：生成多项式的根为 alpha^0 .. alpha^(nroots-1)
+        let mut genpoly = vec![0u8; nroots + 1];
+        genpoly[0] = 1;
+        for (i, root) in (0..nroots).enumerate() {
+            genpoly[i + 1] = 1;
+            for j in (1..=i).rev() {
+                genpoly[j] = if genpoly[j] != 0 {
+                    genpoly[j - 1] ^ alpha_to[modnn(index_of[genpoly[j] as usize] as usize + root)]
+                } else {
+                    genpoly[j - 1]
+                };
+            }
+            genpoly[0] = alpha_to[modnn(index_of[genpoly[0] as usize] as usize + root)];
+        }
+        for g in genpoly.iter_mut() {
+            *g = index_of[*g as usize];
+        }
+        Self { alpha_to, index_of, genpoly, nroots }
+    }
+
+    fn encode(&self, data: &[u8], parity: &mut [u8]) {
+        let n = self.nroots;
+        parity.fill(0);
+        for &byte in data {
+            let feedback = self.index_of[(byte ^ parity[0]) as usize];
+            if feedback != A0 {
+                for (j, p) in parity.iter_mut().enumerate().skip(1) {
+                    *p ^= self.alpha_to[modnn(feedback as usize + self.genpoly[n - j] as usize)];
+                }
+            }
+            parity.copy_within(1.., 0);
+            parity[n - 1] = if feedback != A0 {
+                self.alpha_to[modnn(feedback as usize + self.genpoly[0] as usize)]
+            } else {
+                0
+            };
+        }
+    }
+}
+
+/// 与 `fec --print-fec-size` 一致：校验数据加上末尾 4K 的头部块
+pub fn fec_size(inp_size: u64, roots: u32) -> u64 {
+    let rs_n = (FEC_RSM - roots as usize) as u64;
+    let blocks = inp_size.div_ceil(FEC_BLOCKSIZE as u64);
+    let rounds = blocks.div_ceil(rs_n);
+    rounds * roots as u64 * FEC_BLOCKSIZE as u64 + FEC_BLOCKSIZE as u64
+}
+
+/// 为前 `inp_size` 字节（镜像 + 哈希树）生成 dm-verity FEC 数据，格式与 AOSP `fec --encode` 的输出相同
+pub fn generate_fec<R: Read + Seek>(reader: &mut R, inp_size: u64, roots: u32) -> Result<Vec<u8>> {
+    if !(2..=24).contains(&roots) {
+        return Err(FlashError::InvalidChoice(format!("FEC roots 需在 2~24 之间: {}", roots)));
+    }
+    let roots = roots as usize;
+    let rs_n = FEC_RSM - roots;
+    let rs = ReedSolomon::new(roots);
+    let blocks = inp_size.div_ceil(FEC_BLOCKSIZE as u64);
+    let rounds = blocks.div_ceil(rs_n as u64);
+    // 第 j 个数据符号取自偏移 j * stride + 列号，超出输入的部分视为 0
+    let stride = rounds * FEC_BLOCKSIZE as u64;
+
+    let mut fec = Vec::with_capacity((stride as usize) * roots + FEC_BLOCKSIZE);
+    let mut rows = vec![vec![0u8; CHUNK]; rs_n];
+    let mut data = vec![0u8; rs_n];
+    let mut parity = vec![0u8; roots];
+    let mut col = 0u64;
+    while col < stride {
+        let len = CHUNK.min((stride - col) as usize);
+        for (j, row) in rows.iter_mut().enumerate() {
+            let start = j as u64 * stride + col;
+            row[..len].fill(0);
+            if start < inp_size {
+                let n = len.min((inp_size - start) as usize);
+                reader.seek(SeekFrom::Start(start))?;
+                reader.read_exact(&mut row[..n])?;
+            }
+        }
+        for c in 0..len {
+            for (j, row) in rows.iter().enumerate() {
+                data[j] = row[c];
+            }
+            rs.encode(&data, &mut parity);
+            fec.extend_from_slice(&parity);
+        }
+        col += len as u64;
+    }
+
+    let digest = Sha256::digest(&fec);
+    let mut header = Vec::with_capacity(FEC_HEADER_SIZE);
+    header.extend_from_slice(&FEC_MAGIC.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(FEC_HEADER_SIZE as u32).to_le_bytes());
+    header.extend_from_slice(&(roots as u32).to_le_bytes());
+    header.extend_from_slice(&(fec.len() as u32).to_le_bytes());
+    header.extend_from_slice(&inp_size.to_le_bytes());
+    header.extend_from_slice(&digest);
+
+    // 头部块开头与末尾各存一份头部，libfec 任一份完好即可读取
+    let mut block = vec![0u8; FEC_BLOCKSIZE];
+    block[..FEC_HEADER_SIZE].copy_from_slice(&header);
+    block[FEC_BLOCKSIZE - FEC_HEADER_SIZE..].copy_from_slice(&header);
+    fec.extend_from_slice(&block);
+    Ok(fec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// 合法码字在生成多项式的每个根处取值都为 0
+    fn syndromes_zero(rs: &ReedSolomon, codeword: &[u8]) -> bool {
+        (0..rs.nroots).all(|root| {
+            codeword.iter().fold(0u8, |acc, &b| {
+                let shifted = if acc == 0 {
+                    0
+                } else {
+                    rs.alpha_to[modnn(rs.index_of[acc as usize] as usize + root)]
+                };
+                shifted ^ b
+            }) == 0
+        })
+    }
+
+    #[test]
+    fn test_rs_codeword() {
+        let rs = ReedSolomon::new(2);
+        let data: Vec<u8> = (0..253u32).map(|i| (i * 7 + 3) as u8).collect();
+        let mut parity = vec![0u8; 2];
+        rs.encode(&data, &mut parity);
+        let mut codeword = data.clone();
+        codeword.extend_from_slice(&parity);
+        assert!(syndromes_zero(&rs, &codeword));
+        codeword[10] ^= 1;
+        assert!(!syndromes_zero(&rs, &codeword));
+    }
+
+    #[test]
+    fn test_fec_size_and_header() {
+        let input = vec![0x5au8; 3 * FEC_BLOCKSIZE];
+        let fec = generate_fec(&mut Cursor::new(&input), input.len() as u64, 2).unwrap();
+        assert_eq!(fec.len() as u64, fec_size(input.len() as u64, 2));
+        let header = &fec[fec.len() - FEC_BLOCKSIZE..];
+        assert_eq!(&header[..4], &FEC_MAGIC.to_le_bytes());
+        assert_eq!(&header[..FEC_HEADER_SIZE], &header[FEC_BLOCKSIZE - FEC_HEADER_SIZE..]);
+    }
+}
@@ -0,0 +1,297 @@
+use super::fec::generate_fec;
+use super::parser::{AvbFooter, Descriptor, VbmetaImage};
+use super::{
+    FOOTER_SIZE, HashFooterOptions, be32, be64, build_footer, build_signed_vbmeta,
+    load_signing_key, random_salt, signed_output_path,
+};
+use crate::error::{FlashError, Result};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// hashtree footer 的参数；签名相关的元数据与 hash footer 共用
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashtreeFooterOptions {
+    /// partition_size 为 0 时不补齐，footer 紧跟在 vbmeta 之后（适用于 super 中的动态分区）
+    pub base: HashFooterOptions,
+    pub block_size: u32,
+    /// 0 表示不生成 FEC
+    pub fec_num_roots: u32,
+}
+
+impl HashtreeFooterOptions {
+    /// 默认值与 avbtool 相同：sha1、20 字节随机 salt、4K 块
+    pub fn new(partition_size: u64) -> Self {
+        let mut base = HashFooterOptions::new(partition_size);
+        base.hash_algorithm = "sha1".to_string();
+        base.salt = random_salt(20);
+        Self { base, block_size: 4096, fec_num_roots: 0 }
+    }
+
+    /// 沿用原始镜像 hashtree 描述符中的哈希算法、salt、块大小与 FEC 设置，其余元数据由
+    /// `HashFooterOptions::from_original` 读取。原始镜像没有 footer 时返回 None。
+    pub fn from_original(path: &Path, partition_name: &str) -> Result<Option<Self>> {
+        let Some(base) = HashFooterOptions::from_original(path, partition_name)? else {
+            return Ok(None);
+        };
+        // 没有 hashtree 描述符时不沿用 hash 描述符的算法与 salt，使用 hashtree 的默认值
+        let defaults = Self::new(0);
+        let base = HashFooterOptions { hash_algorithm: defaults.base.hash_algorithm, salt: defaults.base.salt, ..base };
+        let mut opts = Self { base, ..defaults };
+        let image = VbmetaImage::load(path)?;
+        let tree_desc = image
+            .descriptors
+            .iter()
+            .filter_map(|d| match d {
+                Descriptor::Hashtree(h) => Some(h),
+                _ => None,
+            })
+            .max_by_key(|h| h.partition_name == partition_name);
+        if let Some(h) = tree_desc {
+            opts.base.hash_algorithm = h.hash_algorithm.clone();
+            opts.base.salt = h.salt.clone();
+            opts.block_size = h.data_block_size;
+            opts.fec_num_roots = h.fec_num_roots;
+        }
+        Ok(Some(opts))
+    }
+}
+
+fn hash_block<D: Digest>(salt: &[u8], data: &[u8], block_size: usize) -> Vec<u8> {
+    let mut hasher = D::new();
+    hasher.update(salt);
+    hasher.update(data);
+    if data.len() < block_size {
+        hasher.update(vec![0u8; block_size - data.len()]);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// 对一层数据逐块求哈希，摘要补齐到 2 的幂，整层补齐到块大小
+fn hash_level<D: Digest, R: Read>(reader: &mut R, size: u64, block_size: usize, salt: &[u8]) -> Result<Vec<u8>> {
+    let digest_size = <D as Digest>::output_size();
+    let digest_padding = digest_size.next_power_of_two() - digest_size;
+    let mut level = Vec::new();
+    let mut buf = vec![0u8; block_size];
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = (block_size as u64).min(remaining) as usize;
+        reader.read_exact(&mut buf[..n])?;
+        level.extend_from_slice(&hash_block::<D>(salt, &buf[..n], block_size));
+        level.resize(level.len() + digest_padding, 0);
+        remaining -= n as u64;
+    }
+    level.resize(level.len().div_ceil(block_size) * block_size, 0);
+    Ok(level)
+}
+
+fn build_tree<D: Digest, R: Read>(reader: &mut R, image_size: u64, block_size: usize, salt: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if image_size <= block_size as u64 {
+        let mut data = vec![0u8; image_size as usize];
+        reader.read_exact(&mut data)?;
+        return Ok((hash_block::<D>(salt, &data, block_size), Vec::new()));
+    }
+    let mut levels = vec![hash_level::<D, _>(reader, image_size, block_size, salt)?];
+    while let Some(last) = levels.last().filter(|l| l.len() > block_size) {
+        let next = hash_level::<D, _>(&mut last.as_slice(), last.len() as u64, block_size, salt)?;
+        levels.push(next);
+    }
+    let root = hash_block::<D>(salt, levels.last().unwrap(), block_size);
+    // 与 dm-verity 一致，树中最上层在前
+    let tree = levels.into_iter().rev().flatten().collect();
+    Ok((root, tree))
+}
+
+/// 计算 dm-verity 哈希树，返回 (根摘要, 哈希树)。镜像不超过一个块时哈希树为空。
+pub fn build_hash_tree<R: Read>(
+    reader: &mut R,
+    image_size: u64,
+    block_size: u32,
+    hash_algorithm: &str,
+    salt: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    if block_size == 0 || !block_size.is_power_of_two() {
+        return Err(FlashError::InvalidChoice(format!("块大小必须是 2 的幂: {}", block_size)));
+    }
+    let block_size = block_size as usize;
+    match hash_algorithm {
+        "sha1" => build_tree::<Sha1, _>(reader, image_size, block_size, salt),
+        "sha256" => build_tree::<Sha256, _>(reader, image_size, block_size, salt),
+        "sha512" => build_tree::<Sha512, _>(reader, image_size, block_size, salt),
+        other => Err(FlashError::InvalidChoice(format!("不支持的哈希树算法: {}", other))),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_hashtree_descriptor(
+    partition_name: &str,
+    image_size: u64,
+    tree_offset: u64,
+    tree_size: u64,
+    block_size: u32,
+    fec: (u32, u64, u64),
+    hash_algorithm: &str,
+    salt: &[u8],
+    root_digest: &[u8],
+) -> Vec<u8> {
+    let (fec_num_roots, fec_offset, fec_size) = fec;
+    let mut body = Vec::with_capacity(164 + partition_name.len() + salt.len() + root_digest.len());
+    body.extend_from_slice(&be32(1));
+    body.extend_from_slice(&be64(image_size));
+    body.extend_from_slice(&be64(tree_offset));
+    body.extend_from_slice(&be64(tree_size));
+    body.extend_from_slice(&be32(block_size));
+    body.extend_from_slice(&be32(block_size));
+    body.extend_from_slice(&be32(fec_num_roots));
+    body.extend_from_slice(&be64(fec_offset));
+    body.extend_from_slice(&be64(fec_size));
+    let mut algo = [0u8; 32];
+    algo[..hash_algorithm.len()].copy_from_slice(hash_algorithm.as_bytes());
+    body.extend_from_slice(&algo);
+    body.extend_from_slice(&be32(partition_name.len() as u32));
+    body.extend_from_slice(&be32(salt.len() as u32));
+    body.extend_from_slice(&be32(root_digest.len() as u32));
+    body.extend_from_slice(&be32(0));
+    body.extend_from_slice(&[0u8; 60]);
+    body.extend_from_slice(partition_name.as_bytes());
+    body.extend_from_slice(salt);
+    body.extend_from_slice(root_digest);
+    while body.len() % 8 != 0 {
+        body.push(0);
+    }
+    let mut desc = Vec::with_capacity(16 + body.len());
+    desc.extend_from_slice(&be64(1));
+    desc.extend_from_slice(&be64(body.len() as u64));
+    desc.extend_from_slice(&body);
+    desc
+}
+
+/// 镜像末尾已有 AVB footer 时返回原始数据长度，否则返回文件长度
+fn original_data_size(file: &mut File) -> Result<u64> {
+    let len = file.metadata()?.len();
+    if len < FOOTER_SIZE as u64 {
+        return Ok(len);
+    }
+    let mut raw = [0u8; FOOTER_SIZE];
+    file.seek(SeekFrom::Start(len - FOOTER_SIZE as u64))?;
+    file.read_exact(&mut raw)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(AvbFooter::parse(&raw)
+        .map(|f| f.original_image_size)
+        .filter(|&size| size <= len)
+        .unwrap_or(len))
+}
+
+/// 为文件系统镜像（system/vendor/product 等）生成 dm-verity 哈希树、可选的 FEC 并签名。
+/// 布局与 avbtool 相同：镜像 | 哈希树 | FEC | vbmeta | 填充 | footer。镜像已带 footer 时先去掉旧的。
+/// 结果写在原镜像所在目录的 `<原文件名>.signed.img`，返回其路径
+pub async fn add_hashtree_footer(
+    image_path: &str,
+    partition_name: &str,
+    key_pem_path: &str,
+    algorithm: &str,
+    opts: &HashtreeFooterOptions,
+) -> Result<String> {
+    let block_size = opts.block_size as u64;
+    let priv_key = load_signing_key(key_pem_path)?;
+
+    let mut input = File::open(image_path)?;
+    let data_size = original_data_size(&mut input)?;
+    let out_path = signed_output_path(image_path);
+    let mut out = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&out_path)?;
+    io::copy(&mut input.take(data_size), &mut out)?;
+    // dm-verity 按块校验，镜像补齐到块大小
+    let image_size = data_size.div_ceil(block_size) * block_size;
+    out.set_len(image_size)?;
+
+    out.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::with_capacity(1024 * 1024, &mut out).take(image_size);
+    let (root_digest, tree) =
+        build_hash_tree(&mut reader, image_size, opts.block_size, &opts.base.hash_algorithm, &opts.base.salt)?;
+    let tree_offset = image_size;
+    let tree_size = tree.len() as u64;
+    out.seek(SeekFrom::Start(tree_offset))?;
+    out.write_all(&tree)?;
+
+    let fec = if opts.fec_num_roots > 0 {
+        let fec_offset = tree_offset + tree_size;
+        let fec_data = generate_fec(&mut out, fec_offset, opts.fec_num_roots)?;
+        out.seek(SeekFrom::Start(fec_offset))?;
+        out.write_all(&fec_data)?;
+        (opts.fec_num_roots, fec_offset, fec_data.len() as u64)
+    } else {
+        (0, 0, 0)
+    };
+
+    let hashtree_descriptor = build_hashtree_descriptor(
+        partition_name,
+        image_size,
+        tree_offset,
+        tree_size,
+        opts.block_size,
+        fec,
+        &opts.base.hash_algorithm,
+        &opts.base.salt,
+        &root_digest,
+    );
+    let descriptors = opts.base.with_properties(hashtree_descriptor);
+    let vbmeta = build_signed_vbmeta(
+        &descriptors,
+        priv_key,
+        algorithm,
+        opts.base.rollback_index,
+        opts.base.rollback_index_location,
+        opts.base.flags,
+        &opts.base.release_string,
+    )?;
+
+    // 哈希树与 FEC 都按块对齐，vbmeta 紧随其后
+    let vbmeta_offset = out.seek(SeekFrom::End(0))?;
+    let vbmeta_end = vbmeta_offset + vbmeta.len() as u64;
+    let partition_size = if opts.base.partition_size == 0 {
+        (vbmeta_end + FOOTER_SIZE as u64).div_ceil(block_size) * block_size
+    } else {
+        opts.base.partition_size
+    };
+    if vbmeta_end + FOOTER_SIZE as u64 > partition_size {
+        drop(out);
+        let _ = fs::remove_file(&out_path);
+        return Err(FlashError::PatchError(format!(
+            "镜像加上哈希树与 vbmeta 共 {} 字节，超出分区大小 {}",
+            vbmeta_end + FOOTER_SIZE as u64,
+            partition_size
+        )));
+    }
+    out.write_all(&vbmeta)?;
+    out.set_len(partition_size - FOOTER_SIZE as u64)?;
+    out.seek(SeekFrom::End(0))?;
+    out.write_all(&build_footer(image_size, vbmeta_offset, vbmeta.len() as u64))?;
+    Ok(out_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_tree_layout() {
+        // 200 个 4K 块：第 0 层 200 * 32 字节占 2 个块，第 1 层 1 个块
+        let data = vec![0xa5u8; 200 * 4096];
+        let (root, tree) = build_hash_tree(&mut data.as_slice(), data.len() as u64, 4096, "sha256", b"salt").unwrap();
+        assert_eq!(tree.len(), 3 * 4096);
+        assert_eq!(root, hash_block::<Sha256>(b"salt", &tree[..4096], 4096));
+        let leaf = hash_block::<Sha256>(b"salt", &data[..4096], 4096);
+        assert_eq!(&tree[4096..4096 + 32], leaf.as_slice());
+    }
+
+    #[test]
+    fn test_sha1_digest_padding() {
+        let data = vec![0u8; 2 * 4096];
+        let (_, tree) = build_hash_tree(&mut data.as_slice(), data.len() as u64, 4096, "sha1", &[]).unwrap();
+        assert_eq!(tree.len(), 4096);
+        assert_eq!(&tree[20..32], &[0u8; 12]);
+        assert_eq!(&tree[..20], &tree[32..52]);
+    }
+}
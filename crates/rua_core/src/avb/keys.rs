@@ -0,0 +1,144 @@
+use super::{build_public_key_blob, rsa_public_key_from_pem};
+use super::parser::{Algorithm, hex};
+use crate::error::{FlashError, Result};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// AVB 支持的 RSA 密钥长度
+pub const SUPPORTED_KEY_BITS: [usize; 3] = [2048, 4096, 8192];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvbKeyKind {
+    Private,
+    Public,
+}
+
+/// 按内容识别的 PEM 密钥，不依赖文件名
+#[derive(Debug, Clone)]
+pub struct AvbKey {
+    pub path: PathBuf,
+    pub kind: AvbKeyKind,
+    pub public: RsaPublicKey,
+}
+
+impl AvbKey {
+    /// 解析 PKCS#1 / PKCS#8 私钥或 SPKI / PKCS#1 公钥
+    pub fn from_pem(path: &Path, pem_txt: &str) -> Result<Self> {
+        let (kind, public) = if let Ok(key) =
+            RsaPrivateKey::from_pkcs1_pem(pem_txt).or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem_txt))
+        {
+            (AvbKeyKind::Private, key.to_public_key())
+        } else {
+            let key = rsa_public_key_from_pem(pem_txt)
+                .ok_or_else(|| FlashError::PatchError(format!("{} 不是 RSA 公钥或私钥", path.display())))?;
+            (AvbKeyKind::Public, key)
+        };
+        Ok(Self { path: path.to_path_buf(), kind, public })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_pem(path, &fs::read_to_string(path)?)
+    }
+
+    /// 生成新的 RSA 私钥（指数 65537）并以 PKCS#8 PEM 写入 `path`
+    pub fn generate(bits: usize, path: &Path) -> Result<Self> {
+        if !SUPPORTED_KEY_BITS.contains(&bits) {
+            return Err(FlashError::InvalidChoice(format!("AVB 只支持 2048/4096/8192 位密钥: {}", bits)));
+        }
+        let key = RsaPrivateKey::new(&mut rand::rngs::OsRng, bits)
+            .map_err(|e| FlashError::PatchError(format!("生成密钥失败: {}", e)))?;
+        let pem = key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(|e| FlashError::PatchError(format!("导出密钥失败: {}", e)))?;
+        fs::write(path, pem.as_bytes())?;
+        Ok(Self { path: path.to_path_buf(), kind: AvbKeyKind::Private, public: key.to_public_key() })
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.kind == AvbKeyKind::Private
+    }
+
+    pub fn bits(&self) -> usize {
+        self.public.size() * 8
+    }
+
+    /// 按模数长度选择的默认签名算法 (SHA256_RSA2048/4096/8192)
+    pub fn default_algorithm(&self) -> Option<Algorithm> {
+        Algorithm::for_key_bits(self.bits(), false)
+    }
+
+    /// AVB 公钥格式，与 `avbtool extract_public_key` 输出一致，可直接刷入 avb_custom_key
+    pub fn public_key_blob(&self) -> Vec<u8> {
+        build_public_key_blob(&self.public)
+    }
+
+    /// 公钥数据的 sha1，与 `avbtool info_image` 的 Public key (sha1) 相同
+    pub fn fingerprint(&self) -> String {
+        hex(&Sha1::digest(self.public_key_blob()))
+    }
+
+    /// 按 4 位一组、大写显示的指纹，便于与 bootloader 黄色警告界面上的 ID 逐段比对
+    pub fn display_fingerprint(&self) -> String {
+        self.fingerprint()
+            .to_uppercase()
+            .as_bytes()
+            .chunks(4)
+            .map(|c| String::from_utf8_lossy(c).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn write_public_key_blob(&self, output: &Path) -> Result<()> {
+        fs::write(output, self.public_key_blob())?;
+        Ok(())
+    }
+
+    pub fn write_public_pem(&self, output: &Path) -> Result<()> {
+        let pem = self
+            .public
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| FlashError::PatchError(format!("导出公钥失败: {}", e)))?;
+        fs::write(output, pem)?;
+        Ok(())
+    }
+}
+
+/// 列出目录中所有能解析为 RSA 密钥的 .pem，无法解析的文件直接忽略
+pub fn list_keys(dir: &Path) -> Vec<AvbKey> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut pems: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("pem")))
+        .collect();
+    pems.sort();
+    pems.iter().filter_map(|p| AvbKey::load(p).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn avbkey_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../avbkey")
+    }
+
+    #[test]
+    fn test_detect_key_kind_by_content() {
+        let private = AvbKey::load(&avbkey_dir().join("testkey_rsa4096.pem")).unwrap();
+        let public = AvbKey::load(&avbkey_dir().join("testkey_rsa4096_pub.pem")).unwrap();
+        assert!(private.is_private());
+        assert!(!public.is_private());
+        assert_eq!(private.bits(), 4096);
+        assert_eq!(private.public_key_blob(), public.public_key_blob());
+        // AOSP testkey_rsa4096 的公钥 sha1
+        assert_eq!(private.fingerprint(), "2597c218aae470a130f61162feaae70afd97f011");
+    }
+}
@@ -0,0 +1,682 @@
+use crate::error::{FlashError, Result};
+use num_bigint::BigUint;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::der::{Decode, Document};
+use rsa::pkcs8::{DecodePrivateKey, SubjectPublicKeyInfoRef};
+use rsa::traits::PublicKeyParts;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub mod fec;
+pub mod hashtree;
+pub mod keys;
+pub mod parser;
+pub mod vbmeta;
+pub mod verify;
+
+pub use hashtree::{HashtreeFooterOptions, add_hashtree_footer};
+pub use keys::{AvbKey, AvbKeyKind};
+pub use parser::{Algorithm, AvbFooter, Descriptor, VbmetaHeader, VbmetaImage};
+pub use vbmeta::{ChainPartition, MakeVbmetaOptions, make_vbmeta_image};
+pub use verify::{CheckStatus, PartitionCheck, VerifyOptions, VerifyReport};
+
+const FOOTER_SIZE: usize = 64;
+const VBMETA_HEADER_SIZE: usize = 256;
+const VBMETA_FLAGS_OFFSET: usize = 120;
+const AVB_MAGIC: &[u8; 4] = b"AVB0";
+const AVB_FOOTER_MAGIC: &[u8; 4] = b"AVBf";
+/// rsa crate 默认只接受 4096 位以内的公钥，AVB 还支持 8192 位
+const AVB_MAX_KEY_BITS: usize = 8192;
+
+/// vbmeta 头部 flags：关闭 dm-verity 哈希树校验
+pub const FLAG_HASHTREE_DISABLED: u32 = 1;
+/// vbmeta 头部 flags：关闭整个 AVB 校验
+pub const FLAG_VERIFICATION_DISABLED: u32 = 2;
+
+fn align_up(x: usize, a: usize) -> usize {
+    (x + a - 1) / a * a
+}
+
+fn be32(v: u32) -> [u8; 4] {
+    v.to_be_bytes()
+}
+fn be64(v: u64) -> [u8; 8] {
+    v.to_be_bytes()
+}
+
+fn build_public_key_blob(key: &impl PublicKeyParts) -> Vec<u8> {
+    let n = BigUint::from_bytes_be(&key.n().to_bytes_be());
+    let bits = n.bits() as u32;
+    let key_bytes = (bits as usize + 7) / 8;
+
+    let n_be = n.to_bytes_be();
+    let n0 = if n_be.is_empty() {
+        0u32
+    } else {
+        let mut tail = [0u8; 4];
+        for i in 0..4 {
+            let idx = n_be.len().saturating_sub(4 - i);
+            if idx > 0 && idx - 1 < n_be.len() {
+                tail[i] = n_be[n_be.len() - 4 + i];
+            }
+        }
+        u32::from_be_bytes(tail)
+    };
+    let modulus = 0x1_0000_0000u64;
+    fn egcd(a: i64, b: i64) -> (i64, i64, i64) {
+        if a == 0 {
+            (b, 0, 1)
+        } else {
+            let (g, x, y) = egcd(b % a, a);
+            (g, y - (b / a) * x, x)
+        }
+    }
+    let mut inv = 0u32;
+    if n0 != 0 {
+        let a = n0 as i64;
+        let m = modulus as i64;
+        let (g, x, _) = egcd(a.rem_euclid(m), m);
+        if g == 1 {
+            let xi = (x % m + m) % m;
+            let ninv = xi as u64;
+            let n0inv = ((modulus - ninv) % modulus) as u32;
+            inv = n0inv;
+        }
+    }
+    let one = BigUint::from(1u32);
+    let r = &one << bits;
+    let rr = (&r * &r) % &n;
+    let rr_be = rr.to_bytes_be();
+
+    let mut out = Vec::with_capacity(8 + key_bytes * 2);
+    out.extend_from_slice(&be32(bits));
+    out.extend_from_slice(&be32(inv));
+    if n_be.len() < key_bytes {
+        out.resize(out.len() + (key_bytes - n_be.len()), 0);
+    }
+    out.extend_from_slice(&n_be[n_be.len().saturating_sub(key_bytes)..]);
+    if rr_be.len() < key_bytes {
+        out.resize(out.len() + (key_bytes - rr_be.len()), 0);
+    }
+    out.extend_from_slice(&rr_be[rr_be.len().saturating_sub(key_bytes)..]);
+    out
+}
+
+/// 把 PEM 格式的 RSA 公钥或私钥转换为 AVB 公钥格式（与 `avbtool extract_public_key` 输出一致）
+pub fn public_key_blob_from_pem(pem_txt: &str) -> Result<Vec<u8>> {
+    if let Ok(priv_key) = RsaPrivateKey::from_pkcs1_pem(pem_txt).or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem_txt)) {
+        return Ok(build_public_key_blob(&priv_key));
+    }
+    let pub_key = rsa_public_key_from_pem(pem_txt)
+        .ok_or_else(|| FlashError::PatchError("parse rsa key failed: not an RSA public key".to_string()))?;
+    Ok(build_public_key_blob(&pub_key))
+}
+
+/// 解析 SPKI ("PUBLIC KEY") 或 PKCS#1 ("RSA PUBLIC KEY") 公钥。
+/// 手动解码 DER，以便放宽到 8192 位（`DecodePublicKey` 会拒绝 4096 位以上的模数）
+fn rsa_public_key_from_pem(pem_txt: &str) -> Option<RsaPublicKey> {
+    let (label, doc) = Document::from_pem(pem_txt).ok()?;
+    let spki;
+    let pkcs1_der = match label {
+        "PUBLIC KEY" => {
+            spki = SubjectPublicKeyInfoRef::from_der(doc.as_bytes()).ok()?;
+            spki.subject_public_key.as_bytes()?
+        }
+        "RSA PUBLIC KEY" => doc.as_bytes(),
+        _ => return None,
+    };
+    let key = rsa::pkcs1::RsaPublicKey::from_der(pkcs1_der).ok()?;
+    rsa_public_key(key.modulus.as_bytes(), key.public_exponent.as_bytes()).ok()
+}
+
+/// 由大端模数与指数构造公钥，允许最大 8192 位
+fn rsa_public_key(n: &[u8], e: &[u8]) -> rsa::errors::Result<RsaPublicKey> {
+    RsaPublicKey::new_with_max_size(rsa::BigUint::from_bytes_be(n), rsa::BigUint::from_bytes_be(e), AVB_MAX_KEY_BITS)
+}
+
+fn build_hash_descriptor(
+    partition_name: &str,
+    image_data: &[u8],
+    hash_algorithm: &str,
+    salt: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let digest = match hash_algorithm {
+        "sha256" => Sha256::new().chain_update(salt).chain_update(image_data).finalize().to_vec(),
+        "sha512" => Sha512::new().chain_update(salt).chain_update(image_data).finalize().to_vec(),
+        other => {
+            return Err(FlashError::PatchError(format!("unsupported hash algorithm: {}", other)));
+        }
+    };
+
+    let partition_name_bytes = partition_name.as_bytes();
+    let name_len = partition_name_bytes.len() as u32;
+    let salt_len = salt.len() as u32;
+    let digest_len = digest.len() as u32;
+
+    let parent_size = 16usize;
+    let hash_desc_fixed_size = 8 + 32 + 4 + 4 + 4 + 4 + 60;
+    let mut num_following = (hash_desc_fixed_size + name_len as usize + salt_len as usize + digest_len as usize) as u64;
+    if num_following % 8 != 0 {
+        num_following += 8 - (num_following % 8);
+    }
+
+    let mut desc = Vec::with_capacity(parent_size + num_following as usize);
+    desc.extend_from_slice(&be64(2));
+    desc.extend_from_slice(&be64(num_following));
+    desc.extend_from_slice(&be64(image_data.len() as u64));
+    let mut algo = [0u8; 32];
+    let s = hash_algorithm.as_bytes();
+    algo[..s.len()].copy_from_slice(s);
+    desc.extend_from_slice(&algo);
+    desc.extend_from_slice(&be32(name_len));
+    desc.extend_from_slice(&be32(salt_len));
+    desc.extend_from_slice(&be32(digest_len));
+    desc.extend_from_slice(&be32(0));
+    desc.extend_from_slice(&[0u8; 60]);
+    desc.extend_from_slice(partition_name_bytes);
+    desc.extend_from_slice(salt);
+    desc.extend_from_slice(&digest);
+    while desc.len() % 8 != 0 {
+        desc.push(0);
+    }
+    Ok((desc, digest))
+}
+
+fn build_property_descriptor(key: &str, value: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(16 + key.len() + value.len() + 2);
+    body.extend_from_slice(&be64(key.len() as u64));
+    body.extend_from_slice(&be64(value.len() as u64));
+    body.extend_from_slice(key.as_bytes());
+    body.push(0);
+    body.extend_from_slice(value);
+    body.push(0);
+    while body.len() % 8 != 0 {
+        body.push(0);
+    }
+    let mut desc = Vec::with_capacity(16 + body.len());
+    desc.extend_from_slice(&be64(0));
+    desc.extend_from_slice(&be64(body.len() as u64));
+    desc.extend_from_slice(&body);
+    desc
+}
+
+/// 新 vbmeta 中除镜像内容以外的元数据；重新签名已有镜像时应从原始 footer 读取
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashFooterOptions {
+    pub partition_size: u64,
+    /// hash 描述符支持 sha256 / sha512，hashtree 另外支持 sha1
+    pub hash_algorithm: String,
+    pub salt: Vec<u8>,
+    pub rollback_index: u64,
+    pub rollback_index_location: u32,
+    pub flags: u32,
+    pub release_string: String,
+    pub properties: Vec<(String, Vec<u8>)>,
+    /// 属性描述符写在 hash / hashtree 描述符之前；沿用原始镜像中描述符的先后顺序
+    pub properties_first: bool,
+}
+
+impl HashFooterOptions {
+    /// 默认使用 sha256 与 32 字节随机 salt
+    pub fn new(partition_size: u64) -> Self {
+        Self {
+            partition_size,
+            hash_algorithm: "sha256".to_string(),
+            salt: random_salt(32),
+            rollback_index: 0,
+            rollback_index_location: 0,
+            flags: 0,
+            release_string: "rua_avb 1.0".to_string(),
+            properties: Vec::new(),
+            properties_first: false,
+        }
+    }
+
+    /// 读取原始镜像的 AVB footer：分区大小取原始文件大小（footer 总是位于分区末尾），
+    /// 并沿用回滚索引、flags、release string 与属性描述符（保持原有顺序）。
+    /// 哈希算法与 salt 只取自 hash 描述符，没有 hash 描述符时使用 sha256 与随机 salt。
+    /// 原始镜像没有 footer 时返回 None。
+    pub fn from_original(path: &Path, partition_name: &str) -> Result<Option<Self>> {
+        let image = match VbmetaImage::load(path) {
+            Ok(image) if image.footer.is_some() => image,
+            _ => return Ok(None),
+        };
+        let mut opts = Self::new(fs::metadata(path)?.len());
+        let hash = image
+            .descriptors
+            .iter()
+            .filter_map(|d| match d {
+                Descriptor::Hash(h) => Some(h),
+                _ => None,
+            })
+            .max_by_key(|h| h.partition_name == partition_name);
+        if let Some(h) = hash {
+            opts.hash_algorithm = h.hash_algorithm.clone();
+            opts.salt = h.salt.clone();
+        }
+        opts.rollback_index = image.header.rollback_index;
+        opts.rollback_index_location = image.header.rollback_index_location;
+        opts.flags = image.header.flags;
+        opts.release_string = image.header.release_string.clone();
+        opts.properties = image
+            .properties()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_vec()))
+            .collect();
+        let position = |f: fn(&Descriptor) -> bool| image.descriptors.iter().position(f);
+        opts.properties_first = matches!(
+            (
+                position(|d| matches!(d, Descriptor::Property { .. })),
+                position(|d| matches!(d, Descriptor::Hash(_) | Descriptor::Hashtree(_))),
+            ),
+            (Some(prop), Some(hash)) if prop < hash
+        );
+        Ok(Some(opts))
+    }
+
+    /// 按 `properties_first` 把属性描述符与 hash / hashtree 描述符拼接在一起
+    fn with_properties(&self, hash_descriptor: Vec<u8>) -> Vec<u8> {
+        let properties: Vec<u8> = self
+            .properties
+            .iter()
+            .flat_map(|(key, value)| build_property_descriptor(key, value))
+            .collect();
+        if self.properties_first {
+            [properties, hash_descriptor].concat()
+        } else {
+            [hash_descriptor, properties].concat()
+        }
+    }
+}
+
+/// 与 avbtool 一致，未指定 salt 时使用与摘要等长的随机 salt
+pub fn random_salt(len: usize) -> Vec<u8> {
+    use rand::RngCore;
+    let mut salt = vec![0u8; len];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// 去掉镜像末尾已有的 AVB footer 及其 vbmeta，只保留原始镜像内容
+pub fn strip_footer(data: &mut Vec<u8>) -> Option<AvbFooter> {
+    if data.len() < FOOTER_SIZE {
+        return None;
+    }
+    let footer = AvbFooter::parse(&data[data.len() - FOOTER_SIZE..])?;
+    if footer.original_image_size > data.len() as u64 {
+        return None;
+    }
+    data.truncate(footer.original_image_size as usize);
+    Some(footer)
+}
+
+fn load_signing_key(key_pem_path: &str) -> Result<RsaPrivateKey> {
+    let pem_txt = fs::read_to_string(key_pem_path)
+        .map_err(|e| FlashError::PatchError(format!("read key failed: {:?}", e)))?;
+    RsaPrivateKey::from_pkcs1_pem(&pem_txt)
+        .or_else(|_| RsaPrivateKey::from_pkcs8_pem(&pem_txt))
+        .map_err(|e| match AvbKey::from_pem(Path::new(key_pem_path), &pem_txt) {
+            Ok(key) if !key.is_private() => FlashError::PatchError("invalid key: public key not allowed".to_string()),
+            _ => FlashError::PatchError(format!("parse rsa key failed: {:?}", e)),
+        })
+}
+
+/// 组装并签名一个完整的 vbmeta 结构：头部 + 认证块 + 辅助块（公钥与描述符）
+fn build_signed_vbmeta(
+    descriptors: &[u8],
+    priv_key: RsaPrivateKey,
+    algorithm: &str,
+    rollback_index: u64,
+    rollback_index_location: u32,
+    flags: u32,
+    release_string: &str,
+) -> Result<Vec<u8>> {
+    let pubkey_blob = build_public_key_blob(&priv_key);
+    let pubkey_offset = 0u64;
+    let pubkey_size = pubkey_blob.len() as u64;
+    let descriptors_offset = align_up(pubkey_blob.len(), 8) as u64;
+    let desc_size = descriptors.len() as u64;
+
+    let mut aux = Vec::with_capacity(align_up(
+        (descriptors_offset + desc_size) as usize,
+        64,
+    ));
+    aux.extend_from_slice(&pubkey_blob);
+    while aux.len() < descriptors_offset as usize {
+        aux.push(0);
+    }
+    aux.extend_from_slice(descriptors);
+    while aux.len() % 64 != 0 {
+        aux.push(0);
+    }
+    let aux_size = aux.len() as u64;
+
+    let algo = Algorithm::from_name(algorithm)
+        .filter(|a| *a != Algorithm::None)
+        .ok_or_else(|| FlashError::PatchError(format!("unsupported algorithm: {}", algorithm)))?;
+    let key_bits = priv_key.size() * 8;
+    if key_bits != algo.key_bits() {
+        return Err(FlashError::PatchError(format!(
+            "algorithm {} requires a {}-bit key, got {}-bit",
+            algo.name(),
+            algo.key_bits(),
+            key_bits
+        )));
+    }
+    let algo_type = algo.as_u32();
+    let hash_len = algo.hash_len();
+    let sig_len = algo.signature_len();
+
+    let authentication_data_block_size = align_up(hash_len + sig_len, 64) as u64;
+    let auxiliary_data_block_size = aux_size;
+    let hash_offset = 0u64;
+    let hash_size = hash_len as u64;
+    let signature_offset = hash_size;
+    let signature_size = sig_len as u64;
+    let public_key_offset = pubkey_offset;
+    let public_key_size = pubkey_size;
+    let public_key_metadata_offset = 0u64;
+    let public_key_metadata_size = 0u64;
+    let descriptors_off = descriptors_offset;
+    let descriptors_size = desc_size;
+    // rollback_index_location 需要 libavb 1.2
+    let required_minor = if rollback_index_location != 0 { 2 } else { 0 };
+    let mut release_string = release_string.as_bytes().to_vec();
+    release_string.truncate(47);
+
+    let mut header = vec![0u8; VBMETA_HEADER_SIZE];
+    header[0..4].copy_from_slice(AVB_MAGIC);
+    header[4..8].copy_from_slice(&be32(1));
+    header[8..12].copy_from_slice(&be32(required_minor));
+    header[12..20].copy_from_slice(&be64(authentication_data_block_size));
+    header[20..28].copy_from_slice(&be64(auxiliary_data_block_size));
+    header[28..32].copy_from_slice(&be32(algo_type));
+    header[32..40].copy_from_slice(&be64(hash_offset));
+    header[40..48].copy_from_slice(&be64(hash_size));
+    header[48..56].copy_from_slice(&be64(signature_offset));
+    header[56..64].copy_from_slice(&be64(signature_size));
+    header[64..72].copy_from_slice(&be64(public_key_offset));
+    header[72..80].copy_from_slice(&be64(public_key_size));
+    header[80..88].copy_from_slice(&be64(public_key_metadata_offset));
+    header[88..96].copy_from_slice(&be64(public_key_metadata_size));
+    header[96..104].copy_from_slice(&be64(descriptors_off));
+    header[104..112].copy_from_slice(&be64(descriptors_size));
+    header[112..120].copy_from_slice(&be64(rollback_index));
+    header[120..124].copy_from_slice(&be32(flags));
+    header[124..128].copy_from_slice(&be32(rollback_index_location));
+    header[128..128 + release_string.len()].copy_from_slice(&release_string);
+
+    let (vbmeta_digest, scheme) = if hash_len == 32 {
+        let digest = Sha256::new().chain_update(&header).chain_update(&aux).finalize().to_vec();
+        (digest, Pkcs1v15Sign::new::<Sha256>())
+    } else {
+        let digest = Sha512::new().chain_update(&header).chain_update(&aux).finalize().to_vec();
+        (digest, Pkcs1v15Sign::new::<Sha512>())
+    };
+    let signature_bytes = priv_key
+        .sign_with_rng(&mut rand::rngs::OsRng, scheme, &vbmeta_digest)
+        .map_err(|e| FlashError::PatchError(format!("sign failed: {:?}", e)))?;
+    if signature_bytes.len() != sig_len {
+        return Err(FlashError::PatchError(
+            "signature length mismatch".to_string(),
+        ));
+    }
+
+    let mut auth = Vec::with_capacity(align_up(hash_len + sig_len, 64));
+    auth.extend_from_slice(&vbmeta_digest);
+    auth.extend_from_slice(&signature_bytes);
+    while auth.len() % 64 != 0 {
+        auth.push(0);
+    }
+
+    let mut vbmeta = Vec::with_capacity(header.len() + auth.len() + aux.len());
+    vbmeta.extend_from_slice(&header);
+    vbmeta.extend_from_slice(&auth);
+    vbmeta.extend_from_slice(&aux);
+    Ok(vbmeta)
+}
+
+fn build_footer(original_image_size: u64, vbmeta_offset: u64, vbmeta_size: u64) -> Vec<u8> {
+    let mut footer = vec![0u8; FOOTER_SIZE];
+    footer[0..4].copy_from_slice(AVB_FOOTER_MAGIC);
+    footer[4..8].copy_from_slice(&be32(1));
+    footer[8..12].copy_from_slice(&be32(0));
+    footer[12..20].copy_from_slice(&be64(original_image_size));
+    footer[20..28].copy_from_slice(&be64(vbmeta_offset));
+    footer[28..36].copy_from_slice(&be64(vbmeta_size));
+    footer
+}
+
+/// 签名结果写在原镜像所在目录，命名为 `<原文件名>.signed.img`
+fn signed_output_path(image_path: &str) -> String {
+    let path = Path::new(image_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("patched");
+    path.with_file_name(format!("{}.signed.img", stem)).to_string_lossy().to_string()
+}
+
+pub async fn add_hash_footer(
+    image_path: &str,
+    partition_name: &str,
+    partition_size_bytes: u64,
+    key_pem_path: &str,
+    algorithm: &str,
+) -> Result<String> {
+    // 保持旧版行为：不加 salt
+    let opts = HashFooterOptions { salt: Vec::new(), ..HashFooterOptions::new(partition_size_bytes) };
+    add_hash_footer_with_options(image_path, partition_name, key_pem_path, algorithm, &opts).await
+}
+
+/// 为镜像追加 hash footer 并签名。镜像已带 footer 时先去掉旧的；输出文件补齐到分区大小，footer 位于最后 64 字节。
+pub async fn add_hash_footer_with_options(
+    image_path: &str,
+    partition_name: &str,
+    key_pem_path: &str,
+    algorithm: &str,
+    opts: &HashFooterOptions,
+) -> Result<String> {
+    let partition_size_bytes = opts.partition_size;
+    let mut image = fs::read(image_path)
+        .map_err(|e| FlashError::PatchError(format!("read image failed: {:?}", e)))?;
+    strip_footer(&mut image);
+    let orig_size = image.len() as u64;
+    if orig_size > partition_size_bytes {
+        return Err(FlashError::PatchError(
+            "image larger than partition size".to_string(),
+        ));
+    }
+    let priv_key = load_signing_key(key_pem_path)?;
+
+    let (hash_descriptor, _digest) = build_hash_descriptor(partition_name, &image, &opts.hash_algorithm, &opts.salt)?;
+    let descriptors = opts.with_properties(hash_descriptor);
+    let vbmeta = build_signed_vbmeta(
+        &descriptors,
+        priv_key,
+        algorithm,
+        opts.rollback_index,
+        opts.rollback_index_location,
+        opts.flags,
+        &opts.release_string,
+    )?;
+    let vbmeta_size = vbmeta.len() as u64;
+
+    // 与 avbtool 一致，vbmeta 从原始镜像之后的下一个 4K 边界开始
+    let vbmeta_offset = align_up(orig_size as usize, 4096) as u64;
+    let total = vbmeta_offset + vbmeta_size + FOOTER_SIZE as u64;
+    if total > partition_size_bytes {
+        return Err(FlashError::PatchError(
+            "signed image would exceed partition size".to_string(),
+        ));
+    }
+
+    let footer = build_footer(orig_size, vbmeta_offset, vbmeta_size);
+    let out_path = signed_output_path(image_path);
+    let padding_before_vbmeta = vec![0u8; (vbmeta_offset - orig_size) as usize];
+    let padding_before_footer = vec![0u8; (partition_size_bytes - total) as usize];
+    let mut f = fs::File::create(&out_path)
+        .map_err(|e| FlashError::PatchError(format!("create out failed: {:?}", e)))?;
+    f.write_all(&image)
+        .and_then(|_| f.write_all(&padding_before_vbmeta))
+        .map_err(|e| FlashError::PatchError(format!("write image failed: {:?}", e)))?;
+    f.write_all(&vbmeta)
+        .and_then(|_| f.write_all(&padding_before_footer))
+        .map_err(|e| FlashError::PatchError(format!("write vbmeta failed: {:?}", e)))?;
+    f.write_all(&footer)
+        .map_err(|e| FlashError::PatchError(format!("write footer failed: {:?}", e)))?;
+    Ok(out_path)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VbmetaFlagsPatch {
+    pub output: PathBuf,
+    /// vbmeta 头在文件中的偏移，独立 vbmeta 镜像为 0，footer 内嵌时为 footer 中记录的偏移
+    pub vbmeta_offset: u64,
+    pub old_flags: u32,
+    pub new_flags: u32,
+}
+
+pub fn describe_vbmeta_flags(flags: u32) -> String {
+    let mut parts = Vec::new();
+    if flags & FLAG_HASHTREE_DISABLED != 0 {
+        parts.push("关闭 verity");
+    }
+    if flags & FLAG_VERIFICATION_DISABLED != 0 {
+        parts.push("关闭 verification");
+    }
+    if parts.is_empty() {
+        format!("{} (校验开启)", flags)
+    } else {
+        format!("{} ({})", flags, parts.join(" + "))
+    }
+}
+
+/// 定位 vbmeta 头：文件开头即为 AVB0，或者由镜像末尾的 AVBf footer 指向
+pub fn find_vbmeta_offset(data: &[u8]) -> Result<usize> {
+    if data.len() >= VBMETA_HEADER_SIZE && &data[0..4] == AVB_MAGIC {
+        return Ok(0);
+    }
+    if data.len() >= FOOTER_SIZE {
+        let footer = &data[data.len() - FOOTER_SIZE..];
+        if &footer[0..4] == AVB_FOOTER_MAGIC {
+            let offset = u64::from_be_bytes(footer[20..28].try_into().unwrap()) as usize;
+            if offset.checked_add(VBMETA_HEADER_SIZE).is_some_and(|end| end <= data.len())
+                && &data[offset..offset + 4] == AVB_MAGIC
+            {
+                return Ok(offset);
+            }
+            return Err(FlashError::PatchError("AVB footer 指向的位置不是有效的 vbmeta".to_string()));
+        }
+    }
+    Err(FlashError::PatchError("未找到 vbmeta 头 (AVB0) 或 AVB footer (AVBf)".to_string()))
+}
+
+/// 写出一份修改了 vbmeta 头部 flags 的副本，其余内容保持不变（与 fastboot --disable-verity 的做法相同，不重新签名）。
+/// `flags` 只影响低两位：1 关闭 verity，2 关闭 verification，3 两者都关闭。
+pub fn patch_vbmeta_flags(image_path: &Path, flags: u32, output_path: &Path) -> Result<VbmetaFlagsPatch> {
+    if flags > (FLAG_HASHTREE_DISABLED | FLAG_VERIFICATION_DISABLED) {
+        return Err(FlashError::InvalidChoice(format!("不支持的 vbmeta flags: {}", flags)));
+    }
+    let mut data = fs::read(image_path)?;
+    let offset = find_vbmeta_offset(&data)?;
+    let field = offset + VBMETA_FLAGS_OFFSET;
+    let old_flags = u32::from_be_bytes(data[field..field + 4].try_into().unwrap());
+    let new_flags = (old_flags & !(FLAG_HASHTREE_DISABLED | FLAG_VERIFICATION_DISABLED)) | flags;
+    data[field..field + 4].copy_from_slice(&be32(new_flags));
+    fs::write(output_path, &data)?;
+    Ok(VbmetaFlagsPatch { output: output_path.to_path_buf(), vbmeta_offset: offset as u64, old_flags, new_flags })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_vbmeta_flags() {
+        let dir = std::env::temp_dir().join(format!("rua_vbmeta_flags_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, out) = (dir.join("vbmeta.img"), dir.join("patched.img"));
+
+        // 独立 vbmeta: 只改低两位，其余 flags 位保持不变
+        let mut vbmeta = vec![0u8; VBMETA_HEADER_SIZE + 64];
+        vbmeta[..4].copy_from_slice(AVB_MAGIC);
+        vbmeta[VBMETA_FLAGS_OFFSET..VBMETA_FLAGS_OFFSET + 4].copy_from_slice(&be32(0x10 | FLAG_VERIFICATION_DISABLED));
+        fs::write(&src, &vbmeta).unwrap();
+        let patch = patch_vbmeta_flags(&src, FLAG_HASHTREE_DISABLED, &out).unwrap();
+        assert_eq!((patch.vbmeta_offset, patch.old_flags, patch.new_flags), (0, 0x12, 0x11));
+        let patched = fs::read(&out).unwrap();
+        assert_eq!(&patched[VBMETA_FLAGS_OFFSET..VBMETA_FLAGS_OFFSET + 4], &be32(0x11));
+        assert_eq!(patched[VBMETA_FLAGS_OFFSET + 4..], vbmeta[VBMETA_FLAGS_OFFSET + 4..]);
+
+        // 带 AVB footer 的分区镜像: 按 footer 定位 vbmeta
+        let mut image = vec![0u8; 8192];
+        image[4096..4096 + vbmeta.len()].copy_from_slice(&vbmeta);
+        let footer = image.len() - FOOTER_SIZE;
+        image[footer..footer + 4].copy_from_slice(AVB_FOOTER_MAGIC);
+        image[footer + 20..footer + 28].copy_from_slice(&4096u64.to_be_bytes());
+        fs::write(&src, &image).unwrap();
+        let patch = patch_vbmeta_flags(&src, FLAG_HASHTREE_DISABLED | FLAG_VERIFICATION_DISABLED, &out).unwrap();
+        assert_eq!((patch.vbmeta_offset, patch.new_flags), (4096, 0x13));
+
+        assert!(patch_vbmeta_flags(&src, 4, &out).is_err());
+        fs::write(&src, [0u8; 512]).unwrap();
+        assert!(patch_vbmeta_flags(&src, FLAG_HASHTREE_DISABLED, &out).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn hash_descriptor(image: &VbmetaImage) -> &parser::HashDescriptor {
+        image.descriptors.iter().find_map(|d| match d {
+            Descriptor::Hash(h) => Some(h),
+            _ => None,
+        }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resign_keeps_original_metadata() {
+        let dir = std::env::temp_dir().join(format!("rua_hash_footer_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = dir.join("key.pem");
+        AvbKey::generate(2048, &key).unwrap();
+        let key = key.to_string_lossy().to_string();
+        let image = dir.join("boot.img");
+        fs::write(&image, vec![0x5au8; 10000]).unwrap();
+        let image = image.to_string_lossy().to_string();
+
+        // 旧接口不加 salt
+        let legacy = add_hash_footer(&image, "boot", 65536, &key, "SHA256_RSA2048").await.unwrap();
+        assert_eq!(Path::new(&legacy), dir.join("boot.signed.img"));
+        let legacy = VbmetaImage::load(Path::new(&legacy)).unwrap();
+        assert_eq!(hash_descriptor(&legacy).hash_algorithm, "sha256");
+        assert!(hash_descriptor(&legacy).salt.is_empty());
+
+        let mut opts = HashFooterOptions::new(65536);
+        opts.hash_algorithm = "sha512".to_string();
+        opts.rollback_index = 5;
+        opts.properties = vec![("com.android.build.boot.os_version".into(), b"14".to_vec()), ("a".into(), b"b".to_vec())];
+        opts.properties_first = true;
+        let signed = add_hash_footer_with_options(&image, "boot", &key, "SHA256_RSA2048", &opts).await.unwrap();
+        let original = HashFooterOptions::from_original(Path::new(&signed), "boot").unwrap().unwrap();
+        assert_eq!((original.hash_algorithm.as_str(), &original.salt), ("sha512", &opts.salt));
+        assert_eq!((original.rollback_index, original.partition_size), (5, 65536));
+        assert_eq!(original.properties, opts.properties);
+        assert!(original.properties_first);
+
+        // 按原始顺序重新签名后描述符顺序不变
+        let resigned = add_hash_footer_with_options(&signed, "boot", &key, "SHA256_RSA2048", &original).await.unwrap();
+        let resigned = VbmetaImage::load(Path::new(&resigned)).unwrap();
+        assert!(matches!(resigned.descriptors.as_slice(), [Descriptor::Property { .. }, Descriptor::Property { .. }, Descriptor::Hash(_)]));
+
+        // 只有 hashtree 描述符时不沿用其算法与 salt
+        let mut tree_opts = HashtreeFooterOptions::new(1 << 20);
+        tree_opts.base.salt = vec![1; 20];
+        let tree = add_hashtree_footer(&image, "system", &key, "SHA256_RSA2048", &tree_opts).await.unwrap();
+        assert_eq!(Path::new(&tree), dir.join("boot.signed.img"));
+        let hash_opts = HashFooterOptions::from_original(Path::new(&tree), "system").unwrap().unwrap();
+        assert_eq!(hash_opts.hash_algorithm, "sha256");
+        assert_ne!(hash_opts.salt, tree_opts.base.salt);
+        let tree_original = HashtreeFooterOptions::from_original(Path::new(&tree), "system").unwrap().unwrap();
+        assert_eq!((tree_original.base.hash_algorithm.as_str(), &tree_original.base.salt), ("sha1", &tree_opts.base.salt));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
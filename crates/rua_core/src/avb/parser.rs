@@ -0,0 +1,686 @@
+use super::{AVB_FOOTER_MAGIC, AVB_MAGIC, FOOTER_SIZE, VBMETA_HEADER_SIZE};
+use crate::error::{FlashError, Result};
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    None,
+    Sha256Rsa2048,
+    Sha256Rsa4096,
+    Sha256Rsa8192,
+    Sha512Rsa2048,
+    Sha512Rsa4096,
+    Sha512Rsa8192,
+}
+
+impl Algorithm {
+    pub fn from_u32(v: u32) -> Option<Self> {
+        Some(match v {
+            0 => Algorithm::None,
+            1 => Algorithm::Sha256Rsa2048,
+            2 => Algorithm::Sha256Rsa4096,
+            3 => Algorithm::Sha256Rsa8192,
+            4 => Algorithm::Sha512Rsa2048,
+            5 => Algorithm::Sha512Rsa4096,
+            6 => Algorithm::Sha512Rsa8192,
+            _ => return None,
+        })
+    }
+
+    pub fn as_u32(self) -> u32 {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Sha256Rsa2048 => 1,
+            Algorithm::Sha256Rsa4096 => 2,
+            Algorithm::Sha256Rsa8192 => 3,
+            Algorithm::Sha512Rsa2048 => 4,
+            Algorithm::Sha512Rsa4096 => 5,
+            Algorithm::Sha512Rsa8192 => 6,
+        }
+    }
+
+    /// avbtool 使用的名称，如 SHA256_RSA4096
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::None => "NONE",
+            Algorithm::Sha256Rsa2048 => "SHA256_RSA2048",
+            Algorithm::Sha256Rsa4096 => "SHA256_RSA4096",
+            Algorithm::Sha256Rsa8192 => "SHA256_RSA8192",
+            Algorithm::Sha512Rsa2048 => "SHA512_RSA2048",
+            Algorithm::Sha512Rsa4096 => "SHA512_RSA4096",
+            Algorithm::Sha512Rsa8192 => "SHA512_RSA8192",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=6).filter_map(Self::from_u32).find(|a| a.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn hash_len(self) -> usize {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Sha256Rsa2048 | Algorithm::Sha256Rsa4096 | Algorithm::Sha256Rsa8192 => 32,
+            _ => 64,
+        }
+    }
+
+    pub fn key_bits(self) -> usize {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Sha256Rsa2048 | Algorithm::Sha512Rsa2048 => 2048,
+            Algorithm::Sha256Rsa4096 | Algorithm::Sha512Rsa4096 => 4096,
+            Algorithm::Sha256Rsa8192 | Algorithm::Sha512Rsa8192 => 8192,
+        }
+    }
+
+    pub fn signature_len(self) -> usize {
+        self.key_bits() / 8
+    }
+
+    /// 按密钥长度选择签名算法，`sha512` 为 false 时使用 SHA256
+    pub fn for_key_bits(bits: usize, sha512: bool) -> Option<Self> {
+        Some(match (bits, sha512) {
+            (2048, false) => Algorithm::Sha256Rsa2048,
+            (4096, false) => Algorithm::Sha256Rsa4096,
+            (8192, false) => Algorithm::Sha256Rsa8192,
+            (2048, true) => Algorithm::Sha512Rsa2048,
+            (4096, true) => Algorithm::Sha512Rsa4096,
+            (8192, true) => Algorithm::Sha512Rsa8192,
+            _ => return None,
+        })
+    }
+}
+
+/// AvbVBMetaImageHeader，所有整数均为大端
+#[derive(Debug, Clone, PartialEq)]
+pub struct VbmetaHeader {
+    pub required_libavb_version_major: u32,
+    pub required_libavb_version_minor: u32,
+    pub authentication_data_block_size: u64,
+    pub auxiliary_data_block_size: u64,
+    pub algorithm_type: u32,
+    pub hash_offset: u64,
+    pub hash_size: u64,
+    pub signature_offset: u64,
+    pub signature_size: u64,
+    pub public_key_offset: u64,
+    pub public_key_size: u64,
+    pub public_key_metadata_offset: u64,
+    pub public_key_metadata_size: u64,
+    pub descriptors_offset: u64,
+    pub descriptors_size: u64,
+    pub rollback_index: u64,
+    pub flags: u32,
+    pub rollback_index_location: u32,
+    pub release_string: String,
+}
+
+impl VbmetaHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < VBMETA_HEADER_SIZE || &data[0..4] != AVB_MAGIC {
+            return Err(FlashError::UnpackError("不是有效的 vbmeta 头 (缺少 AVB0)".to_string()));
+        }
+        let release = &data[128..176];
+        let release_end = release.iter().position(|&b| b == 0).unwrap_or(release.len());
+        Ok(Self {
+            required_libavb_version_major: be32_at(data, 4),
+            required_libavb_version_minor: be32_at(data, 8),
+            authentication_data_block_size: be64_at(data, 12),
+            auxiliary_data_block_size: be64_at(data, 20),
+            algorithm_type: be32_at(data, 28),
+            hash_offset: be64_at(data, 32),
+            hash_size: be64_at(data, 40),
+            signature_offset: be64_at(data, 48),
+            signature_size: be64_at(data, 56),
+            public_key_offset: be64_at(data, 64),
+            public_key_size: be64_at(data, 72),
+            public_key_metadata_offset: be64_at(data, 80),
+            public_key_metadata_size: be64_at(data, 88),
+            descriptors_offset: be64_at(data, 96),
+            descriptors_size: be64_at(data, 104),
+            rollback_index: be64_at(data, 112),
+            flags: be32_at(data, 120),
+            rollback_index_location: be32_at(data, 124),
+            release_string: String::from_utf8_lossy(&release[..release_end]).to_string(),
+        })
+    }
+
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        Algorithm::from_u32(self.algorithm_type)
+    }
+
+    /// 头 + 认证块 + 辅助块的总长度，块大小被篡改导致溢出时返回错误
+    pub fn total_size(&self) -> Result<u64> {
+        (VBMETA_HEADER_SIZE as u64)
+            .checked_add(self.authentication_data_block_size)
+            .and_then(|n| n.checked_add(self.auxiliary_data_block_size))
+            .ok_or_else(|| FlashError::UnpackError("vbmeta 头中的数据块大小无效".to_string()))
+    }
+}
+
+/// 位于分区末尾 64 字节的 AvbFooter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AvbFooter {
+    pub version_major: u32,
+    pub version_minor: u32,
+    pub original_image_size: u64,
+    pub vbmeta_offset: u64,
+    pub vbmeta_size: u64,
+}
+
+impl AvbFooter {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < FOOTER_SIZE || &data[0..4] != AVB_FOOTER_MAGIC {
+            return None;
+        }
+        Some(Self {
+            version_major: be32_at(data, 4),
+            version_minor: be32_at(data, 8),
+            original_image_size: be64_at(data, 12),
+            vbmeta_offset: be64_at(data, 20),
+            vbmeta_size: be64_at(data, 28),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashDescriptor {
+    pub image_size: u64,
+    pub hash_algorithm: String,
+    pub partition_name: String,
+    pub salt: Vec<u8>,
+    pub digest: Vec<u8>,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashtreeDescriptor {
+    pub dm_verity_version: u32,
+    pub image_size: u64,
+    pub tree_offset: u64,
+    pub tree_size: u64,
+    pub data_block_size: u32,
+    pub hash_block_size: u32,
+    pub fec_num_roots: u32,
+    pub fec_offset: u64,
+    pub fec_size: u64,
+    pub hash_algorithm: String,
+    pub partition_name: String,
+    pub salt: Vec<u8>,
+    pub root_digest: Vec<u8>,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainPartitionDescriptor {
+    pub rollback_index_location: u32,
+    pub partition_name: String,
+    pub public_key: Vec<u8>,
+    pub flags: u32,
+}
+
+impl ChainPartitionDescriptor {
+    pub fn public_key_sha1(&self) -> String {
+        hex(&Sha1::digest(&self.public_key))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Descriptor {
+    Property { key: String, value: Vec<u8> },
+    Hashtree(HashtreeDescriptor),
+    Hash(HashDescriptor),
+    KernelCmdline { flags: u32, cmdline: String },
+    ChainPartition(ChainPartitionDescriptor),
+    Unknown { tag: u64, data: Vec<u8> },
+}
+
+const TAG_PROPERTY: u64 = 0;
+const TAG_HASHTREE: u64 = 1;
+const TAG_HASH: u64 = 2;
+const TAG_KERNEL_CMDLINE: u64 = 3;
+const TAG_CHAIN_PARTITION: u64 = 4;
+
+impl Descriptor {
+    /// 哈希、哈希树和链式描述符对应的分区名
+    pub fn partition_name(&self) -> Option<&str> {
+        match self {
+            Descriptor::Hash(d) => Some(&d.partition_name),
+            Descriptor::Hashtree(d) => Some(&d.partition_name),
+            Descriptor::ChainPartition(d) => Some(&d.partition_name),
+            _ => None,
+        }
+    }
+
+    /// 解析描述符区域，每个描述符以 tag 与后续字节数开头
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Descriptor>> {
+        let mut out = Vec::new();
+        let mut pos = 0usize;
+        while pos + 16 <= data.len() {
+            let tag = be64_at(data, pos);
+            let len = be64_at(data, pos + 8) as usize;
+            let end = pos
+                .checked_add(16)
+                .and_then(|p| p.checked_add(len))
+                .filter(|&e| e <= data.len())
+                .ok_or_else(|| FlashError::UnpackError(format!("描述符越界 (偏移 {})", pos)))?;
+            out.push(Self::parse_one(tag, &data[pos + 16..end])?);
+            pos = end;
+        }
+        Ok(out)
+    }
+
+    fn parse_one(tag: u64, body: &[u8]) -> Result<Descriptor> {
+        let short = || FlashError::UnpackError(format!("描述符 (tag {}) 数据不完整", tag));
+        let take = |offset: usize, len: usize| offset.checked_add(len).and_then(|end| body.get(offset..end)).ok_or_else(short);
+        Ok(match tag {
+            TAG_PROPERTY => {
+                let key_len = be64_at(take(0, 8)?, 0) as usize;
+                let value_len = be64_at(take(8, 8)?, 0) as usize;
+                let key = take(16, key_len)?;
+                // key 之后有一个 NUL 结尾
+                let value = take(key_len.checked_add(17).ok_or_else(short)?, value_len)?;
+                Descriptor::Property { key: String::from_utf8_lossy(key).to_string(), value: value.to_vec() }
+            }
+            TAG_HASHTREE => {
+                let fixed = take(0, 164)?;
+                let name_len = be32_at(fixed, 88) as usize;
+                let salt_len = be32_at(fixed, 92) as usize;
+                let digest_len = be32_at(fixed, 96) as usize;
+                let name = take(164, name_len)?;
+                let salt = take(164 + name_len, salt_len)?;
+                let digest = take(164 + name_len + salt_len, digest_len)?;
+                Descriptor::Hashtree(HashtreeDescriptor {
+                    dm_verity_version: be32_at(fixed, 0),
+                    image_size: be64_at(fixed, 4),
+                    tree_offset: be64_at(fixed, 12),
+                    tree_size: be64_at(fixed, 20),
+                    data_block_size: be32_at(fixed, 28),
+                    hash_block_size: be32_at(fixed, 32),
+                    fec_num_roots: be32_at(fixed, 36),
+                    fec_offset: be64_at(fixed, 40),
+                    fec_size: be64_at(fixed, 48),
+                    hash_algorithm: c_string(&fixed[56..88]),
+                    partition_name: String::from_utf8_lossy(name).to_string(),
+                    salt: salt.to_vec(),
+                    root_digest: digest.to_vec(),
+                    flags: be32_at(fixed, 100),
+                })
+            }
+            TAG_HASH => {
+                let fixed = take(0, 116)?;
+                let name_len = be32_at(fixed, 40) as usize;
+                let salt_len = be32_at(fixed, 44) as usize;
+                let digest_len = be32_at(fixed, 48) as usize;
+                let name = take(116, name_len)?;
+                let salt = take(116 + name_len, salt_len)?;
+                let digest = take(116 + name_len + salt_len, digest_len)?;
+                Descriptor::Hash(HashDescriptor {
+                    image_size: be64_at(fixed, 0),
+                    hash_algorithm: c_string(&fixed[8..40]),
+                    partition_name: String::from_utf8_lossy(name).to_string(),
+                    salt: salt.to_vec(),
+                    digest: digest.to_vec(),
+                    flags: be32_at(fixed, 52),
+                })
+            }
+            TAG_KERNEL_CMDLINE => {
+                let fixed = take(0, 8)?;
+                let len = be32_at(fixed, 4) as usize;
+                Descriptor::KernelCmdline {
+                    flags: be32_at(fixed, 0),
+                    cmdline: String::from_utf8_lossy(take(8, len)?).to_string(),
+                }
+            }
+            TAG_CHAIN_PARTITION => {
+                let fixed = take(0, 76)?;
+                let name_len = be32_at(fixed, 4) as usize;
+                let key_len = be32_at(fixed, 8) as usize;
+                Descriptor::ChainPartition(ChainPartitionDescriptor {
+                    rollback_index_location: be32_at(fixed, 0),
+                    partition_name: String::from_utf8_lossy(take(76, name_len)?).to_string(),
+                    public_key: take(76 + name_len, key_len)?.to_vec(),
+                    flags: be32_at(fixed, 12),
+                })
+            }
+            _ => Descriptor::Unknown { tag, data: body.to_vec() },
+        })
+    }
+}
+
+/// 解析后的 vbmeta：可以来自独立的 vbmeta 镜像，也可以来自带 AVB footer 的分区镜像
+#[derive(Debug, Clone, PartialEq)]
+pub struct VbmetaImage {
+    pub header: VbmetaHeader,
+    pub footer: Option<AvbFooter>,
+    /// vbmeta 在文件中的偏移
+    pub vbmeta_offset: u64,
+    /// 原始 vbmeta 数据（头 + 认证块 + 辅助块）
+    pub raw: Vec<u8>,
+    pub descriptors: Vec<Descriptor>,
+}
+
+impl VbmetaImage {
+    /// 从内存中的完整镜像解析
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() >= 4 && &data[0..4] == AVB_MAGIC {
+            return Self::parse_vbmeta(data, None, 0);
+        }
+        let footer = (data.len() >= FOOTER_SIZE)
+            .then(|| AvbFooter::parse(&data[data.len() - FOOTER_SIZE..]))
+            .flatten()
+            .ok_or_else(|| FlashError::UnpackError("未找到 vbmeta 头 (AVB0) 或 AVB footer (AVBf)".to_string()))?;
+        let start = footer.vbmeta_offset as usize;
+        let end = start.saturating_add(footer.vbmeta_size as usize);
+        let blob = data
+            .get(start..end)
+            .ok_or_else(|| FlashError::UnpackError("AVB footer 中的 vbmeta 位置超出文件范围".to_string()))?;
+        Self::parse_vbmeta(blob, Some(footer), footer.vbmeta_offset)
+    }
+
+    /// 从文件解析，只读取 vbmeta 部分，适合体积很大的分区镜像
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic == AVB_MAGIC {
+            let mut data = Vec::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_end(&mut data)?;
+            return Self::parse_vbmeta(&data, None, 0);
+        }
+        if len < FOOTER_SIZE as u64 {
+            return Err(FlashError::UnpackError("文件过小，不包含 AVB 数据".to_string()));
+        }
+        let mut footer_bytes = [0u8; FOOTER_SIZE];
+        file.seek(SeekFrom::Start(len - FOOTER_SIZE as u64))?;
+        file.read_exact(&mut footer_bytes)?;
+        let footer = AvbFooter::parse(&footer_bytes)
+            .ok_or_else(|| FlashError::UnpackError("未找到 vbmeta 头 (AVB0) 或 AVB footer (AVBf)".to_string()))?;
+        if footer.vbmeta_offset.saturating_add(footer.vbmeta_size) > len {
+            return Err(FlashError::UnpackError("AVB footer 中的 vbmeta 位置超出文件范围".to_string()));
+        }
+        let mut blob = vec![0u8; footer.vbmeta_size as usize];
+        file.seek(SeekFrom::Start(footer.vbmeta_offset))?;
+        file.read_exact(&mut blob)?;
+        Self::parse_vbmeta(&blob, Some(footer), footer.vbmeta_offset)
+    }
+
+    fn parse_vbmeta(data: &[u8], footer: Option<AvbFooter>, vbmeta_offset: u64) -> Result<Self> {
+        let header = VbmetaHeader::parse(data)?;
+        let total = usize::try_from(header.total_size()?)
+            .map_err(|_| FlashError::UnpackError("vbmeta 头中的数据块大小无效".to_string()))?;
+        let raw = data
+            .get(..total)
+            .ok_or_else(|| FlashError::UnpackError("vbmeta 数据不完整".to_string()))?
+            .to_vec();
+        let mut image = Self { header, footer, vbmeta_offset, raw, descriptors: Vec::new() };
+        let descriptors = image
+            .aux_slice(image.header.descriptors_offset, image.header.descriptors_size)
+            .ok_or_else(|| FlashError::UnpackError("描述符区域超出辅助数据块".to_string()))?;
+        image.descriptors = Descriptor::parse_all(descriptors)?;
+        Ok(image)
+    }
+
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.raw[..VBMETA_HEADER_SIZE]
+    }
+
+    pub fn auth_block(&self) -> &[u8] {
+        let start = VBMETA_HEADER_SIZE;
+        &self.raw[start..start + self.header.authentication_data_block_size as usize]
+    }
+
+    pub fn aux_block(&self) -> &[u8] {
+        let start = VBMETA_HEADER_SIZE + self.header.authentication_data_block_size as usize;
+        &self.raw[start..start + self.header.auxiliary_data_block_size as usize]
+    }
+
+    fn aux_slice(&self, offset: u64, size: u64) -> Option<&[u8]> {
+        let aux = self.aux_block();
+        aux.get(offset as usize..(offset as usize).checked_add(size as usize)?)
+    }
+
+    fn auth_slice(&self, offset: u64, size: u64) -> Option<&[u8]> {
+        let auth = self.auth_block();
+        auth.get(offset as usize..(offset as usize).checked_add(size as usize)?)
+    }
+
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        self.header.algorithm()
+    }
+
+    pub fn hash(&self) -> &[u8] {
+        self.auth_slice(self.header.hash_offset, self.header.hash_size).unwrap_or(&[])
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        self.auth_slice(self.header.signature_offset, self.header.signature_size).unwrap_or(&[])
+    }
+
+    /// AVB 格式的公钥（AvbRSAPublicKeyHeader + n + rr），未签名时为空
+    pub fn public_key(&self) -> &[u8] {
+        self.aux_slice(self.header.public_key_offset, self.header.public_key_size).unwrap_or(&[])
+    }
+
+    pub fn public_key_metadata(&self) -> &[u8] {
+        self.aux_slice(self.header.public_key_metadata_offset, self.header.public_key_metadata_size).unwrap_or(&[])
+    }
+
+    /// 公钥 sha1，与 `avbtool info_image` 的 Public key (sha1) 一致
+    pub fn public_key_sha1(&self) -> Option<String> {
+        let key = self.public_key();
+        (!key.is_empty()).then(|| hex(&Sha1::digest(key)))
+    }
+
+    /// 每个描述符的原始字节（含 tag 与长度），顺序与 `descriptors` 一致
+    pub fn raw_descriptors(&self) -> Vec<&[u8]> {
+        let data = self.aux_slice(self.header.descriptors_offset, self.header.descriptors_size).unwrap_or(&[]);
+        let mut out = Vec::with_capacity(self.descriptors.len());
+        let mut pos = 0usize;
+        while pos + 16 <= data.len() {
+            let Some(end) = (pos + 16).checked_add(be64_at(data, pos + 8) as usize).filter(|&e| e <= data.len()) else { break };
+            out.push(&data[pos..end]);
+            pos = end;
+        }
+        out
+    }
+
+    /// 描述符中出现的全部分区名，保持原有顺序
+    pub fn partitions(&self) -> Vec<&str> {
+        self.descriptors.iter().filter_map(|d| d.partition_name()).collect()
+    }
+
+    pub fn properties(&self) -> Vec<(&str, &[u8])> {
+        self.descriptors
+            .iter()
+            .filter_map(|d| match d {
+                Descriptor::Property { key, value } => Some((key.as_str(), value.as_slice())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 解析十六进制字符串（如命令行传入的 salt），长度为奇数或含非法字符时返回 None
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn be32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_descriptor(out: &mut Vec<u8>, tag: u64, body: &[u8]) {
+        let mut body = body.to_vec();
+        while body.len() % 8 != 0 {
+            body.push(0);
+        }
+        out.extend_from_slice(&tag.to_be_bytes());
+        out.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        out.extend_from_slice(&body);
+    }
+
+    fn sample_vbmeta() -> Vec<u8> {
+        let mut descriptors = Vec::new();
+
+        let mut hash = Vec::new();
+        hash.extend_from_slice(&4096u64.to_be_bytes());
+        let mut algo = [0u8; 32];
+        algo[..6].copy_from_slice(b"sha256");
+        hash.extend_from_slice(&algo);
+        hash.extend_from_slice(&4u32.to_be_bytes());
+        hash.extend_from_slice(&2u32.to_be_bytes());
+        hash.extend_from_slice(&32u32.to_be_bytes());
+        hash.extend_from_slice(&0u32.to_be_bytes());
+        hash.extend_from_slice(&[0u8; 60]);
+        hash.extend_from_slice(b"boot");
+        hash.extend_from_slice(&[0xaa, 0xbb]);
+        hash.extend_from_slice(&[0x11; 32]);
+        push_descriptor(&mut descriptors, TAG_HASH, &hash);
+
+        let mut prop = Vec::new();
+        prop.extend_from_slice(&3u64.to_be_bytes());
+        prop.extend_from_slice(&2u64.to_be_bytes());
+        prop.extend_from_slice(b"foo\0ok\0");
+        push_descriptor(&mut descriptors, TAG_PROPERTY, &prop);
+
+        let mut chain = Vec::new();
+        chain.extend_from_slice(&1u32.to_be_bytes());
+        chain.extend_from_slice(&6u32.to_be_bytes());
+        chain.extend_from_slice(&4u32.to_be_bytes());
+        chain.extend_from_slice(&0u32.to_be_bytes());
+        chain.extend_from_slice(&[0u8; 60]);
+        chain.extend_from_slice(b"system");
+        chain.extend_from_slice(&[1, 2, 3, 4]);
+        push_descriptor(&mut descriptors, TAG_CHAIN_PARTITION, &chain);
+
+        let mut cmdline = Vec::new();
+        cmdline.extend_from_slice(&0u32.to_be_bytes());
+        cmdline.extend_from_slice(&5u32.to_be_bytes());
+        cmdline.extend_from_slice(b"quiet");
+        push_descriptor(&mut descriptors, TAG_KERNEL_CMDLINE, &cmdline);
+
+        let mut aux = descriptors.clone();
+        while aux.len() % 64 != 0 {
+            aux.push(0);
+        }
+
+        let mut header = vec![0u8; VBMETA_HEADER_SIZE];
+        header[0..4].copy_from_slice(AVB_MAGIC);
+        header[4..8].copy_from_slice(&1u32.to_be_bytes());
+        header[20..28].copy_from_slice(&(aux.len() as u64).to_be_bytes());
+        header[104..112].copy_from_slice(&(descriptors.len() as u64).to_be_bytes());
+        header[112..120].copy_from_slice(&7u64.to_be_bytes());
+        header[120..124].copy_from_slice(&3u32.to_be_bytes());
+        header[128..135].copy_from_slice(b"test1.0");
+
+        let mut out = header;
+        out.extend_from_slice(&aux);
+        out
+    }
+
+    #[test]
+    fn test_parse_vbmeta_descriptors() {
+        let image = VbmetaImage::parse(&sample_vbmeta()).unwrap();
+        assert_eq!(image.algorithm(), Some(Algorithm::None));
+        assert_eq!(image.header.rollback_index, 7);
+        assert_eq!(image.header.flags, 3);
+        assert_eq!(image.header.release_string, "test1.0");
+        assert_eq!(image.partitions(), vec!["boot", "system"]);
+        assert_eq!(image.properties(), vec![("foo", &b"ok"[..])]);
+        match &image.descriptors[0] {
+            Descriptor::Hash(d) => {
+                assert_eq!(d.image_size, 4096);
+                assert_eq!(d.hash_algorithm, "sha256");
+                assert_eq!(d.salt, vec![0xaa, 0xbb]);
+            }
+            other => panic!("unexpected descriptor {:?}", other),
+        }
+        assert_eq!(image.descriptors[3], Descriptor::KernelCmdline { flags: 0, cmdline: "quiet".to_string() });
+        assert!(image.public_key_sha1().is_none());
+    }
+
+    #[test]
+    fn test_raw_descriptors_roundtrip() {
+        let image = VbmetaImage::parse(&sample_vbmeta()).unwrap();
+        let raw = image.raw_descriptors();
+        assert_eq!(raw.len(), image.descriptors.len());
+        for (desc, bytes) in image.descriptors.iter().zip(raw) {
+            assert_eq!(&Descriptor::parse_all(bytes).unwrap()[..], std::slice::from_ref(desc));
+        }
+    }
+
+    #[test]
+    fn test_parse_footer_embedded_vbmeta() {
+        let vbmeta = sample_vbmeta();
+        let mut data = vec![0u8; 8192];
+        let offset = 4096usize;
+        data[offset..offset + vbmeta.len()].copy_from_slice(&vbmeta);
+        let mut footer = vec![0u8; FOOTER_SIZE];
+        footer[0..4].copy_from_slice(AVB_FOOTER_MAGIC);
+        footer[12..20].copy_from_slice(&4096u64.to_be_bytes());
+        footer[20..28].copy_from_slice(&(offset as u64).to_be_bytes());
+        footer[28..36].copy_from_slice(&(vbmeta.len() as u64).to_be_bytes());
+        let len = data.len();
+        data[len - FOOTER_SIZE..].copy_from_slice(&footer);
+
+        let image = VbmetaImage::parse(&data).unwrap();
+        assert_eq!(image.vbmeta_offset, 4096);
+        assert_eq!(image.footer.unwrap().original_image_size, 4096);
+        assert_eq!(image.partitions(), vec!["boot", "system"]);
+    }
+
+    #[test]
+    fn test_property_length_overflow() {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&TAG_PROPERTY.to_be_bytes());
+        desc.extend_from_slice(&24u64.to_be_bytes());
+        desc.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        desc.extend_from_slice(&1u64.to_be_bytes());
+        desc.extend_from_slice(b"k\0v\0\0\0\0\0");
+        assert!(Descriptor::parse_all(&desc).is_err());
+        desc[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(Descriptor::parse_all(&desc).is_err());
+    }
+
+    #[test]
+    fn test_block_size_overflow() {
+        let mut vbmeta = sample_vbmeta();
+        vbmeta[12..20].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(VbmetaHeader::parse(&vbmeta).unwrap().total_size().is_err());
+        assert!(VbmetaImage::parse(&vbmeta).is_err());
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        assert_eq!(Algorithm::from_name("sha512_rsa8192"), Some(Algorithm::Sha512Rsa8192));
+        assert_eq!(Algorithm::Sha256Rsa4096.signature_len(), 512);
+        assert_eq!(Algorithm::from_u32(9), None);
+    }
+}
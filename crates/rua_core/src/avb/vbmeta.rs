@@ -0,0 +1,346 @@
+use super::parser::{Descriptor, VbmetaImage, hex};
+use super::{be32, be64, build_property_descriptor, build_signed_vbmeta, load_signing_key, public_key_blob_from_pem};
+use crate::error::{FlashError, Result};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 顶层 vbmeta 中的一个链式分区：该分区自带 vbmeta，由 `public_key` 对应的私钥签名
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainPartition {
+    pub partition_name: String,
+    /// 0 保留给顶层 vbmeta 自身
+    pub rollback_index_location: u32,
+    /// AVB 格式的公钥
+    pub public_key: Vec<u8>,
+}
+
+impl ChainPartition {
+    /// 公钥可以是 .pem（公钥或私钥），也可以是 `avbtool extract_public_key` 输出的二进制文件
+    pub fn from_key_file(partition_name: &str, rollback_index_location: u32, key_path: &Path) -> Result<Self> {
+        let data = fs::read(key_path)?;
+        let public_key = if data.starts_with(b"-----BEGIN") {
+            public_key_blob_from_pem(&String::from_utf8_lossy(&data))?
+        } else {
+            data
+        };
+        Ok(Self { partition_name: partition_name.to_string(), rollback_index_location, public_key })
+    }
+
+    pub fn public_key_sha1(&self) -> String {
+        hex(&Sha1::digest(&self.public_key))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(76 + self.partition_name.len() + self.public_key.len());
+        body.extend_from_slice(&be32(self.rollback_index_location));
+        body.extend_from_slice(&be32(self.partition_name.len() as u32));
+        body.extend_from_slice(&be32(self.public_key.len() as u32));
+        body.extend_from_slice(&be32(0));
+        body.extend_from_slice(&[0u8; 60]);
+        body.extend_from_slice(self.partition_name.as_bytes());
+        body.extend_from_slice(&self.public_key);
+        while body.len() % 8 != 0 {
+            body.push(0);
+        }
+        let mut desc = Vec::with_capacity(16 + body.len());
+        desc.extend_from_slice(&be64(4));
+        desc.extend_from_slice(&be64(body.len() as u64));
+        desc.extend_from_slice(&body);
+        desc
+    }
+}
+
+/// 对应 `avbtool make_vbmeta_image` 的参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct MakeVbmetaOptions {
+    /// 复制这些镜像（带 footer 的分区镜像或独立 vbmeta）中的全部描述符
+    pub include_images: Vec<PathBuf>,
+    pub chain_partitions: Vec<ChainPartition>,
+    pub rollback_index: u64,
+    pub rollback_index_location: u32,
+    pub flags: u32,
+    pub release_string: String,
+    pub properties: Vec<(String, Vec<u8>)>,
+    /// 输出补齐到该大小的整数倍，0 表示不补齐
+    pub padding_size: u64,
+}
+
+impl Default for MakeVbmetaOptions {
+    fn default() -> Self {
+        Self {
+            include_images: Vec::new(),
+            chain_partitions: Vec::new(),
+            rollback_index: 0,
+            rollback_index_location: 0,
+            flags: 0,
+            release_string: "rua_avb 1.0".to_string(),
+            properties: Vec::new(),
+            padding_size: 4096,
+        }
+    }
+}
+
+fn partition_of(path: &Path) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    stem.strip_suffix("_a").or_else(|| stem.strip_suffix("_b")).unwrap_or(stem).to_string()
+}
+
+impl MakeVbmetaOptions {
+    /// 按目录中的镜像规划新的 vbmeta.img：
+    /// 原 vbmeta.img 中的链式分区与独立的 vbmeta_*.img 作为链式分区（公钥取自镜像自身的签名），
+    /// 其余带 footer 的镜像复制其描述符（含属性）；目录中没有的分区保留原 vbmeta.img 中的描述符，
+    /// flags 与回滚索引也沿用原 vbmeta.img。
+    pub fn from_image_dir(dir: &Path) -> Result<Self> {
+        let mut opts = Self::default();
+        let old_path = dir.join("vbmeta.img");
+        let old = VbmetaImage::load(&old_path).ok();
+        let mut old_chains = BTreeMap::new();
+        if let Some(old) = &old {
+            // 先复制原 vbmeta 的描述符，目录中缺失的分区沿用原有描述符，存在的由后面的镜像覆盖
+            opts.include_images.push(old_path.clone());
+            opts.rollback_index = old.header.rollback_index;
+            opts.rollback_index_location = old.header.rollback_index_location;
+            opts.flags = old.header.flags;
+            for desc in &old.descriptors {
+                if let Descriptor::ChainPartition(c) = desc {
+                    old_chains.insert(c.partition_name.clone(), c.rollback_index_location);
+                }
+            }
+        }
+
+        let mut images: Vec<PathBuf> = fs::read_dir(dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("img")))
+            .filter(|p| partition_of(p) != "vbmeta")
+            .collect();
+        images.sort();
+
+        let mut seen = HashSet::new();
+        let mut loaded = Vec::new();
+        for path in images {
+            let partition = partition_of(&path);
+            if !seen.insert(partition.clone()) {
+                continue;
+            }
+            if let Ok(image) = VbmetaImage::load(&path) {
+                loaded.push((path, partition, image));
+            }
+        }
+
+        // 已由链式 vbmeta（如 vbmeta_system）描述的分区不再复制到顶层
+        let is_chain = |partition: &str, image: &VbmetaImage| old_chains.contains_key(partition) || image.footer.is_none();
+        let covered: HashSet<String> = loaded
+            .iter()
+            .filter(|(_, partition, image)| is_chain(partition, image))
+            .flat_map(|(_, _, image)| image.partitions().into_iter().map(str::to_string))
+            .collect();
+
+        let mut pending = Vec::new();
+        for (path, partition, image) in loaded {
+            if let Some(&location) = old_chains.get(&partition) {
+                opts.chain_partitions.push(ChainPartition {
+                    partition_name: partition,
+                    rollback_index_location: location,
+                    public_key: image.public_key().to_vec(),
+                });
+            } else if image.footer.is_none() {
+                pending.push((partition, image.public_key().to_vec()));
+            } else if !covered.contains(&partition) {
+                opts.include_images.push(path);
+            }
+        }
+        // 新出现的 vbmeta_* 依次使用未占用的回滚索引位置
+        let mut location = 1;
+        for (partition_name, public_key) in pending {
+            while opts.chain_partitions.iter().any(|c| c.rollback_index_location == location) {
+                location += 1;
+            }
+            opts.chain_partitions.push(ChainPartition { partition_name, rollback_index_location: location, public_key });
+        }
+        opts.chain_partitions.sort_by_key(|c| c.rollback_index_location);
+        Ok(opts)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut locations = HashSet::new();
+        for c in &self.chain_partitions {
+            if c.rollback_index_location == 0 {
+                return Err(FlashError::InvalidChoice(format!("链式分区 {} 的回滚索引位置不能为 0", c.partition_name)));
+            }
+            if c.public_key.is_empty() {
+                return Err(FlashError::InvalidChoice(format!("链式分区 {} 没有公钥 (镜像未签名?)", c.partition_name)));
+            }
+            if !names.insert(c.partition_name.as_str()) {
+                return Err(FlashError::InvalidChoice(format!("链式分区 {} 重复", c.partition_name)));
+            }
+            if !locations.insert(c.rollback_index_location) {
+                return Err(FlashError::InvalidChoice(format!("回滚索引位置 {} 被多个链式分区使用", c.rollback_index_location)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 生成并签名顶层 vbmeta 镜像。描述符顺序与 avbtool 相同：属性、链式分区、复制的描述符；
+/// 多个镜像中同一分区的描述符（以及同名属性）只保留最后一个。
+pub fn make_vbmeta_image(output: &Path, key_pem_path: &str, algorithm: &str, opts: &MakeVbmetaOptions) -> Result<()> {
+    opts.validate()?;
+    let priv_key = load_signing_key(key_pem_path)?;
+
+    let mut descriptors = Vec::new();
+    for (key, value) in &opts.properties {
+        descriptors.extend_from_slice(&build_property_descriptor(key, value));
+    }
+    for chain in &opts.chain_partitions {
+        descriptors.extend_from_slice(&chain.encode());
+    }
+
+    let chained: HashSet<&str> = opts.chain_partitions.iter().map(|c| c.partition_name.as_str()).collect();
+    let mut properties: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut others = Vec::new();
+    let mut named = BTreeMap::new();
+    for path in &opts.include_images {
+        let image = VbmetaImage::load(path)
+            .map_err(|e| FlashError::PatchError(format!("{}: {}", path.display(), e)))?;
+        for (desc, raw) in image.descriptors.iter().zip(image.raw_descriptors()) {
+            let kind = match desc {
+                Descriptor::Property { key, .. } => {
+                    properties.retain(|(k, _)| k != key);
+                    properties.push((key.clone(), raw.to_vec()));
+                    continue;
+                }
+                Descriptor::Hash(_) => "hash",
+                Descriptor::Hashtree(_) => "hashtree",
+                Descriptor::ChainPartition(_) => "chain",
+                _ => {
+                    others.extend_from_slice(raw);
+                    continue;
+                }
+            };
+            let name = desc.partition_name().unwrap_or_default();
+            if chained.contains(name) {
+                // 显式指定的链式分区覆盖被复制镜像中的同名链式描述符
+                if kind == "chain" {
+                    continue;
+                }
+                return Err(FlashError::InvalidChoice(format!(
+                    "分区 {} 既是链式分区又出现在 {} 的描述符中",
+                    name,
+                    path.display()
+                )));
+            }
+            named.insert(format!("{}_{}", kind, name), raw.to_vec());
+        }
+    }
+    for (_, raw) in properties {
+        descriptors.extend_from_slice(&raw);
+    }
+    descriptors.extend_from_slice(&others);
+    for raw in named.values() {
+        descriptors.extend_from_slice(raw);
+    }
+
+    let mut vbmeta = build_signed_vbmeta(
+        &descriptors,
+        priv_key,
+        algorithm,
+        opts.rollback_index,
+        opts.rollback_index_location,
+        opts.flags,
+        &opts.release_string,
+    )?;
+    if opts.padding_size > 0 {
+        let padded = (vbmeta.len() as u64).div_ceil(opts.padding_size) * opts.padding_size;
+        vbmeta.resize(padded as usize, 0);
+    }
+    fs::write(output, &vbmeta)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avb::verify::{CheckStatus, VerifyOptions, verify_image, verify_signature};
+    use crate::avb::{AvbKey, add_hash_footer};
+
+    #[tokio::test]
+    async fn test_make_vbmeta_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rua_make_vbmeta_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = dir.join("key.pem");
+        let signer = AvbKey::generate(2048, &key).unwrap();
+        let chain_key = dir.join("chain.pem");
+        AvbKey::generate(2048, &chain_key).unwrap();
+        let key = key.to_string_lossy().to_string();
+
+        // 签名结果写在原镜像旁边，改名为 boot.img 供按分区名查找镜像的校验使用
+        let raw = dir.join("raw.img");
+        fs::write(&raw, vec![0x11u8; 20000]).unwrap();
+        let signed = add_hash_footer(&raw.to_string_lossy(), "boot", 65536, &key, "SHA256_RSA2048").await.unwrap();
+        assert_eq!(Path::new(&signed), dir.join("raw.signed.img"));
+        let boot = dir.join("boot.img");
+        fs::rename(&signed, &boot).unwrap();
+
+        let opts = MakeVbmetaOptions {
+            include_images: vec![boot.clone()],
+            chain_partitions: vec![ChainPartition::from_key_file("vbmeta_system", 1, &chain_key).unwrap()],
+            rollback_index: 3,
+            properties: vec![("com.example.prop".to_string(), b"value".to_vec())],
+            ..Default::default()
+        };
+        let out = dir.join("vbmeta.img");
+        make_vbmeta_image(&out, &key, "SHA256_RSA2048", &opts).unwrap();
+        assert_eq!(fs::metadata(&out).unwrap().len() % 4096, 0);
+
+        let image = VbmetaImage::load(&out).unwrap();
+        assert_eq!(image.header.rollback_index, 3);
+        assert_eq!(image.public_key(), signer.public_key_blob().as_slice());
+        match image.descriptors.as_slice() {
+            [Descriptor::Property { key, value }, Descriptor::ChainPartition(chain), Descriptor::Hash(hash)] => {
+                assert_eq!((key.as_str(), value.as_slice()), ("com.example.prop", b"value".as_slice()));
+                assert_eq!((chain.partition_name.as_str(), chain.rollback_index_location), ("vbmeta_system", 1));
+                assert_eq!(chain.public_key, opts.chain_partitions[0].public_key);
+                assert_eq!((hash.partition_name.as_str(), hash.image_size), ("boot", 20000));
+            }
+            other => panic!("unexpected descriptors: {:?}", other),
+        }
+        // 复制的描述符与原镜像中的逐字节一致
+        let source = VbmetaImage::load(&boot).unwrap();
+        assert_eq!(image.raw_descriptors()[2], source.raw_descriptors()[0]);
+
+        let report = verify_image(&out, &VerifyOptions::default()).unwrap();
+        assert!(matches!(report.signature, CheckStatus::Pass));
+        let boot_check = report.partitions.iter().find(|p| p.partition == "boot").unwrap();
+        assert!(matches!(boot_check.status, CheckStatus::Pass));
+        assert!(report.is_ok());
+
+        // 改动已签名的描述符后签名失效
+        let mut bytes = fs::read(&out).unwrap();
+        let pos = bytes.windows(16).position(|w| w == b"com.example.prop").unwrap();
+        bytes[pos] ^= 1;
+        let tampered = VbmetaImage::parse(&bytes).unwrap();
+        assert!(verify_signature(&tampered).is_fail());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_validate_chain_partitions() {
+        let chain = |name: &str, location| ChainPartition {
+            partition_name: name.to_string(),
+            rollback_index_location: location,
+            public_key: vec![1; 8],
+        };
+        let with = |chains| MakeVbmetaOptions { chain_partitions: chains, ..Default::default() };
+        assert!(with(vec![chain("system", 1), chain("vendor", 2)]).validate().is_ok());
+        assert!(with(vec![chain("system", 0)]).validate().is_err());
+        assert!(with(vec![chain("system", 1), chain("system", 2)]).validate().is_err());
+        assert!(with(vec![chain("system", 1), chain("vendor", 1)]).validate().is_err());
+        let mut unsigned = chain("system", 1);
+        unsigned.public_key.clear();
+        assert!(with(vec![unsigned]).validate().is_err());
+    }
+}
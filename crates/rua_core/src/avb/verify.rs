@@ -0,0 +1,331 @@
+use super::hashtree::build_hash_tree;
+use super::parser::{Algorithm, Descriptor, HashDescriptor, HashtreeDescriptor, VbmetaImage};
+use super::{public_key_blob_from_pem, rsa_public_key};
+use crate::error::{FlashError, Result};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// 链式分区最多追踪的层数，防止构造的镜像互相引用
+const MAX_CHAIN_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Fail(String),
+    Skipped(String),
+}
+
+impl CheckStatus {
+    pub fn is_fail(&self) -> bool {
+        matches!(self, CheckStatus::Fail(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionCheck {
+    pub partition: String,
+    /// hash / hashtree / chain
+    pub kind: &'static str,
+    pub image: Option<PathBuf>,
+    pub status: CheckStatus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    pub algorithm: Option<Algorithm>,
+    pub signature: CheckStatus,
+    pub public_key_sha1: Option<String>,
+    /// 未提供期望公钥时为 None
+    pub expected_key_matches: Option<bool>,
+    /// 在密钥目录中找到的签名私钥/公钥文件
+    pub signed_by: Option<PathBuf>,
+    pub partitions: Vec<PartitionCheck>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        !self.signature.is_fail()
+            && self.expected_key_matches != Some(false)
+            && !self.partitions.iter().any(|p| p.status.is_fail())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// 存放各分区镜像的目录，默认为被校验镜像所在目录
+    pub image_dir: Option<PathBuf>,
+    /// AVB 格式的期望公钥
+    pub expected_key: Option<Vec<u8>>,
+    /// 用于识别签名密钥的 .pem 目录，例如程序自带的 avbkey
+    pub key_dir: Option<PathBuf>,
+}
+
+/// 从 AVB 公钥格式 (AvbRSAPublicKeyHeader + n + rr) 还原 RSA 公钥，指数固定为 65537
+pub fn rsa_public_key_from_blob(blob: &[u8]) -> Result<RsaPublicKey> {
+    if blob.len() < 8 {
+        return Err(FlashError::PatchError("AVB 公钥数据过短".to_string()));
+    }
+    let bits = u32::from_be_bytes(blob[0..4].try_into().unwrap()) as usize;
+    let key_bytes = bits.div_ceil(8);
+    let n = blob
+        .get(8..8 + key_bytes)
+        .ok_or_else(|| FlashError::PatchError("AVB 公钥数据不完整".to_string()))?;
+    rsa_public_key(n, &65537u32.to_be_bytes())
+        .map_err(|e| FlashError::PatchError(format!("无效的 RSA 公钥: {:?}", e)))
+}
+
+/// 校验 vbmeta 认证块中的哈希与 RSA 签名，使用 vbmeta 自带的公钥
+pub fn verify_signature(image: &VbmetaImage) -> CheckStatus {
+    let Some(algorithm) = image.algorithm() else {
+        return CheckStatus::Fail(format!("未知的签名算法 {}", image.header.algorithm_type));
+    };
+    if algorithm == Algorithm::None {
+        return CheckStatus::Skipped("未签名 (NONE)".to_string());
+    }
+
+    let (digest, scheme) = if algorithm.hash_len() == 32 {
+        let mut h = Sha256::new();
+        h.update(image.header_bytes());
+        h.update(image.aux_block());
+        (h.finalize().to_vec(), Pkcs1v15Sign::new::<Sha256>())
+    } else {
+        let mut h = Sha512::new();
+        h.update(image.header_bytes());
+        h.update(image.aux_block());
+        (h.finalize().to_vec(), Pkcs1v15Sign::new::<Sha512>())
+    };
+    if image.hash() != digest.as_slice() {
+        return CheckStatus::Fail("vbmeta 哈希不匹配，头部或描述符已被修改 (例如改写了 flags)".to_string());
+    }
+
+    let key = match rsa_public_key_from_blob(image.public_key()) {
+        Ok(key) => key,
+        Err(e) => return CheckStatus::Fail(e.to_string()),
+    };
+    if rsa::traits::PublicKeyParts::size(&key) * 8 != algorithm.key_bits() {
+        return CheckStatus::Fail(format!("公钥长度与算法 {} 不符", algorithm.name()));
+    }
+    match key.verify(scheme, &digest, image.signature()) {
+        Ok(_) => CheckStatus::Pass,
+        Err(_) => CheckStatus::Fail("RSA 签名无效".to_string()),
+    }
+}
+
+/// 在目录中查找与给定 AVB 公钥对应的 .pem（公钥或私钥均可）
+pub fn find_key_in_dir(public_key: &[u8], dir: &Path) -> Option<PathBuf> {
+    if public_key.is_empty() {
+        return None;
+    }
+    let mut pems: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("pem")))
+        .collect();
+    pems.sort();
+    pems.into_iter().find(|p| {
+        fs::read_to_string(p)
+            .ok()
+            .and_then(|txt| public_key_blob_from_pem(&txt).ok())
+            .is_some_and(|blob| blob == public_key)
+    })
+}
+
+fn find_partition_image(dir: &Path, partition: &str) -> Option<PathBuf> {
+    [format!("{}.img", partition), format!("{}_a.img", partition)]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+fn hash_prefix<D: Digest>(salt: &[u8], path: &Path, len: u64) -> Result<Vec<u8>> {
+    let mut hasher = D::new();
+    hasher.update(salt);
+    let mut reader = File::open(path)?.take(len);
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// 按哈希描述符校验分区镜像：对 salt + 镜像前 image_size 字节求哈希
+pub fn verify_hash_descriptor(desc: &HashDescriptor, image_path: &Path) -> CheckStatus {
+    let len = match fs::metadata(image_path) {
+        Ok(m) => m.len(),
+        Err(e) => return CheckStatus::Fail(format!("无法读取镜像: {}", e)),
+    };
+    if len < desc.image_size {
+        return CheckStatus::Fail(format!("镜像大小 {} 小于描述符记录的 {}", len, desc.image_size));
+    }
+    let digest = match desc.hash_algorithm.as_str() {
+        "sha256" => hash_prefix::<Sha256>(&desc.salt, image_path, desc.image_size),
+        "sha512" => hash_prefix::<Sha512>(&desc.salt, image_path, desc.image_size),
+        other => return CheckStatus::Skipped(format!("不支持的哈希算法 {}", other)),
+    };
+    match digest {
+        Ok(d) if d == desc.digest => CheckStatus::Pass,
+        Ok(_) => CheckStatus::Fail("哈希不匹配".to_string()),
+        Err(e) => CheckStatus::Fail(format!("读取镜像失败: {}", e)),
+    }
+}
+
+fn hashtree_matches(desc: &HashtreeDescriptor, image_path: &Path) -> Result<CheckStatus> {
+    let mut file = File::open(image_path)?;
+    let mut reader = BufReader::with_capacity(1024 * 1024, &mut file).take(desc.image_size);
+    let (root, tree) =
+        build_hash_tree(&mut reader, desc.image_size, desc.data_block_size, &desc.hash_algorithm, &desc.salt)?;
+    if root != desc.root_digest {
+        return Ok(CheckStatus::Fail("哈希树根摘要不匹配".to_string()));
+    }
+    if tree.len() as u64 != desc.tree_size {
+        return Ok(CheckStatus::Fail(format!("哈希树大小 {} 与描述符记录的 {} 不符", tree.len(), desc.tree_size)));
+    }
+    let mut stored = vec![0u8; tree.len()];
+    file.seek(SeekFrom::Start(desc.tree_offset))?;
+    file.read_exact(&mut stored)?;
+    if stored != tree {
+        return Ok(CheckStatus::Fail("镜像中保存的哈希树已损坏".to_string()));
+    }
+    Ok(CheckStatus::Pass)
+}
+
+/// 按哈希树描述符重新计算 dm-verity 哈希树，并与根摘要及镜像中保存的哈希树比对
+pub fn verify_hashtree_descriptor(desc: &HashtreeDescriptor, image_path: &Path) -> CheckStatus {
+    let len = match fs::metadata(image_path) {
+        Ok(m) => m.len(),
+        Err(e) => return CheckStatus::Fail(format!("无法读取镜像: {}", e)),
+    };
+    let Some(tree_end) = desc.tree_offset.checked_add(desc.tree_size) else {
+        return CheckStatus::Fail("哈希树描述符已损坏: 哈希树偏移与大小溢出".to_string());
+    };
+    if len < tree_end || len < desc.image_size {
+        return CheckStatus::Fail(format!("镜像大小 {} 不足以容纳描述符记录的数据与哈希树", len));
+    }
+    if !matches!(desc.hash_algorithm.as_str(), "sha1" | "sha256" | "sha512") {
+        return CheckStatus::Skipped(format!("不支持的哈希算法 {}", desc.hash_algorithm));
+    }
+    match hashtree_matches(desc, image_path) {
+        Ok(status) => status,
+        Err(e) => CheckStatus::Fail(format!("读取镜像失败: {}", e)),
+    }
+}
+
+/// `own` 为带 footer 的分区镜像自身：其中的 hash/hashtree 描述符描述的就是这个文件
+fn check_descriptors(image: &VbmetaImage, own: Option<&Path>, dir: &Path, depth: usize, out: &mut Vec<PartitionCheck>) {
+    for desc in &image.descriptors {
+        let (partition, kind) = match desc {
+            Descriptor::Hash(d) => (&d.partition_name, "hash"),
+            Descriptor::Hashtree(d) => (&d.partition_name, "hashtree"),
+            Descriptor::ChainPartition(d) => (&d.partition_name, "chain"),
+            _ => continue,
+        };
+        let image_path = match (own, desc) {
+            (Some(own), Descriptor::Hash(_) | Descriptor::Hashtree(_)) => Some(own.to_path_buf()),
+            _ => find_partition_image(dir, partition),
+        };
+        let status = match (&image_path, desc) {
+            (None, _) => CheckStatus::Skipped("目录中没有该分区镜像".to_string()),
+            (Some(path), Descriptor::Hash(d)) => verify_hash_descriptor(d, path),
+            (Some(path), Descriptor::Hashtree(d)) => verify_hashtree_descriptor(d, path),
+            (Some(path), Descriptor::ChainPartition(d)) => {
+                if depth >= MAX_CHAIN_DEPTH {
+                    CheckStatus::Skipped("链式分区层数过多".to_string())
+                } else {
+                    match VbmetaImage::load(path) {
+                        Err(e) => CheckStatus::Fail(format!("无法解析 vbmeta: {}", e)),
+                        Ok(chained) if chained.public_key() != d.public_key.as_slice() => {
+                            CheckStatus::Fail("签名公钥与链式描述符中的公钥不一致".to_string())
+                        }
+                        Ok(chained) => {
+                            let status = verify_signature(&chained);
+                            out.push(PartitionCheck {
+                                partition: partition.clone(),
+                                kind,
+                                image: image_path.clone(),
+                                status,
+                            });
+                            let own = chained.footer.is_some().then_some(path.as_path());
+                            check_descriptors(&chained, own, dir, depth + 1, out);
+                            continue;
+                        }
+                    }
+                }
+            }
+            _ => continue,
+        };
+        out.push(PartitionCheck { partition: partition.clone(), kind, image: image_path, status });
+    }
+}
+
+/// 校验 vbmeta 镜像（或带 footer 的分区镜像）的签名，并逐个检查其描述的分区镜像
+pub fn verify_image(path: &Path, opts: &VerifyOptions) -> Result<VerifyReport> {
+    let image = VbmetaImage::load(path)?;
+    let dir = opts
+        .image_dir
+        .clone()
+        .unwrap_or_else(|| path.parent().map(|p| p.to_path_buf()).unwrap_or_else(|| PathBuf::from(".")));
+
+    let mut partitions = Vec::new();
+    let own = image.footer.is_some().then_some(path);
+    check_descriptors(&image, own, &dir, 0, &mut partitions);
+
+    Ok(VerifyReport {
+        algorithm: image.algorithm(),
+        signature: verify_signature(&image),
+        public_key_sha1: image.public_key_sha1(),
+        expected_key_matches: opts.expected_key.as_ref().map(|k| k.as_slice() == image.public_key()),
+        signed_by: opts.key_dir.as_deref().and_then(|d| find_key_in_dir(image.public_key(), d)),
+        partitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn avbkey(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../avbkey").join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_public_key_blob_roundtrip() {
+        let from_private = public_key_blob_from_pem(&avbkey("testkey_rsa4096.pem")).unwrap();
+        let from_public = public_key_blob_from_pem(&avbkey("testkey_rsa4096_pub.pem")).unwrap();
+        assert_eq!(from_private, from_public);
+        let key = rsa_public_key_from_blob(&from_public).unwrap();
+        assert_eq!(rsa::traits::PublicKeyParts::size(&key), 512);
+    }
+
+    #[test]
+    fn test_hashtree_descriptor_overflow() {
+        let path = std::env::temp_dir().join(format!("rua_verify_tree_{}.img", std::process::id()));
+        fs::write(&path, vec![0u8; 8192]).unwrap();
+        let desc = HashtreeDescriptor {
+            dm_verity_version: 1,
+            image_size: 4096,
+            tree_offset: u64::MAX - 10,
+            tree_size: 4096,
+            data_block_size: 4096,
+            hash_block_size: 4096,
+            fec_num_roots: 0,
+            fec_offset: 0,
+            fec_size: 0,
+            hash_algorithm: "sha256".to_string(),
+            partition_name: "system".to_string(),
+            salt: Vec::new(),
+            root_digest: vec![0; 32],
+            flags: 0,
+        };
+        assert!(matches!(verify_hashtree_descriptor(&desc, &path), CheckStatus::Fail(msg) if msg.contains("已损坏")));
+        let _ = fs::remove_file(&path);
+    }
+}
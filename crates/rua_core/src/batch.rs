@@ -0,0 +1,76 @@
+use crate::error::FlashError;
+use crate::fastboot::FastbootClient;
+use crate::flasher::Flasher;
+use crate::journal::FlashJournal;
+use crate::plan::PlanReporter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone)]
+pub struct DeviceOutcome {
+    pub serial: String,
+    pub steps_done: usize,
+    pub steps_total: usize,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+impl DeviceOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// 在多台设备上并发执行各自的刷机日志。每台设备使用独立的 `FastbootClient`，
+/// 从日志中第一个未完成的步骤开始，某台设备失败只会终止它自己的计划；
+/// 返回结果与 `journals` 顺序一致。
+pub async fn run_journals_on_devices(
+    client: &FastbootClient,
+    journals: Vec<FlashJournal>,
+    reporter: Arc<dyn PlanReporter>,
+) -> Vec<DeviceOutcome> {
+    let serials: Vec<String> = journals.iter().map(|j| j.serial.clone()).collect();
+    let totals: Vec<usize> = journals.iter().map(|j| j.entries.len()).collect();
+    let mut tasks = JoinSet::new();
+
+    for (slot, mut journal) in journals.into_iter().enumerate() {
+        let mut device_client = client.clone();
+        device_client.set_serial(Some(journal.serial.clone()));
+        let reporter = reporter.clone();
+        tasks.spawn(async move {
+            let start = Instant::now();
+            let flasher = Flasher::new(device_client);
+            let res = flasher.run_journaled(&mut journal, reporter.as_ref()).await;
+            let outcome = DeviceOutcome {
+                serial: journal.serial.clone(),
+                steps_done: journal.done_count(),
+                steps_total: journal.entries.len(),
+                elapsed: start.elapsed(),
+                error: res.err().map(|e| e.to_string()),
+            };
+            (slot, outcome)
+        });
+    }
+
+    let mut outcomes: Vec<Option<DeviceOutcome>> = vec![None; serials.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((slot, outcome)) = joined {
+            outcomes[slot] = Some(outcome);
+        }
+    }
+
+    outcomes
+        .into_iter()
+        .zip(serials.into_iter().zip(totals))
+        .map(|(outcome, (serial, steps_total))| {
+            outcome.unwrap_or_else(|| DeviceOutcome {
+                serial,
+                steps_done: 0,
+                steps_total,
+                elapsed: Duration::ZERO,
+                error: Some(FlashError::FastbootError("刷机任务异常退出".into()).to_string()),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,225 @@
+use super::header::{BootHeader, Section};
+use super::inspect::{MTK_HEADER_SIZE, strip_mtk_header};
+use crate::avb;
+use crate::dtb::{DtboImage, FstabChanges, FstabPatch, fdt_size, find_appended_dtb, is_dtbo, split_fdts};
+use crate::error::{FlashError, Result};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DtbLocation {
+    /// boot v2 / vendor_boot 的 dtb 段
+    Section,
+    /// 附加在内核之后 (Image.gz-dtb)
+    Kernel,
+}
+
+/// 读取镜像中的 DTB，优先使用 dtb 段
+pub fn extract_dtb(data: &[u8]) -> Result<Option<(DtbLocation, Vec<u8>)>> {
+    let header = BootHeader::parse(data)?;
+    if let Some(dtb) = header.section(data, Section::Dtb)?.filter(|d| !d.is_empty()) {
+        return Ok(Some((DtbLocation::Section, dtb.to_vec())));
+    }
+    let Some(kernel) = header.section(data, Section::Kernel)? else { return Ok(None) };
+    let (payload, _) = strip_mtk_header(kernel);
+    Ok(find_appended_dtb(payload).map(|off| (DtbLocation::Kernel, payload[off..].to_vec())))
+}
+
+/// 替换镜像中的 DTB，其余数据段保持原样。附加在内核后的 DTB 直接拼接，内核不重新压缩
+pub fn replace_dtb(data: &[u8], dtb: &[u8]) -> Result<Vec<u8>> {
+    let mut header = BootHeader::parse(data)?;
+    let (location, _) = extract_dtb(data)?.ok_or_else(|| FlashError::PatchError("镜像中没有 DTB".to_string()))?;
+    let mut payloads = Vec::new();
+    for (section, _, _) in header.sections() {
+        let mut content = header.section(data, section)?.unwrap_or_default().to_vec();
+        match (section, location) {
+            (Section::Dtb, DtbLocation::Section) => content = dtb.to_vec(),
+            (Section::Kernel, DtbLocation::Kernel) => {
+                let (payload, mtk) = strip_mtk_header(&content);
+                let off = find_appended_dtb(payload).unwrap_or(payload.len());
+                let mut kernel = [&payload[..off], dtb].concat();
+                if mtk {
+                    let mut mtk_header = content[..MTK_HEADER_SIZE].to_vec();
+                    mtk_header[4..8].copy_from_slice(&(kernel.len() as u32).to_le_bytes());
+                    mtk_header.append(&mut kernel);
+                    kernel = mtk_header;
+                }
+                content = kernel;
+            }
+            _ => {}
+        }
+        payloads.push((section, content));
+    }
+    header.assemble(&payloads)
+}
+
+/// dtb 相关命令的输入：dtbo.img、单独的 DTB 文件（可含多个 FDT）或 boot / vendor_boot 镜像
+#[derive(Debug, Clone)]
+pub enum DtbFile {
+    Dtbo(DtboImage),
+    Blob(Vec<u8>),
+    /// 已去除 AVB footer 的镜像
+    Boot { data: Vec<u8>, avb_footer_removed: bool },
+}
+
+impl DtbFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut data = fs::read(path)?;
+        if is_dtbo(&data) {
+            return Ok(Self::Dtbo(DtboImage::parse(&data)?));
+        }
+        if fdt_size(&data, 0).is_some() {
+            return Ok(Self::Blob(data));
+        }
+        let avb_footer_removed = avb::strip_footer(&mut data).is_some();
+        BootHeader::parse(&data)?;
+        Ok(Self::Boot { data, avb_footer_removed })
+    }
+
+    pub fn avb_footer_removed(&self) -> bool {
+        matches!(self, Self::Boot { avb_footer_removed: true, .. })
+    }
+
+    fn blob(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Dtbo(_) => Err(FlashError::PatchError("dtbo 镜像需按 overlay 逐个处理".to_string())),
+            Self::Blob(data) => Ok(data.clone()),
+            Self::Boot { data, .. } => extract_dtb(data)?
+                .map(|(_, dtb)| dtb)
+                .ok_or_else(|| FlashError::PatchError("镜像中没有 DTB".to_string())),
+        }
+    }
+
+    fn with_blob(&self, blob: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Dtbo(_) => Err(FlashError::PatchError("dtbo 镜像需按 overlay 逐个处理".to_string())),
+            Self::Blob(_) => Ok(blob.to_vec()),
+            Self::Boot { data, .. } => replace_dtb(data, blob),
+        }
+    }
+
+    /// 按顺序列出其中的各个 FDT（dtbo 为各 overlay）
+    pub fn fdts(&self) -> Result<Vec<Vec<u8>>> {
+        match self {
+            Self::Dtbo(dtbo) => Ok(dtbo.entries.iter().map(|e| e.data.clone()).collect()),
+            _ => Ok(split_fdts(&self.blob()?).into_iter().map(<[u8]>::to_vec).collect()),
+        }
+    }
+
+    /// 替换第 index 个 FDT，返回新文件内容
+    pub fn replace_fdt(&self, index: usize, fdt: &[u8]) -> Result<Vec<u8>> {
+        if fdt_size(fdt, 0).is_none() {
+            return Err(FlashError::PatchError("替换文件不是有效的 DTB".to_string()));
+        }
+        let out_of_range = || FlashError::InvalidChoice(format!("DTB 序号 {} 超出范围", index));
+        match self {
+            Self::Dtbo(dtbo) => {
+                let mut dtbo = dtbo.clone();
+                dtbo.entries.get_mut(index).ok_or_else(out_of_range)?.data = fdt.to_vec();
+                dtbo.to_bytes()
+            }
+            _ => {
+                let mut fdts = self.fdts()?;
+                *fdts.get_mut(index).ok_or_else(out_of_range)? = fdt.to_vec();
+                self.with_blob(&fdts.concat())
+            }
+        }
+    }
+
+    /// 修补所有 FDT 中的 fstab 节点，返回新文件内容与修改记录 (节点路径, 去掉的选项)
+    pub fn patch_fstab(&self, patch: &FstabPatch) -> Result<(Vec<u8>, FstabChanges)> {
+        match self {
+            Self::Dtbo(dtbo) => {
+                let mut dtbo = dtbo.clone();
+                let changes = patch.apply_to_dtbo(&mut dtbo)?;
+                Ok((dtbo.to_bytes()?, changes))
+            }
+            _ => {
+                let mut changes = Vec::new();
+                let blob = patch.apply_to_blob(&self.blob()?, &mut changes)?;
+                Ok((self.with_blob(&blob)?, changes))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootimg::ImageKind;
+    use crate::dtb::{DtboEntry, Fdt, FdtNode, FdtProp};
+
+    fn fstab_fdt(model: &str) -> Vec<u8> {
+        let prop = |name: &str, value: &str| FdtProp { name: name.to_string(), value: format!("{}\0", value).into_bytes() };
+        let vendor = FdtNode { name: "vendor".to_string(), props: vec![prop("fsmgr_flags", "wait,avb=vbmeta")], children: vec![] };
+        let fstab = FdtNode { name: "fstab".to_string(), props: vec![prop("compatible", "android,fstab")], children: vec![vendor] };
+        let android = FdtNode { name: "android".to_string(), props: vec![], children: vec![fstab] };
+        let firmware = FdtNode { name: "firmware".to_string(), props: vec![], children: vec![android] };
+        let root = FdtNode { name: String::new(), props: vec![prop("model", model)], children: vec![firmware] };
+        Fdt { boot_cpuid_phys: 0, mem_reserve: vec![], root }.to_bytes()
+    }
+
+    fn load(name: &str, data: &[u8]) -> DtbFile {
+        let path = std::env::temp_dir().join(format!("rua_dtb_{}_{}", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        let file = DtbFile::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        file
+    }
+
+    fn models(file: &DtbFile) -> Vec<String> {
+        file.fdts().unwrap().iter().map(|f| Fdt::parse(f).unwrap().model().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_blob_replace_and_patch() {
+        let file = load("blob", &[fstab_fdt("a"), fstab_fdt("b")].concat());
+        assert!(matches!(file, DtbFile::Blob(_)));
+        assert_eq!(models(&file), ["a", "b"]);
+
+        let replaced = load("blob_new", &file.replace_fdt(1, &fstab_fdt("c")).unwrap());
+        assert_eq!(models(&replaced), ["a", "c"]);
+        assert!(file.replace_fdt(2, &fstab_fdt("c")).is_err());
+        assert!(file.replace_fdt(0, b"not a dtb").is_err());
+
+        let (patched, changes) = file.patch_fstab(&FstabPatch { remove_verity: true, remove_encryption: false }).unwrap();
+        assert_eq!(changes.len(), 2);
+        let patched = load("blob_patched", &patched);
+        for fdt in patched.fdts().unwrap() {
+            let fdt = Fdt::parse(&fdt).unwrap();
+            let vendor = fdt.root.child("firmware").and_then(|n| n.child("android")).and_then(|n| n.child("fstab")).and_then(|n| n.child("vendor"));
+            assert_eq!(vendor.and_then(|n| n.prop_str("fsmgr_flags")).as_deref(), Some("wait"));
+        }
+    }
+
+    #[test]
+    fn test_dtbo_replace() {
+        let entry = |id, model| DtboEntry { id, rev: 0, custom: [0; 4], data: fstab_fdt(model) };
+        let dtbo = DtboImage { page_size: 4096, version: 0, entries: vec![entry(1, "a"), entry(2, "b")] };
+        let file = load("dtbo", &dtbo.to_bytes().unwrap());
+        assert!(matches!(file, DtbFile::Dtbo(_)));
+        let replaced = load("dtbo_new", &file.replace_fdt(0, &fstab_fdt("c")).unwrap());
+        assert_eq!(models(&replaced), ["c", "b"]);
+        let (_, changes) = file.patch_fstab(&FstabPatch { remove_verity: true, remove_encryption: false }).unwrap();
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[test]
+    fn test_boot_with_appended_dtb() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.page_size = 2048;
+        let kernel = [b"kernel!!".as_slice(), &fstab_fdt("a"), &fstab_fdt("b")].concat();
+        let image = header.assemble(&[(Section::Kernel, kernel)]).unwrap();
+
+        let file = load("boot", &image);
+        assert!(!file.avb_footer_removed());
+        assert_eq!(models(&file), ["a", "b"]);
+
+        let new_image = file.replace_fdt(0, &fstab_fdt("c")).unwrap();
+        let (location, dtb) = extract_dtb(&new_image).unwrap().unwrap();
+        assert_eq!(location, DtbLocation::Kernel);
+        assert_eq!(dtb, [fstab_fdt("c"), fstab_fdt("b")].concat());
+        let new_header = BootHeader::parse(&new_image).unwrap();
+        assert!(new_header.section(&new_image, Section::Kernel).unwrap().unwrap().starts_with(b"kernel!!"));
+    }
+}
@@ -0,0 +1,168 @@
+use super::header::{BootHeader, ImageKind};
+use super::vendor::VendorBootImage;
+use crate::avb;
+use crate::error::{FlashError, Result};
+use std::fs;
+use std::path::Path;
+
+/// 只修改头部字段（及 vendor_boot 的 bootconfig），kernel / ramdisk 等数据段保持不变。
+/// add 中的参数若与已有参数同名（= 之前的部分相同）则替换，remove 可写参数名或完整参数
+#[derive(Debug, Clone, Default)]
+pub struct HeaderEdit {
+    pub cmdline_add: Vec<String>,
+    pub cmdline_remove: Vec<String>,
+    pub os_version: Option<String>,
+    pub os_patch_level: Option<String>,
+    pub bootconfig_add: Vec<String>,
+    pub bootconfig_remove: Vec<String>,
+}
+
+impl HeaderEdit {
+    pub fn is_empty(&self) -> bool {
+        self.cmdline_add.is_empty()
+            && self.cmdline_remove.is_empty()
+            && self.os_version.is_none()
+            && self.os_patch_level.is_none()
+            && !self.edits_bootconfig()
+    }
+
+    fn edits_bootconfig(&self) -> bool {
+        !self.bootconfig_add.is_empty() || !self.bootconfig_remove.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderEditReport {
+    pub header: BootHeader,
+    pub bootconfig: Option<String>,
+    /// 原镜像带有 AVB footer，修改后已去除，需要重新签名
+    pub avb_footer_removed: bool,
+}
+
+fn arg_key(arg: &str) -> &str {
+    arg.split('=').next().unwrap_or(arg).trim()
+}
+
+fn apply_args<'a>(args: impl Iterator<Item = &'a str>, add: &[String], remove: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = args
+        .filter(|arg| !remove.iter().any(|r| r.trim() == arg.trim() || r.trim() == arg_key(arg)))
+        .map(str::to_string)
+        .collect();
+    for arg in add {
+        match out.iter_mut().find(|a| arg_key(a) == arg_key(arg)) {
+            Some(existing) => *existing = arg.trim().to_string(),
+            None => out.push(arg.trim().to_string()),
+        }
+    }
+    out
+}
+
+/// 内核命令行以空白分隔
+pub fn edit_cmdline(cmdline: &str, add: &[String], remove: &[String]) -> String {
+    apply_args(cmdline.split_whitespace(), add, remove).join(" ")
+}
+
+/// bootconfig 每行一个 key = value
+pub fn edit_bootconfig(bootconfig: &str, add: &[String], remove: &[String]) -> String {
+    let lines = bootconfig.lines().map(str::trim).filter(|l| !l.is_empty());
+    let mut out = apply_args(lines, add, remove).join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// 在内存中修改镜像。头部原位改写；修改 bootconfig 时按 vendor ramdisk 表重新生成 vendor_boot
+pub fn apply_edit(data: &mut Vec<u8>, edit: &HeaderEdit) -> Result<HeaderEditReport> {
+    if edit.is_empty() {
+        return Err(FlashError::InvalidChoice("没有需要修改的字段".to_string()));
+    }
+    let avb_footer_removed = avb::strip_footer(data).is_some();
+    let mut header = BootHeader::parse(data)?;
+
+    if header.kind == ImageKind::VendorBoot && (edit.os_version.is_some() || edit.os_patch_level.is_some()) {
+        return Err(FlashError::PatchError("vendor_boot 头部没有系统版本与安全补丁字段".to_string()));
+    }
+    if edit.edits_bootconfig() && !(header.kind == ImageKind::VendorBoot && header.header_version >= 4) {
+        return Err(FlashError::PatchError("只有 vendor_boot v4 包含 bootconfig".to_string()));
+    }
+
+    if !edit.cmdline_add.is_empty() || !edit.cmdline_remove.is_empty() {
+        let cmdline = edit_cmdline(&header.full_cmdline(), &edit.cmdline_add, &edit.cmdline_remove);
+        header.set_cmdline(&cmdline)?;
+    }
+    if let Some(version) = &edit.os_version {
+        header.set_os_version(version)?;
+    }
+    if let Some(level) = &edit.os_patch_level {
+        header.set_os_patch_level(level)?;
+    }
+
+    let mut bootconfig = None;
+    if edit.edits_bootconfig() {
+        let mut image = VendorBootImage::parse(data)?;
+        let text = edit_bootconfig(&String::from_utf8_lossy(&image.bootconfig), &edit.bootconfig_add, &edit.bootconfig_remove);
+        image.header = header.clone();
+        image.bootconfig = text.clone().into_bytes();
+        *data = image.to_bytes()?;
+        header = BootHeader::parse(data)?;
+        bootconfig = Some(text);
+    } else {
+        // 头部之后到页边界均为填充，整页覆盖不会影响数据段
+        let bytes = header.to_bytes();
+        data.get_mut(..bytes.len())
+            .ok_or_else(|| FlashError::PatchError("镜像不完整".to_string()))?
+            .copy_from_slice(&bytes);
+    }
+    Ok(HeaderEditReport { header, bootconfig, avb_footer_removed })
+}
+
+pub fn edit_header(image: &Path, output: &Path, edit: &HeaderEdit) -> Result<HeaderEditReport> {
+    let mut data = fs::read(image)?;
+    let report = apply_edit(&mut data, edit)?;
+    fs::write(output, data)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::header::Section;
+
+    #[test]
+    fn test_edit_cmdline_args() {
+        let cmdline = "console=ttyMSM0 androidboot.selinux=enforcing quiet";
+        let add = vec!["androidboot.selinux=permissive".to_string(), "loglevel=7".to_string()];
+        let remove = vec!["quiet".to_string()];
+        assert_eq!(
+            edit_cmdline(cmdline, &add, &remove),
+            "console=ttyMSM0 androidboot.selinux=permissive loglevel=7"
+        );
+        assert_eq!(edit_cmdline(cmdline, &[], &["androidboot.selinux".to_string()]), "console=ttyMSM0 quiet");
+    }
+
+    #[test]
+    fn test_apply_edit_keeps_payload() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.header_version = 4;
+        header.page_size = 4096;
+        header.set_os_version("14.0.0").unwrap();
+        header.set_os_patch_level("2024-01").unwrap();
+        let image = header
+            .assemble(&[(Section::Kernel, vec![1u8; 5000]), (Section::Ramdisk, vec![2u8; 300])])
+            .unwrap();
+
+        let mut data = image.clone();
+        let edit = HeaderEdit {
+            cmdline_add: vec!["androidboot.selinux=permissive".to_string()],
+            os_patch_level: Some("2024-05-05".to_string()),
+            ..Default::default()
+        };
+        let report = apply_edit(&mut data, &edit).unwrap();
+        assert_eq!(report.header.os_patch_level().as_deref(), Some("2024-05"));
+        assert_eq!(report.header.os_version_string().as_deref(), Some("14.0.0"));
+        assert_eq!(report.header.full_cmdline(), "androidboot.selinux=permissive");
+        assert_eq!(data.len(), image.len());
+        assert_eq!(data[4096..], image[4096..]);
+    }
+}
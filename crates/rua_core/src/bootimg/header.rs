@@ -0,0 +1,584 @@
+use crate::error::{FlashError, Result};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+pub const BOOT_MAGIC: &[u8; 8] = b"ANDROID!";
+pub const VENDOR_BOOT_MAGIC: &[u8; 8] = b"VNDRBOOT";
+/// v3 及以上的 boot 镜像固定使用 4K 页
+const BOOT_V3_PAGE_SIZE: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    Boot,
+    VendorBoot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Kernel,
+    Ramdisk,
+    Second,
+    RecoveryDtbo,
+    Dtb,
+    Signature,
+    VendorRamdiskTable,
+    Bootconfig,
+    /// 旧版高通镜像在 second 之后附加的 QCDT，大小存放在 header_version 的位置
+    QcomDt,
+}
+
+impl Section {
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Kernel => "kernel",
+            Section::Ramdisk => "ramdisk",
+            Section::Second => "second",
+            Section::RecoveryDtbo => "recovery_dtbo",
+            Section::Dtb => "dtb",
+            Section::Signature => "signature",
+            Section::VendorRamdiskTable => "vendor_ramdisk_table",
+            Section::Bootconfig => "bootconfig",
+            Section::QcomDt => "dt",
+        }
+    }
+}
+
+/// boot (v0~v4) 与 vendor_boot (v3/v4) 头部，字段含义与 AOSP bootimg.h 相同，
+/// 对应版本中不存在的字段为 0 或空
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootHeader {
+    pub kind: ImageKind,
+    pub header_version: u32,
+    pub page_size: u32,
+    pub kernel_size: u32,
+    pub kernel_addr: u32,
+    /// vendor_boot 中为全部 vendor ramdisk 的总大小
+    pub ramdisk_size: u32,
+    pub ramdisk_addr: u32,
+    pub second_size: u32,
+    pub second_addr: u32,
+    pub tags_addr: u32,
+    pub os_version: u32,
+    pub name: String,
+    pub cmdline: String,
+    pub extra_cmdline: String,
+    pub id: [u8; 32],
+    pub recovery_dtbo_size: u32,
+    pub recovery_dtbo_offset: u64,
+    pub header_size: u32,
+    pub dtb_size: u32,
+    pub dtb_addr: u64,
+    pub signature_size: u32,
+    pub vendor_ramdisk_table_size: u32,
+    pub vendor_ramdisk_table_entry_num: u32,
+    pub vendor_ramdisk_table_entry_size: u32,
+    pub bootconfig_size: u32,
+    /// 仅 v0：偏移 40 处大于 4 的值视为 QCDT 大小，写回时原样保留
+    pub qcom_dt_size: u32,
+}
+
+impl BootHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 {
+            return Err(FlashError::PatchError("镜像过小，不是 boot 镜像".to_string()));
+        }
+        match &data[..8] {
+            m if m == BOOT_MAGIC => Self::parse_boot(data),
+            m if m == VENDOR_BOOT_MAGIC => Self::parse_vendor_boot(data),
+            _ => Err(FlashError::PatchError("未找到 ANDROID! / VNDRBOOT 魔数，不是 boot 镜像".to_string())),
+        }
+    }
+
+    pub(super) fn empty(kind: ImageKind) -> Self {
+        Self {
+            kind,
+            header_version: 0,
+            page_size: 0,
+            kernel_size: 0,
+            kernel_addr: 0,
+            ramdisk_size: 0,
+            ramdisk_addr: 0,
+            second_size: 0,
+            second_addr: 0,
+            tags_addr: 0,
+            os_version: 0,
+            name: String::new(),
+            cmdline: String::new(),
+            extra_cmdline: String::new(),
+            id: [0; 32],
+            recovery_dtbo_size: 0,
+            recovery_dtbo_offset: 0,
+            header_size: 0,
+            dtb_size: 0,
+            dtb_addr: 0,
+            signature_size: 0,
+            vendor_ramdisk_table_size: 0,
+            vendor_ramdisk_table_entry_num: 0,
+            vendor_ramdisk_table_entry_size: 0,
+            bootconfig_size: 0,
+            qcom_dt_size: 0,
+        }
+    }
+
+    fn parse_boot(data: &[u8]) -> Result<Self> {
+        let mut h = Self::empty(ImageKind::Boot);
+        let r = Reader(data);
+        // 旧镜像中该位置是未使用的字段（高通存放 dt 大小），超出已知范围时按 v0 处理
+        let version_or_dt = r.u32(40)?;
+        if version_or_dt > 4 {
+            h.qcom_dt_size = version_or_dt;
+        } else {
+            h.header_version = version_or_dt;
+        }
+        if h.header_version >= 3 {
+            h.kernel_size = r.u32(8)?;
+            h.ramdisk_size = r.u32(12)?;
+            h.os_version = r.u32(16)?;
+            h.header_size = r.u32(20)?;
+            h.cmdline = r.string(44, 1536)?;
+            if h.header_version == 4 {
+                h.signature_size = r.u32(1580)?;
+            }
+            h.page_size = BOOT_V3_PAGE_SIZE;
+            return Ok(h);
+        }
+
+        h.kernel_size = r.u32(8)?;
+        h.kernel_addr = r.u32(12)?;
+        h.ramdisk_size = r.u32(16)?;
+        h.ramdisk_addr = r.u32(20)?;
+        h.second_size = r.u32(24)?;
+        h.second_addr = r.u32(28)?;
+        h.tags_addr = r.u32(32)?;
+        h.page_size = r.u32(36)?;
+        h.os_version = r.u32(44)?;
+        h.name = r.string(48, 16)?;
+        h.cmdline = r.string(64, 512)?;
+        h.id.copy_from_slice(r.bytes(576, 32)?);
+        h.extra_cmdline = r.string(608, 1024)?;
+        if h.header_version >= 1 {
+            h.recovery_dtbo_size = r.u32(1632)?;
+            h.recovery_dtbo_offset = r.u64(1636)?;
+            h.header_size = r.u32(1644)?;
+        }
+        if h.header_version == 2 {
+            h.dtb_size = r.u32(1648)?;
+            h.dtb_addr = r.u64(1652)?;
+        }
+        if !h.page_size.is_power_of_two() || h.page_size < 2048 {
+            return Err(FlashError::PatchError(format!("无效的页大小: {}", h.page_size)));
+        }
+        Ok(h)
+    }
+
+    fn parse_vendor_boot(data: &[u8]) -> Result<Self> {
+        let mut h = Self::empty(ImageKind::VendorBoot);
+        let r = Reader(data);
+        h.header_version = r.u32(8)?;
+        h.page_size = r.u32(12)?;
+        h.kernel_addr = r.u32(16)?;
+        h.ramdisk_addr = r.u32(20)?;
+        h.ramdisk_size = r.u32(24)?;
+        h.cmdline = r.string(28, 2048)?;
+        h.tags_addr = r.u32(2076)?;
+        h.name = r.string(2080, 16)?;
+        h.header_size = r.u32(2096)?;
+        h.dtb_size = r.u32(2100)?;
+        h.dtb_addr = r.u64(2104)?;
+        if h.header_version >= 4 {
+            h.vendor_ramdisk_table_size = r.u32(2112)?;
+            h.vendor_ramdisk_table_entry_num = r.u32(2116)?;
+            h.vendor_ramdisk_table_entry_size = r.u32(2120)?;
+            h.bootconfig_size = r.u32(2124)?;
+        }
+        if !h.page_size.is_power_of_two() || h.page_size < 2048 {
+            return Err(FlashError::PatchError(format!("无效的页大小: {}", h.page_size)));
+        }
+        Ok(h)
+    }
+
+    /// 头部本身占用的字节数（对齐前）
+    fn raw_header_size(&self) -> u64 {
+        match (self.kind, self.header_version) {
+            (ImageKind::Boot, 0) => 1632,
+            (ImageKind::Boot, 1) => 1648,
+            (ImageKind::Boot, 2) => 1660,
+            (ImageKind::Boot, 3) => 1580,
+            (ImageKind::Boot, _) => 1584,
+            (ImageKind::VendorBoot, 3) => 2112,
+            (ImageKind::VendorBoot, _) => 2128,
+        }
+    }
+
+    /// 按头部计算各数据段在镜像中的偏移与大小，大小为 0 的段不列出
+    pub fn sections(&self) -> Vec<(Section, u64, u64)> {
+        let page = self.page_size as u64;
+        let align = |v: u64| v.div_ceil(page) * page;
+        let mut layout = Vec::new();
+        match self.kind {
+            ImageKind::Boot => {
+                layout.push((Section::Kernel, self.kernel_size));
+                layout.push((Section::Ramdisk, self.ramdisk_size));
+                match self.header_version {
+                    0..=2 => {
+                        layout.push((Section::Second, self.second_size));
+                        if self.header_version == 0 {
+                            layout.push((Section::QcomDt, self.qcom_dt_size));
+                        }
+                        if self.header_version >= 1 {
+                            layout.push((Section::RecoveryDtbo, self.recovery_dtbo_size));
+                        }
+                        if self.header_version == 2 {
+                            layout.push((Section::Dtb, self.dtb_size));
+                        }
+                    }
+                    3 => {}
+                    _ => layout.push((Section::Signature, self.signature_size)),
+                }
+            }
+            ImageKind::VendorBoot => {
+                layout.push((Section::Ramdisk, self.ramdisk_size));
+                layout.push((Section::Dtb, self.dtb_size));
+                if self.header_version >= 4 {
+                    layout.push((Section::VendorRamdiskTable, self.vendor_ramdisk_table_size));
+                    layout.push((Section::Bootconfig, self.bootconfig_size));
+                }
+            }
+        }
+
+        let mut offset = align(self.raw_header_size());
+        let mut sections = Vec::new();
+        for (section, size) in layout {
+            let size = size as u64;
+            if size > 0 {
+                sections.push((section, offset, size));
+            }
+            offset += align(size);
+        }
+        sections
+    }
+
+    /// 取出某个数据段，超出镜像末尾时报错
+    pub fn section<'a>(&self, data: &'a [u8], section: Section) -> Result<Option<&'a [u8]>> {
+        let Some(&(_, offset, size)) = self.sections().iter().find(|(s, _, _)| *s == section) else {
+            return Ok(None);
+        };
+        data.get(offset as usize..(offset + size) as usize)
+            .map(Some)
+            .ok_or_else(|| FlashError::PatchError(format!("{} 超出镜像范围，镜像可能不完整", section.name())))
+    }
+
+    /// 系统版本，如 "14.0.0"；未设置时为 None
+    pub fn os_version_string(&self) -> Option<String> {
+        let v = self.os_version >> 11;
+        (v != 0).then(|| format!("{}.{}.{}", (v >> 14) & 0x7f, (v >> 7) & 0x7f, v & 0x7f))
+    }
+
+    /// 安全补丁级别，如 "2024-05"；未设置时为 None
+    pub fn os_patch_level(&self) -> Option<String> {
+        let p = self.os_version & 0x7ff;
+        (p != 0).then(|| format!("{}-{:02}", (p >> 4) + 2000, p & 0xf))
+    }
+
+    /// v0~v2 的完整命令行由 cmdline 与 extra_cmdline 拼接而成
+    pub fn full_cmdline(&self) -> String {
+        format!("{}{}", self.cmdline, self.extra_cmdline)
+    }
+
+    /// 与 mkbootimg 相同：v0~v2 超出 cmdline 字段的部分放入 extra_cmdline
+    pub fn set_cmdline(&mut self, cmdline: &str) -> Result<()> {
+        let max = match (self.kind, self.header_version) {
+            (ImageKind::Boot, 0..=2) => 511 + 1023,
+            (ImageKind::Boot, _) => 1535,
+            (ImageKind::VendorBoot, _) => 2047,
+        };
+        if cmdline.len() > max {
+            return Err(FlashError::PatchError(format!("命令行过长: {} 字节，最多 {} 字节", cmdline.len(), max)));
+        }
+        if self.kind == ImageKind::Boot && self.header_version < 3 && cmdline.len() > 511 {
+            let mut split = 511;
+            while !cmdline.is_char_boundary(split) {
+                split -= 1;
+            }
+            self.cmdline = cmdline[..split].to_string();
+            self.extra_cmdline = cmdline[split..].to_string();
+        } else {
+            self.cmdline = cmdline.to_string();
+            self.extra_cmdline.clear();
+        }
+        Ok(())
+    }
+
+    /// 设置系统版本，接受 "14" 或 "14.0.0"
+    pub fn set_os_version(&mut self, version: &str) -> Result<()> {
+        let invalid = || FlashError::InvalidChoice(format!("无效的系统版本: {}", version));
+        let mut parts = [0u32; 3];
+        let fields: Vec<&str> = version.trim().split('.').collect();
+        if fields.len() > 3 {
+            return Err(invalid());
+        }
+        for (part, field) in parts.iter_mut().zip(fields) {
+            *part = field.parse().ok().filter(|v| *v < 128).ok_or_else(invalid)?;
+        }
+        let v = (parts[0] << 14) | (parts[1] << 7) | parts[2];
+        self.os_version = (v << 11) | (self.os_version & 0x7ff);
+        Ok(())
+    }
+
+    /// 设置安全补丁级别，接受 "2024-05" 或 "2024-05-01"（日期部分不保存）
+    pub fn set_os_patch_level(&mut self, level: &str) -> Result<()> {
+        let invalid = || FlashError::InvalidChoice(format!("无效的安全补丁级别: {}", level));
+        let mut fields = level.trim().split('-');
+        let year: u32 = fields.next().and_then(|y| y.parse().ok()).ok_or_else(invalid)?;
+        let month: u32 = fields.next().and_then(|m| m.parse().ok()).ok_or_else(invalid)?;
+        if !(2000..2128).contains(&year) || !(1..=12).contains(&month) {
+            return Err(invalid());
+        }
+        self.os_version = (self.os_version & !0x7ff) | ((year - 2000) << 4) | month;
+        Ok(())
+    }
+
+    fn set_section_size(&mut self, section: Section, size: u32) {
+        match section {
+            Section::Kernel => self.kernel_size = size,
+            Section::Ramdisk => self.ramdisk_size = size,
+            Section::Second => self.second_size = size,
+            Section::RecoveryDtbo => self.recovery_dtbo_size = size,
+            Section::Dtb => self.dtb_size = size,
+            Section::Signature => self.signature_size = size,
+            Section::VendorRamdiskTable => self.vendor_ramdisk_table_size = size,
+            Section::Bootconfig => self.bootconfig_size = size,
+            Section::QcomDt => self.qcom_dt_size = size,
+        }
+    }
+
+    /// 序列化头部，长度补齐到一页
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(vec![0u8; self.raw_header_size().div_ceil(self.page_size as u64) as usize * self.page_size as usize]);
+        match self.kind {
+            ImageKind::Boot if self.header_version >= 3 => {
+                w.bytes(0, BOOT_MAGIC);
+                w.u32(8, self.kernel_size);
+                w.u32(12, self.ramdisk_size);
+                w.u32(16, self.os_version);
+                w.u32(20, self.raw_header_size() as u32);
+                w.u32(40, self.header_version);
+                w.bytes(44, self.cmdline.as_bytes());
+                if self.header_version == 4 {
+                    w.u32(1580, self.signature_size);
+                }
+            }
+            ImageKind::Boot => {
+                w.bytes(0, BOOT_MAGIC);
+                w.u32(8, self.kernel_size);
+                w.u32(12, self.kernel_addr);
+                w.u32(16, self.ramdisk_size);
+                w.u32(20, self.ramdisk_addr);
+                w.u32(24, self.second_size);
+                w.u32(28, self.second_addr);
+                w.u32(32, self.tags_addr);
+                w.u32(36, self.page_size);
+                w.u32(40, if self.header_version == 0 { self.qcom_dt_size } else { self.header_version });
+                w.u32(44, self.os_version);
+                w.bytes(48, self.name.as_bytes());
+                w.bytes(64, self.cmdline.as_bytes());
+                w.bytes(576, &self.id);
+                w.bytes(608, self.extra_cmdline.as_bytes());
+                if self.header_version >= 1 {
+                    w.u32(1632, self.recovery_dtbo_size);
+                    w.u64(1636, self.recovery_dtbo_offset);
+                    w.u32(1644, self.raw_header_size() as u32);
+                }
+                if self.header_version == 2 {
+                    w.u32(1648, self.dtb_size);
+                    w.u64(1652, self.dtb_addr);
+                }
+            }
+            ImageKind::VendorBoot => {
+                w.bytes(0, VENDOR_BOOT_MAGIC);
+                w.u32(8, self.header_version);
+                w.u32(12, self.page_size);
+                w.u32(16, self.kernel_addr);
+                w.u32(20, self.ramdisk_addr);
+                w.u32(24, self.ramdisk_size);
+                w.bytes(28, self.cmdline.as_bytes());
+                w.u32(2076, self.tags_addr);
+                w.bytes(2080, self.name.as_bytes());
+                w.u32(2096, self.raw_header_size() as u32);
+                w.u32(2100, self.dtb_size);
+                w.u64(2104, self.dtb_addr);
+                if self.header_version >= 4 {
+                    w.u32(2112, self.vendor_ramdisk_table_size);
+                    w.u32(2116, self.vendor_ramdisk_table_entry_num);
+                    w.u32(2120, self.vendor_ramdisk_table_entry_size);
+                    w.u32(2124, self.bootconfig_size);
+                }
+            }
+        }
+        w.0
+    }
+
+    /// 按当前头部重新拼装镜像：更新各段大小，v0~v2 重新计算 id 与 recovery_dtbo 偏移。
+    /// 头部版本中不存在的段会被忽略
+    pub fn assemble(&mut self, payloads: &[(Section, Vec<u8>)]) -> Result<Vec<u8>> {
+        for (section, data) in payloads {
+            let size = u32::try_from(data.len())
+                .map_err(|_| FlashError::PatchError(format!("{} 过大", section.name())))?;
+            self.set_section_size(*section, size);
+        }
+        let payload = |section: Section| {
+            payloads.iter().find(|(s, _)| *s == section).map(|(_, d)| d.as_slice()).unwrap_or_default()
+        };
+        let sections = self.sections();
+        if self.kind == ImageKind::Boot && self.header_version < 3 {
+            // 与 mkbootimg 相同：依次对各段内容及其长度做 sha1
+            let mut hasher = Sha1::new();
+            let mut hashed = vec![Section::Kernel, Section::Ramdisk, Section::Second];
+            // 高通 mkbootimg 在 second 之后同样对 dt 做 sha1
+            if self.header_version == 0 && self.qcom_dt_size != 0 {
+                hashed.push(Section::QcomDt);
+            }
+            if self.header_version >= 1 {
+                hashed.push(Section::RecoveryDtbo);
+            }
+            if self.header_version >= 2 {
+                hashed.push(Section::Dtb);
+            }
+            for section in hashed {
+                let data = payload(section);
+                hasher.update(data);
+                hasher.update((data.len() as u32).to_le_bytes());
+            }
+            self.id = [0; 32];
+            self.id[..20].copy_from_slice(&hasher.finalize());
+            self.recovery_dtbo_offset =
+                sections.iter().find(|(s, _, _)| *s == Section::RecoveryDtbo).map_or(0, |&(_, offset, _)| offset);
+        }
+
+        let mut image = self.to_bytes();
+        let page = self.page_size as usize;
+        for (section, offset, _) in sections {
+            image.resize(offset as usize, 0);
+            image.extend_from_slice(payload(section));
+            image.resize(image.len().div_ceil(page) * page, 0);
+        }
+        Ok(image)
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, offset: usize, data: &[u8]) {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn u32(&mut self, offset: usize, v: u32) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+
+    fn u64(&mut self, offset: usize, v: u64) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        self.0
+            .get(offset..offset + len)
+            .ok_or_else(|| FlashError::PatchError("boot 镜像头部不完整".to_string()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    fn string(&self, offset: usize, len: usize) -> Result<String> {
+        let bytes = self.bytes(offset, len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put32(buf: &mut [u8], offset: usize, v: u32) {
+        buf[offset..offset + 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse_boot_v2_layout() {
+        let mut img = vec![0u8; 4096];
+        img[..8].copy_from_slice(BOOT_MAGIC);
+        put32(&mut img, 8, 5000); // kernel
+        put32(&mut img, 16, 100); // ramdisk
+        put32(&mut img, 36, 2048); // page_size
+        put32(&mut img, 40, 2);
+        // Android 11, 2021-03
+        put32(&mut img, 44, ((11 << 14) << 11) | (21 << 4) | 3);
+        img[64..72].copy_from_slice(b"console=");
+        put32(&mut img, 1648, 300); // dtb
+        let h = BootHeader::parse(&img).unwrap();
+        assert_eq!(h.kind, ImageKind::Boot);
+        assert_eq!(h.os_version_string().as_deref(), Some("11.0.0"));
+        assert_eq!(h.os_patch_level().as_deref(), Some("2021-03"));
+        assert_eq!(h.cmdline, "console=");
+        assert_eq!(
+            h.sections(),
+            vec![(Section::Kernel, 2048, 5000), (Section::Ramdisk, 8192, 100), (Section::Dtb, 10240, 300)]
+        );
+    }
+
+    #[test]
+    fn test_parse_vendor_boot_v4() {
+        let mut img = vec![0u8; 4096];
+        img[..8].copy_from_slice(VENDOR_BOOT_MAGIC);
+        put32(&mut img, 8, 4);
+        put32(&mut img, 12, 4096);
+        put32(&mut img, 24, 6000); // vendor ramdisk
+        put32(&mut img, 2100, 10); // dtb
+        put32(&mut img, 2112, 216); // table
+        put32(&mut img, 2116, 2);
+        put32(&mut img, 2120, 108);
+        let h = BootHeader::parse(&img).unwrap();
+        assert_eq!(h.kind, ImageKind::VendorBoot);
+        assert_eq!(
+            h.sections(),
+            vec![
+                (Section::Ramdisk, 4096, 6000),
+                (Section::Dtb, 12288, 10),
+                (Section::VendorRamdiskTable, 16384, 216),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_qcom_dt_roundtrip() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.page_size = 2048;
+        header.kernel_addr = 0x8000;
+        let image = header
+            .assemble(&[(Section::Kernel, vec![1u8; 3000]), (Section::Ramdisk, vec![2u8; 100]), (Section::QcomDt, vec![3u8; 5000])])
+            .unwrap();
+        assert_eq!(u32::from_le_bytes(image[40..44].try_into().unwrap()), 5000);
+
+        let h = BootHeader::parse(&image).unwrap();
+        assert_eq!((h.header_version, h.qcom_dt_size), (0, 5000));
+        assert_eq!(h.section(&image, Section::QcomDt).unwrap().unwrap(), vec![3u8; 5000]);
+        assert_eq!(h.to_bytes()[..], image[..2048]);
+
+        let mut rebuilt = h.clone();
+        let payloads: Vec<(Section, Vec<u8>)> =
+            h.sections().iter().map(|&(s, _, _)| (s, h.section(&image, s).unwrap().unwrap().to_vec())).collect();
+        assert_eq!(rebuilt.assemble(&payloads).unwrap(), image);
+    }
+}
@@ -0,0 +1,257 @@
+use super::header::{BootHeader, ImageKind, Section};
+use super::vendor::{VendorBootImage, VendorRamdiskEntry};
+use crate::avb::{self, VbmetaImage};
+use crate::cpio::Cpio;
+use crate::error::{FlashError, Result};
+use crate::dtb::find_appended_dtb;
+use crate::kernel::{KernelFormat, KernelImage};
+use crate::kernel_info::KernelAnalysis;
+use crate::utils::{self, RamdiskFormat};
+use std::fs;
+use std::path::Path;
+
+/// MTK 在 kernel / ramdisk 前附加的 512 字节头部
+const MTK_MAGIC: u32 = 0x5888_1688;
+pub(crate) const MTK_HEADER_SIZE: usize = 512;
+/// KernelPatch (APatch) 写入内核的 preset 魔数
+const KP_MAGIC: &[u8] = b"KP1158";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootSolution {
+    Magisk,
+    KernelSuLkm,
+    KernelSuBuiltin,
+    APatch,
+}
+
+impl RootSolution {
+    pub fn name(self) -> &'static str {
+        match self {
+            RootSolution::Magisk => "Magisk",
+            RootSolution::KernelSuLkm => "KernelSU (LKM)",
+            RootSolution::KernelSuBuiltin => "KernelSU (内置于内核)",
+            RootSolution::APatch => "APatch (KernelPatch)",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KernelInfo {
+    pub size: usize,
+    pub format: KernelFormat,
+    pub mtk_header: bool,
+    /// 内核后附加的 DTB (Image.gz-dtb) 的偏移
+    pub appended_dtb: Option<usize>,
+    /// 内核无法解压时为 None
+    pub analysis: Option<KernelAnalysis>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RamdiskInfo {
+    pub size: usize,
+    pub format: RamdiskFormat,
+    pub mtk_header: bool,
+    /// 解压后 cpio 魔数之前的厂商头部长度
+    pub vendor_header: usize,
+}
+
+/// boot / init_boot / vendor_boot 镜像的检查结果
+#[derive(Debug, Clone)]
+pub struct BootImageReport {
+    pub image_size: u64,
+    pub header: BootHeader,
+    pub kernel: Option<KernelInfo>,
+    pub ramdisk: Option<RamdiskInfo>,
+    /// vendor_boot v4 按 ramdisk 表拆分的各个 vendor ramdisk，此时 ramdisk 为 None
+    pub vendor_ramdisks: Vec<(VendorRamdiskEntry, RamdiskInfo)>,
+    pub dtb_size: u64,
+    pub avb: Option<VbmetaImage>,
+    pub root: Vec<RootSolution>,
+}
+
+impl BootImageReport {
+    pub fn has_dtb(&self) -> bool {
+        self.dtb_size > 0 || self.kernel.as_ref().is_some_and(|k| k.appended_dtb.is_some())
+    }
+}
+
+pub(crate) fn strip_mtk_header(data: &[u8]) -> (&[u8], bool) {
+    let is_mtk = data.len() >= MTK_HEADER_SIZE && u32::from_le_bytes(data[..4].try_into().unwrap()) == MTK_MAGIC;
+    if is_mtk { (&data[MTK_HEADER_SIZE..], true) } else { (data, false) }
+}
+
+fn decompress(data: &[u8], format: RamdiskFormat) -> Option<Vec<u8>> {
+    match format {
+        RamdiskFormat::Uncompressed => Some(data.to_vec()),
+        _ => utils::decompress_ramdisk(data).ok(),
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn inspect_kernel(data: &[u8], root: &mut Vec<RootSolution>) -> KernelInfo {
+    let (payload, mtk_header) = strip_mtk_header(data);
+    let format = KernelFormat::detect(payload);
+    let image = KernelImage::parse(data).ok();
+    let raw = image.as_ref().map(|k| k.raw.as_slice()).unwrap_or_default();
+    if contains(raw, KP_MAGIC) {
+        root.push(RootSolution::APatch);
+    }
+    if contains(raw, b"KernelSU") {
+        root.push(RootSolution::KernelSuBuiltin);
+    }
+    let analysis = image.as_ref().map(KernelAnalysis::from_image);
+    KernelInfo { size: data.len(), format, mtk_header, appended_dtb: find_appended_dtb(payload), analysis }
+}
+
+fn inspect_ramdisk(data: &[u8], root: &mut Vec<RootSolution>) -> RamdiskInfo {
+    let (payload, mtk_header) = strip_mtk_header(data);
+    let format = utils::detect_ramdisk_format(payload);
+    let raw = decompress(payload, format).unwrap_or_default();
+    let vendor_header = raw
+        .windows(6)
+        .position(|w| w == b"070701" || w == b"070702" || w == b"070707")
+        .unwrap_or(0);
+    let cpio = Cpio::parse(&raw[vendor_header..]).unwrap_or_default();
+    if cpio.contains(".backup/.magisk") {
+        root.push(RootSolution::Magisk);
+    }
+    if ["kernelsu.ko", "lib/modules/kernelsu.ko"].iter().any(|f| cpio.contains(f)) {
+        root.push(RootSolution::KernelSuLkm);
+    }
+    RamdiskInfo { size: data.len(), format, mtk_header, vendor_header }
+}
+
+/// 只读取并分析镜像，不做任何修改
+pub fn inspect(path: &Path) -> Result<BootImageReport> {
+    let data = fs::read(path)?;
+    let header = BootHeader::parse(&data)?;
+    let mut root = Vec::new();
+    let kernel = header.section(&data, Section::Kernel)?.map(|k| inspect_kernel(k, &mut root));
+    let mut vendor_ramdisks = Vec::new();
+    let mut ramdisk = None;
+    if header.kind == ImageKind::VendorBoot && header.header_version >= 4 {
+        for (entry, data) in VendorBootImage::parse(&data)?.ramdisks {
+            let info = inspect_ramdisk(&data, &mut root);
+            vendor_ramdisks.push((entry, info));
+        }
+    } else {
+        ramdisk = header.section(&data, Section::Ramdisk)?.map(|r| inspect_ramdisk(r, &mut root));
+    }
+    let dtb_size = header.section(&data, Section::Dtb)?.map_or(0, |d| d.len() as u64);
+    let avb = VbmetaImage::load(path).ok().filter(|image| image.footer.is_some());
+    Ok(BootImageReport { image_size: data.len() as u64, header, kernel, ramdisk, vendor_ramdisks, dtb_size, avb, root })
+}
+
+/// 分析 boot 镜像中的内核，也可以直接传入 kernel 分区镜像或 Image.gz 等内核文件
+pub fn analyze_kernel(path: &Path) -> Result<KernelAnalysis> {
+    let mut data = fs::read(path)?;
+    avb::strip_footer(&mut data);
+    match BootHeader::parse(&data) {
+        Ok(header) => {
+            let kernel = header
+                .section(&data, Section::Kernel)?
+                .filter(|k| !k.is_empty())
+                .ok_or_else(|| FlashError::PatchError("镜像中没有内核".to_string()))?;
+            KernelAnalysis::analyze(kernel)
+        }
+        Err(_) => KernelAnalysis::analyze(&data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootimg::{VendorRamdiskEntry, VendorRamdiskType};
+    use crate::cpio::CpioEntry;
+
+    fn ramdisk_with(path: &str, format: RamdiskFormat) -> Vec<u8> {
+        let mut cpio = Cpio::new();
+        cpio.insert(path, CpioEntry::file(0o644, b"x".to_vec()));
+        utils::compress_ramdisk(format, &cpio.to_bytes()).unwrap()
+    }
+
+    fn inspect_bytes(name: &str, data: &[u8]) -> BootImageReport {
+        let path = std::env::temp_dir().join(format!("rua_inspect_{}_{}.img", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        let report = inspect(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        report
+    }
+
+    #[test]
+    fn test_inspect_boot_versions() {
+        for version in 0..=4 {
+            let mut header = BootHeader::empty(ImageKind::Boot);
+            header.header_version = version;
+            header.page_size = if version >= 3 { 4096 } else { 2048 };
+            let mut kernel = vec![0u8; 3000];
+            kernel[100..108].copy_from_slice(b"KernelSU");
+            let mut ramdisk = ramdisk_with(".backup/.magisk", RamdiskFormat::Gzip);
+            if version == 1 {
+                let mut mtk = vec![0u8; MTK_HEADER_SIZE];
+                mtk[..4].copy_from_slice(&MTK_MAGIC.to_le_bytes());
+                mtk.append(&mut ramdisk);
+                ramdisk = mtk;
+            }
+            let mut sections = vec![(Section::Kernel, kernel), (Section::Ramdisk, ramdisk.clone())];
+            if version == 2 {
+                sections.push((Section::Dtb, vec![2u8; 100]));
+            }
+            let image = header.assemble(&sections).unwrap();
+
+            let report = inspect_bytes(&format!("v{}", version), &image);
+            assert_eq!(report.header.header_version, version);
+            assert_eq!(report.image_size, image.len() as u64);
+            let kernel = report.kernel.as_ref().unwrap();
+            assert_eq!((kernel.size, kernel.format, kernel.appended_dtb), (3000, KernelFormat::Raw, None));
+            let info = report.ramdisk.as_ref().unwrap();
+            assert_eq!((info.size, info.format, info.mtk_header), (ramdisk.len(), RamdiskFormat::Gzip, version == 1));
+            assert_eq!(report.dtb_size, if version == 2 { 100 } else { 0 });
+            assert_eq!(report.has_dtb(), version == 2);
+            assert_eq!(report.root, [RootSolution::KernelSuBuiltin, RootSolution::Magisk]);
+            assert!(report.vendor_ramdisks.is_empty() && report.avb.is_none());
+        }
+    }
+
+    #[test]
+    fn test_inspect_vendor_boot() {
+        let mut header = BootHeader::empty(ImageKind::VendorBoot);
+        header.header_version = 3;
+        header.page_size = 4096;
+        let ramdisk = ramdisk_with("lib/modules/kernelsu.ko", RamdiskFormat::Uncompressed);
+        let image = header.assemble(&[(Section::Ramdisk, ramdisk.clone()), (Section::Dtb, vec![2u8; 64])]).unwrap();
+        let report = inspect_bytes("vendor_v3", &image);
+        assert_eq!((report.header.kind, report.header.header_version), (ImageKind::VendorBoot, 3));
+        assert!(report.kernel.as_ref().is_none_or(|k| k.size == 0));
+        assert_eq!(report.ramdisk.as_ref().map(|r| (r.size, r.format)), Some((ramdisk.len(), RamdiskFormat::Uncompressed)));
+        assert_eq!(report.dtb_size, 64);
+        assert_eq!(report.root, [RootSolution::KernelSuLkm]);
+
+        let mut header = BootHeader::empty(ImageKind::VendorBoot);
+        header.header_version = 4;
+        header.page_size = 4096;
+        let entry = |name: &str, ramdisk_type| VendorRamdiskEntry { name: name.to_string(), ramdisk_type, board_id: vec![0; 16] };
+        let image = VendorBootImage {
+            header,
+            ramdisks: vec![
+                (entry("", VendorRamdiskType::Platform), ramdisk_with(".backup/.magisk", RamdiskFormat::Gzip)),
+                (entry("dlkm", VendorRamdiskType::Dlkm), ramdisk.clone()),
+            ],
+            dtb: vec![3u8; 32],
+            bootconfig: Vec::new(),
+        }
+        .to_bytes()
+        .unwrap();
+        let report = inspect_bytes("vendor_v4", &image);
+        assert_eq!(report.header.header_version, 4);
+        assert!(report.ramdisk.is_none());
+        let ramdisks: Vec<(&str, RamdiskFormat)> =
+            report.vendor_ramdisks.iter().map(|(e, info)| (e.name.as_str(), info.format)).collect();
+        assert_eq!(ramdisks, [("", RamdiskFormat::Gzip), ("dlkm", RamdiskFormat::Uncompressed)]);
+        assert_eq!(report.dtb_size, 32);
+        assert_eq!(report.root, [RootSolution::Magisk, RootSolution::KernelSuLkm]);
+    }
+}
@@ -2,6 +2,22 @@ use crate::error::{FlashError, Result};
 use android_bootimg::{parser::BootImage, patcher::BootImagePatchOption};
 use std::io::Cursor;
 
+pub mod dtb;
+pub mod edit;
+pub mod header;
+pub mod inspect;
+pub mod ramdisk;
+pub mod unpack;
+pub mod vendor;
+
+pub use dtb::{DtbFile, DtbLocation, extract_dtb, replace_dtb};
+pub use edit::{HeaderEdit, HeaderEditReport, edit_header};
+pub use header::{BootHeader, ImageKind, Section};
+pub use inspect::{BootImageReport, RootSolution, analyze_kernel, inspect};
+pub use ramdisk::ImageRamdisk;
+pub use unpack::{UnpackedHeader, repack, unpack};
+pub use vendor::{RamdiskChoice, VendorBootImage, VendorRamdiskEntry, VendorRamdiskType};
+
 pub fn new_patcher<'a>(boot_img: &'a BootImage) -> BootImagePatchOption<'a> {
     BootImagePatchOption::new(boot_img)
 }
@@ -0,0 +1,143 @@
+use super::header::{BootHeader, ImageKind, Section};
+use super::inspect::{MTK_HEADER_SIZE, strip_mtk_header};
+use super::vendor::{RamdiskChoice, VendorBootImage};
+use crate::avb;
+use crate::cpio::Cpio;
+use crate::error::{FlashError, Result};
+use crate::utils::{self, RamdiskFormat};
+use std::fs;
+use std::path::Path;
+
+/// 镜像中的 ramdisk 及写回时需要保留的信息：压缩格式、MTK 头部与 cpio 之前的厂商头部
+#[derive(Debug, Clone)]
+pub struct ImageRamdisk {
+    /// 已去除 AVB footer 的镜像
+    data: Vec<u8>,
+    vendor: Option<(VendorBootImage, usize)>,
+    mtk_header: Option<Vec<u8>>,
+    vendor_header: Vec<u8>,
+    pub format: RamdiskFormat,
+    pub cpio: Cpio,
+    pub avb_footer_removed: bool,
+}
+
+impl ImageRamdisk {
+    /// vendor_ramdisk 仅对 vendor_boot 生效，含义同 VendorBootImage::select_ramdisk。
+    /// 没有 ramdisk 的镜像得到空归档，写回时使用 lz4_legacy 压缩
+    pub fn load(path: &Path, vendor_ramdisk: Option<RamdiskChoice<'_>>) -> Result<Self> {
+        let mut data = fs::read(path)?;
+        let avb_footer_removed = avb::strip_footer(&mut data).is_some();
+        let header = BootHeader::parse(&data)?;
+        let (raw, vendor) = if header.kind == ImageKind::VendorBoot {
+            let image = VendorBootImage::parse(&data)?;
+            let index = image.select_ramdisk(vendor_ramdisk)?;
+            (image.ramdisks[index].1.clone(), Some((image, index)))
+        } else {
+            (header.section(&data, Section::Ramdisk)?.unwrap_or_default().to_vec(), None)
+        };
+
+        if raw.is_empty() {
+            return Ok(Self {
+                data,
+                vendor,
+                mtk_header: None,
+                vendor_header: Vec::new(),
+                format: RamdiskFormat::Lz4Legacy,
+                cpio: Cpio::new(),
+                avb_footer_removed,
+            });
+        }
+        let (payload, mtk) = strip_mtk_header(&raw);
+        let format = utils::detect_ramdisk_format(payload);
+        let decompressed = match format {
+            RamdiskFormat::Uncompressed => payload.to_vec(),
+            _ => utils::decompress_ramdisk(payload)?,
+        };
+        let start = decompressed
+            .windows(6)
+            .position(|w| w == b"070701" || w == b"070702")
+            .ok_or_else(|| FlashError::PatchError("ramdisk 中没有 newc 格式的 cpio".to_string()))?;
+        Ok(Self {
+            mtk_header: mtk.then(|| raw[..MTK_HEADER_SIZE].to_vec()),
+            vendor_header: decompressed[..start].to_vec(),
+            cpio: Cpio::parse(&decompressed[start..])?,
+            data,
+            vendor,
+            format,
+            avb_footer_removed,
+        })
+    }
+
+    /// 按原压缩格式重新打包 ramdisk 并写回镜像，其余数据段保持原样
+    pub fn to_image(&self) -> Result<Vec<u8>> {
+        let cpio = [self.vendor_header.as_slice(), &self.cpio.to_bytes()].concat();
+        let mut ramdisk = utils::compress_ramdisk(self.format, &cpio)?;
+        if let Some(mtk_header) = &self.mtk_header {
+            let mut with_header = mtk_header.clone();
+            with_header[4..8].copy_from_slice(&(ramdisk.len() as u32).to_le_bytes());
+            with_header.append(&mut ramdisk);
+            ramdisk = with_header;
+        }
+
+        if let Some((image, index)) = &self.vendor {
+            let mut image = image.clone();
+            image.ramdisks[*index].1 = ramdisk;
+            return image.to_bytes();
+        }
+        let mut header = BootHeader::parse(&self.data)?;
+        let mut payloads = vec![(Section::Ramdisk, ramdisk)];
+        for (section, _, _) in header.sections() {
+            if section != Section::Ramdisk {
+                payloads.push((section, header.section(&self.data, section)?.unwrap_or_default().to_vec()));
+            }
+        }
+        header.assemble(&payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpio::CpioCommand;
+
+    #[test]
+    fn test_ramdisk_commands_repack() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.header_version = 4;
+        header.page_size = 4096;
+        let mut cpio = Cpio::new();
+        cpio.exec(&CpioCommand::parse("mkdir 0755 system").unwrap()).unwrap();
+        let ramdisk = utils::compress_ramdisk(RamdiskFormat::Gzip, &cpio.to_bytes()).unwrap();
+        let image = header.assemble(&[(Section::Kernel, vec![1u8; 5000]), (Section::Ramdisk, ramdisk)]).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("rua_ramdisk_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, rc, orig) = (dir.join("boot.img"), dir.join("init.custom.rc"), dir.join("orig.cpio"));
+        fs::write(&src, &image).unwrap();
+        fs::write(&rc, b"on boot\n").unwrap();
+        fs::write(&orig, cpio.to_bytes()).unwrap();
+
+        let mut loaded = ImageRamdisk::load(&src, None).unwrap();
+        assert_eq!(loaded.format, RamdiskFormat::Gzip);
+        assert_eq!(loaded.to_image().unwrap(), image);
+        let script = [
+            format!("add 0644 init.custom.rc {}", rc.display()),
+            "ln /system/bin/sh system/sh".to_string(),
+            "mv system vendor".to_string(),
+            format!("backup {}", orig.display()),
+        ];
+        for line in &script {
+            loaded.cpio.exec(&CpioCommand::parse(line).unwrap()).unwrap();
+        }
+        assert!(loaded.cpio.get("vendor/sh").unwrap().is_symlink());
+        assert!(loaded.cpio.contains(".backup/system"));
+
+        let repacked = dir.join("patched.img");
+        fs::write(&repacked, loaded.to_image().unwrap()).unwrap();
+        let mut reloaded = ImageRamdisk::load(&repacked, None).unwrap();
+        assert_eq!(reloaded.cpio.get("init.custom.rc").unwrap().data, b"on boot\n");
+        reloaded.cpio.exec(&CpioCommand::Restore).unwrap();
+        assert_eq!(reloaded.cpio.to_bytes(), cpio.to_bytes());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,275 @@
+use super::header::{BootHeader, ImageKind, Section};
+use super::inspect::{MTK_HEADER_SIZE, strip_mtk_header};
+use super::vendor::{VendorBootImage, VendorRamdiskEntry};
+use crate::avb;
+use crate::error::{FlashError, Result};
+use crate::utils::{self, RamdiskFormat};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const HEADER_FILE: &str = "header.json";
+pub const RAMDISK_CPIO_FILE: &str = "ramdisk.cpio";
+/// 目录中没有 ramdisk.cpio 时，原样打包的 ramdisk
+pub const RAMDISK_RAW_FILE: &str = "ramdisk";
+const RAMDISK_MTK_FILE: &str = "ramdisk.mtk";
+/// vendor_boot v4 的各个 vendor ramdisk 解压到该子目录
+pub const VENDOR_RAMDISK_DIR: &str = "vendor_ramdisk";
+
+/// header.json 中记录的一个 vendor ramdisk，file 为 vendor_ramdisk 目录下的 cpio 文件名
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnpackedVendorRamdisk {
+    #[serde(flatten)]
+    pub entry: VendorRamdiskEntry,
+    pub file: String,
+    pub format: RamdiskFormat,
+}
+
+/// 解包目录中的 header.json，可手动修改命令行、系统版本等字段后重新打包
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnpackedHeader {
+    pub kind: ImageKind,
+    pub header_version: u32,
+    pub page_size: u32,
+    pub kernel_addr: u32,
+    pub ramdisk_addr: u32,
+    pub second_addr: u32,
+    pub tags_addr: u32,
+    pub dtb_addr: u64,
+    pub os_version: Option<String>,
+    pub os_patch_level: Option<String>,
+    pub name: String,
+    pub cmdline: String,
+    /// ramdisk.cpio 重新打包时使用的压缩格式；为 None 时 ramdisk 以原始数据保存
+    pub ramdisk_format: Option<RamdiskFormat>,
+    /// 仅 vendor_boot v4：按 ramdisk 表顺序排列，重新打包时重新生成 ramdisk 表
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vendor_ramdisks: Vec<UnpackedVendorRamdisk>,
+    /// 原镜像带有 AVB footer；重新打包的镜像不含 footer，需要重新签名或关闭校验
+    #[serde(default)]
+    pub avb_footer_removed: bool,
+}
+
+fn section_file(section: Section) -> &'static str {
+    match section {
+        Section::Ramdisk => RAMDISK_RAW_FILE,
+        other => other.name(),
+    }
+}
+
+/// 解包 boot / init_boot / vendor_boot 镜像到目录：各数据段写为单独文件，
+/// ramdisk 解压为 ramdisk.cpio (vendor_boot v4 按序号解压到 vendor_ramdisk 目录)，头部字段写入 header.json
+pub fn unpack(image: &Path, out_dir: &Path) -> Result<UnpackedHeader> {
+    let mut data = fs::read(image)?;
+    let avb_footer_removed = avb::strip_footer(&mut data).is_some();
+    let header = BootHeader::parse(&data)?;
+    fs::create_dir_all(out_dir)?;
+
+    let split_vendor = header.kind == ImageKind::VendorBoot && header.header_version >= 4;
+    let mut vendor_ramdisks = Vec::new();
+    if split_vendor {
+        let image = VendorBootImage::parse(&data)?;
+        fs::create_dir_all(out_dir.join(VENDOR_RAMDISK_DIR))?;
+        for (index, (entry, content)) in image.ramdisks.iter().enumerate() {
+            let format = utils::detect_ramdisk_format(content);
+            let cpio = match format {
+                RamdiskFormat::Uncompressed => content.to_vec(),
+                _ => utils::decompress_ramdisk(content)?,
+            };
+            // 名称来自镜像中的 ramdisk 表，不能直接作为文件名，只记录在 header.json 中
+            let file = format!("vendor_ramdisk_{}.cpio", index);
+            fs::write(out_dir.join(VENDOR_RAMDISK_DIR).join(&file), cpio)?;
+            vendor_ramdisks.push(UnpackedVendorRamdisk { entry: entry.clone(), file, format });
+        }
+    }
+
+    let mut ramdisk_format = None;
+    for (section, _, _) in header.sections() {
+        // ramdisk 表在重新打包时按 vendor_ramdisks 重新生成
+        if split_vendor && matches!(section, Section::Ramdisk | Section::VendorRamdiskTable) {
+            continue;
+        }
+        let Some(content) = header.section(&data, section)? else { continue };
+        if section == Section::Ramdisk {
+            let (payload, mtk) = strip_mtk_header(content);
+            let format = utils::detect_ramdisk_format(payload);
+            let cpio = match format {
+                RamdiskFormat::Uncompressed => payload.to_vec(),
+                _ => utils::decompress_ramdisk(payload)?,
+            };
+            if mtk {
+                fs::write(out_dir.join(RAMDISK_MTK_FILE), &content[..MTK_HEADER_SIZE])?;
+            }
+            fs::write(out_dir.join(RAMDISK_CPIO_FILE), cpio)?;
+            ramdisk_format = Some(format);
+            continue;
+        }
+        fs::write(out_dir.join(section_file(section)), content)?;
+    }
+
+    let config = UnpackedHeader {
+        kind: header.kind,
+        header_version: header.header_version,
+        page_size: header.page_size,
+        kernel_addr: header.kernel_addr,
+        ramdisk_addr: header.ramdisk_addr,
+        second_addr: header.second_addr,
+        tags_addr: header.tags_addr,
+        dtb_addr: header.dtb_addr,
+        os_version: header.os_version_string(),
+        os_patch_level: header.os_patch_level(),
+        name: header.name.clone(),
+        cmdline: header.full_cmdline(),
+        ramdisk_format,
+        vendor_ramdisks,
+        avb_footer_removed,
+    };
+    let text = serde_json::to_string_pretty(&config).map_err(|e| FlashError::UnpackError(e.to_string()))?;
+    fs::write(out_dir.join(HEADER_FILE), text)?;
+    Ok(config)
+}
+
+/// 按解包目录重新生成镜像。ramdisk.cpio 按 header.json 中记录的原格式压缩，
+/// 目录中缺失的数据段视为空。返回读取到的 header.json，调用方据此提示 AVB footer 已去除
+pub fn repack(dir: &Path, output: &Path) -> Result<UnpackedHeader> {
+    let text = fs::read_to_string(dir.join(HEADER_FILE))
+        .map_err(|e| FlashError::UnpackError(format!("读取 {} 失败: {}", HEADER_FILE, e)))?;
+    let config: UnpackedHeader =
+        serde_json::from_str(&text).map_err(|e| FlashError::UnpackError(format!("{} 格式错误: {}", HEADER_FILE, e)))?;
+
+    let mut header = BootHeader::empty(config.kind);
+    header.header_version = config.header_version;
+    header.page_size = config.page_size;
+    header.kernel_addr = config.kernel_addr;
+    header.ramdisk_addr = config.ramdisk_addr;
+    header.second_addr = config.second_addr;
+    header.tags_addr = config.tags_addr;
+    header.dtb_addr = config.dtb_addr;
+    header.name = config.name.clone();
+    header.set_cmdline(&config.cmdline)?;
+    if let Some(version) = &config.os_version {
+        header.set_os_version(version)?;
+    }
+    if let Some(level) = &config.os_patch_level {
+        header.set_os_patch_level(level)?;
+    }
+
+    let read_optional = |section: Section| -> Result<Vec<u8>> {
+        let path = dir.join(section_file(section));
+        Ok(if path.is_file() { fs::read(path)? } else { Vec::new() })
+    };
+
+    if config.kind == ImageKind::VendorBoot && config.header_version >= 4 {
+        let mut ramdisks = Vec::new();
+        for item in &config.vendor_ramdisks {
+            let cpio = fs::read(dir.join(VENDOR_RAMDISK_DIR).join(&item.file))
+                .map_err(|e| FlashError::UnpackError(format!("读取 {} 失败: {}", item.file, e)))?;
+            ramdisks.push((item.entry.clone(), utils::compress_ramdisk(item.format, &cpio)?));
+        }
+        let image = VendorBootImage {
+            header,
+            ramdisks,
+            dtb: read_optional(Section::Dtb)?,
+            bootconfig: read_optional(Section::Bootconfig)?,
+        };
+        fs::write(output, image.to_bytes()?)?;
+        return Ok(config);
+    }
+
+    let all = [
+        Section::Kernel,
+        Section::Ramdisk,
+        Section::Second,
+        Section::RecoveryDtbo,
+        Section::Dtb,
+        Section::Signature,
+        Section::Bootconfig,
+        Section::QcomDt,
+    ];
+    let cpio_path = dir.join(RAMDISK_CPIO_FILE);
+    let mut payloads = Vec::new();
+    for section in all {
+        let data = if section == Section::Ramdisk && cpio_path.is_file() {
+            let format = config.ramdisk_format.unwrap_or(RamdiskFormat::Uncompressed);
+            let mut ramdisk = utils::compress_ramdisk(format, &fs::read(&cpio_path)?)?;
+            let mtk_path = dir.join(RAMDISK_MTK_FILE);
+            if mtk_path.is_file() {
+                let mut mtk = fs::read(&mtk_path)?;
+                if mtk.len() != MTK_HEADER_SIZE || !strip_mtk_header(&mtk).1 {
+                    return Err(FlashError::UnpackError(format!("{} 不是有效的 MTK 头部", RAMDISK_MTK_FILE)));
+                }
+                mtk[4..8].copy_from_slice(&(ramdisk.len() as u32).to_le_bytes());
+                mtk.append(&mut ramdisk);
+                ramdisk = mtk;
+            }
+            ramdisk
+        } else {
+            read_optional(section)?
+        };
+        payloads.push((section, data));
+    }
+
+    let image = header.assemble(&payloads)?;
+    fs::write(output, image)?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpack_repack_roundtrip() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.header_version = 2;
+        header.page_size = 2048;
+        header.kernel_addr = 0x8000;
+        header.set_cmdline(&"a=b ".repeat(200)).unwrap();
+        header.set_os_version("13.0.0").unwrap();
+        header.set_os_patch_level("2023-07").unwrap();
+        let image = header
+            .assemble(&[(Section::Kernel, vec![1u8; 3000]), (Section::Dtb, vec![2u8; 100])])
+            .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("rua_unpack_test_{}", std::process::id()));
+        let src = dir.join("boot.img");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&src, &image).unwrap();
+        let config = unpack(&src, &dir.join("out")).unwrap();
+        assert_eq!(config.os_patch_level.as_deref(), Some("2023-07"));
+        assert_eq!(config.cmdline.len(), 800);
+        assert!(!config.avb_footer_removed);
+
+        let dst = dir.join("new.img");
+        repack(&dir.join("out"), &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), image);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unpack_strips_avb_footer() {
+        let mut header = BootHeader::empty(ImageKind::Boot);
+        header.header_version = 2;
+        header.page_size = 4096;
+        let image = header.assemble(&[(Section::Kernel, vec![1u8; 5000])]).unwrap();
+        let mut signed = image.clone();
+        signed.resize(image.len() + 8192, 0);
+        let mut footer = vec![0u8; 64];
+        footer[0..4].copy_from_slice(b"AVBf");
+        footer[4..8].copy_from_slice(&1u32.to_be_bytes());
+        footer[12..20].copy_from_slice(&(image.len() as u64).to_be_bytes());
+        footer[20..28].copy_from_slice(&(image.len() as u64).to_be_bytes());
+        signed.extend_from_slice(&footer);
+
+        let dir = std::env::temp_dir().join(format!("rua_unpack_avb_test_{}", std::process::id()));
+        let src = dir.join("boot.img");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&src, &signed).unwrap();
+        assert!(unpack(&src, &dir.join("out")).unwrap().avb_footer_removed);
+
+        let dst = dir.join("new.img");
+        assert!(repack(&dir.join("out"), &dst).unwrap().avb_footer_removed);
+        assert_eq!(fs::read(&dst).unwrap(), image);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,230 @@
+use super::header::{BootHeader, ImageKind, Section, VENDOR_BOOT_MAGIC};
+use crate::error::{FlashError, Result};
+use serde::{Deserialize, Serialize};
+
+/// vendor_boot v4 ramdisk 表中每一项的大小
+pub const VENDOR_RAMDISK_TABLE_ENTRY_SIZE: u32 = 108;
+const VENDOR_RAMDISK_NAME_SIZE: usize = 32;
+const VENDOR_RAMDISK_BOARD_ID_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VendorRamdiskType {
+    None,
+    Platform,
+    Recovery,
+    Dlkm,
+    Other(u32),
+}
+
+impl VendorRamdiskType {
+    fn from_u32(v: u32) -> Self {
+        match v {
+            0 => Self::None,
+            1 => Self::Platform,
+            2 => Self::Recovery,
+            3 => Self::Dlkm,
+            other => Self::Other(other),
+        }
+    }
+
+    fn as_u32(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Platform => 1,
+            Self::Recovery => 2,
+            Self::Dlkm => 3,
+            Self::Other(v) => v,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Platform => "platform",
+            Self::Recovery => "recovery",
+            Self::Dlkm => "dlkm",
+            Self::Other(_) => "other",
+        }
+    }
+}
+
+/// vendor ramdisk 表中的一项（不含大小与偏移，重新打包时按数据计算）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VendorRamdiskEntry {
+    pub name: String,
+    pub ramdisk_type: VendorRamdiskType,
+    pub board_id: Vec<u32>,
+}
+
+impl VendorRamdiskEntry {
+    /// 用于界面显示，v3 或未命名的片段按序号命名
+    pub fn display_name(&self, index: usize) -> String {
+        if self.name.is_empty() { format!("ramdisk_{}", index) } else { self.name.clone() }
+    }
+}
+
+/// 要修补的 vendor ramdisk：命令行按名称或类型指定，交互界面按 ramdisk 表中的序号选择
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamdiskChoice<'a> {
+    Name(&'a str),
+    Index(usize),
+}
+
+/// 拆分后的 vendor_boot：每个 vendor ramdisk 片段单独保存，便于只修补其中一个
+#[derive(Debug, Clone, PartialEq)]
+pub struct VendorBootImage {
+    pub header: BootHeader,
+    pub ramdisks: Vec<(VendorRamdiskEntry, Vec<u8>)>,
+    pub dtb: Vec<u8>,
+    pub bootconfig: Vec<u8>,
+}
+
+pub fn is_vendor_boot(data: &[u8]) -> bool {
+    data.starts_with(VENDOR_BOOT_MAGIC)
+}
+
+impl VendorBootImage {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let header = BootHeader::parse(data)?;
+        if header.kind != ImageKind::VendorBoot {
+            return Err(FlashError::PatchError("不是 vendor_boot 镜像".to_string()));
+        }
+        let section = |s| header.section(data, s).map(|d| d.unwrap_or_default().to_vec());
+        let ramdisk = section(Section::Ramdisk)?;
+        let dtb = section(Section::Dtb)?;
+        let bootconfig = section(Section::Bootconfig)?;
+
+        let mut ramdisks = Vec::new();
+        if header.header_version < 4 {
+            let entry = VendorRamdiskEntry { name: String::new(), ramdisk_type: VendorRamdiskType::Platform, board_id: vec![0; 16] };
+            ramdisks.push((entry, ramdisk));
+        } else {
+            let table = section(Section::VendorRamdiskTable)?;
+            let entry_size = header.vendor_ramdisk_table_entry_size as usize;
+            if entry_size < VENDOR_RAMDISK_TABLE_ENTRY_SIZE as usize {
+                return Err(FlashError::PatchError(format!("无效的 vendor ramdisk 表项大小: {}", entry_size)));
+            }
+            for i in 0..header.vendor_ramdisk_table_entry_num as usize {
+                let raw = table
+                    .get(i * entry_size..i * entry_size + VENDOR_RAMDISK_TABLE_ENTRY_SIZE as usize)
+                    .ok_or_else(|| FlashError::PatchError("vendor ramdisk 表不完整".to_string()))?;
+                let le32 = |off: usize| u32::from_le_bytes(raw[off..off + 4].try_into().unwrap());
+                let (size, offset) = (le32(0) as usize, le32(4) as usize);
+                let data = ramdisk
+                    .get(offset..offset + size)
+                    .ok_or_else(|| FlashError::PatchError(format!("vendor ramdisk #{} 超出范围", i)))?;
+                let name_bytes = &raw[12..12 + VENDOR_RAMDISK_NAME_SIZE];
+                let end = name_bytes.iter().position(|&b| b == 0).unwrap_or(name_bytes.len());
+                let entry = VendorRamdiskEntry {
+                    name: String::from_utf8_lossy(&name_bytes[..end]).to_string(),
+                    ramdisk_type: VendorRamdiskType::from_u32(le32(8)),
+                    board_id: (0..VENDOR_RAMDISK_BOARD_ID_SIZE).map(|j| le32(44 + j * 4)).collect(),
+                };
+                ramdisks.push((entry, data.to_vec()));
+            }
+        }
+        Ok(Self { header, ramdisks, dtb, bootconfig })
+    }
+
+    /// 按名称、类型 (platform/recovery/dlkm) 或序号选择 vendor ramdisk。未指定时与 Magisk 一致：
+    /// 优先名为 init_boot 的片段，其次第一个 platform 片段，只有一个片段时直接使用
+    pub fn select_ramdisk(&self, choice: Option<RamdiskChoice<'_>>) -> Result<usize> {
+        let found = match choice {
+            Some(RamdiskChoice::Name(name)) => self
+                .ramdisks
+                .iter()
+                .position(|(e, _)| e.name == name)
+                .or_else(|| self.ramdisks.iter().position(|(e, _)| e.ramdisk_type.name().eq_ignore_ascii_case(name)))
+                .or_else(|| {
+                    let index = name.strip_prefix("ramdisk_")?.parse::<usize>().ok()?;
+                    (index < self.ramdisks.len()).then_some(index)
+                }),
+            Some(RamdiskChoice::Index(index)) => (index < self.ramdisks.len()).then_some(index),
+            None => self
+                .ramdisks
+                .iter()
+                .position(|(e, _)| e.name == "init_boot")
+                .or_else(|| self.ramdisks.iter().position(|(e, _)| e.ramdisk_type == VendorRamdiskType::Platform))
+                .or_else(|| (self.ramdisks.len() == 1).then_some(0)),
+        };
+        found.ok_or_else(|| {
+            let names: Vec<String> = self.ramdisks.iter().enumerate().map(|(i, (e, _))| e.display_name(i)).collect();
+            let wanted = match choice {
+                Some(RamdiskChoice::Name(name)) => name.to_string(),
+                Some(RamdiskChoice::Index(index)) => format!("#{}", index + 1),
+                None => "(默认)".to_string(),
+            };
+            FlashError::InvalidChoice(format!("未找到 vendor ramdisk {}，可选: {}", wanted, names.join(", ")))
+        })
+    }
+
+    /// 重新生成 vendor_boot，ramdisk 表按各片段的新大小重新计算
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut header = self.header.clone();
+        let mut ramdisk = Vec::new();
+        let mut table = Vec::new();
+        if header.header_version < 4 && self.ramdisks.len() != 1 {
+            return Err(FlashError::PatchError("vendor_boot v3 只能包含一个 ramdisk".to_string()));
+        }
+        for (entry, data) in &self.ramdisks {
+            if entry.name.len() >= VENDOR_RAMDISK_NAME_SIZE {
+                return Err(FlashError::PatchError(format!("vendor ramdisk 名称过长: {}", entry.name)));
+            }
+            table.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            table.extend_from_slice(&(ramdisk.len() as u32).to_le_bytes());
+            table.extend_from_slice(&entry.ramdisk_type.as_u32().to_le_bytes());
+            let mut name = [0u8; VENDOR_RAMDISK_NAME_SIZE];
+            name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            table.extend_from_slice(&name);
+            for j in 0..VENDOR_RAMDISK_BOARD_ID_SIZE {
+                table.extend_from_slice(&entry.board_id.get(j).copied().unwrap_or(0).to_le_bytes());
+            }
+            ramdisk.extend_from_slice(data);
+        }
+
+        let mut payloads = vec![(Section::Ramdisk, ramdisk), (Section::Dtb, self.dtb.clone())];
+        if header.header_version >= 4 {
+            header.vendor_ramdisk_table_entry_num = self.ramdisks.len() as u32;
+            header.vendor_ramdisk_table_entry_size = VENDOR_RAMDISK_TABLE_ENTRY_SIZE;
+            payloads.push((Section::VendorRamdiskTable, table));
+            payloads.push((Section::Bootconfig, self.bootconfig.clone()));
+        }
+        header.assemble(&payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vendor_boot_v4_roundtrip() {
+        let mut header = BootHeader::empty(ImageKind::VendorBoot);
+        header.header_version = 4;
+        header.page_size = 4096;
+        let entry = |name: &str, ramdisk_type| VendorRamdiskEntry { name: name.to_string(), ramdisk_type, board_id: vec![0; 16] };
+        let mut image = VendorBootImage {
+            header,
+            ramdisks: vec![
+                (entry("", VendorRamdiskType::Dlkm), vec![1u8; 100]),
+                (entry("init_boot", VendorRamdiskType::Platform), vec![2u8; 5000]),
+            ],
+            dtb: vec![3u8; 10],
+            bootconfig: b"androidboot.hardware=qcom\n".to_vec(),
+        };
+        let bytes = image.to_bytes().unwrap();
+        let parsed = VendorBootImage::parse(&bytes).unwrap();
+        assert_eq!(parsed.ramdisks, image.ramdisks);
+        assert_eq!(parsed.bootconfig, image.bootconfig);
+        assert_eq!(parsed.select_ramdisk(None).unwrap(), 1);
+        assert_eq!(parsed.select_ramdisk(Some(RamdiskChoice::Name("dlkm"))).unwrap(), 0);
+        assert_eq!(parsed.select_ramdisk(Some(RamdiskChoice::Index(0))).unwrap(), 0);
+        assert!(parsed.select_ramdisk(Some(RamdiskChoice::Index(2))).is_err());
+
+        image.ramdisks[1].1 = vec![4u8; 10];
+        let parsed = VendorBootImage::parse(&image.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.ramdisks[1].1, vec![4u8; 10]);
+        assert_eq!(parsed.ramdisks[0].1, vec![1u8; 100]);
+    }
+}
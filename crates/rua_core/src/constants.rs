@@ -40,5 +40,7 @@ pub const MENU_OPTIONS: &[(&str, &str)] = &[
     ("20", "切换槽位 (极其危险)"),
     ("21", "ADB 激活 (Shizuku/冰箱/黑阈等)"),
     ("22", "打开设备管理器"),
+    ("23", "多设备并行刷入 (批量刷机)"),
+    ("24", "镜像工具箱"),
     ("0", "退出程序"),
 ];
@@ -0,0 +1,796 @@
+use crate::error::{FlashError, Result};
+use crate::utils::{self, RamdiskFormat};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const MAGIC_NEWC: &[u8; 6] = b"070701";
+const MAGIC_CRC: &[u8; 6] = b"070702";
+const TRAILER: &str = "TRAILER!!!";
+const HEADER_SIZE: usize = 110;
+const BLOCK_SIZE: usize = 512;
+const BACKUP_DIR: &str = ".backup";
+const RMLIST: &str = ".backup/.rmlist";
+const MAGISK_CONFIG: &str = ".backup/.magisk";
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+
+fn invalid(msg: String) -> FlashError {
+    FlashError::PatchError(format!("无效的 cpio 归档: {}", msg))
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+/// newc 头部中的全部字段；namesize / filesize 由名称与数据推出
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpioEntry {
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u32,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    /// 070702 格式的校验和，070701 中恒为 0
+    pub check: u32,
+    /// 普通文件为内容，符号链接为目标路径
+    pub data: Vec<u8>,
+}
+
+impl CpioEntry {
+    /// 新建条目默认属主为 root，nlink 为 1，mtime 为 0；ino 在加入归档时分配
+    fn new(mode: u32, data: Vec<u8>) -> Self {
+        Self { mode, nlink: 1, data, ..Self::default() }
+    }
+
+    pub fn file(perm: u32, data: Vec<u8>) -> Self {
+        Self::new(S_IFREG | (perm & 0o7777), data)
+    }
+
+    pub fn dir(perm: u32) -> Self {
+        Self::new(S_IFDIR | (perm & 0o7777), Vec::new())
+    }
+
+    pub fn symlink(target: &str) -> Self {
+        Self::new(S_IFLNK | 0o777, target.as_bytes().to_vec())
+    }
+
+    pub fn device(kind: EntryKind, perm: u32, major: u32, minor: u32) -> Self {
+        let ty = if kind == EntryKind::BlockDevice { S_IFBLK } else { S_IFCHR };
+        Self { rdev_major: major, rdev_minor: minor, ..Self::new(ty | (perm & 0o7777), Vec::new()) }
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        match self.mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Dir,
+            S_IFLNK => EntryKind::Symlink,
+            S_IFCHR => EntryKind::CharDevice,
+            S_IFBLK => EntryKind::BlockDevice,
+            S_IFIFO => EntryKind::Fifo,
+            S_IFSOCK => EntryKind::Socket,
+            _ => EntryKind::Unknown,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind() == EntryKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind() == EntryKind::Symlink
+    }
+
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+
+    /// 符号链接的目标路径
+    pub fn link_target(&self) -> Option<String> {
+        self.is_symlink().then(|| String::from_utf8_lossy(&self.data).to_string())
+    }
+}
+
+/// 路径按目录层级逐级比较，与 mkbootfs 深度优先、目录内按名称排序的输出顺序一致
+fn path_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    a.split('/').cmp(b.split('/'))
+}
+
+fn normalize(name: &str) -> &str {
+    name.trim_start_matches("./").trim_matches('/')
+}
+
+/// 完整保留 newc 字段的 cpio 归档。条目保持读入时的顺序，新条目按路径顺序插入，
+/// 未作修改时 to_bytes 的输出与输入逐字节一致
+#[derive(Debug, Clone)]
+pub struct Cpio {
+    entries: Vec<(String, CpioEntry)>,
+    crc: bool,
+    uppercase: bool,
+    trailer: CpioEntry,
+    /// 原归档按 512 字节块补零，修改后写回时重新补齐
+    block_padded: bool,
+    /// TRAILER!!! 之后除补齐零以外的数据，原样写回
+    tail: Vec<u8>,
+}
+
+impl Default for Cpio {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            crc: false,
+            uppercase: false,
+            trailer: CpioEntry::new(0, Vec::new()),
+            block_padded: false,
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl Cpio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 解析 070701 / 070702 格式，任何截断或格式错误都会返回错误而不是静默截止
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut cpio = Self::default();
+        let mut pos = 0;
+        let mut first = true;
+        loop {
+            let header = data
+                .get(pos..pos + HEADER_SIZE)
+                .ok_or_else(|| invalid(format!("偏移 {} 处缺少 TRAILER!!!", pos)))?;
+            let magic = &header[..6];
+            if magic != MAGIC_NEWC && magic != MAGIC_CRC {
+                return Err(invalid(format!("偏移 {} 处魔数错误 {:02x?}", pos, magic)));
+            }
+            if first {
+                cpio.crc = magic == MAGIC_CRC;
+                cpio.uppercase = header[6..].iter().any(u8::is_ascii_uppercase);
+                first = false;
+            }
+            let mut fields = [0u32; 13];
+            for (i, field) in fields.iter_mut().enumerate() {
+                let hex = std::str::from_utf8(&header[6 + i * 8..14 + i * 8]).unwrap_or("");
+                *field = u32::from_str_radix(hex, 16)
+                    .map_err(|_| invalid(format!("偏移 {} 处头部字段不是十六进制", pos)))?;
+            }
+            let [ino, mode, uid, gid, nlink, mtime, filesize, dev_major, dev_minor, rdev_major, rdev_minor, namesize, check] = fields;
+
+            let name_start = pos + HEADER_SIZE;
+            let name = data
+                .get(name_start..name_start + namesize as usize)
+                .ok_or_else(|| invalid(format!("偏移 {} 处文件名越界", pos)))?;
+            let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name)).to_string();
+            let data_start = align4(name_start + namesize as usize);
+            let content = data
+                .get(data_start..data_start + filesize as usize)
+                .ok_or_else(|| invalid(format!("{} 的数据越界", name)))?
+                .to_vec();
+            pos = align4(data_start + filesize as usize);
+
+            let entry = CpioEntry {
+                ino, mode, uid, gid, nlink, mtime, dev_major, dev_minor, rdev_major, rdev_minor, check, data: content,
+            };
+            if name == TRAILER {
+                cpio.trailer = entry;
+                let tail = data.get(pos..).unwrap_or_default();
+                if data.len().is_multiple_of(BLOCK_SIZE) && tail.len() < BLOCK_SIZE && tail.iter().all(|&b| b == 0) {
+                    cpio.block_padded = true;
+                } else {
+                    cpio.tail = tail.to_vec();
+                }
+                return Ok(cpio);
+            }
+            cpio.entries.push((name, entry));
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, entry) in &self.entries {
+            self.write_entry(&mut out, name, entry);
+        }
+        self.write_entry(&mut out, TRAILER, &self.trailer);
+        if self.block_padded {
+            out.resize(out.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+        out.extend_from_slice(&self.tail);
+        out
+    }
+
+    fn write_entry(&self, out: &mut Vec<u8>, name: &str, entry: &CpioEntry) {
+        // 070702 的校验和为数据各字节之和，数据修改后需要重新计算
+        let check = if self.crc && entry.kind() == EntryKind::File {
+            entry.data.iter().fold(0u32, |sum, &b| sum.wrapping_add(b as u32))
+        } else {
+            entry.check
+        };
+        let fields = [
+            entry.ino,
+            entry.mode,
+            entry.uid,
+            entry.gid,
+            entry.nlink,
+            entry.mtime,
+            entry.data.len() as u32,
+            entry.dev_major,
+            entry.dev_minor,
+            entry.rdev_major,
+            entry.rdev_minor,
+            name.len() as u32 + 1,
+            check,
+        ];
+        out.extend_from_slice(if self.crc { MAGIC_CRC } else { MAGIC_NEWC });
+        for field in fields {
+            let hex = if self.uppercase { format!("{:08X}", field) } else { format!("{:08x}", field) };
+            out.extend_from_slice(hex.as_bytes());
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(&entry.data);
+        out.resize(align4(out.len()), 0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &CpioEntry)> {
+        self.entries.iter().map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        let name = normalize(name);
+        self.entries.iter().position(|(n, _)| normalize(n) == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CpioEntry> {
+        self.position(name).map(|i| &self.entries[i].1)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut CpioEntry> {
+        self.position(name).map(|i| &mut self.entries[i].1)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// 已存在的条目只替换内容与 mode，ino、属主、时间等字段保持不变，返回 true；
+    /// 否则按路径顺序插入，ino 为 0 时分配一个未使用的 ino。需要整体替换时先 remove
+    pub fn insert(&mut self, name: &str, mut entry: CpioEntry) -> bool {
+        let name = normalize(name);
+        if let Some(i) = self.position(name) {
+            let existing = &mut self.entries[i].1;
+            existing.mode = entry.mode;
+            existing.data = entry.data;
+            return true;
+        }
+        if entry.ino == 0 {
+            entry.ino = self.entries.iter().map(|(_, e)| e.ino).max().unwrap_or(0) + 1;
+        }
+        let i = self.entries.partition_point(|(n, _)| path_cmp(n, name).is_lt());
+        self.entries.insert(i, (name.to_string(), entry));
+        false
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<CpioEntry> {
+        self.position(name).map(|i| self.entries.remove(i).1)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &CpioEntry) -> bool) {
+        self.entries.retain(|(name, entry)| keep(name, entry));
+    }
+
+    /// 逐级创建缺失的目录，已存在的不做修改
+    pub fn mkdir_all(&mut self, path: &str, perm: u32) {
+        let path = normalize(path);
+        if path.is_empty() {
+            return;
+        }
+        let mut end = 0;
+        for component in path.split('/') {
+            end += component.len();
+            if !self.contains(&path[..end]) {
+                self.insert(&path[..end], CpioEntry::dir(perm));
+            }
+            end += 1;
+        }
+    }
+}
+
+/// ramdisk 的修补状态，对应 magiskboot cpio test 的返回值 0 / 1 / 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchState {
+    Stock,
+    Magisk,
+    /// 其他 Root 方案修补过，不能在其上继续修补
+    Other,
+}
+
+impl PatchState {
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Stock => 0,
+            Self::Magisk => 1,
+            Self::Other => 2,
+        }
+    }
+}
+
+/// magiskboot 风格的 cpio 命令，参数以空白分隔，权限为八进制
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpioCommand {
+    /// add MODE ENTRY INFILE
+    Add { mode: u32, path: String, file: PathBuf },
+    /// rm [-r] ENTRY
+    Rm { path: String, recursive: bool },
+    /// mkdir MODE ENTRY
+    Mkdir { mode: u32, path: String },
+    /// mv SOURCE DEST
+    Mv { from: String, to: String },
+    /// ln TARGET ENTRY
+    Ln { target: String, path: String },
+    /// extract [ENTRY OUT]，不带参数时全部提取到 ramdisk 目录
+    Extract { path: Option<String>, out: PathBuf },
+    /// exists ENTRY
+    Exists(String),
+    /// test
+    Test,
+    /// backup ORIG：与原始 cpio 比较，被修改或删除的条目存入 .backup，新增的条目记入 .backup/.rmlist
+    Backup(PathBuf),
+    /// restore：按 .backup 还原
+    Restore,
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|m| *m <= 0o7777)
+        .ok_or_else(|| FlashError::InvalidChoice(format!("无效的权限: {}", mode)))
+}
+
+/// 按空白拆分参数，单引号或双引号内的空白保留。
+/// 反斜杠不作转义，以免破坏 Windows 路径
+fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.get_or_insert_with(String::new).push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => args.extend(current.take()),
+            None => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(FlashError::InvalidChoice(format!("引号未闭合: {}", line.trim())));
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+impl CpioCommand {
+    /// 解析一条 magiskboot 风格的命令，含空格的路径可用引号括起
+    pub fn parse(line: &str) -> Result<Self> {
+        let args = split_args(line)?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let cmd = match args.as_slice() {
+            ["add", mode, path, file] => Self::Add { mode: parse_mode(mode)?, path: path.to_string(), file: PathBuf::from(file) },
+            ["rm", "-r", path] => Self::Rm { path: path.to_string(), recursive: true },
+            ["rm", path] => Self::Rm { path: path.to_string(), recursive: false },
+            ["mkdir", mode, path] => Self::Mkdir { mode: parse_mode(mode)?, path: path.to_string() },
+            ["mv", from, to] => Self::Mv { from: from.to_string(), to: to.to_string() },
+            ["ln", target, path] => Self::Ln { target: target.to_string(), path: path.to_string() },
+            ["extract"] => Self::Extract { path: None, out: PathBuf::from("ramdisk") },
+            ["extract", path, out] => Self::Extract { path: Some(path.to_string()), out: PathBuf::from(out) },
+            ["exists", path] => Self::Exists(path.to_string()),
+            ["test"] => Self::Test,
+            ["backup", orig] => Self::Backup(PathBuf::from(orig)),
+            ["restore"] => Self::Restore,
+            _ => {
+                return Err(FlashError::InvalidChoice(format!(
+                    "无法识别的 cpio 命令: {}\n可用命令: add MODE ENTRY INFILE | rm [-r] ENTRY | mkdir MODE ENTRY | mv SOURCE DEST | \
+                     ln TARGET ENTRY | extract [ENTRY OUT] | exists ENTRY | test | backup ORIG | restore",
+                    line.trim()
+                )));
+            }
+        };
+        Ok(cmd)
+    }
+
+    /// 是否会修改归档；只有查询与提取命令时无需重新打包
+    pub fn modifies(&self) -> bool {
+        !matches!(self, Self::Extract { .. } | Self::Exists(_) | Self::Test)
+    }
+
+    /// 查询命令对应的 magiskboot 退出码：exists 存在为 0、否则为 1，test 为 PatchState::code；
+    /// 其他命令返回 None
+    pub fn exit_code(&self, cpio: &Cpio) -> Option<i32> {
+        match self {
+            Self::Exists(path) => Some(if cpio.contains(path) { 0 } else { 1 }),
+            Self::Test => Some(cpio.patch_state().code()),
+            _ => None,
+        }
+    }
+}
+
+/// 只接受全部由普通分量组成的相对路径；`\` 与 `:` 在 Windows 上会被当作分隔符或盘符，一律拒绝
+fn safe_relative_path(name: &str) -> Option<&Path> {
+    if name.is_empty() || name.contains(['\\', ':']) {
+        return None;
+    }
+    let path = Path::new(name);
+    path.components().all(|c| matches!(c, Component::Normal(_))).then_some(path)
+}
+
+fn is_under(name: &str, dir: &str) -> bool {
+    name == dir || name.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// 比较内容与属性，不比较 ino / mtime
+fn same_content(a: &CpioEntry, b: &CpioEntry) -> bool {
+    (a.mode, a.uid, a.gid, a.rdev_major, a.rdev_minor) == (b.mode, b.uid, b.gid, b.rdev_major, b.rdev_minor) && a.data == b.data
+}
+
+impl Cpio {
+    /// 读取 cpio 文件，支持压缩过的 ramdisk
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        match utils::detect_ramdisk_format(&data) {
+            RamdiskFormat::Uncompressed => Self::parse(&data),
+            _ => Self::parse(&utils::decompress_ramdisk(&data)?),
+        }
+    }
+
+    pub fn patch_state(&self) -> PatchState {
+        const OTHER: &[&str] = &[
+            "sbin/launch_daemonsu.sh",
+            "sbin/su",
+            "init.xposed.rc",
+            "boot/sbin/launch_daemonsu.sh",
+            "kernelsu.ko",
+            "lib/modules/kernelsu.ko",
+        ];
+        if OTHER.iter().any(|name| self.contains(name)) {
+            return PatchState::Other;
+        }
+        let magisk = [MAGISK_CONFIG, "init.magisk.rc"].iter().any(|name| self.contains(name))
+            || self.entries().any(|(name, _)| normalize(name).starts_with("overlay.d/sbin/magisk"));
+        if magisk { PatchState::Magisk } else { PatchState::Stock }
+    }
+
+    /// 执行一条命令，返回执行结果说明
+    pub fn exec(&mut self, cmd: &CpioCommand) -> Result<String> {
+        let not_found = |path: &str| FlashError::PatchError(format!("ramdisk 中没有 {}", path));
+        match cmd {
+            CpioCommand::Add { mode, path, file } => {
+                if self.get(path).is_some_and(CpioEntry::is_dir) {
+                    return Err(FlashError::PatchError(format!("{} 是目录，不能用文件覆盖", path)));
+                }
+                let data = fs::read(file)?;
+                let len = data.len();
+                let replaced = self.insert(path, CpioEntry::file(*mode, data));
+                Ok(format!("{} {} ({} 字节, {:04o})", if replaced { "已替换" } else { "已添加" }, path, len, mode))
+            }
+            CpioCommand::Rm { path, recursive } => {
+                let path = normalize(path);
+                let before = self.len();
+                self.retain(|name, _| {
+                    let name = normalize(name);
+                    !(name == path || (*recursive && is_under(name, path)))
+                });
+                match before - self.len() {
+                    0 => Ok(format!("{} 不存在，已跳过", path)),
+                    n => Ok(format!("已删除 {} ({} 项)", path, n)),
+                }
+            }
+            CpioCommand::Mkdir { mode, path } => {
+                self.insert(path, CpioEntry::dir(*mode));
+                Ok(format!("已创建目录 {} ({:04o})", path, mode))
+            }
+            CpioCommand::Mv { from, to } => {
+                let (from, to) = (normalize(from), normalize(to));
+                let moved: Vec<String> = self
+                    .entries()
+                    .map(|(name, _)| normalize(name))
+                    .filter(|name| is_under(name, from))
+                    .map(str::to_string)
+                    .collect();
+                if moved.is_empty() {
+                    return Err(not_found(from));
+                }
+                for name in &moved {
+                    let entry = self.remove(name).ok_or_else(|| not_found(name))?;
+                    let target = format!("{}{}", to, &name[from.len()..]);
+                    self.remove(&target);
+                    self.insert(&target, entry);
+                }
+                Ok(format!("已移动 {} -> {}", from, to))
+            }
+            CpioCommand::Ln { target, path } => {
+                self.insert(path, CpioEntry::symlink(target));
+                Ok(format!("已创建链接 {} -> {}", path, target))
+            }
+            CpioCommand::Extract { path: Some(path), out } => {
+                let entry = self.get(path).ok_or_else(|| not_found(path))?;
+                match entry.kind() {
+                    EntryKind::File => fs::write(out, &entry.data)?,
+                    EntryKind::Dir => fs::create_dir_all(out)?,
+                    _ => return Err(FlashError::PatchError(format!("{} 不是普通文件或目录，无法提取", path))),
+                }
+                Ok(format!("已提取 {} -> {}", path, out.display()))
+            }
+            CpioCommand::Extract { path: None, out } => {
+                let (written, skipped) = self.extract_all(out)?;
+                let mut msg = format!("已提取 {} 项到 {}", written, out.display());
+                if skipped > 0 {
+                    msg += &format!("，跳过 {} 个链接 / 设备节点", skipped);
+                }
+                Ok(msg)
+            }
+            CpioCommand::Exists(path) => {
+                Ok(format!("{} {}", path, if self.contains(path) { "存在" } else { "不存在" }))
+            }
+            CpioCommand::Test => Ok(match self.patch_state() {
+                PatchState::Stock => "0: 未修补的 ramdisk".to_string(),
+                PatchState::Magisk => "1: 已由 Magisk 修补".to_string(),
+                PatchState::Other => "2: 已由其他 Root 方案修补".to_string(),
+            }),
+            CpioCommand::Backup(orig) => self.backup(&Self::load(orig)?),
+            CpioCommand::Restore => self.restore(),
+        }
+    }
+
+    /// 普通文件与目录写入 out 目录，符号链接与设备节点无法在 Windows 上创建，跳过
+    fn extract_all(&self, out: &Path) -> Result<(usize, usize)> {
+        let (mut written, mut skipped) = (0, 0);
+        for (name, entry) in self.entries() {
+            let Some(name) = safe_relative_path(normalize(name)) else {
+                skipped += 1;
+                continue;
+            };
+            let target = out.join(name);
+            match entry.kind() {
+                EntryKind::Dir => fs::create_dir_all(&target)?,
+                EntryKind::File => {
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&target, &entry.data)?;
+                }
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            }
+            written += 1;
+        }
+        Ok((written, skipped))
+    }
+
+    fn backup(&mut self, orig: &Cpio) -> Result<String> {
+        self.retain(|name, _| !is_under(normalize(name), BACKUP_DIR) || normalize(name) == MAGISK_CONFIG);
+        let backups: Vec<(String, CpioEntry)> = orig
+            .entries()
+            .filter(|(name, _)| !is_under(normalize(name), BACKUP_DIR))
+            .filter(|(name, entry)| !self.get(name).is_some_and(|e| same_content(e, entry)))
+            .map(|(name, entry)| (format!("{}/{}", BACKUP_DIR, normalize(name)), entry.clone()))
+            .collect();
+        let rm_list: Vec<String> = self
+            .entries()
+            .map(|(name, _)| normalize(name))
+            .filter(|name| !is_under(name, BACKUP_DIR) && !orig.contains(name))
+            .map(|name| format!("{}\0", name))
+            .collect();
+        if backups.is_empty() && rm_list.is_empty() {
+            return Ok("与原始 ramdisk 相同，无需备份".to_string());
+        }
+        self.mkdir_all(BACKUP_DIR, 0o000);
+        let count = backups.len();
+        for (name, entry) in backups {
+            self.insert(&name, entry);
+        }
+        if !rm_list.is_empty() {
+            self.insert(RMLIST, CpioEntry::file(0o000, rm_list.concat().into_bytes()));
+        }
+        Ok(format!("已备份 {} 项原始条目，记录 {} 项新增条目", count, rm_list.len()))
+    }
+
+    fn restore(&mut self) -> Result<String> {
+        if !self.contains(BACKUP_DIR) {
+            return Err(FlashError::PatchError("ramdisk 中没有 .backup，无法还原".to_string()));
+        }
+        let rm_list = self.get(RMLIST).map(|e| e.data.clone()).unwrap_or_default();
+        let backups: Vec<(String, CpioEntry)> = self
+            .entries()
+            .filter_map(|(name, entry)| {
+                let name = normalize(name);
+                let original = name.strip_prefix(BACKUP_DIR)?.strip_prefix('/')?;
+                (name != RMLIST && name != MAGISK_CONFIG).then(|| (original.to_string(), entry.clone()))
+            })
+            .collect();
+        self.retain(|name, _| !is_under(normalize(name), BACKUP_DIR));
+        let removed: Vec<String> = rm_list
+            .split(|&b| b == 0)
+            .filter(|n| !n.is_empty())
+            .map(|n| String::from_utf8_lossy(n).to_string())
+            .collect();
+        for name in &removed {
+            self.remove(name);
+        }
+        let count = backups.len();
+        for (name, entry) in backups {
+            self.remove(&name);
+            self.insert(&name, entry);
+        }
+        Ok(format!("已还原 {} 项，删除 {} 项新增条目", count, removed.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(ino: u32, mode: u32, uid: u32, name: &str, data: &[u8], rdev: (u32, u32)) -> Vec<u8> {
+        let fields = [ino, mode, uid, uid, 1, 1700000000, data.len() as u32, 0, 0, rdev.0, rdev.1, name.len() as u32 + 1, 0];
+        let mut out = MAGIC_NEWC.to_vec();
+        for f in fields {
+            out.extend_from_slice(format!("{:08x}", f).as_bytes());
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(data);
+        out.resize(align4(out.len()), 0);
+        out
+    }
+
+    #[test]
+    fn test_roundtrip_and_edit() {
+        let mut raw = [
+            header(300000, S_IFDIR | 0o755, 0, "dev", b"", (0, 0)),
+            header(300001, S_IFCHR | 0o600, 0, "dev/console", b"", (5, 1)),
+            header(300002, S_IFREG | 0o750, 0, "init", b"\x7fELF", (0, 0)),
+            header(300003, S_IFLNK | 0o777, 2000, "sdcard", b"/storage/self/primary", (0, 0)),
+            header(0, 0, 0, TRAILER, b"", (0, 0)),
+        ]
+        .concat();
+        raw.resize(raw.len().next_multiple_of(512), 0);
+
+        let mut cpio = Cpio::parse(&raw).unwrap();
+        assert_eq!(cpio.len(), 4);
+        assert_eq!(cpio.to_bytes(), raw);
+        let console = cpio.get("dev/console").unwrap();
+        assert_eq!((console.kind(), console.rdev_major, console.rdev_minor), (EntryKind::CharDevice, 5, 1));
+        assert_eq!(cpio.get("/sdcard").unwrap().link_target().as_deref(), Some("/storage/self/primary"));
+
+        let init = cpio.remove("init").unwrap();
+        cpio.insert("init.real", init);
+        cpio.insert("init", CpioEntry::file(0o750, b"magiskinit".to_vec()));
+        cpio.mkdir_all("overlay.d/sbin", 0o750);
+        let names: Vec<&str> = cpio.entries().map(|(n, _)| n).collect();
+        assert_eq!(names, ["dev", "dev/console", "init", "init.real", "overlay.d", "overlay.d/sbin", "sdcard"]);
+
+        let reparsed = Cpio::parse(&cpio.to_bytes()).unwrap();
+        assert_eq!(reparsed.get("init.real").unwrap().ino, 300002);
+        assert_eq!(reparsed.get("init").unwrap().data, b"magiskinit");
+        assert!(Cpio::parse(&raw[..200]).is_err());
+    }
+
+    #[test]
+    fn test_replace_keeps_metadata() {
+        let raw = [header(300002, S_IFREG | 0o750, 1000, "init", b"\x7fELF", (0, 0)), header(0, 0, 0, TRAILER, b"", (0, 0))].concat();
+        let mut cpio = Cpio::parse(&raw).unwrap();
+        assert!(cpio.insert("/init", CpioEntry::file(0o755, b"new".to_vec())));
+        let init = cpio.get("init").unwrap();
+        assert_eq!((init.ino, init.uid, init.mtime), (300002, 1000, 1700000000));
+        assert_eq!((init.permissions(), init.data.as_slice()), (0o755, b"new".as_slice()));
+
+        // mv 到已存在的路径时整体替换目标
+        cpio.insert("init.stub", CpioEntry::file(0o644, b"stub".to_vec()));
+        cpio.exec(&CpioCommand::Mv { from: "init.stub".into(), to: "init".into() }).unwrap();
+        let init = cpio.get("init").unwrap();
+        assert_eq!((init.ino, init.uid, init.data.as_slice()), (300003, 0, b"stub".as_slice()));
+        assert_eq!(cpio.len(), 1);
+    }
+
+    #[test]
+    fn test_insert_order_and_trailer_padding() {
+        let mut raw = [header(1, S_IFDIR | 0o755, 0, "system", b"", (0, 0)), header(0, 0, 0, TRAILER, b"", (0, 0))].concat();
+        raw.resize(raw.len().next_multiple_of(BLOCK_SIZE), 0);
+        let mut cpio = Cpio::parse(&raw).unwrap();
+        cpio.mkdir_all("/system/etc/init", 0o755);
+        cpio.insert("system-ext", CpioEntry::file(0o644, Vec::new()));
+        cpio.insert("a/b", CpioEntry::file(0o644, Vec::new()));
+        cpio.mkdir_all("a", 0o750);
+        assert!(cpio.remove("system/etc/init").is_some());
+        assert!(cpio.remove("missing").is_none());
+        let names: Vec<&str> = cpio.entries().map(|(n, _)| n).collect();
+        assert_eq!(names, ["a", "a/b", "system", "system/etc", "system-ext"]);
+
+        // 新增条目后仍以 TRAILER!!! 结尾并补齐到 512 字节
+        let out = cpio.to_bytes();
+        assert_eq!(out.len() % BLOCK_SIZE, 0);
+        let reparsed = Cpio::parse(&out).unwrap();
+        assert_eq!(reparsed.len(), 5);
+        assert_eq!(reparsed.to_bytes(), out);
+
+        // TRAILER!!! 之后的非零数据原样保留，缺少 TRAILER!!! 则报错
+        let mut with_tail = raw.clone();
+        with_tail.extend_from_slice(b"extra");
+        assert!(Cpio::parse(&with_tail).unwrap().to_bytes().ends_with(b"extra"));
+        let no_trailer = header(1, S_IFDIR | 0o755, 0, "system", b"", (0, 0));
+        assert!(Cpio::parse(&no_trailer).is_err());
+    }
+
+    #[test]
+    fn test_command_quoting_and_exit_code() {
+        let cmd = CpioCommand::parse(r#"add 0644 "my dir/init rc" 'C:\Users\a b\x.rc'"#).unwrap();
+        assert!(matches!(&cmd, CpioCommand::Add { mode: 0o644, path, file }
+            if path == "my dir/init rc" && file == Path::new(r"C:\Users\a b\x.rc")));
+        assert!(matches!(CpioCommand::parse("exists ''").unwrap(), CpioCommand::Exists(p) if p.is_empty()));
+        assert!(CpioCommand::parse("rm 'unterminated").is_err());
+
+        let mut cpio = Cpio::new();
+        cpio.mkdir_all("a b", 0o755);
+        assert_eq!(CpioCommand::parse("exists 'a b'").unwrap().exit_code(&cpio), Some(0));
+        assert_eq!(CpioCommand::parse("exists a").unwrap().exit_code(&cpio), Some(1));
+        assert_eq!(CpioCommand::Test.exit_code(&cpio), Some(0));
+        cpio.insert(MAGISK_CONFIG, CpioEntry::file(0, Vec::new()));
+        assert_eq!(CpioCommand::Test.exit_code(&cpio), Some(1));
+        assert_eq!(CpioCommand::Restore.exit_code(&cpio), None);
+    }
+
+    #[test]
+    fn test_extract_all_rejects_unsafe_names() {
+        let mut cpio = Cpio::new();
+        for name in ["ok/init.rc", "../evil", r"a\..\..\evil", "C:evil", "C:/evil"] {
+            cpio.insert(name, CpioEntry::file(0o644, b"x".to_vec()));
+        }
+        let dir = std::env::temp_dir().join(format!("rua_cpio_extract_{}", std::process::id()));
+        let out = dir.join("out");
+        let (written, skipped) = cpio.extract_all(&out).unwrap();
+        assert_eq!((written, skipped), (1, 4));
+        assert!(out.join("ok/init.rc").is_file());
+        let names: Vec<_> = fs::read_dir(&out).unwrap().flatten().map(|e| e.file_name()).collect();
+        assert_eq!(names, ["ok"]);
+        assert!(!dir.join("evil").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
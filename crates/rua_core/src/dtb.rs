@@ -0,0 +1,519 @@
+use crate::error::{FlashError, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{Read, Write};
+
+pub const FDT_MAGIC: [u8; 4] = [0xd0, 0x0d, 0xfe, 0xed];
+/// Android DT 表 (dtbo.img) 魔数
+pub const DTBO_MAGIC: [u8; 4] = [0xd7, 0xb7, 0xab, 0x1e];
+const FDT_HEADER_SIZE: usize = 40;
+const DTBO_HEADER_SIZE: usize = 32;
+const DTBO_ENTRY_SIZE: usize = 32;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    data.get(off..off + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn invalid(msg: &str) -> FlashError {
+    FlashError::PatchError(format!("无效的 DTB: {}", msg))
+}
+
+/// data[off..] 处头部字段自洽的 FDT 的总大小
+pub fn fdt_size(data: &[u8], off: usize) -> Option<usize> {
+    let total = be32(data, off + 4)? as usize;
+    let valid = data.get(off..off + 4)? == FDT_MAGIC
+        && total >= FDT_HEADER_SIZE
+        && off + total <= data.len()
+        && be32(data, off + 20).is_some_and(|version| (16..=17).contains(&version));
+    valid.then_some(total)
+}
+
+/// 从 off 开始依次读取首尾相接的 FDT，返回各个 FDT 与停止读取的位置
+fn walk_fdts(data: &[u8], mut off: usize) -> (Vec<&[u8]>, usize) {
+    let mut out = Vec::new();
+    while let Some(size) = fdt_size(data, off) {
+        out.push(&data[off..off + size]);
+        off += size;
+        // 部分厂商在 DTB 之间按 4 字节对齐填充
+        while off < data.len() && !off.is_multiple_of(4) && data[off] == 0 {
+            off += 1;
+        }
+    }
+    (out, off)
+}
+
+/// 拆分首尾相接的多个 FDT（dtb 段或 Image.gz-dtb 末尾常见），遇到无法识别的数据即停止
+pub fn split_fdts(data: &[u8]) -> Vec<&[u8]> {
+    walk_fdts(data, 0).0
+}
+
+/// 查找附加在内核末尾的 DTB：头部字段自洽且一个或多个 DTB 首尾相接直到数据末尾，
+/// 避免把内核代码中偶然出现的魔数当成 DTB
+pub fn find_appended_dtb(kernel: &[u8]) -> Option<usize> {
+    (0..kernel.len().saturating_sub(FDT_HEADER_SIZE))
+        .find(|&off| fdt_size(kernel, off).is_some() && walk_fdts(kernel, off).1 == kernel.len())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdtProp {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FdtNode {
+    pub name: String,
+    pub props: Vec<FdtProp>,
+    pub children: Vec<FdtNode>,
+}
+
+impl FdtNode {
+    pub fn prop(&self, name: &str) -> Option<&[u8]> {
+        self.props.iter().find(|p| p.name == name).map(|p| p.value.as_slice())
+    }
+
+    /// 字符串属性（可能是以 NUL 分隔的字符串列表），返回第一个字符串
+    pub fn prop_str(&self, name: &str) -> Option<String> {
+        let value = self.prop(name)?;
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        Some(String::from_utf8_lossy(&value[..end]).to_string())
+    }
+
+    pub fn set_prop(&mut self, name: &str, value: Vec<u8>) {
+        match self.props.iter_mut().find(|p| p.name == name) {
+            Some(p) => p.value = value,
+            None => self.props.push(FdtProp { name: name.to_string(), value }),
+        }
+    }
+
+    pub fn child(&self, name: &str) -> Option<&FdtNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// 深度优先遍历，回调参数为节点路径与节点
+    pub fn walk_mut(&mut self, path: &str, f: &mut dyn FnMut(&str, &mut FdtNode)) {
+        let path = if path.is_empty() { "/".to_string() } else { path.to_string() };
+        f(&path, self);
+        for child in &mut self.children {
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), child.name);
+            child.walk_mut(&child_path, f);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fdt {
+    pub boot_cpuid_phys: u32,
+    pub mem_reserve: Vec<(u64, u64)>,
+    pub root: FdtNode,
+}
+
+impl Fdt {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let total = fdt_size(data, 0).ok_or_else(|| invalid("头部错误"))?;
+        let data = &data[..total];
+        let field = |i: usize| be32(data, i * 4).unwrap() as usize;
+        let (off_struct, off_strings, off_rsvmap) = (field(2), field(3), field(4));
+        let boot_cpuid_phys = field(7) as u32;
+        let strings = data.get(off_strings..).ok_or_else(|| invalid("字符串表越界"))?;
+
+        let mut mem_reserve = Vec::new();
+        let mut off = off_rsvmap;
+        loop {
+            let entry = data.get(off..off + 16).ok_or_else(|| invalid("保留内存表越界"))?;
+            let addr = u64::from_be_bytes(entry[..8].try_into().unwrap());
+            let size = u64::from_be_bytes(entry[8..].try_into().unwrap());
+            if addr == 0 && size == 0 {
+                break;
+            }
+            mem_reserve.push((addr, size));
+            off += 16;
+        }
+
+        let cstr = |buf: &[u8], at: usize| -> Result<String> {
+            let s = buf.get(at..).ok_or_else(|| invalid("字符串越界"))?;
+            let end = s.iter().position(|&b| b == 0).ok_or_else(|| invalid("字符串未结束"))?;
+            Ok(String::from_utf8_lossy(&s[..end]).to_string())
+        };
+
+        let mut stack: Vec<FdtNode> = Vec::new();
+        let mut root = None;
+        let mut pos = off_struct;
+        loop {
+            let token = be32(data, pos).ok_or_else(|| invalid("结构块越界"))?;
+            pos += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(data, pos)?;
+                    pos += (name.len() + 1).div_ceil(4) * 4;
+                    stack.push(FdtNode { name, ..Default::default() });
+                }
+                FDT_END_NODE => {
+                    let node = stack.pop().ok_or_else(|| invalid("节点不匹配"))?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => root = Some(node),
+                    }
+                }
+                FDT_PROP => {
+                    let len = be32(data, pos).ok_or_else(|| invalid("属性越界"))? as usize;
+                    let name_off = be32(data, pos + 4).ok_or_else(|| invalid("属性越界"))? as usize;
+                    let value = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("属性越界"))?.to_vec();
+                    pos += 8 + len.div_ceil(4) * 4;
+                    let node = stack.last_mut().ok_or_else(|| invalid("属性不在节点内"))?;
+                    node.props.push(FdtProp { name: cstr(strings, name_off)?, value });
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                other => return Err(invalid(&format!("未知标记 {}", other))),
+            }
+        }
+        let root = root.ok_or_else(|| invalid("缺少根节点"))?;
+        Ok(Self { boot_cpuid_phys, mem_reserve, root })
+    }
+
+    /// 序列化为 version 17 的 FDT，字符串表去重
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn put(buf: &mut Vec<u8>, v: u32) {
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        fn pad(buf: &mut Vec<u8>) {
+            while buf.len() % 4 != 0 {
+                buf.push(0);
+            }
+        }
+        fn emit(node: &FdtNode, st: &mut Vec<u8>, strings: &mut Vec<u8>) {
+            put(st, FDT_BEGIN_NODE);
+            st.extend_from_slice(node.name.as_bytes());
+            st.push(0);
+            pad(st);
+            for prop in &node.props {
+                let needle: Vec<u8> = prop.name.bytes().chain([0]).collect();
+                let name_off = strings
+                    .windows(needle.len())
+                    .enumerate()
+                    .find(|(i, w)| *w == needle && (*i == 0 || strings[i - 1] == 0))
+                    .map(|(i, _)| i)
+                    .unwrap_or_else(|| {
+                        strings.extend_from_slice(&needle);
+                        strings.len() - needle.len()
+                    });
+                put(st, FDT_PROP);
+                put(st, prop.value.len() as u32);
+                put(st, name_off as u32);
+                st.extend_from_slice(&prop.value);
+                pad(st);
+            }
+            for child in &node.children {
+                emit(child, st, strings);
+            }
+            put(st, FDT_END_NODE);
+        }
+
+        let mut st = Vec::new();
+        let mut strings = Vec::new();
+        emit(&self.root, &mut st, &mut strings);
+        put(&mut st, FDT_END);
+
+        let mut rsv = Vec::new();
+        for (addr, size) in self.mem_reserve.iter().chain([(0, 0)].iter()) {
+            rsv.extend_from_slice(&addr.to_be_bytes());
+            rsv.extend_from_slice(&size.to_be_bytes());
+        }
+        let off_rsvmap = FDT_HEADER_SIZE.div_ceil(8) * 8;
+        let off_struct = off_rsvmap + rsv.len();
+        let off_strings = off_struct + st.len();
+        let total = off_strings + strings.len();
+
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&FDT_MAGIC);
+        for v in [total, off_struct, off_strings, off_rsvmap, 17, 16, self.boot_cpuid_phys as usize, strings.len(), st.len()] {
+            put(&mut out, v as u32);
+        }
+        out.resize(off_rsvmap, 0);
+        out.extend_from_slice(&rsv);
+        out.extend_from_slice(&st);
+        out.extend_from_slice(&strings);
+        out
+    }
+
+    pub fn model(&self) -> Option<String> {
+        self.root.prop_str("model")
+    }
+
+    pub fn compatible(&self) -> Option<String> {
+        self.root.prop_str("compatible")
+    }
+}
+
+/// dtbo.img 中的一个 overlay，data 为解压后的 FDT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtboEntry {
+    pub id: u32,
+    pub rev: u32,
+    /// v1 中 custom[0] 的低 4 位为压缩方式
+    pub custom: [u32; 4],
+    pub data: Vec<u8>,
+}
+
+impl DtboEntry {
+    fn compression(&self, version: u32) -> u32 {
+        if version >= 1 { self.custom[0] & 0xf } else { 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtboImage {
+    pub page_size: u32,
+    pub version: u32,
+    pub entries: Vec<DtboEntry>,
+}
+
+pub fn is_dtbo(data: &[u8]) -> bool {
+    data.starts_with(&DTBO_MAGIC)
+}
+
+impl DtboImage {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !is_dtbo(data) {
+            return Err(FlashError::PatchError("不是 DTBO 镜像".to_string()));
+        }
+        let field = |off: usize| be32(data, off).ok_or_else(|| invalid("DTBO 头部不完整"));
+        let (entry_size, count, entries_off) = (field(12)? as usize, field(16)? as usize, field(20)? as usize);
+        let page_size = field(24)?;
+        let version = field(28)?;
+        if entry_size < DTBO_ENTRY_SIZE {
+            return Err(invalid("DTBO 表项大小错误"));
+        }
+        // 表项数量来自镜像，先确认整张表都在文件内再分配
+        count
+            .checked_mul(entry_size)
+            .and_then(|len| entries_off.checked_add(len))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| invalid("DTBO 表项越界"))?;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let base = entries_off + i * entry_size;
+            let (size, offset) = (field(base)? as usize, field(base + 4)? as usize);
+            let mut custom = [0u32; 4];
+            for (j, c) in custom.iter_mut().enumerate() {
+                *c = field(base + 16 + j * 4)?;
+            }
+            let blob = offset.checked_add(size).and_then(|end| data.get(offset..end)).ok_or_else(|| invalid("DTBO 表项越界"))?;
+            let mut entry = DtboEntry { id: field(base + 8)?, rev: field(base + 12)?, custom, data: Vec::new() };
+            entry.data = match entry.compression(version) {
+                0 => blob.to_vec(),
+                1 => read_all(ZlibDecoder::new(blob))?,
+                2 => read_all(GzDecoder::new(blob))?,
+                other => return Err(invalid(&format!("不支持的 DTBO 压缩方式 {}", other))),
+            };
+            entries.push(entry);
+        }
+        Ok(Self { page_size, version, entries })
+    }
+
+    /// 与 mkdtboimg 相同：表项之后依次存放各 overlay，内容相同的 overlay 只存一份
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut blobs: Vec<Vec<u8>> = Vec::new();
+        let mut table = Vec::new();
+        let data_start = DTBO_HEADER_SIZE + self.entries.len() * DTBO_ENTRY_SIZE;
+        for entry in &self.entries {
+            let blob = match entry.compression(self.version) {
+                0 => entry.data.clone(),
+                1 => {
+                    let mut enc = ZlibEncoder::new(Vec::new(), Compression::best());
+                    enc.write_all(&entry.data)?;
+                    enc.finish()?
+                }
+                2 => {
+                    let mut enc = GzEncoder::new(Vec::new(), Compression::best());
+                    enc.write_all(&entry.data)?;
+                    enc.finish()?
+                }
+                other => return Err(invalid(&format!("不支持的 DTBO 压缩方式 {}", other))),
+            };
+            let index = blobs.iter().position(|b| *b == blob).unwrap_or_else(|| {
+                blobs.push(blob);
+                blobs.len() - 1
+            });
+            let offset = data_start + blobs[..index].iter().map(Vec::len).sum::<usize>();
+            for v in [blobs[index].len() as u32, offset as u32, entry.id, entry.rev] {
+                table.extend_from_slice(&v.to_be_bytes());
+            }
+            for v in entry.custom {
+                table.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        let total = data_start + blobs.iter().map(Vec::len).sum::<usize>();
+        let mut out = DTBO_MAGIC.to_vec();
+        for v in [total, DTBO_HEADER_SIZE, DTBO_ENTRY_SIZE, self.entries.len(), DTBO_HEADER_SIZE] {
+            out.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        out.extend_from_slice(&self.page_size.to_be_bytes());
+        out.extend_from_slice(&self.version.to_be_bytes());
+        out.extend_from_slice(&table);
+        for blob in blobs {
+            out.extend_from_slice(&blob);
+        }
+        Ok(out)
+    }
+}
+
+fn read_all(mut reader: impl Read) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// fstab 修改记录：(节点路径, 去掉的选项)
+pub type FstabChanges = Vec<(String, String)>;
+
+/// 从 fstab 节点的 fsmgr_flags 中去掉的选项
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FstabPatch {
+    /// verify、avb、avb=...、avb_keys=...
+    pub remove_verity: bool,
+    /// forceencrypt=...、forcefdeorfbe=...、fileencryption=...
+    pub remove_encryption: bool,
+}
+
+impl FstabPatch {
+    fn should_remove(&self, flag: &str) -> bool {
+        let key = flag.split('=').next().unwrap_or(flag);
+        (self.remove_verity && matches!(key, "verify" | "avb" | "avb_keys"))
+            || (self.remove_encryption && matches!(key, "forceencrypt" | "forcefdeorfbe" | "fileencryption"))
+    }
+
+    /// 修补所有带 fsmgr_flags 的节点，返回修改记录
+    pub fn apply(&self, fdt: &mut Fdt) -> FstabChanges {
+        let mut changes = Vec::new();
+        fdt.root.walk_mut("", &mut |path, node| {
+            let Some(flags) = node.prop_str("fsmgr_flags") else { return };
+            let (removed, kept): (Vec<&str>, Vec<&str>) = flags.split(',').partition(|f| self.should_remove(f));
+            if removed.is_empty() {
+                return;
+            }
+            let mut value = kept.join(",").into_bytes();
+            value.push(0);
+            node.set_prop("fsmgr_flags", value);
+            changes.push((path.to_string(), removed.join(",")));
+        });
+        changes
+    }
+
+    /// 修补首尾相接的一个或多个 FDT
+    pub fn apply_to_blob(&self, data: &[u8], changes: &mut FstabChanges) -> Result<Vec<u8>> {
+        let fdts = split_fdts(data);
+        if fdts.is_empty() {
+            return Err(invalid("未找到 FDT"));
+        }
+        let mut out = Vec::with_capacity(data.len());
+        for blob in fdts {
+            let mut fdt = Fdt::parse(blob)?;
+            let found = self.apply(&mut fdt);
+            if found.is_empty() {
+                out.extend_from_slice(blob);
+            } else {
+                out.extend_from_slice(&fdt.to_bytes());
+                changes.extend(found);
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn apply_to_dtbo(&self, dtbo: &mut DtboImage) -> Result<FstabChanges> {
+        let mut changes = Vec::new();
+        for entry in &mut dtbo.entries {
+            let mut fdt = Fdt::parse(&entry.data)?;
+            let found = self.apply(&mut fdt);
+            if !found.is_empty() {
+                entry.data = fdt.to_bytes();
+                changes.extend(found.into_iter().map(|(path, flags)| (format!("id {:#x}: {}", entry.id, path), flags)));
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_fdt() -> Fdt {
+        let prop = |name: &str, value: &[u8]| FdtProp { name: name.to_string(), value: value.to_vec() };
+        let vendor = FdtNode {
+            name: "vendor".to_string(),
+            props: vec![
+                prop("dev", b"/dev/block/platform/soc/by-name/vendor\0"),
+                prop("fsmgr_flags", b"wait,slotselect,avb=vbmeta,forceencrypt=footer\0"),
+            ],
+            children: vec![],
+        };
+        let fstab = FdtNode { name: "fstab".to_string(), props: vec![prop("compatible", b"android,fstab\0")], children: vec![vendor] };
+        let android = FdtNode { name: "android".to_string(), props: vec![], children: vec![fstab] };
+        let firmware = FdtNode { name: "firmware".to_string(), props: vec![], children: vec![android] };
+        let root = FdtNode { name: String::new(), props: vec![prop("model", b"Test Board\0"), prop("compatible", b"test\0")], children: vec![firmware] };
+        Fdt { boot_cpuid_phys: 0, mem_reserve: vec![(0x1000, 0x2000)], root }
+    }
+
+    #[test]
+    fn test_fdt_roundtrip_and_fstab_patch() {
+        let fdt = sample_fdt();
+        let bytes = fdt.to_bytes();
+        assert_eq!(Fdt::parse(&bytes).unwrap(), fdt);
+
+        let mut blob = bytes.clone();
+        blob.extend_from_slice(&bytes);
+        assert_eq!(split_fdts(&blob).len(), 2);
+        assert_eq!(find_appended_dtb(&[b"kernel".as_slice(), &blob].concat()), Some(6));
+        // 大小不是 4 的倍数的 DTB 之后带有对齐填充，同样视为一直到达内核末尾
+        let mut odd = bytes.clone();
+        while odd.len() % 4 != 2 {
+            odd.push(0);
+        }
+        let size = odd.len() as u32;
+        odd[4..8].copy_from_slice(&size.to_be_bytes());
+        let padded = [odd.as_slice(), &[0, 0], &odd, &[0, 0]].concat();
+        assert_eq!(split_fdts(&padded).len(), 2);
+        assert_eq!(find_appended_dtb(&[b"kernel!!".as_slice(), &padded].concat()), Some(8));
+
+        let patch = FstabPatch { remove_verity: true, remove_encryption: true };
+        let mut changes = Vec::new();
+        let patched = patch.apply_to_blob(&blob, &mut changes).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0], ("/firmware/android/fstab/vendor".to_string(), "avb=vbmeta,forceencrypt=footer".to_string()));
+        let parsed = Fdt::parse(split_fdts(&patched)[1]).unwrap();
+        let vendor = parsed.root.child("firmware").and_then(|n| n.child("android")).and_then(|n| n.child("fstab")).and_then(|n| n.child("vendor"));
+        assert_eq!(vendor.and_then(|n| n.prop_str("fsmgr_flags")).as_deref(), Some("wait,slotselect"));
+    }
+
+    #[test]
+    fn test_dtbo_roundtrip() {
+        let overlay = sample_fdt().to_bytes();
+        let dtbo = DtboImage {
+            page_size: 4096,
+            version: 1,
+            entries: vec![
+                DtboEntry { id: 1, rev: 0, custom: [0; 4], data: overlay.clone() },
+                DtboEntry { id: 2, rev: 1, custom: [1, 0, 0, 0], data: overlay.clone() },
+            ],
+        };
+        let bytes = dtbo.to_bytes().unwrap();
+        assert_eq!(DtboImage::parse(&bytes).unwrap(), dtbo);
+
+        // 表项数量或表偏移被改成极大值时直接报错，不按其分配内存
+        let mut huge_count = bytes.clone();
+        huge_count[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(DtboImage::parse(&huge_count).is_err());
+        let mut huge_offset = bytes.clone();
+        huge_offset[20..24].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(DtboImage::parse(&huge_offset).is_err());
+    }
+}
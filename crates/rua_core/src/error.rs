@@ -29,6 +29,9 @@ pub enum FlashError {
     #[error("修补错误: {0}")]
     PatchError(String),
 
+    #[error("设备校验失败: {0}")]
+    CheckFailed(String),
+
     #[error("无效的选择: {0}")]
     InvalidChoice(String),
 
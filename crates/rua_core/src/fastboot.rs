@@ -69,6 +69,23 @@ impl FastbootClient {
         Ok(status.success())
     }
 
+    /// 不向控制台输出 fastboot 的进度信息，失败时以 stderr 最后一行作为错误原因
+    pub async fn run_quiet(&self, args: &[&str]) -> Result<()> {
+        let cmd_args = self.build_args(args);
+        let output = Command::new(&self.fastboot_path)
+            .args(&cmd_args)
+            .output()
+            .await?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("").trim();
+            Err(FlashError::FastbootError(reason.to_string()))
+        }
+    }
+
     pub async fn capture(&self, args: &[&str]) -> Result<String> {
         let cmd_args = self.build_args(args);
         let output = Command::new(&self.fastboot_path)
@@ -84,45 +101,60 @@ impl FastbootClient {
         }
     }
 
+    pub async fn list_serials(&self) -> Result<Vec<(String, String)>> {
+        let mut serials = Vec::new();
+        let output = Command::new(&self.fastboot_path)
+            .arg("devices")
+            .output()
+            .await?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                serials.push((parts[0].to_string(), parts[1].to_string()));
+            }
+        }
+        Ok(serials)
+    }
+
     pub async fn list_devices(&self) -> Result<Vec<ConnectedDevice>> {
         let mut devices = Vec::new();
 
-        if let Ok(output) = self.capture(&["devices"]).await {
-            for line in output.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let serial = parts[0].to_string();
-                    let status = parts[1].to_string();
-                    let mode = if status.contains("fastboot") {
-                        DeviceMode::Fastboot
-                    } else {
-                        DeviceMode::Recovery
-                    };
-
-                    let mut dev = ConnectedDevice {
-                        serial: serial.clone(),
-                        mode,
-                        status,
-                        product: None,
-                        current_slot: None,
-                    };
-
-                    if let Ok(product) = self.get_var(&serial, "product").await {
-                        dev.product = Some(product);
-                    }
-                    if let Ok(slot) = self.get_var(&serial, "current-slot").await {
-                        dev.current_slot = Some(slot);
-                    }
-
-                    devices.push(dev);
-                }
+        if let Ok(serials) = self.list_serials().await {
+            for (serial, status) in serials {
+                devices.push(self.describe(&serial, &status).await);
             }
         }
 
         Ok(devices)
     }
 
-    async fn get_var(&self, serial: &str, var: &str) -> Result<String> {
+    pub(crate) async fn describe(&self, serial: &str, status: &str) -> ConnectedDevice {
+        let mode = if !status.contains("fastboot") {
+            DeviceMode::Recovery
+        } else if self.get_var(serial, "is-userspace").await.is_ok_and(|v| v == "yes") {
+            DeviceMode::FastbootD
+        } else {
+            DeviceMode::Fastboot
+        };
+
+        let mut dev = ConnectedDevice {
+            serial: serial.to_string(),
+            mode,
+            status: status.to_string(),
+            product: None,
+            current_slot: None,
+        };
+
+        if let Ok(product) = self.get_var(serial, "product").await {
+            dev.product = Some(product);
+        }
+        if let Ok(slot) = self.get_var(serial, "current-slot").await {
+            dev.current_slot = Some(slot);
+        }
+        dev
+    }
+
+    pub async fn get_var(&self, serial: &str, var: &str) -> Result<String> {
         let output = Command::new(&self.fastboot_path)
             .args(["-s", serial, "getvar", var])
             .output()
@@ -132,12 +164,10 @@ impl FastbootClient {
         let err_str = String::from_utf8_lossy(&output.stderr);
         let combined = format!("{}{}", out_str, err_str);
 
+        // 变量名本身可能带冒号（如 has-slot:boot），取变量名之后的部分作为值
         for line in combined.lines() {
-            if line.contains(var) {
-                let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() >= 2 {
-                    return Ok(parts[1].trim().to_string());
-                }
+            if let Some(value) = line.trim().strip_prefix(var).and_then(|rest| rest.strip_prefix(':')) {
+                return Ok(value.trim().to_string());
             }
         }
         Err(FlashError::PropertyNotFound(var.to_string()))
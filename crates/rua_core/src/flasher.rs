@@ -1,14 +1,20 @@
 use crate::fastboot::FastbootClient;
 use crate::error::{FlashError, Result};
 use crate::utils;
+use crate::avb;
+use crate::cpio::{Cpio, CpioEntry};
+use crate::journal::{FlashJournal, JournalReporter};
+use crate::plan::{FlashPlan, FlashStep, PlanReporter};
 use std::path::{Path, PathBuf};
 use std::fs::{self, File};
 use std::io::{Read, Write};
+use crate::bootimg::vendor::{self, RamdiskChoice, VendorBootImage};
+use crate::kernel::{self, KernelImage};
+use crate::kernel_info::KernelAnalysis;
+use crate::kmod::{ModuleInfo, TargetKernel};
 use android_bootimg::parser::BootImage;
 use zip::ZipArchive;
 use sha1::{Sha1, Digest};
-use flate2::write::GzEncoder;
-use flate2::Compression;
 use colored::Colorize;
 
 fn detect_and_skip_cpio_header(data: &[u8]) -> usize {
@@ -42,6 +48,18 @@ fn detect_and_skip_cpio_header(data: &[u8]) -> usize {
     0
 }
 
+/// AnyKernel3 刷机包中可能的内核文件名
+const AK3_KERNEL_NAMES: &[&str] = &["Image", "Image.gz", "Image.lz4", "Image.gz-dtb", "Image.lz4-dtb", "zImage"];
+
+/// 从 Magisk APK 或单独文件中提取的修补资产
+#[derive(Debug, Default)]
+pub struct MagiskAssets {
+    pub magiskinit: Vec<u8>,
+    pub magiskbin: Vec<u8>,
+    pub stub: Vec<u8>,
+    pub init_ld: Vec<u8>,
+}
+
 pub struct Flasher {
     pub client: FastbootClient,
 }
@@ -55,19 +73,158 @@ impl Flasher {
         self.flash_partition("", "boot", path).await
     }
 
-    pub async fn flash_vbmeta(&self, device_id: &str, path: &str) -> Result<()> {
+    pub async fn flash_vbmeta(&self, device_id: &str, path: &str) -> Result<avb::VbmetaFlagsPatch> {
+        self.flash_vbmeta_with_flags(device_id, "vbmeta", path, avb::FLAG_HASHTREE_DISABLED | avb::FLAG_VERIFICATION_DISABLED).await
+    }
+
+    /// 先离线修改 vbmeta 头部 flags，在系统临时目录生成副本再刷入 `partition`。
+    /// 带 AVB footer 的镜像（如 boot）应刷回它自己的分区；无论成败临时副本都会删除
+    pub async fn flash_vbmeta_with_flags(&self, device_id: &str, partition: &str, path: &str, flags: u32) -> Result<avb::VbmetaFlagsPatch> {
+        let temp_name = std::env::temp_dir().join(format!("rua_{}_{}.img", partition, std::process::id()));
+        let res = self.flash_patched_vbmeta(device_id, partition, Path::new(path), flags, &temp_name).await;
+        let _ = fs::remove_file(&temp_name);
+        res
+    }
+
+    async fn flash_patched_vbmeta(&self, device_id: &str, partition: &str, path: &Path, flags: u32, temp: &Path) -> Result<avb::VbmetaFlagsPatch> {
+        let patch = avb::patch_vbmeta_flags(path, flags, temp)?;
+        let temp_str = temp.to_string_lossy().to_string();
         let args: Vec<&str> = if device_id.is_empty() {
-            vec!["--disable-verity", "--disable-verification", "flash", "vbmeta", path]
+            vec!["flash", partition, &temp_str]
         } else {
-            vec!["-s", device_id, "--disable-verity", "--disable-verification", "flash", "vbmeta", path]
+            vec!["-s", device_id, "flash", partition, &temp_str]
         };
         if self.client.run(&args).await? {
-            Ok(())
+            Ok(patch)
         } else {
-            Err(FlashError::FastbootError("Failed to flash vbmeta".into()))
+            Err(FlashError::FastbootError(format!("Failed to flash {}", partition)))
+        }
+    }
+
+    /// 在当前选中的设备上按顺序执行刷机计划，遇到第一个失败的步骤即停止
+    pub async fn run_plan(&self, plan: &FlashPlan, reporter: &dyn PlanReporter) -> Result<()> {
+        let serial = self.client.get_serial().unwrap_or("").to_string();
+        let total = plan.steps.len();
+        for (index, step) in plan.steps.iter().enumerate() {
+            if reporter.should_cancel() {
+                return Err(FlashError::Interrupted);
+            }
+            reporter.on_step_start(&serial, index, total, step);
+            let res = self.run_step(step, reporter).await;
+            reporter.on_step_done(&serial, index, total, step, &res);
+            res?;
+        }
+        Ok(())
+    }
+
+    async fn run_step(&self, step: &FlashStep, reporter: &dyn PlanReporter) -> Result<()> {
+        let serial = self.client.get_serial().unwrap_or("");
+        match step {
+            FlashStep::CheckProduct { products } => {
+                let product = self.client.get_var(serial, "product").await?;
+                if products.iter().any(|p| p.eq_ignore_ascii_case(&product)) {
+                    Ok(())
+                } else {
+                    Err(FlashError::CheckFailed(format!(
+                        "刷机包适用于 {}，当前设备为 {}",
+                        products.join("/"),
+                        product
+                    )))
+                }
+            }
+            FlashStep::CheckAntiRollback { package_version } => {
+                // 读取不到 anti 时无法确认是否会触发防回滚，只有用户明确确认才继续
+                let device_version = match self.client.get_var(serial, "anti").await {
+                    Ok(v) => v.trim().parse::<u32>().map_err(|_| format!("设备返回的 anti 值无法识别: {}", v)),
+                    Err(e) => Err(format!("读取设备 anti 版本失败: {}", e)),
+                };
+                let device_version = match device_version {
+                    Ok(v) => v,
+                    Err(reason) => {
+                        let reason = format!("{}，无法确认是否会触发防回滚", reason);
+                        if reporter.confirm_override(serial, &reason) {
+                            return Ok(());
+                        }
+                        return Err(FlashError::CheckFailed(reason));
+                    }
+                };
+                match crate::xiaomi::anti_rollback_violation(device_version, *package_version) {
+                    Some(reason) => Err(FlashError::CheckFailed(reason)),
+                    None => Ok(()),
+                }
+            }
+            FlashStep::PatchAndFlash { partition, image, apk } => {
+                let patched = self.patch_image(image, apk).await?;
+                let patched_str = patched.to_string_lossy().to_string();
+                let res = self.client.run_quiet(&["flash", partition, &patched_str]).await;
+                let _ = fs::remove_file(&patched);
+                res
+            }
+            _ => {
+                let args = step.fastboot_args();
+                let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                self.client.run_quiet(&args).await
+            }
         }
     }
 
+    /// 用 Magisk APK 修补镜像（不刷入），结果保存为原镜像旁的 <名称>_magisk_patched.img；
+    /// 该文件已存在时依次尝试 <名称>_magisk_patched_1.img 等，不覆盖已有文件
+    async fn patch_image(&self, image: &Path, apk: &Path) -> Result<PathBuf> {
+        let out = self.magisk_patch(&image.to_string_lossy(), &apk.to_string_lossy(), None).await?;
+        let stem = image.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "boot".to_string());
+        let target = (0..)
+            .map(|i| match i {
+                0 => image.with_file_name(format!("{}_magisk_patched.img", stem)),
+                i => image.with_file_name(format!("{}_magisk_patched_{}.img", stem, i)),
+            })
+            .find(|p| !p.exists())
+            .unwrap();
+        fs::copy(&out, &target)?;
+        let _ = fs::remove_file(&out);
+        Ok(target)
+    }
+
+    /// 把计划中的 PatchAndFlash 步骤预先修补为普通的 Flash 步骤，同一镜像只修补一次。
+    /// 批量刷机时先调用，各设备共用修补结果，避免并发修补互相覆盖输出文件
+    pub async fn patch_plan(&self, plan: &FlashPlan) -> Result<FlashPlan> {
+        let mut patched: Vec<((PathBuf, PathBuf), PathBuf)> = Vec::new();
+        let mut steps = Vec::with_capacity(plan.steps.len());
+        for step in &plan.steps {
+            let FlashStep::PatchAndFlash { partition, image, apk } = step else {
+                steps.push(step.clone());
+                continue;
+            };
+            let key = (image.clone(), apk.clone());
+            let output = match patched.iter().find(|(k, _)| *k == key) {
+                Some((_, output)) => output.clone(),
+                None => {
+                    let output = self.patch_image(image, apk).await?;
+                    patched.push((key, output.clone()));
+                    output
+                }
+            };
+            steps.push(FlashStep::Flash { partition: partition.clone(), image: output });
+        }
+        Ok(FlashPlan { name: plan.name.clone(), steps })
+    }
+
+    /// 按日志执行计划，从第一个未完成的步骤开始，每一步的状态都会写回日志
+    pub async fn run_journaled(&self, journal: &mut FlashJournal, reporter: &dyn PlanReporter) -> Result<()> {
+        let serial = self.client.get_serial().unwrap_or("");
+        if journal.serial != serial {
+            return Err(FlashError::InvalidChoice(format!(
+                "刷机日志属于设备 {}，当前设备为 {}",
+                journal.serial, serial
+            )));
+        }
+        let Some(start) = journal.first_incomplete() else { return Ok(()) };
+        let plan = journal.plan();
+        let remaining = FlashPlan { name: plan.name, steps: plan.steps[start..].to_vec() };
+        let journaled = JournalReporter::new(journal, reporter, start);
+        self.run_plan(&remaining, &journaled).await
+    }
+
     pub async fn list_devices(&self) -> Result<Vec<super::ConnectedDevice>> {
         self.client.list_devices().await
     }
@@ -84,11 +241,15 @@ impl Flasher {
         }
     }
 
-    pub async fn disable_avb(&self, device_id: &str, vbmeta_path: &str) -> Result<()> {
+    pub async fn disable_avb(&self, device_id: &str, vbmeta_path: &str) -> Result<avb::VbmetaFlagsPatch> {
         self.flash_vbmeta(device_id, vbmeta_path).await
     }
 
+    /// 优先解压内核并按 Linux version 信息判断，失败时再在原始数据中按字符串猜测
     pub fn detect_kmi_from_kernel(kernel_data: &[u8]) -> Option<String> {
+        if let Some(kmi) = KernelAnalysis::analyze(kernel_data).ok().and_then(|a| a.kmi()) {
+            return Some(kmi);
+        }
         let printable_strings: Vec<&str> = kernel_data
             .split(|&b| b == 0)
             .filter_map(|slice| std::str::from_utf8(slice).ok())
@@ -118,56 +279,85 @@ impl Flasher {
     }
 
     pub fn read_kernel_version_and_kmi_from_boot_img(boot_img_path: &str) -> Result<(Option<String>, Option<String>)> {
+        if let Ok(analysis) = crate::bootimg::analyze_kernel(Path::new(boot_img_path))
+            && let Some(banner) = analysis.banner
+        {
+            return Ok((banner.kmi(), Some(banner.full)));
+        }
         let mut boot_data = Vec::new();
         File::open(boot_img_path)?.read_to_end(&mut boot_data)?;
         let boot_img = BootImage::parse(&boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?;
         if let Some(kernel) = boot_img.get_blocks().get_kernel() {
-            let data = kernel.get_data();
-            let mut printable_strings: Vec<String> = Vec::new();
-            let mut buf: Vec<u8> = Vec::new();
-            for &b in data {
-                if (0x20..=0x7e).contains(&b) {
-                    buf.push(b);
-                } else {
-                    if buf.len() >= 6 {
-                        if let Ok(s) = String::from_utf8(buf.clone()) {
-                            printable_strings.push(s);
-                        }
+            return Ok(Self::read_kernel_version_and_kmi(kernel.get_data()));
+        }
+        Ok((None, None))
+    }
+
+    /// 从未压缩的内核中查找版本字符串，返回 (KMI, 完整版本字符串)
+    pub fn read_kernel_version_and_kmi(data: &[u8]) -> (Option<String>, Option<String>) {
+        let mut printable_strings: Vec<String> = Vec::new();
+        let mut buf: Vec<u8> = Vec::new();
+        for &b in data {
+            if (0x20..=0x7e).contains(&b) {
+                buf.push(b);
+            } else {
+                if buf.len() >= 6 {
+                    if let Ok(s) = String::from_utf8(buf.clone()) {
+                        printable_strings.push(s);
                     }
-                    buf.clear();
                 }
+                buf.clear();
             }
-            if buf.len() >= 6 {
-                if let Ok(s) = String::from_utf8(buf.clone()) {
-                    printable_strings.push(s);
-                }
+        }
+        if buf.len() >= 6 {
+            if let Ok(s) = String::from_utf8(buf.clone()) {
+                printable_strings.push(s);
             }
-            let re = regex::Regex::new(r"(?i)(\d+\.\d+)[^\n\r]*?(android(\d{2}))").ok();
-            if let Some(re) = re {
-                for s in printable_strings {
-                    if let Some(caps) = re.captures(&s) {
-                        let kver = caps.get(1).map(|m| m.as_str()).unwrap_or("");
-                        let android = caps.get(2).map(|m| m.as_str()).unwrap_or("");
-                        let kmi = if !kver.is_empty() && !android.is_empty() {
-                            Some(format!("{}-{}", android.to_lowercase(), kver))
-                        } else { None };
-                        return Ok((kmi, Some(s)));
-                    }
+        }
+        let re = regex::Regex::new(r"(?i)(\d+\.\d+)[^\n\r]*?(android(\d{2}))").ok();
+        if let Some(re) = re {
+            for s in printable_strings {
+                if let Some(caps) = re.captures(&s) {
+                    let kver = caps.get(1).map(|m| m.as_str()).unwrap_or("");
+                    let android = caps.get(2).map(|m| m.as_str()).unwrap_or("");
+                    let kmi = if !kver.is_empty() && !android.is_empty() {
+                        Some(format!("{}-{}", android.to_lowercase(), kver))
+                    } else { None };
+                    return (kmi, Some(s));
                 }
             }
-            return Ok((None, None));
         }
-        Ok((None, None))
+        (None, None)
     }
 
-    fn is_magisk_patched(entries: &[(String, u32, Vec<u8>)]) -> bool {
-        entries.iter().any(|(name, _, _)| name == ".backup/.magisk")
+    /// 指定了 boot 镜像或内核文件时分析该文件，否则分析待修补镜像自带的内核
+    fn analyze_target_kernel(kernel: Option<TargetKernel<'_>>, boot_img: Option<&BootImage>) -> Result<KernelAnalysis> {
+        if let Some(TargetKernel::File(path)) = kernel {
+            return crate::bootimg::analyze_kernel(Path::new(path));
+        }
+        let kernel = boot_img
+            .and_then(|b| b.get_blocks().get_kernel())
+            .ok_or_else(|| FlashError::PatchError("镜像中没有内核，请提供 boot 镜像、内核文件或设备 KMI".to_string()))?;
+        KernelAnalysis::analyze(kernel.get_data())
+    }
+
+    fn is_magisk_patched(cpio: &Cpio) -> bool {
+        cpio.contains(".backup/.magisk")
     }
 
-    fn is_kernelsu_patched(entries: &[(String, u32, Vec<u8>)]) -> bool {
-        entries.iter().any(|(name, _, _)| name == "kernelsu.ko")
+    fn is_kernelsu_patched(cpio: &Cpio) -> bool {
+        cpio.contains("kernelsu.ko")
     }
 
+    /// 原 init 连同属主、时间等字段一起改名为 init.real，再写入新的 init
+    fn replace_init(cpio: &mut Cpio, init: Vec<u8>, perm: u32) {
+        if let Some(old) = cpio.remove("init") {
+            cpio.insert("init.real", old);
+        }
+        cpio.insert("init", CpioEntry::file(perm, init));
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn kernelsu_lkm_install(
         &self,
         boot_img_path: &str,
@@ -175,9 +365,11 @@ impl Flasher {
         ksuinit_d_dir: Option<&str>,
         ko_path: &str,
         target_partition: &str,
+        vendor_ramdisk: Option<RamdiskChoice<'_>>,
+        kernel: Option<TargetKernel<'_>>,
         force: bool
     ) -> Result<()> {
-        let out_name = self.kernelsu_lkm_patch(boot_img_path, ksuinit_path, ksuinit_d_dir, ko_path, target_partition, force).await?;
+        let out_name = Self::kernelsu_lkm_patch(boot_img_path, ksuinit_path, ksuinit_d_dir, ko_path, target_partition, vendor_ramdisk, kernel, force).await?;
         let res = self.client.run(&["flash", target_partition, &out_name]).await;
         let _ = fs::remove_file(&out_name);
         if res? {
@@ -187,28 +379,59 @@ impl Flasher {
         }
     }
 
+    /// vendor_ramdisk 仅对 vendor_boot 镜像生效，含义同 magisk_patch。
+    /// kernel 为用于校验内核模块的目标内核，None 时使用镜像自带的内核；
+    /// 无法读取目标内核时拒绝修补，除非指定 force。
+    /// 只修补镜像、不访问设备，离线修补时无需 fastboot
+    #[allow(clippy::too_many_arguments)]
     pub async fn kernelsu_lkm_patch(
-        &self,
         boot_img_path: &str,
         ksuinit_path: &str,
         ksuinit_d_dir: Option<&str>,
         ko_path: &str,
         target_partition: &str,
+        vendor_ramdisk: Option<RamdiskChoice<'_>>,
+        kernel: Option<TargetKernel<'_>>,
         force: bool
     ) -> Result<String> {
         let mut boot_data = Vec::new();
         File::open(boot_img_path)?.read_to_end(&mut boot_data)?;
-        let boot_img = BootImage::parse(&boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?;
+        let mut vendor_img = None;
+        let mut boot_img = None;
+        if vendor::is_vendor_boot(&boot_data) {
+            let image = VendorBootImage::parse(&boot_data)?;
+            let index = image.select_ramdisk(vendor_ramdisk)?;
+            println!("- vendor ramdisk: {}", image.ramdisks[index].0.display_name(index));
+            vendor_img = Some((image, index));
+        } else {
+            boot_img = Some(BootImage::parse(&boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?);
+        }
 
-        if let Some(kernel) = boot_img.get_blocks().get_kernel() {
-            let kernel_data = kernel.get_data();
-            if let Some(kmi) = Self::detect_kmi_from_kernel(kernel_data) {
-                println!("- KMI: {}", kmi);
+        // 模块 KMI 与内核不一致时无法加载，严重时无法开机，因此直接拒绝
+        let module = ModuleInfo::load(Path::new(ko_path))?;
+        println!("- 内核模块: {} ({})", module.name.as_deref().unwrap_or("?"), module.vermagic.as_deref().unwrap_or("无 vermagic"));
+        match kernel {
+            Some(TargetKernel::Kmi(kmi)) => {
+                println!("- KMI: {} (设备)", kmi);
+                module.check_kmi(kmi)?;
             }
+            _ => match Self::analyze_target_kernel(kernel, boot_img.as_ref()) {
+                Ok(analysis) => {
+                    if let Some(kmi) = analysis.kmi() {
+                        println!("- KMI: {}", kmi);
+                    }
+                    module.check_kernel(&analysis)?;
+                }
+                Err(e) if force => println!("- 警告: {}，已跳过内核模块与内核的匹配校验", e),
+                Err(e) => return Err(FlashError::PatchError(format!("无法校验内核模块与内核是否匹配: {}", e))),
+            },
         }
 
-        let rd = boot_img.get_blocks().get_ramdisk().ok_or_else(|| FlashError::PatchError("no ramdisk".into()))?;
-        let rd_raw = rd.get_data();
+        let rd_raw = match (&vendor_img, &boot_img) {
+            (Some((image, index)), _) => image.ramdisks[*index].1.as_slice(),
+            (None, Some(b)) => b.get_blocks().get_ramdisk().ok_or_else(|| FlashError::PatchError("no ramdisk".into()))?.get_data(),
+            (None, None) => return Err(FlashError::PatchError("no ramdisk".into())),
+        };
         let fmt = utils::detect_ramdisk_format(rd_raw);
         let rd_decomp = utils::decompress_ramdisk(rd_raw)?;
 
@@ -219,13 +442,9 @@ impl Flasher {
             rd_cpio = sliced.to_vec();
         }
 
-        let (mut entries, old_init_info) = if rd_cpio.is_empty() {
-            (Vec::new(), None)
-        } else {
-            utils::cpio_load_with_threecpio(&rd_cpio)?
-        };
+        let mut cpio = if rd_cpio.is_empty() { Cpio::new() } else { Cpio::parse(&rd_cpio)? };
 
-        if Self::is_magisk_patched(&entries) {
+        if Self::is_magisk_patched(&cpio) {
             println!("- 警告: 检测到此镜像已由 Magisk 修补，继续可能导致冲突");
             if !force {
                 let proceed = Self::prompt_yes_no("是否继续安装 KernelSU？[y/N]: ", false);
@@ -235,21 +454,14 @@ impl Flasher {
             }
         }
 
-        if Self::is_kernelsu_patched(&entries) {
+        if Self::is_kernelsu_patched(&cpio) {
             println!("- 警告: 此镜像可能已由 KernelSU 修补");
         }
 
-        entries.retain(|(name, _, _)| name != "init");
-        
-        if let Some((mode, old_data)) = old_init_info {
-            entries.push(("init.real".to_string(), mode as u32, old_data));
-        }
-        
-        let ksuinit_bytes = fs::read(ksuinit_path)?;
-        entries.push(("init".to_string(), 0o755, ksuinit_bytes));
-        
-        let ko_bytes = fs::read(ko_path)?;
-        entries.push(("lib/modules/kernelsu.ko".to_string(), 0o755, ko_bytes));
+        Self::replace_init(&mut cpio, fs::read(ksuinit_path)?, 0o755);
+
+        cpio.mkdir_all("lib/modules", 0o755);
+        cpio.insert("lib/modules/kernelsu.ko", CpioEntry::file(0o755, fs::read(ko_path)?));
         
         if let Some(dir) = ksuinit_d_dir {
             let base = Path::new(dir);
@@ -259,9 +471,8 @@ impl Flasher {
                     let p = entry.path();
                     if p.is_file() {
                         if let Some(file_name) = p.file_name().and_then(|s| s.to_str()) {
-                            let target = format!("etc/ksuinit.d/{}", file_name);
-                            let content = fs::read(&p)?;
-                            entries.push((target, 0o755, content));
+                            cpio.mkdir_all("etc/ksuinit.d", 0o755);
+                            cpio.insert(&format!("etc/ksuinit.d/{}", file_name), CpioEntry::file(0o755, fs::read(&p)?));
                         }
                     }
                 }
@@ -272,11 +483,11 @@ impl Flasher {
             match crate::sepolicy::Sepolicy::parse(&sep) {
                 Ok(mut s) => {
                     s.add_magisk_rules();
-                    entries.push(("sepolicy".to_string(), 0o644, s.data));
+                    cpio.insert("sepolicy", CpioEntry::file(0o644, s.data));
                     println!("- 已注入 SELinux 规则（KernelSU 路径）");
                 }
                 Err(_) => {
-                    entries.push(("sepolicy".to_string(), 0o644, sep));
+                    cpio.insert("sepolicy", CpioEntry::file(0o644, sep));
                     println!("- 已写入原始 sepolicy（KernelSU 路径）");
                 }
             }
@@ -284,37 +495,31 @@ impl Flasher {
             println!("- 未找到 sepolicy，跳过（KernelSU 路径）");
         }
         
-        let new_cpio = utils::cpio_create_with_threecpio(&entries)?;
+        let new_cpio = cpio.to_bytes();
         let final_ramdisk = utils::compress_ramdisk(fmt, &new_cpio)?;
-        let patched = crate::bootimg::patch_with_replacements(&boot_img, None, Some((final_ramdisk, true)))?;
+        let patched = match (vendor_img, &boot_img) {
+            (Some((mut image, index)), _) => {
+                image.ramdisks[index].1 = final_ramdisk;
+                image.to_bytes()?
+            }
+            (None, Some(b)) => crate::bootimg::patch_with_replacements(b, None, Some((final_ramdisk, true)))?,
+            (None, None) => unreachable!(),
+        };
         let out_name = format!("ksu_lkm_patched_{}.img", target_partition);
         fs::write(&out_name, patched)?;
         Ok(out_name)
     }
 
     pub async fn apatch_patch(&self, boot_img_path: &str, skey: &str, target_partition: &str, is_raw_kernel: bool, auto_flash: bool) -> Result<()> {
-        let mut new_kernel_data;
-        let mut was_compressed = false;
-
         if is_raw_kernel {
             // 如果是原始内核 (Huawei 等设备)
             let mut kernel_data = Vec::new();
             File::open(boot_img_path)?.read_to_end(&mut kernel_data)?;
-            
-            let mut raw_kernel = kernel_data.clone();
-            if let Ok(decompressed) = utils::decompress_ramdisk(&kernel_data)
-                && decompressed.len() != kernel_data.len() {
-                    raw_kernel = decompressed;
-                    was_compressed = true;
-                }
-            
-            new_kernel_data = self.run_kptools(&raw_kernel, skey, target_partition).await?;
-            
-            if was_compressed {
-                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(&new_kernel_data)?;
-                new_kernel_data = encoder.finish()?;
-            }
+
+            let mut kernel_image = KernelImage::parse(&kernel_data)?;
+            println!("[INFO] 内核压缩格式: {}", kernel_image.format.name());
+            kernel_image.raw = self.run_kptools(&kernel_image.raw, skey, target_partition).await?;
+            let new_kernel_data = kernel_image.to_bytes()?;
 
             let out_name = format!("apatch_patched_{}.img", target_partition);
             fs::write(&out_name, new_kernel_data)?;
@@ -345,21 +550,10 @@ impl Flasher {
                 return Err(FlashError::PatchError("未在镜像中找到内核数据".into()));
             }
 
-            let mut raw_kernel = kernel_data.clone();
-            if let Ok(decompressed) = utils::decompress_ramdisk(&kernel_data)
-                && decompressed.len() != kernel_data.len() {
-                    raw_kernel = decompressed;
-                    was_compressed = true;
-                }
-
-            let patched_raw_kernel = self.run_kptools(&raw_kernel, skey, target_partition).await?;
-            
-            new_kernel_data = patched_raw_kernel;
-            if was_compressed {
-                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(&new_kernel_data)?;
-                new_kernel_data = encoder.finish()?;
-            }
+            let mut kernel_image = KernelImage::parse(&kernel_data)?;
+            println!("[INFO] 内核压缩格式: {}", kernel_image.format.name());
+            kernel_image.raw = self.run_kptools(&kernel_image.raw, skey, target_partition).await?;
+            let new_kernel_data = kernel_image.to_bytes()?;
 
             let out_name = format!("apatch_patched_{}.img", target_partition);
             let patched = crate::bootimg::patch_with_replacements(&boot_img, Some((new_kernel_data, false)), None)?;
@@ -431,13 +625,14 @@ impl Flasher {
         let mut found = false;
         for i in 0..archive.len() {
             let mut file = archive.by_index(i).map_err(|e| FlashError::PatchError(e.to_string()))?;
-            if file.name() == "Image" || file.name().ends_with("/Image") {
+            let name = file.name().rsplit('/').next().unwrap_or_default();
+            if AK3_KERNEL_NAMES.contains(&name) {
                 file.read_to_end(&mut kernel_data)?;
                 found = true;
                 break;
             }
         }
-        if !found { return Err(FlashError::PatchError("ZIP 中未找到 Image 文件".into())); }
+        if !found { return Err(FlashError::PatchError("ZIP 中未找到 Image / Image.gz / Image.lz4 等内核文件".into())); }
  
         let mut old_boot_data = Vec::new();
         File::open(boot_img_path)?.read_to_end(&mut old_boot_data)?;
@@ -461,14 +656,15 @@ impl Flasher {
             println!("{}", format!("- 新内核版本:   {}", v).green());
         }
 
+        // 新内核按原内核的压缩格式重新压缩，原内核附加的 DTB 与 MTK 头部保持不变
         let out_name = format!("ak3_patched_{}.img", target_partition);
         if is_raw_kernel {
-            // 原始内核模式，直接写出 Image
-            fs::write(&out_name, &kernel_data)?;
+            fs::write(&out_name, kernel::replace_kernel(&old_boot_data, &kernel_data)?)?;
         } else {
-            // 标准 Boot 模式，替换内核重新打包
             let boot_img = BootImage::parse(&old_boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?;
-            let patched = crate::bootimg::patch_with_replacements(&boot_img, Some((kernel_data, false)), None)?;
+            let old_kernel = boot_img.get_blocks().get_kernel().map(|k| k.get_data()).unwrap_or_default();
+            let new_kernel = if old_kernel.is_empty() { kernel_data } else { kernel::replace_kernel(old_kernel, &kernel_data)? };
+            let patched = crate::bootimg::patch_with_replacements(&boot_img, Some((new_kernel, false)), None)?;
             fs::write(&out_name, patched)?;
         }
  
@@ -485,51 +681,50 @@ impl Flasher {
         }
     }
 
-    pub async fn magisk_patch(&self, boot_img_path: &str, apk_path: &str, _target_partition: &str) -> Result<String> {
+    /// vendor_ramdisk 仅对 vendor_boot 镜像生效，按名称、类型或序号选择要修补的 vendor ramdisk，
+    /// 为 None 时与 Magisk 一致优先选择名为 init_boot 的片段
+    pub async fn magisk_patch(&self, boot_img_path: &str, apk_path: &str, vendor_ramdisk: Option<RamdiskChoice<'_>>) -> Result<String> {
         let apk_file = File::open(apk_path)?;
         let mut archive = ZipArchive::new(apk_file).map_err(|e| FlashError::PatchError(e.to_string()))?;
-        let (mut magiskinit, mut magiskbin, mut stub, mut init_ld) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let mut assets = MagiskAssets::default();
         for i in 0..archive.len() {
             let mut file = archive.by_index(i).map_err(|e| FlashError::PatchError(e.to_string()))?;
             let name = file.name();
             if name.contains("libmagiskinit.so") && name.contains("arm64-v8a") {
-                file.read_to_end(&mut magiskinit)?;
+                file.read_to_end(&mut assets.magiskinit)?;
             } else if name == "assets/magisk64" || name.contains("libmagisk.so") {
-                magiskbin.clear();
-                file.read_to_end(&mut magiskbin)?;
+                assets.magiskbin.clear();
+                file.read_to_end(&mut assets.magiskbin)?;
             } else if name == "assets/stub.apk" {
-                file.read_to_end(&mut stub)?;
+                file.read_to_end(&mut assets.stub)?;
             } else if name == "assets/init-ld" || name.contains("libinit-ld.so") {
-                file.read_to_end(&mut init_ld)?;
+                file.read_to_end(&mut assets.init_ld)?;
             }
         }
-        if magiskinit.is_empty() { return Err(FlashError::PatchError("APK 中未找到关键资产 (libmagiskinit.so)".into())); }
+        if assets.magiskinit.is_empty() { return Err(FlashError::PatchError("APK 中未找到关键资产 (libmagiskinit.so)".into())); }
 
-        self.do_magisk_patch(boot_img_path, magiskinit, magiskbin, stub, init_ld, "").await
+        self.do_magisk_patch(boot_img_path, &assets, vendor_ramdisk, "").await
     }
 
-    pub async fn magisk_patch_with_files(&self, boot_img_path: &str, files: &[(String, PathBuf)], _target_partition: &str) -> Result<String> {
-        let mut magiskinit = Vec::new();
-        let mut magiskbin = Vec::new();
-        let mut stub = Vec::new();
-        let mut init_ld = Vec::new();
+    pub async fn magisk_patch_with_files(&self, boot_img_path: &str, files: &[(String, PathBuf)], vendor_ramdisk: Option<RamdiskChoice<'_>>) -> Result<String> {
+        let mut assets = MagiskAssets::default();
 
         for (key, path) in files {
             let mut content = Vec::new();
             File::open(path)?.read_to_end(&mut content)?;
 
             match key.as_str() {
-                "magiskinit" => magiskinit = content,
-                "magiskbin" => magiskbin = content,
-                "stub" => stub = content,
-                "init_ld" => init_ld = content,
+                "magiskinit" => assets.magiskinit = content,
+                "magiskbin" => assets.magiskbin = content,
+                "stub" => assets.stub = content,
+                "init_ld" => assets.init_ld = content,
                 _ => {}
             }
         }
 
-        if magiskinit.is_empty() { return Err(FlashError::PatchError("未找到 libmagiskinit.so".into())); }
+        if assets.magiskinit.is_empty() { return Err(FlashError::PatchError("未找到 libmagiskinit.so".into())); }
 
-        self.do_magisk_patch(boot_img_path, magiskinit, magiskbin, stub, init_ld, "").await
+        self.do_magisk_patch(boot_img_path, &assets, vendor_ramdisk, "").await
     }
 
     pub async fn flash_partition(&self, device_id: &str, partition: &str, image_path: &str) -> Result<()> {
@@ -555,10 +750,8 @@ impl Flasher {
     async fn do_magisk_patch(
         &self,
         boot_img_path: &str,
-        magiskinit: Vec<u8>,
-        magiskbin: Vec<u8>,
-        stub: Vec<u8>,
-        init_ld: Vec<u8>,
+        assets: &MagiskAssets,
+        vendor_ramdisk: Option<RamdiskChoice<'_>>,
         target_partition: &str
     ) -> Result<String> {
         println!("{}", ">> 正在读取 Boot 镜像...".cyan().bold());
@@ -574,11 +767,23 @@ impl Flasher {
             sum
         };
 
-        println!("{}", ">> 正在解析 BootImage 格式...".cyan().bold());
-        let boot_img = BootImage::parse(&boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?;
+        // vendor_boot 由 rua_core 自行拆分 vendor ramdisk 表，只修补选中的片段
+        let mut vendor_img = None;
+        let mut boot_img = None;
+        if vendor::is_vendor_boot(&boot_data) {
+            println!("{}", ">> 正在解析 vendor_boot 格式...".cyan().bold());
+            let image = VendorBootImage::parse(&boot_data)?;
+            let index = image.select_ramdisk(vendor_ramdisk)?;
+            let (entry, _) = &image.ramdisks[index];
+            println!("{}", format!(">> 修补 vendor ramdisk: {} ({})", entry.display_name(index), entry.ramdisk_type.name()).cyan().bold());
+            vendor_img = Some((image, index));
+        } else {
+            println!("{}", ">> 正在解析 BootImage 格式...".cyan().bold());
+            boot_img = Some(BootImage::parse(&boot_data).map_err(|e| FlashError::PatchError(e.to_string()))?);
+        }
 
-        let has_kernel = boot_img.get_blocks().get_kernel().map(|k| !k.get_data().is_empty()).unwrap_or(false);
-        let is_init_boot = !has_kernel;
+        let has_kernel = boot_img.as_ref().and_then(|b| b.get_blocks().get_kernel()).map(|k| !k.get_data().is_empty()).unwrap_or(false);
+        let is_init_boot = !has_kernel && vendor_img.is_none();
 
         if is_init_boot {
             println!("{}", ">> 检测到 init_boot 分区（无 Kernel，仅 Ramdisk）".cyan().bold());
@@ -587,8 +792,12 @@ impl Flasher {
         println!("{}", ">> 正在解压 Ramdisk...".cyan().bold());
         let mut ramdisk_data = Vec::new();
         let mut ramdisk_fmt = utils::RamdiskFormat::Uncompressed;
-        if let Some(rd) = boot_img.get_blocks().get_ramdisk() {
-            let raw_rd = rd.get_data();
+        let raw_ramdisk = match (&vendor_img, &boot_img) {
+            (Some((image, index)), _) => Some(image.ramdisks[*index].1.as_slice()),
+            (None, Some(b)) => b.get_blocks().get_ramdisk().map(|rd| rd.get_data()),
+            (None, None) => None,
+        };
+        if let Some(raw_rd) = raw_ramdisk {
             println!("{}", format!(">> 原始 Ramdisk 大小: {} bytes", raw_rd.len()).green());
             println!("{}", format!(">> Ramdisk 魔数: {:02x?}", &raw_rd[0..std::cmp::min(16, raw_rd.len())]).yellow());
             ramdisk_fmt = utils::detect_ramdisk_format(raw_rd);
@@ -636,13 +845,9 @@ impl Flasher {
             println!("{}", ">> No Ramdisk data found".yellow());
         }
 
-        let (mut entries, old_init_info) = if ramdisk_data.is_empty() {
-            (Vec::new(), None)
-        } else {
-            utils::cpio_load_with_threecpio(&ramdisk_data)?
-        };
-        
-        if Self::is_magisk_patched(&entries) {
+        let mut cpio = if ramdisk_data.is_empty() { Cpio::new() } else { Cpio::parse(&ramdisk_data)? };
+
+        if Self::is_magisk_patched(&cpio) {
             println!("{}", ">> 警告: 检测到镜像已包含 Magisk 修补".yellow());
             let proceed = Self::prompt_yes_no("是否在已修补基础上继续？[y/N]: ", false);
             if !proceed {
@@ -650,17 +855,43 @@ impl Flasher {
             }
         }
         
-        Self::patch_ramdisk_entries(&mut entries, old_init_info.as_ref(), &magiskinit, &magiskbin, &stub, &init_ld, &sha1_sum, &ramdisk_data)?;
+        let config = Self::magisk_config(&sha1_sum, vendor_img.is_some());
+        Self::patch_ramdisk_entries(&mut cpio, assets, &config, &ramdisk_data)?;
 
         println!("{}", ">> 正在重新打包 Ramdisk (CPIO)...".cyan().bold());
-        let new_cpio_data = utils::cpio_create_with_threecpio(&entries)?;
+        let new_cpio_data = cpio.to_bytes();
         println!("{}", format!(">> CPIO 包大小: {} bytes", new_cpio_data.len()).green());
 
         println!("{}", ">> 正在压缩 Ramdisk (保持原格式)...".cyan().bold());
         let final_ramdisk = utils::compress_ramdisk(ramdisk_fmt, &new_cpio_data)?;
         println!("{}", format!(">> 最终 Ramdisk 大小: {} bytes", final_ramdisk.len()).green());
 
-        if is_init_boot {
+        if let Some((mut image, index)) = vendor_img {
+            println!("{}", ">> 正在重新打包 vendor_boot...".cyan().bold());
+            image.ramdisks[index].1 = final_ramdisk;
+            let patched_image = image.to_bytes()?;
+            println!("{}", format!(">> 修补后镜像大小: {} bytes", patched_image.len()).green());
+
+            let out_name = format!("magisk_patched_{}.img", if target_partition.is_empty() { "vendor_boot" } else { target_partition });
+            fs::write(&out_name, &patched_image)?;
+            println!("{}", format!(">> Saved patched image: {}", out_name).green());
+
+            if target_partition.is_empty() {
+                println!("{}", ">> Skipping flash step (patch only)".yellow());
+                return Ok(out_name);
+            }
+
+            println!("{}", format!(">> Flashing {} partition...", target_partition).cyan().bold());
+            let res = self.client.run(&["flash", target_partition, &out_name]).await;
+            let _ = fs::remove_file(&out_name);
+
+            if res? {
+                Ok(out_name)
+            } else {
+                Err(FlashError::FastbootError("Failed to flash patched vendor_boot image".into()))
+            }
+        } else if is_init_boot {
+            let boot_img = boot_img.ok_or_else(|| FlashError::PatchError("no boot image".into()))?;
             println!("{}", ">> 正在修补 BootImage (init_boot)...".cyan().bold());
             let patched_image = crate::bootimg::patch_with_replacements(&boot_img, None, Some((final_ramdisk, true)))?;
             println!("{}", format!(">> 修补后镜像大小: {} bytes", patched_image.len()).green());
@@ -684,6 +915,7 @@ impl Flasher {
                 Err(FlashError::FastbootError("Failed to flash patched init_boot image".into()))
             }
         } else {
+            let boot_img = boot_img.ok_or_else(|| FlashError::PatchError("no boot image".into()))?;
             println!("{}", ">> 正在修补 BootImage...".cyan().bold());
             let mut kernel_rep = None;
             if let Some(kernel) = boot_img.get_blocks().get_kernel() {
@@ -721,30 +953,25 @@ impl Flasher {
     }
 
     fn patch_ramdisk_entries(
-        entries: &mut Vec<(String, u32, Vec<u8>)>,
-        old_init_info: Option<&(usize, Vec<u8>)>,
-        magiskinit: &[u8],
-        magiskbin: &[u8],
-        stub: &[u8],
-        init_ld: &[u8],
-        sha1_sum: &str,
+        cpio: &mut Cpio,
+        assets: &MagiskAssets,
+        config: &str,
         ramdisk_data: &[u8]
     ) -> Result<()> {
-        entries.retain(|(name, _, _)| name != "init");
-        if let Some((mode, old)) = old_init_info {
-            entries.push(("init.real".to_string(), *mode as u32, old.clone()));
-        }
-        entries.push(("init".to_string(), 0o750, magiskinit.to_vec()));
+        let MagiskAssets { magiskinit, magiskbin, stub, init_ld } = assets;
+        Self::replace_init(cpio, magiskinit.to_vec(), 0o750);
         println!("{}", ">> 已替换 init 为 Magiskinit".green());
 
-        entries.retain(|(name, _, _)| !name.starts_with("overlay.d") && !name.starts_with(".backup"));
+        cpio.retain(|name, _| !name.starts_with("overlay.d") && !name.starts_with(".backup"));
+        cpio.mkdir_all("overlay.d/sbin", 0o750);
+        cpio.mkdir_all(".backup", 0o000);
         println!("{}", ">> 已清理旧的 overlay.d 和 .backup".green());
 
         if !magiskbin.is_empty() {
             println!("{}", ">> 正在压缩 Magisk 二进制 (XZ)...".cyan().bold());
             let mut compressed = Vec::new();
             lzma_rs::xz_compress(&mut &magiskbin[..], &mut compressed).map_err(|e| FlashError::PatchError(format!("XZ compression failed: {:?}", e)))?;
-            entries.push(("overlay.d/sbin/magisk.xz".to_string(), 0o644, compressed));
+            cpio.insert("overlay.d/sbin/magisk.xz", CpioEntry::file(0o644, compressed));
             println!("{}", ">> 已添加 overlay.d/sbin/magisk.xz".green());
         }
 
@@ -752,7 +979,7 @@ impl Flasher {
             println!("{}", ">> 正在压缩 Stub APK (XZ)...".cyan().bold());
             let mut compressed = Vec::new();
             lzma_rs::xz_compress(&mut &stub[..], &mut compressed).map_err(|e| FlashError::PatchError(format!("XZ compression failed: {:?}", e)))?;
-            entries.push(("overlay.d/sbin/stub.xz".to_string(), 0o644, compressed));
+            cpio.insert("overlay.d/sbin/stub.xz", CpioEntry::file(0o644, compressed));
             println!("{}", ">> 已添加 overlay.d/sbin/stub.xz".green());
         }
 
@@ -760,12 +987,11 @@ impl Flasher {
             println!("{}", ">> 正在压缩 init-ld (XZ)...".cyan().bold());
             let mut compressed = Vec::new();
             lzma_rs::xz_compress(&mut &init_ld[..], &mut compressed).map_err(|e| FlashError::PatchError(format!("XZ compression failed: {:?}", e)))?;
-            entries.push(("overlay.d/sbin/init-ld.xz".to_string(), 0o644, compressed));
+            cpio.insert("overlay.d/sbin/init-ld.xz", CpioEntry::file(0o644, compressed));
             println!("{}", ">> 已添加 overlay.d/sbin/init-ld.xz".green());
         }
 
-        let config = format!("KEEPVERITY=false\nKEEPFORCEENCRYPT=false\nRECOVERYMODE=false\nVENDORBOOT=false\nSHA1={}\n", sha1_sum);
-        entries.push((".backup/.magisk".to_string(), 0o000, config.into_bytes()));
+        cpio.insert(".backup/.magisk", CpioEntry::file(0o000, config.as_bytes().to_vec()));
         println!("{}", ">> 已添加 .magisk 配置".green());
 
         if let Some(sepolicy_data) = crate::sepolicy::extract_sepolicy(ramdisk_data) {
@@ -773,11 +999,11 @@ impl Flasher {
                 Ok(mut sepolicy) => {
                     println!("{}", ">> 正在注入 Magisk SELinux 规则...".cyan().bold());
                     sepolicy.add_magisk_rules();
-                    entries.push(("sepolicy".to_string(), 0o644, sepolicy.data));
+                    cpio.insert("sepolicy", CpioEntry::file(0o644, sepolicy.data));
                     println!("{}", ">> 已添加 sepolicy (含 Magisk 规则)".green());
                 }
                 Err(_) => {
-                    entries.push(("sepolicy".to_string(), 0o644, sepolicy_data));
+                    cpio.insert("sepolicy", CpioEntry::file(0o644, sepolicy_data));
                     println!("{}", ">> 已添加 sepolicy".green());
                 }
             }
@@ -788,6 +1014,14 @@ impl Flasher {
         Ok(())
     }
 
+    /// .backup/.magisk 配置；修补 vendor_boot 时 VENDORBOOT=true，供 magiskinit 定位 ramdisk
+    fn magisk_config(sha1_sum: &str, vendor_boot: bool) -> String {
+        format!(
+            "KEEPVERITY=false\nKEEPFORCEENCRYPT=false\nRECOVERYMODE=false\nVENDORBOOT={}\nSHA1={}\n",
+            vendor_boot, sha1_sum
+        )
+    }
+
     fn hex_patch_kernel_skip_initramfs(kernel: &[u8]) -> Option<Vec<u8>> {
         let from = b"skip_initramfs";
         let to = b"want_initramfs";
@@ -0,0 +1,315 @@
+use crate::error::{FlashError, Result};
+use crate::plan::{FlashPlan, FlashStep, PlanReporter};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub step: FlashStep,
+    pub image_sha256: Option<String>,
+    /// 镜像大小与修改时间 (毫秒)，未计算哈希时用于判断镜像是否被改动
+    #[serde(default)]
+    pub image_size: Option<u64>,
+    #[serde(default)]
+    pub image_modified: Option<u64>,
+    pub status: StepStatus,
+    pub error: Option<String>,
+}
+
+/// 多步骤刷机的磁盘日志：每一步开始、结束时都会立即落盘，
+/// 进程崩溃、断线或 Ctrl+C 之后仍能知道哪些分区已经写入。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashJournal {
+    pub serial: String,
+    pub plan_name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub entries: Vec<JournalEntry>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// 镜像大小与修改时间，读取代价远小于完整哈希
+fn image_fingerprint(path: &Path) -> Result<(u64, u64)> {
+    let meta = fs::metadata(path)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Ok((meta.len(), modified))
+}
+
+impl FlashJournal {
+    /// 日志默认保存在程序目录下的 journal 文件夹
+    pub fn default_dir() -> PathBuf {
+        env::current_exe()
+            .ok()
+            .and_then(|p| p.parent().map(|d| d.to_path_buf()))
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("journal")
+    }
+
+    /// 同一台设备上的同一个计划名（通常是镜像目录）始终对应同一个日志文件
+    pub fn path_for(dir: &Path, plan_name: &str, serial: &str) -> PathBuf {
+        let digest = Sha256::digest(format!("{}\n{}", serial, plan_name).as_bytes());
+        let key: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+        dir.join(format!("{}.json", key))
+    }
+
+    /// 为设备新建日志，会覆盖同一计划的旧日志。
+    /// 始终记录镜像大小与修改时间，`hash_images` 为 true 时额外计算完整的 sha256
+    pub fn create(dir: &Path, serial: &str, plan: &FlashPlan, hash_images: bool) -> Result<Self> {
+        let mut entries = Vec::with_capacity(plan.steps.len());
+        for step in &plan.steps {
+            let mut entry = JournalEntry {
+                step: step.clone(),
+                image_sha256: None,
+                image_size: None,
+                image_modified: None,
+                status: StepStatus::Pending,
+                error: None,
+            };
+            if let FlashStep::Flash { image, .. } | FlashStep::PatchAndFlash { image, .. } = step {
+                let (size, modified) = image_fingerprint(image)?;
+                entry.image_size = Some(size);
+                entry.image_modified = Some(modified);
+                if hash_images {
+                    entry.image_sha256 = Some(sha256_file(image)?);
+                }
+            }
+            entries.push(entry);
+        }
+        fs::create_dir_all(dir)?;
+        let now = now_secs();
+        let journal = Self {
+            serial: serial.to_string(),
+            plan_name: plan.name.clone(),
+            created_at: now,
+            updated_at: now,
+            entries,
+            path: Self::path_for(dir, &plan.name, serial),
+        };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    /// 读取某个计划尚未完成的日志；不存在、无法解析或已全部完成时返回 None
+    pub fn load_incomplete(dir: &Path, plan_name: &str, serial: &str) -> Option<Self> {
+        let path = Self::path_for(dir, plan_name, serial);
+        let text = fs::read_to_string(&path).ok()?;
+        let mut journal: Self = serde_json::from_str(&text).ok()?;
+        if journal.plan_name != plan_name || journal.serial != serial || journal.is_complete() {
+            return None;
+        }
+        journal.path = path;
+        Some(journal)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| FlashError::Anyhow(anyhow::anyhow!("序列化刷机日志失败: {}", e)))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn plan(&self) -> FlashPlan {
+        FlashPlan { name: self.plan_name.clone(), steps: self.entries.iter().map(|e| e.step.clone()).collect() }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.entries.iter().all(|e| e.status == StepStatus::Done)
+    }
+
+    pub fn done_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.status == StepStatus::Done).count()
+    }
+
+    /// 第一个未完成的步骤；中途中断的 Running 步骤也视为未完成，需要重新执行
+    pub fn first_incomplete(&self) -> Option<usize> {
+        self.entries.iter().position(|e| e.status != StepStatus::Done)
+    }
+
+    /// 续刷前检查：必须是同一台设备，且剩余步骤的镜像内容未被改动
+    pub fn check_resume(&self, serial: &str) -> Result<()> {
+        if self.serial != serial {
+            return Err(FlashError::InvalidChoice(format!(
+                "刷机日志属于设备 {}，当前设备为 {}，拒绝续刷",
+                self.serial, serial
+            )));
+        }
+        let start = self.first_incomplete().unwrap_or(self.entries.len());
+        for entry in &self.entries[start..] {
+            let (FlashStep::Flash { image, partition } | FlashStep::PatchAndFlash { image, partition, .. }) = &entry.step else {
+                continue;
+            };
+            let changed = match &entry.image_sha256 {
+                Some(expected) => &sha256_file(image)? != expected,
+                None => {
+                    let (size, modified) = image_fingerprint(image)?;
+                    entry.image_size.is_some_and(|s| s != size) || entry.image_modified.is_some_and(|m| m != modified)
+                }
+            };
+            if changed {
+                return Err(FlashError::InvalidChoice(format!(
+                    "{} 的镜像 {} 自上次刷入后已被修改，拒绝续刷",
+                    partition,
+                    image.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mark(&mut self, index: usize, status: StepStatus, error: Option<String>) -> Result<()> {
+        if let Some(entry) = self.entries.get_mut(index) {
+            entry.status = status;
+            entry.error = error;
+        }
+        self.updated_at = now_secs();
+        self.save()
+    }
+}
+
+/// 在执行计划的同时把每一步的状态写入日志，再转发给内层 reporter
+pub struct JournalReporter<'a> {
+    journal: Mutex<&'a mut FlashJournal>,
+    inner: &'a dyn PlanReporter,
+    offset: usize,
+}
+
+impl<'a> JournalReporter<'a> {
+    /// `offset` 为本次执行的第一个步骤在日志中的下标，用于从中途续刷
+    pub fn new(journal: &'a mut FlashJournal, inner: &'a dyn PlanReporter, offset: usize) -> Self {
+        Self { journal: Mutex::new(journal), inner, offset }
+    }
+
+    fn mark(&self, index: usize, status: StepStatus, error: Option<String>) {
+        if let Ok(mut journal) = self.journal.lock() {
+            let _ = journal.mark(self.offset + index, status, error);
+        }
+    }
+}
+
+/// 转发给内层 reporter 的下标与总数都按整个日志计算，续刷时进度不会从头开始
+impl PlanReporter for JournalReporter<'_> {
+    fn on_step_start(&self, serial: &str, index: usize, total: usize, step: &FlashStep) {
+        self.mark(index, StepStatus::Running, None);
+        self.inner.on_step_start(serial, self.offset + index, self.offset + total, step);
+    }
+
+    fn on_step_done(&self, serial: &str, index: usize, total: usize, step: &FlashStep, result: &Result<()>) {
+        match result {
+            Ok(_) => self.mark(index, StepStatus::Done, None),
+            Err(e) => self.mark(index, StepStatus::Failed, Some(e.to_string())),
+        }
+        self.inner.on_step_done(serial, self.offset + index, self.offset + total, step, result);
+    }
+
+    fn should_cancel(&self) -> bool {
+        self.inner.should_cancel()
+    }
+
+    fn confirm_override(&self, serial: &str, reason: &str) -> bool {
+        self.inner.confirm_override(serial, reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rua_journal_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn two_step_plan(dir: &Path) -> FlashPlan {
+        fs::write(dir.join("boot.img"), b"boot").unwrap();
+        fs::write(dir.join("dtbo.img"), b"dtbo").unwrap();
+        FlashPlan::from_image_dir(dir, &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_save_load_and_resume() {
+        let dir = test_dir("resume");
+        let plan = two_step_plan(&dir);
+        let mut journal = FlashJournal::create(&dir, "serial1", &plan, true).unwrap();
+        assert!(journal.entries.iter().all(|e| e.image_sha256.is_some() && e.image_size == Some(4)));
+
+        journal.mark(0, StepStatus::Done, None).unwrap();
+        journal.mark(1, StepStatus::Running, None).unwrap();
+        let loaded = FlashJournal::load_incomplete(&dir, &plan.name, "serial1").unwrap();
+        assert_eq!(loaded.path(), journal.path());
+        assert_eq!(loaded.done_count(), 1);
+        assert_eq!(loaded.first_incomplete(), Some(1));
+        assert_eq!(loaded.plan(), plan);
+        assert!(loaded.check_resume("serial1").is_ok());
+        assert!(loaded.check_resume("serial2").is_err());
+        assert!(FlashJournal::load_incomplete(&dir, &plan.name, "serial2").is_none());
+
+        // 已完成的步骤不再校验，剩余步骤的镜像被改动则拒绝续刷
+        fs::write(dir.join("boot.img"), b"BOOT").unwrap();
+        assert!(loaded.check_resume("serial1").is_ok());
+        fs::write(dir.join("dtbo.img"), b"DTBO").unwrap();
+        assert!(loaded.check_resume("serial1").is_err());
+
+        journal.mark(1, StepStatus::Done, None).unwrap();
+        assert!(FlashJournal::load_incomplete(&dir, &plan.name, "serial1").is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fingerprint_without_hash() {
+        let dir = test_dir("fingerprint");
+        let plan = two_step_plan(&dir);
+        let journal = FlashJournal::create(&dir, "serial1", &plan, false).unwrap();
+        assert!(journal.entries.iter().all(|e| e.image_sha256.is_none() && e.image_size.is_some()));
+        assert!(journal.check_resume("serial1").is_ok());
+
+        fs::write(dir.join("boot.img"), b"boot-patched").unwrap();
+        assert!(journal.check_resume("serial1").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::bootimg::inspect::{MTK_HEADER_SIZE, strip_mtk_header};
+use crate::dtb::find_appended_dtb;
+use crate::error::{FlashError, Result};
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+const LZ4_LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4c, 0x18];
+/// lz4 -l 的固定块大小
+const LZ4_LEGACY_BLOCK_SIZE: usize = 8 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelFormat {
+    Raw,
+    Gzip,
+    Lz4,
+    Lz4Legacy,
+    Bzip2,
+    Lzma,
+    Xz,
+    Zstd,
+}
+
+impl KernelFormat {
+    pub fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0x04, 0x22, 0x4d, 0x18, ..] => Self::Lz4,
+            [0x02, 0x21, 0x4c, 0x18, ..] => Self::Lz4Legacy,
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Self::Bzip2,
+            [0x5d, 0x00, 0x00, ..] => Self::Lzma,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Self::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            _ => Self::Raw,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Gzip => "gzip",
+            Self::Lz4 => "lz4",
+            Self::Lz4Legacy => "lz4_legacy",
+            Self::Bzip2 => "bzip2",
+            Self::Lzma => "lzma",
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+fn codec_err(format: KernelFormat, e: impl std::fmt::Debug) -> FlashError {
+    FlashError::PatchError(format!("{} 内核处理失败: {:?}", format.name(), e))
+}
+
+/// 内核构建时 lz4 -l 的输出：魔数 + 若干 (块大小, 块) ，末尾可能带 4 字节解压后大小
+fn lz4_legacy_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = LZ4_LEGACY_MAGIC.len();
+    let mut block = vec![0u8; LZ4_LEGACY_BLOCK_SIZE];
+    while pos + 4 <= data.len() {
+        let size = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        pos += 4;
+        if data[pos - 4..pos] == LZ4_LEGACY_MAGIC {
+            continue;
+        }
+        if size == 0 || size > data.len() - pos {
+            break;
+        }
+        let n = lz4_flex::block::decompress_into(&data[pos..pos + size], &mut block)
+            .map_err(|e| codec_err(KernelFormat::Lz4Legacy, e))?;
+        out.extend_from_slice(&block[..n]);
+        pos += size;
+    }
+    Ok(out)
+}
+
+fn lz4_legacy_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = LZ4_LEGACY_MAGIC.to_vec();
+    for chunk in data.chunks(LZ4_LEGACY_BLOCK_SIZE) {
+        let block = lz4_flex::block::compress(chunk);
+        out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        out.extend_from_slice(&block);
+    }
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub fn decompress(format: KernelFormat, data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let err = |e| codec_err(format, e);
+    match format {
+        KernelFormat::Raw => out.extend_from_slice(data),
+        KernelFormat::Gzip => {
+            GzDecoder::new(data).read_to_end(&mut out).map_err(err)?;
+        }
+        KernelFormat::Lz4 => {
+            lz4_flex::frame::FrameDecoder::new(data).read_to_end(&mut out).map_err(err)?;
+        }
+        KernelFormat::Lz4Legacy => out = lz4_legacy_decompress(data)?,
+        KernelFormat::Bzip2 => {
+            BzDecoder::new(data).read_to_end(&mut out).map_err(err)?;
+        }
+        KernelFormat::Lzma => lzma_rs::lzma_decompress(&mut &data[..], &mut out).map_err(|e| codec_err(format, e))?,
+        KernelFormat::Xz => lzma_rs::xz_decompress(&mut &data[..], &mut out).map_err(|e| codec_err(format, e))?,
+        KernelFormat::Zstd => out = zstd::stream::decode_all(data).map_err(err)?,
+    }
+    Ok(out)
+}
+
+pub fn compress(format: KernelFormat, data: &[u8]) -> Result<Vec<u8>> {
+    let err = |e| codec_err(format, e);
+    Ok(match format {
+        KernelFormat::Raw => data.to_vec(),
+        KernelFormat::Gzip => {
+            let mut enc = GzEncoder::new(Vec::new(), Compression::best());
+            enc.write_all(data).map_err(err)?;
+            enc.finish().map_err(err)?
+        }
+        KernelFormat::Lz4 => {
+            let mut enc = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            enc.write_all(data).map_err(err)?;
+            enc.finish().map_err(|e| codec_err(format, e))?
+        }
+        KernelFormat::Lz4Legacy => lz4_legacy_compress(data),
+        KernelFormat::Bzip2 => {
+            let mut enc = BzEncoder::new(Vec::new(), bzip2::Compression::best());
+            enc.write_all(data).map_err(err)?;
+            enc.finish().map_err(err)?
+        }
+        KernelFormat::Lzma => {
+            let mut out = Vec::new();
+            lzma_rs::lzma_compress(&mut &data[..], &mut out).map_err(err)?;
+            out
+        }
+        KernelFormat::Xz => {
+            let mut out = Vec::new();
+            lzma_rs::xz_compress(&mut &data[..], &mut out).map_err(err)?;
+            out
+        }
+        KernelFormat::Zstd => zstd::stream::encode_all(data, 19).map_err(err)?,
+    })
+}
+
+/// 拆分后的内核：可选的 MTK 头部、解压后的内核、附加在压缩内核之后的 DTB (Image.gz-dtb)
+#[derive(Debug, Clone)]
+pub struct KernelImage {
+    pub format: KernelFormat,
+    pub raw: Vec<u8>,
+    pub mtk_header: Option<Vec<u8>>,
+    pub appended_dtb: Vec<u8>,
+}
+
+impl KernelImage {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (payload, mtk) = strip_mtk_header(data);
+        let mtk_header = mtk.then(|| data[..MTK_HEADER_SIZE].to_vec());
+        let format = KernelFormat::detect(payload);
+
+        // 附加 DTB 之前是压缩流；若该处解压失败，说明找到的魔数在压缩数据内部
+        if let Some(off) = find_appended_dtb(payload)
+            && let Ok(raw) = decompress(format, &payload[..off])
+        {
+            return Ok(Self { format, raw, mtk_header, appended_dtb: payload[off..].to_vec() });
+        }
+        let raw = decompress(format, payload)?;
+        Ok(Self { format, raw, mtk_header, appended_dtb: Vec::new() })
+    }
+
+    /// 按原格式重新压缩，再拼接附加 DTB 与 MTK 头部（更新其中的大小字段）
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut payload = compress(self.format, &self.raw)?;
+        payload.extend_from_slice(&self.appended_dtb);
+        Ok(match &self.mtk_header {
+            Some(header) => {
+                let mut out = header.clone();
+                out[4..8].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+                out.extend_from_slice(&payload);
+                out
+            }
+            None => payload,
+        })
+    }
+}
+
+/// 用新内核替换原内核并保持原内核的压缩格式、MTK 头部；新内核不带 DTB 时沿用原内核附加的 DTB
+pub fn replace_kernel(original: &[u8], new_kernel: &[u8]) -> Result<Vec<u8>> {
+    let mut image = KernelImage::parse(original)?;
+    let new = KernelImage::parse(new_kernel)?;
+    image.raw = new.raw;
+    if !new.appended_dtb.is_empty() {
+        image.appended_dtb = new.appended_dtb;
+    }
+    image.to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_dtb() -> Vec<u8> {
+        let mut dtb = vec![0u8; 64];
+        dtb[..4].copy_from_slice(&crate::dtb::FDT_MAGIC);
+        dtb[4..8].copy_from_slice(&64u32.to_be_bytes());
+        dtb[20..24].copy_from_slice(&17u32.to_be_bytes());
+        dtb
+    }
+
+    #[test]
+    fn test_lz4_legacy_multi_block() {
+        let raw: Vec<u8> = (0..LZ4_LEGACY_BLOCK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        let packed = compress(KernelFormat::Lz4Legacy, &raw).unwrap();
+        assert_eq!(KernelFormat::detect(&packed), KernelFormat::Lz4Legacy);
+        assert_eq!(decompress(KernelFormat::Lz4Legacy, &packed).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_replace_kernel_keeps_format_and_dtb() {
+        let old_raw = b"old kernel ".repeat(100);
+        let mut original = compress(KernelFormat::Gzip, &old_raw).unwrap();
+        original.extend_from_slice(&fake_dtb());
+
+        let new_raw = b"new kernel ".repeat(120);
+        let replaced = replace_kernel(&original, &new_raw).unwrap();
+        let image = KernelImage::parse(&replaced).unwrap();
+        assert_eq!(image.format, KernelFormat::Gzip);
+        assert_eq!(image.raw, new_raw);
+        assert_eq!(image.appended_dtb, fake_dtb());
+    }
+}
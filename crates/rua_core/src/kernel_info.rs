@@ -0,0 +1,302 @@
+use crate::error::Result;
+use crate::kernel::{KernelFormat, KernelImage};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// CONFIG_IKCONFIG 在内核中保存的 gzip 配置前后的标记
+const IKCFG_START: &[u8] = b"IKCFG_ST";
+const BANNER_PREFIX: &[u8] = b"Linux version ";
+/// arm64 Image 头部 0x38 处的魔数
+const ARM64_MAGIC: &[u8] = b"ARM\x64";
+/// GKI 2.0 从 android12-5.10 开始，更早的 android 内核没有稳定的 KMI
+const GKI_MIN_VERSION: (u32, u32) = (5, 10);
+/// KernelPatch 支持的最低内核版本
+const APATCH_MIN_VERSION: (u32, u32) = (3, 18);
+
+/// 与 Root 方式相关的内核配置
+pub const KEY_CONFIGS: &[&str] = &["CONFIG_MODULES", "CONFIG_KPROBES", "CONFIG_KALLSYMS"];
+
+/// 内核中的 linux_banner，如
+/// `Linux version 5.15.123-android14-11-gabc (build-user@build-host) (Android clang version 17.0.2, LLD 17.0.2) #1 SMP PREEMPT Mon Jan 1 00:00:00 UTC 2024`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinuxBanner {
+    /// 即 uname -r
+    pub release: String,
+    /// user@host
+    pub builder: Option<String>,
+    pub compiler: Option<String>,
+    pub build_date: Option<String>,
+    pub full: String,
+}
+
+/// 取出开头的一个括号组（允许嵌套），返回 (括号内内容, 剩余部分)
+fn take_group(s: &str) -> Option<(&str, &str)> {
+    let s = s.strip_prefix('(')?;
+    let mut depth = 1;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some((&s[..i], s[i + 1..].trim_start()));
+        }
+    }
+    None
+}
+
+impl LinuxBanner {
+    pub fn find(raw: &[u8]) -> Option<Self> {
+        let start = raw
+            .windows(BANNER_PREFIX.len() + 1)
+            .position(|w| w.starts_with(BANNER_PREFIX) && w[BANNER_PREFIX.len()].is_ascii_digit())?;
+        let line = &raw[start..];
+        let end = line.iter().position(|&b| b == 0 || b == b'\n').unwrap_or(line.len());
+        Self::parse(&String::from_utf8_lossy(&line[..end]))
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix("Linux version ")?;
+        let (release, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let (mut builder, mut compiler) = (None, None);
+        while let Some((group, tail)) = take_group(rest) {
+            if builder.is_none() && compiler.is_none() && group.contains('@') && !group.contains(' ') {
+                builder = Some(group.to_string());
+            } else if compiler.is_none() {
+                compiler = Some(group.to_string());
+            }
+            rest = tail;
+        }
+        let date_re = regex::Regex::new(r"(?:Mon|Tue|Wed|Thu|Fri|Sat|Sun) \w{3} +\d+ [\d:]+ (?:\S+ )?\d{4}").ok()?;
+        Some(Self {
+            release: release.to_string(),
+            builder,
+            compiler,
+            build_date: date_re.find(rest).map(|m| m.as_str().to_string()),
+            full: line.trim().to_string(),
+        })
+    }
+
+    pub fn version(&self) -> Option<(u32, u32)> {
+        release_version(&self.release)
+    }
+
+    pub fn kmi(&self) -> Option<String> {
+        release_kmi(&self.release)
+    }
+}
+
+/// uname -r 中的 (主版本, 次版本)
+pub fn release_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// 由 uname -r 得到 KMI，如 5.15.123-android14-11-gabc 为 android14-5.15，非 android 通用内核分支返回 None
+pub fn release_kmi(release: &str) -> Option<String> {
+    let re = regex::Regex::new(r"^(\d+\.\d+)\.\d+-(android\d+)-").ok()?;
+    let caps = re.captures(release)?;
+    Some(format!("{}-{}", &caps[2], &caps[1]))
+}
+
+/// 解析后的 .config，未启用的选项 (# CONFIG_X is not set) 记为 n
+#[derive(Debug, Clone, Default)]
+pub struct KernelConfig {
+    pub text: String,
+    options: BTreeMap<String, String>,
+}
+
+impl KernelConfig {
+    pub fn parse(text: &str) -> Self {
+        let mut options = BTreeMap::new();
+        for line in text.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix("# ").and_then(|l| l.strip_suffix(" is not set")) {
+                options.insert(name.to_string(), "n".to_string());
+            } else if let Some((name, value)) = line.split_once('=').filter(|_| line.starts_with("CONFIG_")) {
+                options.insert(name.to_string(), value.trim_matches('"').to_string());
+            }
+        }
+        Self { text: text.to_string(), options }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    /// y 或 m
+    pub fn enabled(&self, name: &str) -> bool {
+        matches!(self.get(name), Some("y" | "m"))
+    }
+}
+
+/// 提取 CONFIG_IKCONFIG 内置的 .config
+pub fn extract_ikconfig(raw: &[u8]) -> Option<String> {
+    let start = raw.windows(IKCFG_START.len()).position(|w| w == IKCFG_START)? + IKCFG_START.len();
+    let mut text = String::new();
+    GzDecoder::new(&raw[start..]).read_to_string(&mut text).ok()?;
+    Some(text)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootMethod {
+    KernelSuLkm,
+    APatch,
+    /// 刷入 AnyKernel3 等第三方内核
+    KernelReplace,
+}
+
+impl RootMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            RootMethod::KernelSuLkm => "KernelSU (LKM)",
+            RootMethod::APatch => "APatch (KernelPatch)",
+            RootMethod::KernelReplace => "替换内核 (AnyKernel3)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viability {
+    Viable,
+    /// 缺少版本或配置信息，无法判断
+    Unknown,
+    NotViable,
+}
+
+#[derive(Debug, Clone)]
+pub struct RootMethodCheck {
+    pub method: RootMethod,
+    pub viability: Viability,
+    pub reason: String,
+}
+
+/// 对解压后内核的分析结果
+#[derive(Debug, Clone)]
+pub struct KernelAnalysis {
+    pub format: KernelFormat,
+    pub arm64: bool,
+    pub banner: Option<LinuxBanner>,
+    /// 内核未启用 CONFIG_IKCONFIG 时为 None
+    pub config: Option<KernelConfig>,
+}
+
+impl KernelAnalysis {
+    /// data 为镜像中的 kernel 段或内核文件，可以是压缩的
+    pub fn analyze(data: &[u8]) -> Result<Self> {
+        Ok(Self::from_image(&KernelImage::parse(data)?))
+    }
+
+    pub fn from_image(image: &KernelImage) -> Self {
+        let raw = &image.raw;
+        Self {
+            format: image.format,
+            arm64: raw.get(0x38..0x3c) == Some(ARM64_MAGIC),
+            banner: LinuxBanner::find(raw),
+            config: extract_ikconfig(raw).map(|text| KernelConfig::parse(&text)),
+        }
+    }
+
+    pub fn version(&self) -> Option<(u32, u32)> {
+        self.banner.as_ref()?.version()
+    }
+
+    pub fn kmi(&self) -> Option<String> {
+        self.banner.as_ref()?.kmi()
+    }
+
+    pub fn is_gki(&self) -> bool {
+        self.kmi().is_some() && self.version().is_some_and(|v| v >= GKI_MIN_VERSION)
+    }
+
+    /// 配置项是否启用，没有内置配置时为 None
+    pub fn option(&self, name: &str) -> Option<bool> {
+        self.config.as_ref().map(|c| c.enabled(name))
+    }
+
+    pub fn root_methods(&self) -> Vec<RootMethodCheck> {
+        let check = |method, viability, reason: String| RootMethodCheck { method, viability, reason };
+        let kmi = self.kmi();
+
+        let lkm = if self.banner.is_none() {
+            check(RootMethod::KernelSuLkm, Viability::Unknown, "未找到内核版本信息".to_string())
+        } else if !self.is_gki() {
+            check(RootMethod::KernelSuLkm, Viability::NotViable, "非 GKI 内核，LKM 模式需要 android12-5.10 及以上的 GKI 内核".to_string())
+        } else if self.option("CONFIG_MODULES") == Some(false) {
+            check(RootMethod::KernelSuLkm, Viability::NotViable, "内核未启用 CONFIG_MODULES".to_string())
+        } else {
+            check(RootMethod::KernelSuLkm, Viability::Viable, format!("选择 KMI 为 {} 的 .ko", kmi.clone().unwrap_or_default()))
+        };
+
+        let apatch = if !self.arm64 {
+            check(RootMethod::APatch, Viability::NotViable, "KernelPatch 仅支持 arm64 内核".to_string())
+        } else if self.version().is_some_and(|v| v < APATCH_MIN_VERSION) {
+            check(RootMethod::APatch, Viability::NotViable, "KernelPatch 需要 3.18 及以上的内核".to_string())
+        } else {
+            match self.option("CONFIG_KALLSYMS") {
+                Some(true) => check(RootMethod::APatch, Viability::Viable, "arm64 内核且已启用 CONFIG_KALLSYMS".to_string()),
+                Some(false) => check(RootMethod::APatch, Viability::NotViable, "内核未启用 CONFIG_KALLSYMS".to_string()),
+                None => check(RootMethod::APatch, Viability::Unknown, "内核未内置配置，无法确认 CONFIG_KALLSYMS".to_string()),
+            }
+        };
+
+        let replace = match &kmi {
+            Some(kmi) if self.is_gki() => check(RootMethod::KernelReplace, Viability::Viable, format!("可使用 KMI 为 {} 的 GKI 内核", kmi)),
+            _ if self.option("CONFIG_KPROBES") == Some(true) => check(
+                RootMethod::KernelReplace,
+                Viability::Unknown,
+                "非 GKI 内核，需使用为本机编译的内核 (已启用 CONFIG_KPROBES，可用 kprobe 方式集成 KernelSU)".to_string(),
+            ),
+            _ => check(RootMethod::KernelReplace, Viability::Unknown, "非 GKI 内核，需使用为本机编译的内核".to_string()),
+        };
+
+        vec![lkm, apatch, replace]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_banner() {
+        let banner = LinuxBanner::parse(
+            "Linux version 5.15.123-android14-11-g1234abcd (build-user@build-host) (Android (10087095, +pgo) clang version 17.0.2, LLD 17.0.2) #1 SMP PREEMPT Mon Jan  1 00:00:00 UTC 2024",
+        )
+        .unwrap();
+        assert_eq!(banner.release, "5.15.123-android14-11-g1234abcd");
+        assert_eq!(banner.builder.as_deref(), Some("build-user@build-host"));
+        assert_eq!(banner.compiler.as_deref(), Some("Android (10087095, +pgo) clang version 17.0.2, LLD 17.0.2"));
+        assert_eq!(banner.build_date.as_deref(), Some("Mon Jan  1 00:00:00 UTC 2024"));
+        assert_eq!(banner.kmi().as_deref(), Some("android14-5.15"));
+
+        let old = LinuxBanner::parse("Linux version 4.19.157-perf+ (root@localhost) (gcc version 4.9.x) #1 SMP Tue Mar 2 10:00:00 CST 2021").unwrap();
+        assert_eq!(old.version(), Some((4, 19)));
+        assert_eq!(old.kmi(), None);
+    }
+
+    #[test]
+    fn test_analyze_ikconfig() {
+        let text = "CONFIG_MODULES=y\n# CONFIG_KPROBES is not set\nCONFIG_KALLSYMS=y\nCONFIG_LOCALVERSION=\"-perf\"\n";
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(text.as_bytes()).unwrap();
+        let mut raw = vec![0u8; 0x40];
+        raw[0x38..0x3c].copy_from_slice(ARM64_MAGIC);
+        raw.extend_from_slice(b"Linux version 6.1.57-android14-11-gabc (a@b) (clang) #1 SMP PREEMPT Mon Jan 1 00:00:00 UTC 2024\n\0");
+        raw.extend_from_slice(IKCFG_START);
+        raw.extend_from_slice(&enc.finish().unwrap());
+        raw.extend_from_slice(b"IKCFG_ED");
+
+        let analysis = KernelAnalysis::analyze(&raw).unwrap();
+        let config = analysis.config.as_ref().unwrap();
+        assert_eq!(config.text, text);
+        assert_eq!(config.get("CONFIG_LOCALVERSION"), Some("-perf"));
+        assert_eq!(analysis.option("CONFIG_KPROBES"), Some(false));
+        assert!(analysis.is_gki());
+        assert!(analysis.root_methods().iter().all(|c| c.viability == Viability::Viable));
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{FlashError, Result};
+use crate::kernel_info::{KernelAnalysis, release_kmi};
+use std::fs;
+use std::path::Path;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+fn invalid(msg: &str) -> FlashError {
+    FlashError::PatchError(format!("无效的内核模块: {}", msg))
+}
+
+/// 内核模块 .modinfo 段中的信息
+#[derive(Debug, Clone, Default)]
+pub struct ModuleInfo {
+    pub name: Option<String>,
+    /// 如 `6.1.57-android14-11-gabc SMP preempt mod_unload modversions aarch64`
+    pub vermagic: Option<String>,
+    pub fields: Vec<(String, String)>,
+}
+
+/// 校验内核模块时使用的目标内核
+#[derive(Debug, Clone, Copy)]
+pub enum TargetKernel<'a> {
+    /// boot 镜像或内核文件 (kernel 分区镜像、Image.gz 等)
+    File(&'a str),
+    /// 从设备 uname -r 读取的 KMI
+    Kmi(&'a str),
+}
+
+/// 按 ELF 节头表找到 .modinfo 段，支持 32/64 位与大小端
+fn find_modinfo(data: &[u8]) -> Result<&[u8]> {
+    if !data.starts_with(ELF_MAGIC) || data.len() < 0x40 {
+        return Err(invalid("不是 ELF 文件"));
+    }
+    let is64 = data[4] == 2;
+    let le = data[5] != 2;
+    let read = |off: usize, len: usize| -> Result<u64> {
+        let b = off.checked_add(len).and_then(|end| data.get(off..end)).ok_or_else(|| invalid("节头越界"))?;
+        let mut v = 0u64;
+        for i in 0..len {
+            let byte = if le { b[len - 1 - i] } else { b[i] };
+            v = (v << 8) | byte as u64;
+        }
+        Ok(v)
+    };
+    let to_usize = |v: u64, what: &str| usize::try_from(v).map_err(|_| invalid(what));
+    let (shoff, shentsize, shnum, shstrndx) = if is64 {
+        (read(0x28, 8)?, read(0x3a, 2)?, read(0x3c, 2)?, read(0x3e, 2)?)
+    } else {
+        (read(0x20, 4)?, read(0x2e, 2)?, read(0x30, 2)?, read(0x32, 2)?)
+    };
+    // (名称偏移, 段偏移, 段大小)
+    let section = |index: u64| -> Result<(usize, usize, usize)> {
+        let base = index
+            .checked_mul(shentsize)
+            .and_then(|n| n.checked_add(shoff))
+            .ok_or_else(|| invalid("节头越界"))?;
+        let base = to_usize(base, "节头越界")?;
+        let at = |delta: usize| base.checked_add(delta).ok_or_else(|| invalid("节头越界"));
+        let (name, off, size) = if is64 {
+            (read(base, 4)?, read(at(0x18)?, 8)?, read(at(0x20)?, 8)?)
+        } else {
+            (read(base, 4)?, read(at(0x10)?, 4)?, read(at(0x14)?, 4)?)
+        };
+        Ok((to_usize(name, "节头越界")?, to_usize(off, "段越界")?, to_usize(size, "段越界")?))
+    };
+    let slice = |off: usize, size: usize| {
+        off.checked_add(size).and_then(|end| data.get(off..end)).ok_or_else(|| invalid("段越界"))
+    };
+
+    let (_, str_off, str_size) = section(shstrndx)?;
+    let names = slice(str_off, str_size)?;
+    for i in 0..shnum {
+        let (name_off, off, size) = section(i)?;
+        let name = names.get(name_off..).unwrap_or_default();
+        if name.starts_with(b".modinfo\0") {
+            return slice(off, size);
+        }
+    }
+    Err(invalid("缺少 .modinfo 段"))
+}
+
+impl ModuleInfo {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut info = Self::default();
+        for field in find_modinfo(data)?.split(|&b| b == 0).filter(|f| !f.is_empty()) {
+            let field = String::from_utf8_lossy(field);
+            let Some((key, value)) = field.split_once('=') else { continue };
+            match key {
+                "name" => info.name = Some(value.to_string()),
+                "vermagic" => info.vermagic = Some(value.trim().to_string()),
+                _ => {}
+            }
+            info.fields.push((key.to_string(), value.to_string()));
+        }
+        Ok(info)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// vermagic 中的内核版本 (uname -r)
+    pub fn release(&self) -> Option<&str> {
+        self.vermagic.as_deref()?.split_whitespace().next()
+    }
+
+    pub fn kmi(&self) -> Option<String> {
+        release_kmi(self.release()?)
+    }
+
+    /// 与目标内核比较 KMI；GKI 模块只要求 KMI 一致，非 GKI 模块要求版本号完全相同
+    pub fn check_kernel(&self, kernel: &KernelAnalysis) -> Result<()> {
+        let release = self.release().ok_or_else(|| invalid("缺少 vermagic"))?;
+        let Some(banner) = &kernel.banner else {
+            return Err(FlashError::PatchError("无法读取目标内核版本，不能校验内核模块".to_string()));
+        };
+        let mismatch = || {
+            FlashError::PatchError(format!(
+                "内核模块与目标内核不匹配: 模块 {} ({})，内核 {} ({})",
+                release,
+                self.kmi().unwrap_or_else(|| "非 GKI".to_string()),
+                banner.release,
+                kernel.kmi().unwrap_or_else(|| "非 GKI".to_string())
+            ))
+        };
+        match (self.kmi(), kernel.kmi()) {
+            (Some(module_kmi), Some(kernel_kmi)) if module_kmi == kernel_kmi => Ok(()),
+            (None, None) if release == banner.release => Ok(()),
+            _ => Err(mismatch()),
+        }
+    }
+
+    /// 只知道目标 KMI 时的校验，要求模块同为 GKI 模块且 KMI 一致
+    pub fn check_kmi(&self, kmi: &str) -> Result<()> {
+        match self.kmi() {
+            Some(module_kmi) if module_kmi == kmi => Ok(()),
+            module_kmi => Err(FlashError::PatchError(format!(
+                "内核模块与目标内核不匹配: 模块 {}，内核 {}",
+                module_kmi.unwrap_or_else(|| "非 GKI".to_string()),
+                kmi
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::KernelFormat;
+    use crate::kernel_info::LinuxBanner;
+
+    /// 只含 .modinfo 与 .shstrtab 的最小 ELF64 小端文件
+    fn fake_module(modinfo: &[u8]) -> Vec<u8> {
+        let shstrtab = b"\0.modinfo\0.shstrtab\0";
+        let mut data = vec![0u8; 0x40];
+        data[..4].copy_from_slice(ELF_MAGIC);
+        data[4] = 2;
+        data[5] = 1;
+        let modinfo_off = data.len();
+        data.extend_from_slice(modinfo);
+        let shstrtab_off = data.len();
+        data.extend_from_slice(shstrtab);
+        let shoff = data.len();
+        let sections = [(0usize, 0usize, 0usize), (1, modinfo_off, modinfo.len()), (10, shstrtab_off, shstrtab.len())];
+        for (name, off, size) in sections {
+            let mut sh = [0u8; 64];
+            sh[..4].copy_from_slice(&(name as u32).to_le_bytes());
+            sh[0x18..0x20].copy_from_slice(&(off as u64).to_le_bytes());
+            sh[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+            data.extend_from_slice(&sh);
+        }
+        data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        data[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        data[0x3e..0x40].copy_from_slice(&2u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_modinfo_vermagic_check() {
+        let module = fake_module(b"license=GPL\0name=kernelsu\0vermagic=6.1.57-android14-11-gabc SMP preempt mod_unload modversions aarch64\0");
+        let info = ModuleInfo::parse(&module).unwrap();
+        assert_eq!(info.name.as_deref(), Some("kernelsu"));
+        assert_eq!(info.kmi().as_deref(), Some("android14-6.1"));
+
+        let kernel = |release: &str| KernelAnalysis {
+            format: KernelFormat::Gzip,
+            arm64: true,
+            banner: LinuxBanner::parse(&format!("Linux version {} (a@b) (clang) #1 SMP PREEMPT", release)),
+            config: None,
+        };
+        assert!(info.check_kernel(&kernel("6.1.75-android14-11-gdef")).is_ok());
+        assert!(info.check_kernel(&kernel("5.15.123-android14-11-gdef")).is_err());
+        assert!(info.check_kernel(&kernel("6.1.25-android13-4-gdef")).is_err());
+        assert!(info.check_kmi("android14-6.1").is_ok());
+        assert!(info.check_kmi("android13-6.1").is_err());
+    }
+
+    #[test]
+    fn test_garbage_section_headers() {
+        let module = fake_module(b"vermagic=6.1.57-android14-11 SMP\0");
+        let mut truncated = module.clone();
+        truncated.truncate(truncated.len() - 40);
+        assert!(ModuleInfo::parse(&truncated).is_err());
+
+        let mut huge_offset = module.clone();
+        huge_offset[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ModuleInfo::parse(&huge_offset).is_err());
+
+        // 段大小被改成极大值时 off + size 溢出
+        let shoff = u64::from_le_bytes(module[0x28..0x30].try_into().unwrap()) as usize;
+        let mut huge_size = module.clone();
+        let size_at = shoff + 64 + 0x20;
+        huge_size[size_at..size_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ModuleInfo::parse(&huge_size).is_err());
+    }
+}
@@ -4,17 +4,32 @@ pub mod adb;
 pub mod fastboot;
 pub mod flasher;
 pub mod sepolicy;
+pub mod monitor;
+pub mod plan;
+pub mod batch;
+pub mod journal;
+pub mod android_info;
+pub mod xiaomi;
 
 pub mod constants;
 pub mod utils;
+pub mod cpio;
 pub mod payload;
 pub mod bootimg;
+pub mod kernel;
+pub mod kernel_info;
+pub mod kmod;
+pub mod lkm;
+pub mod dtb;
 pub mod avb;
 
 pub use error::{FlashError, Result};
 pub use device::{DeviceMode, ConnectedDevice};
 pub use adb::AdbClient;
 pub use fastboot::FastbootClient;
+pub use monitor::{DeviceMonitor, DeviceEvent, FlashGuard};
+pub use plan::{FlashPlan, FlashStep, PlanReporter};
+pub use journal::FlashJournal;
 pub use payload::{ProgressReporter, unpack_payload};
 
 #[cfg(not(target_os = "windows"))]
@@ -0,0 +1,196 @@
+use crate::adb::AdbClient;
+use crate::bootimg;
+use crate::error::{FlashError, Result};
+use crate::kernel_info::{release_kmi, release_version};
+use crate::kmod::ModuleInfo;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct LkmPackage {
+    pub ko_path: PathBuf,
+    pub kmi: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct KsuLkmVersion {
+    pub version_name: String,
+    pub ksuinit_path: PathBuf,
+    pub ksuinit_d_path: PathBuf,
+    pub ko_files: Vec<LkmPackage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KsuLkmBranch {
+    pub name: String,
+    pub versions: Vec<KsuLkmVersion>,
+}
+
+/// 扫描 base_dir 下的 KSUINIT/<分支>/<版本>/ksuinit 与 LKM/<分支>/<版本>/*.ko
+pub fn find_branches(base_dir: &Path) -> Vec<KsuLkmBranch> {
+    let mut branches = Vec::new();
+    let ksuinit_base = base_dir.join("KSUINIT");
+    let lkm_base = base_dir.join("LKM");
+    if !ksuinit_base.is_dir() || !lkm_base.is_dir() {
+        return branches;
+    }
+
+    let subdirs = |dir: &Path| -> Vec<(String, PathBuf)> {
+        let mut dirs: Vec<(String, PathBuf)> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|e| e.path().is_dir())
+                    .map(|e| (e.file_name().to_string_lossy().to_string(), e.path()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        dirs.sort();
+        dirs
+    };
+
+    for (branch_name, ksuinit_branch_dir) in subdirs(&ksuinit_base) {
+        let lkm_branch_dir = lkm_base.join(&branch_name);
+        if !lkm_branch_dir.is_dir() {
+            continue;
+        }
+        let mut versions = Vec::new();
+        for (version_name, ksuinit_version_dir) in subdirs(&ksuinit_branch_dir) {
+            let lkm_version_dir = lkm_branch_dir.join(&version_name);
+            let ksuinit_path = ksuinit_version_dir.join("ksuinit");
+            if !lkm_version_dir.is_dir() || !ksuinit_path.exists() {
+                continue;
+            }
+            let mut ko_files: Vec<LkmPackage> = fs::read_dir(&lkm_version_dir)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "ko"))
+                        .filter_map(|ko_path| Some(LkmPackage { kmi: module_kmi(&ko_path)?, ko_path }))
+                        .collect()
+                })
+                .unwrap_or_default();
+            ko_files.sort_by(|a, b| a.kmi.cmp(&b.kmi));
+            if !ko_files.is_empty() {
+                versions.push(KsuLkmVersion {
+                    version_name,
+                    ksuinit_d_path: ksuinit_version_dir.join("ksuinit.d"),
+                    ksuinit_path,
+                    ko_files,
+                });
+            }
+        }
+        if !versions.is_empty() {
+            branches.push(KsuLkmBranch { name: branch_name, versions });
+        }
+    }
+    branches
+}
+
+/// .ko 对应的 KMI，以 .modinfo 中的 vermagic 为准；读不到 vermagic 的 .ko 不列出，
+/// 不按文件名猜测
+pub fn module_kmi(path: &Path) -> Option<String> {
+    ModuleInfo::load(path).ok()?.kmi()
+}
+
+/// 按名称选择；未指定名称且只有一项时直接使用
+fn pick<'a, T>(items: &'a [T], name: Option<&str>, item_name: impl Fn(&T) -> &str, what: &str) -> Result<&'a T> {
+    let found = match name {
+        Some(name) => items.iter().find(|i| item_name(i) == name),
+        None if items.len() == 1 => items.first(),
+        None => None,
+    };
+    found.ok_or_else(|| {
+        let names: Vec<&str> = items.iter().map(&item_name).collect();
+        FlashError::InvalidChoice(format!("请指定{} {}，可选: {}", what, name.unwrap_or(""), names.join(", ")))
+    })
+}
+
+pub fn select_branch<'a>(branches: &'a [KsuLkmBranch], name: Option<&str>) -> Result<&'a KsuLkmBranch> {
+    pick(branches, name, |b| b.name.as_str(), "KernelSU 分支")
+}
+
+impl KsuLkmBranch {
+    pub fn select_version(&self, name: Option<&str>) -> Result<&KsuLkmVersion> {
+        pick(&self.versions, name, |v| v.version_name.as_str(), "KernelSU 版本")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LkmResolution<'a> {
+    Exact(&'a LkmPackage),
+    /// 没有与目标内核 KMI 完全一致的 .ko，附带原因，需由用户手动选择或更换版本
+    Unresolved(String),
+}
+
+impl KsuLkmVersion {
+    /// 只接受 KMI 完全一致的 .ko：Android 版本不同的同版本内核 KMI 也不兼容
+    pub fn resolve(&self, kmi: Option<&str>) -> LkmResolution<'_> {
+        let Some(kmi) = kmi else {
+            return LkmResolution::Unresolved("无法确定目标内核的 KMI (镜像中没有内核或不是 GKI 内核)".to_string());
+        };
+        if let Some(package) = self.ko_files.iter().find(|p| p.kmi == kmi) {
+            return LkmResolution::Exact(package);
+        }
+        let available: Vec<&str> = self.ko_files.iter().map(|p| p.kmi.as_str()).collect();
+        let kernel_version = |kmi: &str| kmi.split_once('-').and_then(|(_, v)| release_version(v));
+        let same_kernel: Vec<&str> = available
+            .iter()
+            .copied()
+            .filter(|k| kernel_version(k).is_some() && kernel_version(k) == kernel_version(kmi))
+            .collect();
+        LkmResolution::Unresolved(if same_kernel.is_empty() {
+            format!("{} 没有 {} 的 .ko (提供: {})，请更换 KernelSU 版本或分支", self.version_name, kmi, available.join(", "))
+        } else {
+            format!(
+                "{} 没有 {} 的 .ko；{} 内核版本相同但 Android 版本不同，KMI 不兼容，不能混用",
+                self.version_name,
+                kmi,
+                same_kernel.join(", ")
+            )
+        })
+    }
+}
+
+/// 从 boot 镜像或内核文件读取 KMI，init_boot 等不含内核的镜像会返回错误
+pub fn kmi_from_image(path: &Path) -> Result<Option<String>> {
+    Ok(bootimg::analyze_kernel(path)?.kmi())
+}
+
+/// 从已开机并开启 USB 调试的设备读取 KMI (uname -r)
+pub async fn kmi_from_device(adb: &AdbClient, serial: &str) -> Result<Option<String>> {
+    let release = adb.shell(serial, "uname -r").await?;
+    Ok(release_kmi(release.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_kmi_ignores_filename() {
+        let dir = std::env::temp_dir().join(format!("rua_lkm_kmi_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ko = dir.join("android14-6.1_kernelsu.ko");
+        fs::write(&ko, b"not an elf").unwrap();
+        assert_eq!(module_kmi(&ko), None);
+        assert_eq!(module_kmi(&dir.join("android13-5.10_kernelsu.ko")), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_package() {
+        let package = |kmi: &str| LkmPackage { ko_path: PathBuf::from(format!("{}_kernelsu.ko", kmi)), kmi: kmi.to_string() };
+        let version = KsuLkmVersion {
+            version_name: "v1.0.0".to_string(),
+            ksuinit_path: PathBuf::new(),
+            ksuinit_d_path: PathBuf::new(),
+            ko_files: vec![package("android13-5.15"), package("android14-6.1")],
+        };
+        assert!(matches!(version.resolve(Some("android14-6.1")), LkmResolution::Exact(p) if p.kmi == "android14-6.1"));
+        let LkmResolution::Unresolved(reason) = version.resolve(Some("android14-5.15")) else { panic!() };
+        assert!(reason.contains("android13-5.15"));
+        assert!(matches!(version.resolve(None), LkmResolution::Unresolved(_)));
+    }
+}
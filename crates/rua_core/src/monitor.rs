@@ -0,0 +1,355 @@
+use crate::adb::AdbClient;
+use crate::device::{ConnectedDevice, DeviceMode};
+use crate::fastboot::FastbootClient;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// 设备连续缺席多少次轮询后才视为断开，避免刷机期间偶发的枚举失败被误判
+const MISSING_POLLS_BEFORE_DISCONNECT: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    Connected(ConnectedDevice),
+    Disconnected(ConnectedDevice),
+    ModeChanged { device: ConnectedDevice, from: DeviceMode },
+}
+
+impl DeviceEvent {
+    pub fn serial(&self) -> &str {
+        match self {
+            DeviceEvent::Connected(d) | DeviceEvent::Disconnected(d) => &d.serial,
+            DeviceEvent::ModeChanged { device, .. } => &device.serial,
+        }
+    }
+}
+
+struct Tracked {
+    device: ConnectedDevice,
+    state: String,
+    missing: u32,
+}
+
+/// 轮询结果的比对：记录每台设备上次的状态，得出连接、断开与模式切换事件
+#[derive(Default)]
+struct DeviceTable {
+    tracked: HashMap<String, Tracked>,
+}
+
+impl DeviceTable {
+    /// 记录设备本轮仍在，返回它上次的状态
+    fn touch(&mut self, serial: &str) -> Option<&str> {
+        let t = self.tracked.get_mut(serial)?;
+        t.missing = 0;
+        Some(&t.state)
+    }
+
+    fn update(&mut self, device: ConnectedDevice, state: String) -> Option<DeviceEvent> {
+        let old = self.tracked.insert(device.serial.clone(), Tracked { device: device.clone(), state, missing: 0 });
+        match old {
+            Some(old) if old.device.mode != device.mode => Some(DeviceEvent::ModeChanged { device, from: old.device.mode }),
+            Some(_) => None,
+            None => Some(DeviceEvent::Connected(device)),
+        }
+    }
+
+    /// 本轮未出现的设备累计缺席次数，达到阈值后移除并返回断开事件
+    fn sweep(&mut self, seen: &[&str]) -> Vec<DeviceEvent> {
+        let mut gone = Vec::new();
+        for (serial, t) in self.tracked.iter_mut() {
+            if seen.contains(&serial.as_str()) {
+                continue;
+            }
+            t.missing += 1;
+            if t.missing >= MISSING_POLLS_BEFORE_DISCONNECT {
+                gone.push(serial.clone());
+            }
+        }
+        gone.into_iter()
+            .filter_map(|serial| self.tracked.remove(&serial))
+            .map(|t| DeviceEvent::Disconnected(t.device))
+            .collect()
+    }
+
+    fn snapshot(&self) -> Vec<ConnectedDevice> {
+        let mut devices: Vec<ConnectedDevice> = self.tracked.values().map(|t| t.device.clone()).collect();
+        devices.sort_by(|a, b| a.serial.cmp(&b.serial));
+        devices
+    }
+}
+
+/// 后台轮询 adb / fastboot 设备列表，并通过广播通道发出连接、断开、模式切换事件。
+/// 克隆得到的句柄共享同一个后台任务。
+#[derive(Clone)]
+pub struct DeviceMonitor {
+    devices: Arc<RwLock<Vec<ConnectedDevice>>>,
+    events: broadcast::Sender<DeviceEvent>,
+    cancel: CancellationToken,
+    /// 正在刷机的设备，轮询时不向其发送 getvar，避免与刷机命令争用设备
+    busy: Arc<Mutex<HashSet<String>>>,
+    /// 刷机计划中即将重启的设备，其断开不视为意外断开
+    rebooting: Arc<Mutex<HashSet<String>>>,
+}
+
+impl DeviceMonitor {
+    fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            devices: Arc::new(RwLock::new(Vec::new())),
+            events,
+            cancel: CancellationToken::new(),
+            busy: Arc::new(Mutex::new(HashSet::new())),
+            rebooting: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn start(mut fastboot: FastbootClient, adb: Option<AdbClient>, interval: Duration) -> Self {
+        fastboot.set_serial(None);
+        let monitor = Self::new();
+
+        let worker = monitor.clone();
+        tokio::spawn(async move {
+            let mut table = DeviceTable::default();
+            loop {
+                worker.poll_once(&fastboot, adb.as_ref(), &mut table).await;
+                tokio::select! {
+                    _ = worker.cancel.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+            }
+        });
+
+        monitor
+    }
+
+    async fn poll_once(&self, fastboot: &FastbootClient, adb: Option<&AdbClient>, table: &mut DeviceTable) {
+        let mut seen: Vec<(String, String, bool)> = Vec::new();
+        if let Ok(list) = fastboot.list_serials().await {
+            seen.extend(list.into_iter().map(|(serial, status)| (serial, status, true)));
+        }
+        if let Some(adb) = adb
+            && let Ok(list) = adb.list_serials().await
+        {
+            seen.extend(list.into_iter().map(|(serial, status)| (serial, status, false)));
+        }
+
+        let mut events = Vec::new();
+        for (serial, status, is_fastboot) in &seen {
+            if self.is_busy(serial) {
+                // 刷机期间只记录设备仍在；重新出现的设备暂按列表状态记录，结束后再读取详细信息
+                if table.touch(serial).is_none() {
+                    let device = ConnectedDevice {
+                        serial: serial.clone(),
+                        mode: DeviceMode::from(status.as_str()),
+                        status: status.clone(),
+                        product: None,
+                        current_slot: None,
+                    };
+                    events.extend(table.update(device, String::new()));
+                }
+                continue;
+            }
+            // fastboot devices 对 bootloader 与 fastbootd 给出相同的状态，需另外比较 is-userspace
+            let state = if *is_fastboot {
+                let userspace = fastboot.get_var(serial, "is-userspace").await.is_ok_and(|v| v == "yes");
+                format!("fastboot:{}:{}", status, userspace)
+            } else {
+                format!("adb:{}", status)
+            };
+            if table.touch(serial) == Some(state.as_str()) {
+                continue;
+            }
+            let device = if *is_fastboot {
+                fastboot.describe(serial, status).await
+            } else if let Some(adb) = adb {
+                adb.describe(serial, status).await
+            } else {
+                continue;
+            };
+            events.extend(table.update(device, state));
+        }
+
+        let seen: Vec<&str> = seen.iter().map(|(s, _, _)| s.as_str()).collect();
+        events.extend(table.sweep(&seen));
+        if let Ok(mut devices) = self.devices.write() {
+            *devices = table.snapshot();
+        }
+        for event in events {
+            if let DeviceEvent::Connected(d) = &event
+                && let Ok(mut rebooting) = self.rebooting.lock()
+            {
+                rebooting.remove(&d.serial);
+            }
+            let _ = self.events.send(event);
+        }
+    }
+
+    fn is_busy(&self, serial: &str) -> bool {
+        self.busy.lock().is_ok_and(|busy| busy.contains(serial))
+    }
+
+    /// 标记设备正在刷机：期间暂停对它的 getvar 查询，返回的 guard 释放后恢复
+    pub fn hold(&self, serial: &str) -> FlashGuard {
+        if let Ok(mut busy) = self.busy.lock() {
+            busy.insert(serial.to_string());
+        }
+        FlashGuard { monitor: self.clone(), serial: serial.to_string() }
+    }
+
+    /// 刷机计划即将重启设备：直到设备重新出现之前，它的断开不会触发 `DisconnectWatch`
+    pub fn expect_reboot(&self, serial: &str) {
+        if let Ok(mut rebooting) = self.rebooting.lock() {
+            rebooting.insert(serial.to_string());
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn devices(&self) -> Vec<ConnectedDevice> {
+        self.devices.read().map(|d| d.clone()).unwrap_or_default()
+    }
+
+    pub fn find(&self, serial: &str) -> Option<ConnectedDevice> {
+        self.devices().into_iter().find(|d| d.serial == serial)
+    }
+
+    /// 仅当恰好连接了一台处于给定模式之一的设备时返回该设备
+    pub fn single_device(&self, modes: &[DeviceMode]) -> Option<ConnectedDevice> {
+        let mut matching: Vec<ConnectedDevice> = self
+            .devices()
+            .into_iter()
+            .filter(|d| modes.is_empty() || modes.contains(&d.mode))
+            .collect();
+        if matching.len() == 1 { matching.pop() } else { None }
+    }
+
+    /// 从现在起关注指定设备的断开事件，供多步骤操作在步骤间或步骤中途检查
+    pub fn watch(&self, serial: &str) -> DisconnectWatch {
+        DisconnectWatch {
+            serial: serial.to_string(),
+            rx: self.subscribe(),
+            rebooting: self.rebooting.clone(),
+            lost: false,
+        }
+    }
+
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+}
+
+/// 刷机结束时释放，恢复对设备的轮询并清除未完成的重启标记
+pub struct FlashGuard {
+    monitor: DeviceMonitor,
+    serial: String,
+}
+
+impl Drop for FlashGuard {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = self.monitor.busy.lock() {
+            busy.remove(&self.serial);
+        }
+        if let Ok(mut rebooting) = self.monitor.rebooting.lock() {
+            rebooting.remove(&self.serial);
+        }
+    }
+}
+
+pub struct DisconnectWatch {
+    serial: String,
+    rx: broadcast::Receiver<DeviceEvent>,
+    rebooting: Arc<Mutex<HashSet<String>>>,
+    lost: bool,
+}
+
+impl DisconnectWatch {
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    fn is_unexpected(&self, device: &ConnectedDevice) -> bool {
+        device.serial == self.serial && !self.rebooting.lock().is_ok_and(|r| r.contains(&self.serial))
+    }
+
+    /// 非阻塞地检查自开始关注以来设备是否已断开
+    pub fn is_lost(&mut self) -> bool {
+        while !self.lost {
+            match self.rx.try_recv() {
+                Ok(DeviceEvent::Disconnected(d)) if self.is_unexpected(&d) => self.lost = true,
+                Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        self.lost
+    }
+
+    /// 等待设备断开；监视器停止后永不返回，适合放在 `tokio::select!` 中与实际操作竞争
+    pub async fn disconnected(&mut self) {
+        while !self.lost {
+            match self.rx.recv().await {
+                Ok(DeviceEvent::Disconnected(d)) if self.is_unexpected(&d) => self.lost = true,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => std::future::pending::<()>().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(serial: &str, mode: DeviceMode) -> ConnectedDevice {
+        ConnectedDevice { serial: serial.to_string(), mode, status: "fastboot".to_string(), product: None, current_slot: None }
+    }
+
+    #[test]
+    fn test_table_diffing() {
+        let mut table = DeviceTable::default();
+        let state = "fastboot:fastboot:false";
+        assert!(matches!(table.update(device("a", DeviceMode::Fastboot), state.into()), Some(DeviceEvent::Connected(_))));
+        assert_eq!(table.touch("a"), Some(state));
+        assert_eq!(table.touch("b"), None);
+
+        // bootloader -> fastbootd：状态不同，重新读取后报告模式切换
+        let state = "fastboot:fastboot:true";
+        assert_ne!(table.touch("a"), Some(state));
+        match table.update(device("a", DeviceMode::FastbootD), state.into()) {
+            Some(DeviceEvent::ModeChanged { device, from }) => {
+                assert_eq!(device.mode, DeviceMode::FastbootD);
+                assert_eq!(from, DeviceMode::Fastboot);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(table.update(device("a", DeviceMode::FastbootD), state.into()).is_none());
+
+        // 缺席一次不算断开，再次出现后重新计数
+        assert!(table.sweep(&[]).is_empty());
+        table.touch("a");
+        assert!(table.sweep(&[]).is_empty());
+        let events = table.sweep(&[]);
+        assert!(matches!(events.as_slice(), [DeviceEvent::Disconnected(d)] if d.serial == "a"));
+        assert!(table.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_watch_ignores_planned_reboot() {
+        let monitor = DeviceMonitor::new();
+        let mut watch = monitor.watch("a");
+        {
+            let _guard = monitor.hold("a");
+            assert!(monitor.is_busy("a"));
+            monitor.expect_reboot("a");
+            let _ = monitor.events.send(DeviceEvent::Disconnected(device("a", DeviceMode::Fastboot)));
+            let _ = monitor.events.send(DeviceEvent::Disconnected(device("b", DeviceMode::Fastboot)));
+            assert!(!watch.is_lost());
+        }
+        assert!(!monitor.is_busy("a"));
+        let _ = monitor.events.send(DeviceEvent::Disconnected(device("a", DeviceMode::Fastboot)));
+        assert!(watch.is_lost());
+    }
+}
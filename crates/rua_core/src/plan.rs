@@ -0,0 +1,193 @@
+use crate::error::{FlashError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FlashStep {
+    Flash { partition: String, image: PathBuf },
+    Erase { partition: String },
+    SetActive { slot: String },
+    Reboot { target: Option<String> },
+    /// 设备 getvar product 必须是其中之一
+    CheckProduct { products: Vec<String> },
+    /// 设备 getvar anti 不得高于刷机包的防回滚版本
+    CheckAntiRollback { package_version: u32 },
+    Lock { oem: bool },
+    /// 先用 Magisk APK 修补 image 再刷入 partition；批量刷机前由 `Flasher::patch_plan` 统一修补为 Flash 步骤
+    PatchAndFlash { partition: String, image: PathBuf, apk: PathBuf },
+    /// 其他原样透传给 fastboot 的命令
+    Command { args: Vec<String> },
+}
+
+impl FlashStep {
+    pub fn fastboot_args(&self) -> Vec<String> {
+        match self {
+            FlashStep::Flash { partition, image } | FlashStep::PatchAndFlash { partition, image, .. } => {
+                vec!["flash".into(), partition.clone(), image.to_string_lossy().to_string()]
+            }
+            FlashStep::Erase { partition } => vec!["erase".into(), partition.clone()],
+            FlashStep::SetActive { slot } => vec!["set_active".into(), slot.clone()],
+            FlashStep::Reboot { target } => {
+                let mut args = vec!["reboot".to_string()];
+                args.extend(target.clone());
+                args
+            }
+            FlashStep::CheckProduct { .. } => vec!["getvar".into(), "product".into()],
+            FlashStep::CheckAntiRollback { .. } => vec!["getvar".into(), "anti".into()],
+            FlashStep::Lock { oem: true } => vec!["oem".into(), "lock".into()],
+            FlashStep::Lock { oem: false } => vec!["flashing".into(), "lock".into()],
+            FlashStep::Command { args } => args.clone(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            FlashStep::Flash { partition, image } => format!(
+                "刷入 {} ({})",
+                partition,
+                image.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+            ),
+            FlashStep::PatchAndFlash { partition, image, .. } => format!(
+                "修补并刷入 {} ({}, Magisk)",
+                partition,
+                image.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+            ),
+            FlashStep::Erase { partition } => format!("擦除 {}", partition),
+            FlashStep::SetActive { slot } => format!("切换槽位 {}", slot),
+            FlashStep::Reboot { target: Some(t) } => format!("重启到 {}", t),
+            FlashStep::Reboot { target: None } => "重启".to_string(),
+            FlashStep::CheckProduct { products } => format!("校验机型 ({})", products.join("/")),
+            FlashStep::CheckAntiRollback { package_version } => format!("校验防回滚版本 (刷机包: {})", package_version),
+            FlashStep::Lock { .. } => "回锁 Bootloader".to_string(),
+            FlashStep::Command { args } => format!("执行 fastboot {}", args.join(" ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FlashPlan {
+    pub name: String,
+    pub steps: Vec<FlashStep>,
+}
+
+impl FlashPlan {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), steps: Vec::new() }
+    }
+
+    pub fn single(partition: &str, image: &Path) -> Self {
+        let mut plan = Self::new(&format!("刷入 {}", partition));
+        plan.steps.push(FlashStep::Flash { partition: partition.to_string(), image: image.to_path_buf() });
+        plan
+    }
+
+    pub fn patch_and_flash(partition: &str, image: &Path, apk: &Path) -> Self {
+        let mut plan = Self::new(&format!("修补并刷入 {}", partition));
+        plan.steps.push(FlashStep::PatchAndFlash {
+            partition: partition.to_string(),
+            image: image.to_path_buf(),
+            apk: apk.to_path_buf(),
+        });
+        plan
+    }
+
+    /// 目录下每个 .img 对应一个同名分区，按文件名排序，`skip` 中的分区名（小写）被跳过
+    pub fn from_image_dir(dir: &Path, skip: &HashSet<String>) -> Result<Self> {
+        let mut images: Vec<PathBuf> = fs::read_dir(dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "img"))
+            .collect();
+        images.sort();
+
+        let mut plan = Self::new(&dir.to_string_lossy());
+        for image in images {
+            let Some(partition) = image.file_stem().map(|s| s.to_string_lossy().to_string()) else { continue };
+            if skip.contains(&partition.to_lowercase()) {
+                continue;
+            }
+            plan.steps.push(FlashStep::Flash { partition, image });
+        }
+        if plan.steps.is_empty() {
+            return Err(FlashError::InvalidChoice(format!("目录下未发现任何 .img 文件: {}", dir.display())));
+        }
+        Ok(plan)
+    }
+
+    /// 计划中引用但不存在的镜像文件（含修补用的 APK）
+    pub fn missing_images(&self) -> Vec<&Path> {
+        self.steps
+            .iter()
+            .flat_map(|s| match s {
+                FlashStep::Flash { image, .. } => vec![image.as_path()],
+                FlashStep::PatchAndFlash { image, apk, .. } => vec![image.as_path(), apk.as_path()],
+                _ => Vec::new(),
+            })
+            .filter(|path| !path.is_file())
+            .collect()
+    }
+
+    pub fn partitions(&self) -> Vec<&str> {
+        self.steps
+            .iter()
+            .filter_map(|s| match s {
+                FlashStep::Flash { partition, .. } | FlashStep::PatchAndFlash { partition, .. } => Some(partition.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn needs_patch(&self) -> bool {
+        self.steps.iter().any(|s| matches!(s, FlashStep::PatchAndFlash { .. }))
+    }
+}
+
+/// 刷机计划执行进度回调，`serial` 用于区分并行执行的多台设备
+pub trait PlanReporter: Send + Sync {
+    fn on_step_start(&self, serial: &str, index: usize, total: usize, step: &FlashStep);
+    fn on_step_done(&self, serial: &str, index: usize, total: usize, step: &FlashStep, result: &Result<()>);
+    fn should_cancel(&self) -> bool { false }
+    /// 检查步骤无法得出结论时询问是否仍然继续，默认不继续
+    fn confirm_override(&self, _serial: &str, _reason: &str) -> bool { false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_from_image_dir() {
+        let dir = std::env::temp_dir().join(format!("rua_plan_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub.img")).unwrap();
+        for name in ["vendor_boot.img", "boot.img", "dtbo.img", "notes.txt"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        let plan = FlashPlan::from_image_dir(&dir, &HashSet::new()).unwrap();
+        assert_eq!(plan.name, dir.to_string_lossy());
+        assert_eq!(plan.partitions(), ["boot", "dtbo", "vendor_boot"]);
+        assert!(plan.missing_images().is_empty());
+        assert!(!plan.needs_patch());
+
+        let skip: HashSet<String> = ["dtbo".to_string()].into();
+        assert_eq!(FlashPlan::from_image_dir(&dir, &skip).unwrap().partitions(), ["boot", "vendor_boot"]);
+        let all: HashSet<String> = ["boot", "dtbo", "vendor_boot"].map(String::from).into();
+        assert!(FlashPlan::from_image_dir(&dir, &all).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_single_and_patch_steps() {
+        let single = FlashPlan::single("init_boot", Path::new("patched.img"));
+        assert_eq!(single.steps[0].fastboot_args(), ["flash", "init_boot", "patched.img"]);
+        assert_eq!(single.missing_images(), [Path::new("patched.img")]);
+
+        let plan = FlashPlan::patch_and_flash("boot", Path::new("boot.img"), Path::new("Magisk.apk"));
+        assert!(plan.needs_patch());
+        assert_eq!(plan.partitions(), ["boot"]);
+        assert_eq!(plan.missing_images(), [Path::new("boot.img"), Path::new("Magisk.apk")]);
+        assert_eq!(plan.steps[0].describe(), "修补并刷入 boot (boot.img, Magisk)");
+    }
+}
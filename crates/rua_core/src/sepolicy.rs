@@ -54,8 +54,8 @@ impl Sepolicy {
 }
 
 pub fn extract_sepolicy(ramdisk_data: &[u8]) -> Option<Vec<u8>> {
-    // 使用统一的 cpio 解析逻辑
-    crate::utils::cpio_extract_file(ramdisk_data, "sepolicy")
+    let cpio = crate::cpio::Cpio::parse(ramdisk_data).ok()?;
+    cpio.get("sepolicy").map(|e| e.data.clone())
 }
 
 pub fn get_magisk_selinux_rules() -> &'static str {
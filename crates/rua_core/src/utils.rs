@@ -8,9 +8,10 @@ use zstd::stream::read::Decoder as ZstdDecoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 use lz4_flex::frame::FrameDecoder as Lz4Decoder;
 use lz4_flex::frame::FrameEncoder as Lz4Encoder;
-use cpio::newc::Reader as CpioReader;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RamdiskFormat {
     Gzip,
     Xz,
@@ -147,143 +148,3 @@ pub fn compress_ramdisk(fmt: RamdiskFormat, data: &[u8]) -> Result<Vec<u8>> {
         RamdiskFormat::Uncompressed => Ok(data.to_vec()),
     }
 }
-
-pub fn cpio_extract_file(data: &[u8], target_name: &str) -> Option<Vec<u8>> {
-    let mut cursor = Cursor::new(data);
-    while let Ok(mut reader) = CpioReader::new(cursor) {
-        let name = reader.entry().name().to_string();
-        if name == target_name {
-            let mut content = Vec::new();
-            if reader.read_to_end(&mut content).is_ok() {
-                return Some(content);
-            }
-        }
-        if name == "TRAILER!!!" {
-            break;
-        }
-        cursor = reader.finish().ok()?;
-    }
-    None
-}
-
-pub fn cpio_extract_file_newc(data: &[u8], target_name: &str) -> Option<(usize, Vec<u8>)> {
-    let mut cursor = Cursor::new(data);
-    while let Ok(mut reader) = CpioReader::new(cursor) {
-        let name = reader.entry().name().to_string();
-        let mode = reader.entry().mode();
-        if name == target_name {
-            let mut content = Vec::new();
-            if reader.read_to_end(&mut content).is_ok() {
-                return Some((mode as usize, content));
-            }
-        }
-        if name == "TRAILER!!!" {
-            break;
-        }
-        cursor = reader.finish().ok()?;
-    }
-    None
-}
-
-pub fn cpio_load_with_threecpio(data: &[u8]) -> Result<(Vec<(String, u32, Vec<u8>)>, Option<(usize, Vec<u8>)>)> {
-    if data.is_empty() {
-        return Ok((Vec::new(), None));
-    }
-
-    let header_magic = &data[0..6];
-    println!("[DEBUG] CPIO header check: {:02x?}", header_magic);
-    println!("[DEBUG] Expected newc magic: 070701 = {:02x?}", b"070701");
-
-    let mut cursor = Cursor::new(data);
-    let mut entries = Vec::new();
-    let mut old_init_info = None;
-
-    loop {
-         let reader_result = CpioReader::new(cursor);
-         let mut reader = match reader_result {
-             Ok(reader) => reader,
-             Err(e) => {
-                 println!("[DEBUG] CPIO reader error: {:?}, trying to continue", e);
-                 break;
-             }
-         };
-
-        let name = reader.entry().name().to_string();
-        let mode = reader.entry().mode();
-
-        println!("[DEBUG] CPIO entry: name={}, mode=0o{:o}", name, mode);
-
-        if name == "TRAILER!!!" {
-            println!("[DEBUG] Found CPIO trailer, parsing complete");
-            break;
-        }
-
-        let mut content = Vec::new();
-        match reader.read_to_end(&mut content) {
-            Ok(_) => {
-                if name == "init" {
-                    old_init_info = Some((mode as usize, content.clone()));
-                }
-                entries.push((name, mode as u32, content));
-            }
-            Err(e) => {
-                println!("[DEBUG] Error reading CPIO entry: {:?}", e);
-                break;
-            }
-        }
-
-        cursor = match reader.finish() {
-            Ok(c) => c,
-            Err(e) => {
-                println!("[DEBUG] Error getting next CPIO entry: {:?}", e);
-                break;
-            }
-        };
-    }
-
-    if entries.is_empty() {
-        println!("[DEBUG] No CPIO entries found. Data size: {} bytes", data.len());
-        println!("[DEBUG] First 32 bytes: {:02x?}", &data[0..std::cmp::min(32, data.len())]);
-
-        if data.len() >= 6 {
-            let magic_check = u32::from_be_bytes([
-                data[0], data[1], data[2], data[3]
-            ]);
-            println!("[DEBUG] Magic check: 0x{:08x}", magic_check);
-
-            if magic_check == 0x30373037u32 {
-                println!("[DEBUG] Magic looks like newc format but parsing failed");
-            }
-        }
-
-        return Err(FlashError::PatchError(format!(
-            "Failed to parse cpio archive: no entries found (format may be unsupported). Data size: {} bytes, magic: 0x{:08x}",
-            data.len(),
-            u32::from_be_bytes([data[0], data[1], data[2], data[3]])
-        )));
-    }
-
-    println!("[DEBUG] Successfully parsed {} CPIO entries", entries.len());
-    Ok((entries, old_init_info))
-}
-
-pub fn cpio_create_with_threecpio(entries: &[(String, u32, Vec<u8>)]) -> Result<Vec<u8>> {
-    let mut output = Vec::new();
-    {
-        let mut cursor = Cursor::new(&mut output);
-        
-        for (name, mode, content) in entries {
-            let builder = cpio::NewcBuilder::new(name)
-                .mode(*mode)
-                .uid(1000)
-                .gid(1000)
-                .nlink(1);
-            let mut writer = builder.write(&mut cursor, content.len() as u32);
-            writer.write_all(content)?;
-            writer.finish()?;
-        }
-        
-        let _ = cpio::newc::trailer(&mut cursor);
-    }
-    Ok(output)
-}
@@ -0,0 +1,250 @@
+use crate::error::{FlashError, Result};
+use crate::plan::{FlashPlan, FlashStep};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 已知的小米线刷脚本及其说明，未列出的 flash_all*.bat/.sh 以文件名作为说明
+const KNOWN_SCRIPTS: &[(&str, &str)] = &[
+    ("flash_all", "刷机并清除所有数据"),
+    ("flash_all_lock", "刷机、清除数据并回锁 Bootloader"),
+    ("flash_all_except_storage", "刷机并保留个人数据"),
+    ("flash_all_except_data_storage", "刷机并保留个人数据"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashScript {
+    pub path: PathBuf,
+    pub description: String,
+}
+
+impl FlashScript {
+    pub fn stem(&self) -> String {
+        self.path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+    }
+
+    pub fn wipes_data(&self) -> bool {
+        !self.stem().contains("except")
+    }
+
+    pub fn locks_bootloader(&self) -> bool {
+        self.stem().ends_with("_lock")
+    }
+}
+
+/// 列出线刷包目录下的 flash_all 脚本；Windows 优先 .bat，其他平台优先 .sh，缺失时退回另一种
+pub fn find_flash_scripts(dir: &Path) -> Vec<FlashScript> {
+    let (preferred, fallback) = if cfg!(target_os = "windows") { ("bat", "sh") } else { ("sh", "bat") };
+    let collect = |ext: &str| -> Vec<FlashScript> {
+        let mut scripts: Vec<FlashScript> = fs::read_dir(dir)
+            .map(|rd| rd.flatten().map(|e| e.path()).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext)))
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+                if !stem.starts_with("flash_all") {
+                    return None;
+                }
+                let description = KNOWN_SCRIPTS
+                    .iter()
+                    .find(|(name, _)| *name == stem)
+                    .map(|(_, desc)| desc.to_string())
+                    .unwrap_or_else(|| stem.clone());
+                Some(FlashScript { path, description })
+            })
+            .collect();
+        scripts.sort_by_key(|s| {
+            let stem = s.stem().to_lowercase();
+            KNOWN_SCRIPTS.iter().position(|(name, _)| *name == stem).unwrap_or(KNOWN_SCRIPTS.len())
+        });
+        scripts
+    };
+    let scripts = collect(preferred);
+    if scripts.is_empty() { collect(fallback) } else { scripts }
+}
+
+pub fn parse_flash_script(path: &Path) -> Result<FlashPlan> {
+    let text = fs::read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let mut plan = parse_flash_script_text(&text, base_dir)?;
+    plan.name = path.to_string_lossy().to_string();
+
+    // 部分刷机包的脚本里没有 CURRENT_ANTI_VER，但附带了 anti_version.txt，补上校验步骤
+    if !plan.steps.iter().any(|s| matches!(s, FlashStep::CheckAntiRollback { .. }))
+        && let Some(package_version) = read_anti_version_file(base_dir)
+    {
+        let at = plan.steps.iter().take_while(|s| matches!(s, FlashStep::CheckProduct { .. })).count();
+        plan.steps.insert(at, FlashStep::CheckAntiRollback { package_version });
+    }
+    Ok(plan)
+}
+
+fn read_anti_version_file(dir: &Path) -> Option<u32> {
+    [dir.join("anti_version.txt"), dir.join("images").join("anti_version.txt")]
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .and_then(|text| text.trim().parse().ok())
+}
+
+/// 设备防回滚版本高于刷机包时返回阻止刷机的说明
+pub fn anti_rollback_violation(device_version: u32, package_version: u32) -> Option<String> {
+    (device_version > package_version).then(|| {
+        format!(
+            "设备当前防回滚版本 (ARB) 为 {}，刷机包为 {}。引导程序会拒绝启动版本更低的固件，\
+             刷入后设备将硬砖，只能通过 EDL 售后救砖。请改用防回滚版本不低于 {} 的刷机包。",
+            device_version, package_version, device_version
+        )
+    })
+}
+
+/// 把 flash_all 脚本转换为刷机计划。脚本中的镜像路径（`%~dp0`、`` `dirname $0` ``）
+/// 相对于 `base_dir` 解析；getvar product 检查与 CURRENT_ANTI_VER 转为校验步骤。
+pub fn parse_flash_script_text(text: &str, base_dir: &Path) -> Result<FlashPlan> {
+    let fastboot_re = Regex::new(r#"^fastboot(?:\.exe)?\s+(?:%\*|\$\*|"?\$@"?)\s+(.*)$"#).unwrap();
+    let product_re = Regex::new(r"product: \*([A-Za-z0-9_\-]+)").unwrap();
+    let anti_re = Regex::new(r"^(?:set\s+)?CURRENT_ANTI_VER=(\d+)").unwrap();
+
+    let mut plan = FlashPlan::new("xiaomi");
+    for raw in text.lines() {
+        let line = raw.trim().trim_start_matches('@').trim();
+        if line.is_empty() || line.starts_with("::") || line.starts_with('#') || line.to_lowercase().starts_with("rem ") {
+            continue;
+        }
+
+        if let Some(caps) = anti_re.captures(line) {
+            let package_version = caps[1].parse().unwrap_or(0);
+            plan.steps.push(FlashStep::CheckAntiRollback { package_version });
+            continue;
+        }
+
+        // `for /f ... ('fastboot %* getvar anti ...')` 这类行不以 fastboot 开头，直接忽略
+        let Some(caps) = fastboot_re.captures(line) else { continue };
+        let command = normalize_script_dir(strip_shell_tail(&caps[1]));
+        let args: Vec<String> = command.split_whitespace().map(|a| resolve_path_token(a, base_dir)).collect();
+        let Some(verb) = args.first() else { continue };
+
+        let step = match (verb.as_str(), args.len()) {
+            ("getvar", _) if args.get(1).is_some_and(|v| v == "product") => {
+                let products: Vec<String> = product_re.captures_iter(&caps[1]).map(|c| c[1].to_string()).collect();
+                if products.is_empty() {
+                    continue;
+                }
+                // 官方脚本通常连续写两行相同的检查（一行提示，一行退出），只保留一次
+                if matches!(plan.steps.last(), Some(FlashStep::CheckProduct { products: last }) if *last == products) {
+                    continue;
+                }
+                FlashStep::CheckProduct { products }
+            }
+            ("getvar", _) => continue,
+            ("flash", 3) => FlashStep::Flash { partition: args[1].clone(), image: PathBuf::from(&args[2]) },
+            ("erase", 2) => FlashStep::Erase { partition: args[1].clone() },
+            ("set_active", 2) => FlashStep::SetActive { slot: args[1].clone() },
+            ("reboot", 1) => FlashStep::Reboot { target: None },
+            ("reboot", 2) => FlashStep::Reboot { target: Some(args[1].clone()) },
+            ("oem", 2) if args[1] == "lock" => FlashStep::Lock { oem: true },
+            ("flashing", 2) if args[1] == "lock" => FlashStep::Lock { oem: false },
+            _ => FlashStep::Command { args },
+        };
+        plan.steps.push(step);
+    }
+
+    if !plan.steps.iter().any(|s| matches!(s, FlashStep::Flash { .. })) {
+        return Err(FlashError::InvalidChoice("刷机脚本中未找到任何 fastboot flash 命令".to_string()));
+    }
+    Ok(plan)
+}
+
+/// 去掉 `|| exit /B 1`、`2>&1 | findstr ...`、重定向等 shell 尾巴，只保留 fastboot 参数
+fn strip_shell_tail(command: &str) -> &str {
+    let mut end = command.len();
+    for marker in ["||", "&&", "|", " 2>", " 1>", " >"] {
+        if let Some(pos) = command.find(marker) {
+            end = end.min(pos);
+        }
+    }
+    command[..end].trim()
+}
+
+/// 把 shell 脚本中表示脚本所在目录的写法统一为 `%~dp0`，避免 `` `dirname $0` `` 中的空格影响分词
+fn normalize_script_dir(command: &str) -> String {
+    ["`dirname $0`/", "$(dirname $0)/", "$(dirname \"$0\")/"]
+        .iter()
+        .fold(command.to_string(), |acc, pattern| acc.replace(pattern, "%~dp0"))
+}
+
+fn resolve_path_token(token: &str, base_dir: &Path) -> String {
+    let token = token.trim_matches('"');
+    match token.strip_prefix("%~dp0") {
+        Some(rest) => {
+            let mut path = base_dir.to_path_buf();
+            for part in rest.split(['\\', '/']).filter(|p| !p.is_empty()) {
+                path.push(part);
+            }
+            path.to_string_lossy().to_string()
+        }
+        None => token.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAT: &str = r#"fastboot %* getvar product 2>&1 | findstr /r /c:"^product: *marble" || echo Missmatching image and device
+fastboot %* getvar product 2>&1 | findstr /r /c:"^product: *marble" || exit /B 1
+set CURRENT_ANTI_VER=1
+for /f "tokens=2 delims=: " %%i in ('fastboot %* getvar anti 2^>^&1 ^| findstr /r /c:"anti:"') do (set version=%%i)
+fastboot %* erase boot_ab || @echo "Erase boot error" && exit /B 1
+fastboot %* flash xbl_ab %~dp0images\xbl.elf || @echo "Flash xbl error" && exit /B 1
+fastboot %* flash super %~dp0images\super.img || @echo "Flash super error" && exit /B 1
+fastboot %* set_active a || @echo "Set active a error" && exit /B 1
+fastboot %* oem lock || @echo "Oem lock error" && exit /B 1
+fastboot %* reboot || @echo "Reboot error" && exit /B 1
+"#;
+
+    const SH: &str = r#"fastboot $* getvar product 2>&1 | grep "^product: *marble"
+if [ $? -ne 0 ] ; then echo "Missmatching image and device"; exit 1; fi
+CURRENT_ANTI_VER=1
+fastboot $* erase boot_ab
+fastboot $* flash xbl_ab `dirname $0`/images/xbl.elf
+fastboot $* reboot
+"#;
+
+    #[test]
+    fn test_parse_bat_script() {
+        let plan = parse_flash_script_text(BAT, Path::new("pkg")).unwrap();
+        assert_eq!(plan.steps.len(), 8);
+        assert_eq!(plan.steps[0], FlashStep::CheckProduct { products: vec!["marble".to_string()] });
+        assert_eq!(plan.steps[1], FlashStep::CheckAntiRollback { package_version: 1 });
+        assert_eq!(plan.steps[2], FlashStep::Erase { partition: "boot_ab".to_string() });
+        assert_eq!(
+            plan.steps[3],
+            FlashStep::Flash { partition: "xbl_ab".to_string(), image: Path::new("pkg").join("images").join("xbl.elf") }
+        );
+        assert_eq!(plan.steps[5], FlashStep::SetActive { slot: "a".to_string() });
+        assert_eq!(plan.steps[6], FlashStep::Lock { oem: true });
+        assert_eq!(plan.steps[7], FlashStep::Reboot { target: None });
+    }
+
+    #[test]
+    fn test_parse_sh_script() {
+        let plan = parse_flash_script_text(SH, Path::new("pkg")).unwrap();
+        assert_eq!(plan.steps.len(), 5);
+        assert_eq!(
+            plan.steps[3],
+            FlashStep::Flash { partition: "xbl_ab".to_string(), image: Path::new("pkg").join("images").join("xbl.elf") }
+        );
+    }
+
+    #[test]
+    fn test_anti_rollback_violation() {
+        assert!(anti_rollback_violation(2, 1).is_some());
+        assert!(anti_rollback_violation(1, 1).is_none());
+    }
+
+    #[test]
+    fn test_parse_script_without_flash() {
+        assert!(parse_flash_script_text("echo hello\n", Path::new(".")).is_err());
+    }
+}